@@ -13,6 +13,7 @@ users = Users
 categories = Categories
 metadata = Metadata
 metrics = Metrics
+cache = Cache
 
 # Pages
 welcome = Welcome to MVT Server!
@@ -100,6 +101,10 @@ info = Info
 edit = Edit
 switch-published = Publish / Unpublish
 delete-cache = Delete Cache
+invalidate-area = Invalidate area
+info-invalidate-area = Drops only the cached tiles inside a bounding box or a GeoJSON geometry; the rest of the layer cache is kept. Empty zooms default to the layer zooms.
+area-bbox = Bounding box (west,south,east,north)
+area-geometry = GeoJSON geometry (instead of the bounding box)
 delete = Delete
 open-json = Open JSON
 open-png = Open PNG
@@ -167,7 +172,10 @@ table = Table
 fields = Fields
 sql-mode = SQL Mode
 geom = Geom
+id-column = ID column
+info-id-column = Optional. Integer column written as the feature id of the tiles, so clients such as MapLibre can use feature state for hover and selection.
 srid = SRID
+tile-matrix-set = Tile matrix set
 buffer = Buffer
 extent = Extent
 zmin = Minimum zoom
@@ -182,6 +190,42 @@ info-time-in-seconds = Time must enter in seconds
 info-value-infinity = The value of 0 means infinity
 max-records = Maximum number of records
 info-max-records = Maximum records to retrieve. Using 0 ignores this directive.
+query-timeout = Query timeout (ms)
+info-query-timeout = Tile queries of this layer running longer are cancelled and answered with 504 Gateway Timeout. Using 0 keeps the database's statement timeout.
+notify-changes = Invalidate on table changes
+info-notify-changes = A trigger on the table reports every insert, update and delete, and the tiles around the changed features are invalidated within a second. Installing the trigger requires owning the table.
+priority = Priority
+info-priority = Optional. Column or SQL expression ranking the features kept when Maximum number of records applies; ties and layers without priority fall back to the ID column or the geometry, so every request keeps the same features.
+priority-order = Priority order
+priority-desc = Highest first
+priority-asc = Lowest first
+priority-random = Random (changes on every request)
+cluster-zoom = Cluster below zoom
+info-cluster-zoom = Optional, point layers only. Tiles of lower zooms carry one point per cluster of nearby features, with a point_count attribute; leave empty to never cluster.
+cluster-radius = Cluster radius (px)
+cluster-aggregates = Cluster aggregates
+info-cluster-aggregates = Optional. Attributes of the cluster points besides point_count, e.g. 'sum(victims), max(severity)', published as sum_victims and max_severity. Functions: sum, avg, min, max.
+bin-shape = Binning
+info-bin-shape = Optional. Turns the layer into a density grid: each tile holds hexagon or square cells with the count of features falling in them, as polygons.
+bin-none = None
+bin-hexagon = Hexagons
+bin-square = Squares
+bin-size = Bin size (px)
+bin-aggregates = Bin aggregates
+info-bin-aggregates = Optional. Attributes of the cells besides count, e.g. 'sum(victims), avg(severity)', published as sum_victims and avg_severity. Functions: sum, avg, min, max.
+source-kind = Source
+static-file = Tile file
+info-static-file = Files placed in the static tiles directory. New files are listed without restarting the server.
+function = PostGIS function
+info-function = Functions with the signature fn(z integer, x integer, y integer, query_params json) RETURNS bytea. Query parameters of the tile request are passed as JSON.
+sql-query = SQL query
+info-sql-query = A SELECT returning the geometry column and the fields to publish. Use !bbox! for the tile envelope (in the layer SRID), !zoom!, !x! and !y! for the tile, and !name! for each declared parameter. The query is checked against the database when the layer is saved.
+sql-params = SQL parameters
+info-sql-params = Comma separated names of request parameters the query uses as !name!. They are bound as text (NULL when missing); cast them in the query, e.g. !min_area!::numeric.
+zoom-fields = Fields by zoom
+info-zoom-fields = Optional. One 'zmin-zmax: field, field' line per zoom range, using the selected fields; zooms no line covers get all the selected fields.
+generalization = Generalization
+info-generalization = Optional. One 'zmin-zmax: simplify=1, min_area=4, min_length=2' line per zoom range, sizes in pixels of a 256 px tile: simplification tolerance, smallest polygon area and shortest line kept.
 published = Published
 allowed-groups = Allowed Groups
 info-empty-allowed-groups = If it's empty, all groups are allowed
@@ -199,3 +243,20 @@ plugins-dir-hint = Place .lua files in the plugins directory to get started.
 plugin-layer = Layer
 plugin-category = Category
 show-code = Show code
+
+# Cache
+cache-stats = Tile cache
+cache-backend = Backend
+info-cache-stats = Tiles stored per layer, read from the cache backend at most every 30 seconds; expired tiles are not counted. Hits and misses are counted by this instance since it started, for requests that can be served from the cache.
+cached-tiles = Tiles
+cache-size = Size
+memory-tier = In memory
+hit-ratio = Hit ratio
+cache-hits-misses = Hits / misses
+oldest-tile = Oldest
+newest-tile = Newest
+browse-tiles = Browse tiles
+all-zooms = All zooms
+tile-age = Age
+no-cached-tiles = No cached tiles
+cache-stats-error = Could not read the cached tiles
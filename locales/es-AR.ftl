@@ -13,6 +13,7 @@ users = Usuarios
 categories = Categorías
 metadata = Metadatos
 metrics = Métricas
+cache = Caché
 
 # Pages
 welcome = ¡Bienvenido a MVT Server!
@@ -100,6 +101,10 @@ info = Información
 edit = Editar
 switch-published = Publicar / Despublicar
 delete-cache = Eliminar Caché
+invalidate-area = Invalidar área
+info-invalidate-area = Elimina solo los tiles en caché dentro de un bounding box o de una geometría GeoJSON; el resto del caché de la capa se conserva. Si dejás los zooms vacíos se usan los de la capa.
+area-bbox = Bounding box (oeste,sur,este,norte)
+area-geometry = Geometría GeoJSON (en lugar del bounding box)
 delete = Eliminar
 open-json = Abrir JSON
 open-png = Abrir PNG
@@ -169,7 +174,10 @@ table = Tabla
 fields = Campos
 sql-mode = Modo SQL
 geom = Geom
+id-column = Columna ID
+info-id-column = Opcional. Columna entera que se escribe como id de los features de las teselas, para que clientes como MapLibre puedan usar feature state para hover y selección.
 srid = SRID
+tile-matrix-set = Conjunto de matrices de tiles
 buffer = Buffer
 extent = Extent
 zmin = Zoom mínimo
@@ -184,6 +192,42 @@ info-time-in-seconds = El tiempo debe ingresarse en segundos
 info-value-infinity = Un valor de 0 significa edad infinita
 max-records = Máxima cantidad de registros
 info-max-records = Máxima cantidad de registros a recuperar. Usando 0 ignora esta directiva.
+query-timeout = Tiempo máximo de consulta (ms)
+info-query-timeout = Las consultas de teselas de esta capa que tarden más se cancelan y se responden con 504 Gateway Timeout. Con 0 se mantiene el statement timeout de la base de datos.
+notify-changes = Invalidar con cambios en la tabla
+info-notify-changes = Un trigger en la tabla informa cada insert, update y delete, y las teselas alrededor de los features modificados se invalidan en menos de un segundo. Instalar el trigger requiere ser dueño de la tabla.
+priority = Prioridad
+info-priority = Opcional. Columna o expresión SQL que ordena los features que se conservan cuando aplica la máxima cantidad de registros; los empates y las capas sin prioridad se ordenan por la columna ID o la geometría, así cada pedido conserva los mismos features.
+priority-order = Orden de prioridad
+priority-desc = Mayor primero
+priority-asc = Menor primero
+priority-random = Aleatorio (cambia en cada pedido)
+cluster-zoom = Agrupar debajo del zoom
+info-cluster-zoom = Opcional, solo capas de puntos. Las teselas de zooms menores llevan un punto por grupo de features cercanos, con el atributo point_count; dejalo vacío para no agrupar nunca.
+cluster-radius = Radio de agrupamiento (px)
+cluster-aggregates = Agregados del grupo
+info-cluster-aggregates = Opcional. Atributos de los puntos agrupados además de point_count, p. ej. 'sum(victims), max(severity)', publicados como sum_victims y max_severity. Funciones: sum, avg, min, max.
+bin-shape = Agregación en grilla
+info-bin-shape = Opcional. Convierte la capa en una grilla de densidad: cada tesela lleva celdas hexagonales o cuadradas con la cantidad de features que caen en ellas, como polígonos.
+bin-none = Ninguna
+bin-hexagon = Hexágonos
+bin-square = Cuadrados
+bin-size = Tamaño de celda (px)
+bin-aggregates = Agregados de celda
+info-bin-aggregates = Opcional. Atributos de las celdas además de count, p. ej. 'sum(victims), avg(severity)', publicados como sum_victims y avg_severity. Funciones: sum, avg, min, max.
+source-kind = Origen
+static-file = Archivo de teselas
+info-static-file = Archivos ubicados en el directorio de teselas estáticas. Los archivos nuevos se listan sin reiniciar el servidor.
+function = Función PostGIS
+info-function = Funciones con la firma fn(z integer, x integer, y integer, query_params json) RETURNS bytea. Los parámetros de la petición de teselas se pasan como JSON.
+sql-query = Consulta SQL
+info-sql-query = Un SELECT que devuelve la columna de geometría y los campos a publicar. Use !bbox! para el envolvente de la tesela (en el SRID de la capa), !zoom!, !x! e !y! para la tesela, y !nombre! para cada parámetro declarado. La consulta se valida contra la base de datos al guardar la capa.
+sql-params = Parámetros SQL
+info-sql-params = Nombres, separados por comas, de los parámetros de la petición que la consulta usa como !nombre!. Se pasan como texto (NULL si faltan); conviértalos en la consulta, p. ej. !min_area!::numeric.
+zoom-fields = Campos por zoom
+info-zoom-fields = Opcional. Una línea 'zmin-zmax: campo, campo' por rango de zoom, con los campos seleccionados; los zooms que ninguna línea cubre llevan todos los campos seleccionados.
+generalization = Generalización
+info-generalization = Opcional. Una línea 'zmin-zmax: simplify=1, min_area=4, min_length=2' por rango de zoom, con tamaños en píxeles de una tesela de 256 px: tolerancia de simplificación, área mínima de polígonos y longitud mínima de líneas que se conservan.
 published = Publicada
 allowed-groups = Grupos Permitidos
 info-empty-allowed-groups = Si está vacío, todos los grupos están permitidos
@@ -201,3 +245,20 @@ plugins-dir-hint = Colocá archivos .lua en el directorio de plugins para comenz
 plugin-layer = Capa
 plugin-category = Categoría
 show-code = Ver código
+
+# Caché
+cache-stats = Caché de tiles
+cache-backend = Backend
+info-cache-stats = Tiles guardados por capa, leídos del backend del caché como mucho cada 30 segundos; los tiles vencidos no se cuentan. Los aciertos y fallos los cuenta esta instancia desde que arrancó, para los pedidos que se pueden servir desde el caché.
+cached-tiles = Tiles
+cache-size = Tamaño
+memory-tier = En memoria
+hit-ratio = Tasa de aciertos
+cache-hits-misses = Aciertos / fallos
+oldest-tile = Más viejo
+newest-tile = Más nuevo
+browse-tiles = Explorar tiles
+all-zooms = Todos los zooms
+tile-age = Antigüedad
+no-cached-tiles = No hay tiles en caché
+cache-stats-error = No se pudieron leer los tiles cacheados
@@ -13,6 +13,7 @@ users = Usuarios
 categories = Categorías
 metadata = Metadatos
 metrics = Métricas
+cache = Caché
 
 # Pages
 welcome = ¡Bienvenido a MVT Server!
@@ -100,6 +101,10 @@ info = Información
 edit = Editar
 switch-published = Publicar / Despublicar
 delete-cache = Eliminar Caché
+invalidate-area = Invalidar área
+info-invalidate-area = Elimina solo las teselas en caché dentro de un bounding box o de una geometría GeoJSON; el resto de la caché de la capa se conserva. Si dejas los zooms vacíos se usan los de la capa.
+area-bbox = Bounding box (oeste,sur,este,norte)
+area-geometry = Geometría GeoJSON (en lugar del bounding box)
 delete = Eliminar
 open-json = Abrir JSON
 open-png = Abrir PNG
@@ -167,7 +172,10 @@ table = Tabla
 fields = Campos
 sql-mode = Modo SQL
 geom = Geom
+id-column = Columna ID
+info-id-column = Opcional. Columna entera que se escribe como id de los features de las teselas, para que clientes como MapLibre puedan usar feature state para hover y selección.
 srid = SRID
+tile-matrix-set = Conjunto de matrices de teselas
 buffer = Buffer
 extent = Extent
 zmin = Zoom mínimo
@@ -182,6 +190,42 @@ info-time-in-seconds = El tiempo debe indicarse en segundos
 info-value-infinity = El valor 0 significa infinito
 max-records = Número máximo de registros
 info-max-records = Máximo de registros a recuperar. Usar 0 ignora esta directiva.
+query-timeout = Tiempo máximo de consulta (ms)
+info-query-timeout = Las consultas de teselas de esta capa que tarden más se cancelan y se responden con 504 Gateway Timeout. Con 0 se mantiene el statement timeout de la base de datos.
+notify-changes = Invalidar con cambios en la tabla
+info-notify-changes = Un trigger en la tabla informa de cada insert, update y delete, y las teselas alrededor de los elementos modificados se invalidan en menos de un segundo. Instalar el trigger requiere ser propietario de la tabla.
+priority = Prioridad
+info-priority = Opcional. Columna o expresión SQL que ordena los features que se conservan cuando se aplica el número máximo de registros; los empates y las capas sin prioridad se ordenan por la columna ID o la geometría, así cada petición conserva los mismos features.
+priority-order = Orden de prioridad
+priority-desc = Mayor primero
+priority-asc = Menor primero
+priority-random = Aleatorio (cambia en cada petición)
+cluster-zoom = Agrupar por debajo del zoom
+info-cluster-zoom = Opcional, solo capas de puntos. Las teselas de zooms menores llevan un punto por grupo de features cercanos, con el atributo point_count; déjalo vacío para no agrupar nunca.
+cluster-radius = Radio de agrupación (px)
+cluster-aggregates = Agregados del grupo
+info-cluster-aggregates = Opcional. Atributos de los puntos agrupados además de point_count, p. ej. 'sum(victims), max(severity)', publicados como sum_victims y max_severity. Funciones: sum, avg, min, max.
+bin-shape = Agregación en rejilla
+info-bin-shape = Opcional. Convierte la capa en una rejilla de densidad: cada tesela lleva celdas hexagonales o cuadradas con el número de features que caen en ellas, como polígonos.
+bin-none = Ninguna
+bin-hexagon = Hexágonos
+bin-square = Cuadrados
+bin-size = Tamaño de celda (px)
+bin-aggregates = Agregados de celda
+info-bin-aggregates = Opcional. Atributos de las celdas además de count, p. ej. 'sum(victims), avg(severity)', publicados como sum_victims y avg_severity. Funciones: sum, avg, min, max.
+source-kind = Origen
+static-file = Archivo de teselas
+info-static-file = Archivos ubicados en el directorio de teselas estáticas. Los archivos nuevos se listan sin reiniciar el servidor.
+function = Función PostGIS
+info-function = Funciones con la firma fn(z integer, x integer, y integer, query_params json) RETURNS bytea. Los parámetros de la petición de teselas se pasan como JSON.
+sql-query = Consulta SQL
+info-sql-query = Un SELECT que devuelve la columna de geometría y los campos a publicar. Use !bbox! para la envolvente de la tesela (en el SRID de la capa), !zoom!, !x! e !y! para la tesela, y !nombre! para cada parámetro declarado. La consulta se valida contra la base de datos al guardar la capa.
+sql-params = Parámetros SQL
+info-sql-params = Nombres, separados por comas, de los parámetros de la petición que la consulta usa como !nombre!. Se pasan como texto (NULL si faltan); conviértalos en la consulta, p. ej. !min_area!::numeric.
+zoom-fields = Campos por zoom
+info-zoom-fields = Opcional. Una línea 'zmin-zmax: campo, campo' por rango de zoom, con los campos seleccionados; los zooms que ninguna línea cubre llevan todos los campos seleccionados.
+generalization = Generalización
+info-generalization = Opcional. Una línea 'zmin-zmax: simplify=1, min_area=4, min_length=2' por rango de zoom, con tamaños en píxeles de una tesela de 256 px: tolerancia de simplificación, área mínima de polígonos y longitud mínima de líneas que se conservan.
 published = Publicado
 allowed-groups = Grupos Permitidos
 info-empty-allowed-groups = Si está vacío, se permiten todos los grupos
@@ -199,3 +243,20 @@ plugins-dir-hint = Coloca archivos .lua en el directorio de plugins para empezar
 plugin-layer = Capa
 plugin-category = Categoría
 show-code = Ver código
+
+# Caché
+cache-stats = Caché de teselas
+cache-backend = Backend
+info-cache-stats = Teselas guardadas por capa, leídas del backend de la caché como mucho cada 30 segundos; las teselas caducadas no se cuentan. Los aciertos y fallos los cuenta esta instancia desde que se inició, para las peticiones que se pueden servir desde la caché.
+cached-tiles = Teselas
+cache-size = Tamaño
+memory-tier = En memoria
+hit-ratio = Tasa de aciertos
+cache-hits-misses = Aciertos / fallos
+oldest-tile = Más antigua
+newest-tile = Más reciente
+browse-tiles = Explorar teselas
+all-zooms = Todos los zooms
+tile-age = Antigüedad
+no-cached-tiles = No hay teselas en caché
+cache-stats-error = No se han podido leer las teselas en caché
@@ -13,6 +13,7 @@ users = Utilisateurs
 categories = Catégories
 metadata = Métadonnées
 metrics = Métriques
+cache = Cache
 
 # Pages
 welcome = Bienvenue sur MVT Server !
@@ -100,6 +101,10 @@ info = Informations
 edit = Modifier
 switch-published = Publier / Dépublier
 delete-cache = Supprimer le Cache
+invalidate-area = Invalider une zone
+info-invalidate-area = Supprime uniquement les tuiles en cache dans une emprise ou une géométrie GeoJSON ; le reste du cache de la couche est conservé. Sans zooms, ceux de la couche sont utilisés.
+area-bbox = Emprise (ouest,sud,est,nord)
+area-geometry = Géométrie GeoJSON (à la place de l'emprise)
 delete = Supprimer
 open-json = Ouvrir le JSON
 open-png = Ouvrir le PNG
@@ -167,7 +172,10 @@ table = Table
 fields = Champs
 sql-mode = Mode SQL
 geom = Geom
+id-column = Colonne ID
+info-id-column = Facultatif. Colonne entière écrite comme identifiant des entités des tuiles, pour que des clients comme MapLibre puissent utiliser le feature state pour le survol et la sélection.
 srid = SRID
+tile-matrix-set = Jeu de matrices de tuiles
 buffer = Buffer
 extent = Extent
 zmin = Zoom minimum
@@ -182,6 +190,42 @@ info-time-in-seconds = Le temps doit être saisi en secondes
 info-value-infinity = La valeur 0 signifie infini
 max-records = Nombre maximum d'enregistrements
 info-max-records = Nombre maximum d'enregistrements à récupérer. Utiliser 0 ignore cette directive.
+query-timeout = Délai maximal de requête (ms)
+info-query-timeout = Les requêtes de tuiles de cette couche qui durent plus longtemps sont annulées et reçoivent une réponse 504 Gateway Timeout. 0 conserve le statement timeout de la base de données.
+notify-changes = Invalider lors des modifications de la table
+info-notify-changes = Un trigger sur la table signale chaque insert, update et delete, et les tuiles autour des entités modifiées sont invalidées en moins d’une seconde. Installer le trigger nécessite d’être propriétaire de la table.
+priority = Priorité
+info-priority = Facultatif. Colonne ou expression SQL classant les entités conservées quand le nombre maximal d'enregistrements s'applique ; les égalités et les couches sans priorité sont départagées par la colonne ID ou la géométrie, chaque requête garde donc les mêmes entités.
+priority-order = Ordre de priorité
+priority-desc = La plus haute d'abord
+priority-asc = La plus basse d'abord
+priority-random = Aléatoire (change à chaque requête)
+cluster-zoom = Regrouper sous le zoom
+info-cluster-zoom = Facultatif, couches de points uniquement. Les tuiles des zooms inférieurs portent un point par groupe d'entités proches, avec un attribut point_count ; laisser vide pour ne jamais regrouper.
+cluster-radius = Rayon de regroupement (px)
+cluster-aggregates = Agrégats des groupes
+info-cluster-aggregates = Facultatif. Attributs des points de regroupement en plus de point_count, p. ex. 'sum(victims), max(severity)', publiés comme sum_victims et max_severity. Fonctions : sum, avg, min, max.
+bin-shape = Agrégation en grille
+info-bin-shape = Facultatif. Transforme la couche en grille de densité : chaque tuile porte des cellules hexagonales ou carrées avec le nombre d'entités qui y tombent, sous forme de polygones.
+bin-none = Aucune
+bin-hexagon = Hexagones
+bin-square = Carrés
+bin-size = Taille de cellule (px)
+bin-aggregates = Agrégats des cellules
+info-bin-aggregates = Facultatif. Attributs des cellules en plus de count, p. ex. 'sum(victims), avg(severity)', publiés comme sum_victims et avg_severity. Fonctions : sum, avg, min, max.
+source-kind = Source
+static-file = Fichier de tuiles
+info-static-file = Fichiers placés dans le répertoire des tuiles statiques. Les nouveaux fichiers sont listés sans redémarrer le serveur.
+function = Fonction PostGIS
+info-function = Fonctions de signature fn(z integer, x integer, y integer, query_params json) RETURNS bytea. Les paramètres de la requête de tuile sont transmis en JSON.
+sql-query = Requête SQL
+info-sql-query = Un SELECT renvoyant la colonne géométrique et les champs à publier. Utilisez !bbox! pour l'emprise de la tuile (dans le SRID de la couche), !zoom!, !x! et !y! pour la tuile, et !nom! pour chaque paramètre déclaré. La requête est vérifiée auprès de la base lors de l'enregistrement de la couche.
+sql-params = Paramètres SQL
+info-sql-params = Noms, séparés par des virgules, des paramètres de requête utilisés comme !nom!. Ils sont transmis en texte (NULL s'ils manquent) ; convertissez-les dans la requête, par ex. !min_area!::numeric.
+zoom-fields = Champs par zoom
+info-zoom-fields = Facultatif. Une ligne 'zmin-zmax: champ, champ' par plage de zoom, parmi les champs sélectionnés ; les zooms non couverts reçoivent tous les champs sélectionnés.
+generalization = Généralisation
+info-generalization = Facultatif. Une ligne 'zmin-zmax: simplify=1, min_area=4, min_length=2' par plage de zoom, tailles en pixels d'une tuile de 256 px : tolérance de simplification, surface minimale des polygones et longueur minimale des lignes conservés.
 published = Publié
 allowed-groups = Groupes Autorisés
 info-empty-allowed-groups = Si vide, tous les groupes sont autorisés
@@ -199,3 +243,20 @@ plugins-dir-hint = Placez des fichiers .lua dans le répertoire des plugins pour
 plugin-layer = Couche
 plugin-category = Catégorie
 show-code = Voir le code
+
+# Cache
+cache-stats = Cache de tuiles
+cache-backend = Stockage
+info-cache-stats = Tuiles stockées par couche, lues depuis le stockage du cache au plus toutes les 30 secondes ; les tuiles expirées ne sont pas comptées. Les succès et échecs sont comptés par cette instance depuis son démarrage, pour les requêtes pouvant être servies depuis le cache.
+cached-tiles = Tuiles
+cache-size = Taille
+memory-tier = En mémoire
+hit-ratio = Taux de succès
+cache-hits-misses = Succès / échecs
+oldest-tile = Plus ancienne
+newest-tile = Plus récente
+browse-tiles = Parcourir les tuiles
+all-zooms = Tous les zooms
+tile-age = Âge
+no-cached-tiles = Aucune tuile en cache
+cache-stats-error = Impossible de lire les tuiles en cache
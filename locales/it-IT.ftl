@@ -13,6 +13,7 @@ users = Utenti
 categories = Categorie
 metadata = Metadati
 metrics = Metriche
+cache = Cache
 
 # Pages
 welcome = Benvenuto su MVT Server!
@@ -100,6 +101,10 @@ info = Informazioni
 edit = Modifica
 switch-published = Pubblica / Spubblica
 delete-cache = Elimina Cache
+invalidate-area = Invalida area
+info-invalidate-area = Elimina solo i tile in cache all'interno di un bounding box o di una geometria GeoJSON; il resto della cache del layer viene mantenuto. Senza zoom si usano quelli del layer.
+area-bbox = Bounding box (ovest,sud,est,nord)
+area-geometry = Geometria GeoJSON (al posto del bounding box)
 delete = Elimina
 open-json = Apri JSON
 open-png = Apri PNG
@@ -167,7 +172,10 @@ table = Tabella
 fields = Campi
 sql-mode = Modalità SQL
 geom = Geom
+id-column = Colonna ID
+info-id-column = Facoltativo. Colonna intera scritta come id delle feature delle tile, così client come MapLibre possono usare il feature state per hover e selezione.
 srid = SRID
+tile-matrix-set = Insieme di matrici di tile
 buffer = Buffer
 extent = Extent
 zmin = Zoom minimo
@@ -182,6 +190,42 @@ info-time-in-seconds = Il tempo deve essere inserito in secondi
 info-value-infinity = Il valore 0 significa infinito
 max-records = Numero massimo di record
 info-max-records = Record massimi da recuperare. Usando 0 ignora questa direttiva.
+query-timeout = Timeout della query (ms)
+info-query-timeout = Le query delle tile di questo layer che durano di più vengono annullate e ricevono la risposta 504 Gateway Timeout. 0 mantiene lo statement timeout del database.
+notify-changes = Invalida alle modifiche della tabella
+info-notify-changes = Un trigger sulla tabella segnala ogni insert, update e delete, e le tile intorno alle feature modificate vengono invalidate entro un secondo. Installare il trigger richiede di essere proprietari della tabella.
+priority = Priorità
+info-priority = Facoltativo. Colonna o espressione SQL che ordina le feature mantenute quando si applica il numero massimo di record; i pari merito e i layer senza priorità sono ordinati per colonna ID o geometria, così ogni richiesta mantiene le stesse feature.
+priority-order = Ordine di priorità
+priority-desc = Più alta prima
+priority-asc = Più bassa prima
+priority-random = Casuale (cambia a ogni richiesta)
+cluster-zoom = Raggruppa sotto lo zoom
+info-cluster-zoom = Facoltativo, solo layer di punti. Le tile degli zoom inferiori contengono un punto per gruppo di feature vicine, con l'attributo point_count; lasciare vuoto per non raggruppare mai.
+cluster-radius = Raggio di raggruppamento (px)
+cluster-aggregates = Aggregati dei gruppi
+info-cluster-aggregates = Facoltativo. Attributi dei punti di raggruppamento oltre a point_count, es. 'sum(victims), max(severity)', pubblicati come sum_victims e max_severity. Funzioni: sum, avg, min, max.
+bin-shape = Aggregazione a griglia
+info-bin-shape = Facoltativo. Trasforma il layer in una griglia di densità: ogni tile contiene celle esagonali o quadrate con il numero di feature che vi ricadono, come poligoni.
+bin-none = Nessuna
+bin-hexagon = Esagoni
+bin-square = Quadrati
+bin-size = Dimensione cella (px)
+bin-aggregates = Aggregati delle celle
+info-bin-aggregates = Facoltativo. Attributi delle celle oltre a count, es. 'sum(victims), avg(severity)', pubblicati come sum_victims e avg_severity. Funzioni: sum, avg, min, max.
+source-kind = Origine
+static-file = File di tile
+info-static-file = File presenti nella directory delle tile statiche. I nuovi file vengono elencati senza riavviare il server.
+function = Funzione PostGIS
+info-function = Funzioni con la firma fn(z integer, x integer, y integer, query_params json) RETURNS bytea. I parametri della richiesta di tile vengono passati come JSON.
+sql-query = Query SQL
+info-sql-query = Una SELECT che restituisce la colonna geometrica e i campi da pubblicare. Usa !bbox! per l'inviluppo della tile (nello SRID del layer), !zoom!, !x! e !y! per la tile, e !nome! per ogni parametro dichiarato. La query viene verificata sul database al salvataggio del layer.
+sql-params = Parametri SQL
+info-sql-params = Nomi, separati da virgole, dei parametri della richiesta usati nella query come !nome!. Sono passati come testo (NULL se assenti); convertili nella query, ad es. !min_area!::numeric.
+zoom-fields = Campi per zoom
+info-zoom-fields = Facoltativo. Una riga 'zmin-zmax: campo, campo' per intervallo di zoom, tra i campi selezionati; gli zoom non coperti ricevono tutti i campi selezionati.
+generalization = Generalizzazione
+info-generalization = Facoltativo. Una riga 'zmin-zmax: simplify=1, min_area=4, min_length=2' per intervallo di zoom, dimensioni in pixel di una tile da 256 px: tolleranza di semplificazione, area minima dei poligoni e lunghezza minima delle linee mantenuti.
 published = Pubblicato
 allowed-groups = Gruppi Autorizzati
 info-empty-allowed-groups = Se vuoto, tutti i gruppi sono autorizzati
@@ -199,3 +243,20 @@ plugins-dir-hint = Inserisci file .lua nella directory dei plugin per iniziare.
 plugin-layer = Layer
 plugin-category = Categoria
 show-code = Mostra codice
+
+# Cache
+cache-stats = Cache dei tile
+cache-backend = Backend
+info-cache-stats = Tile memorizzati per layer, letti dal backend della cache al massimo ogni 30 secondi; i tile scaduti non vengono contati. Hit e miss sono contati da questa istanza dal suo avvio, per le richieste che possono essere servite dalla cache.
+cached-tiles = Tile
+cache-size = Dimensione
+memory-tier = In memoria
+hit-ratio = Tasso di hit
+cache-hits-misses = Hit / miss
+oldest-tile = Più vecchio
+newest-tile = Più recente
+browse-tiles = Sfoglia i tile
+all-zooms = Tutti gli zoom
+tile-age = Età
+no-cached-tiles = Nessun tile in cache
+cache-stats-error = Impossibile leggere i tile in cache
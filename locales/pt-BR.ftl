@@ -13,6 +13,7 @@ users = Usuários
 categories = Categorias
 metadata = Metadados
 metrics = Métricas
+cache = Cache
 
 # Pages
 welcome = Bem-vindo ao MVT Server!
@@ -100,6 +101,10 @@ info = Informações
 edit = Editar
 switch-published = Publicar / Despublicar
 delete-cache = Excluir Cache
+invalidate-area = Invalidar área
+info-invalidate-area = Exclui apenas os tiles em cache dentro de um bounding box ou de uma geometria GeoJSON; o restante do cache da camada é mantido. Sem zooms, são usados os da camada.
+area-bbox = Bounding box (oeste,sul,leste,norte)
+area-geometry = Geometria GeoJSON (em vez do bounding box)
 delete = Excluir
 open-json = Abrir JSON
 open-png = Abrir PNG
@@ -167,7 +172,10 @@ table = Tabela
 fields = Campos
 sql-mode = Modo SQL
 geom = Geom
+id-column = Coluna ID
+info-id-column = Opcional. Coluna inteira gravada como id das feições dos tiles, para que clientes como MapLibre possam usar feature state para hover e seleção.
 srid = SRID
+tile-matrix-set = Conjunto de matrizes de tiles
 buffer = Buffer
 extent = Extent
 zmin = Zoom mínimo
@@ -182,6 +190,42 @@ info-time-in-seconds = O tempo deve ser informado em segundos
 info-value-infinity = O valor 0 significa infinito
 max-records = Número máximo de registros
 info-max-records = Máximo de registros a recuperar. Usar 0 ignora esta diretiva.
+query-timeout = Tempo limite da consulta (ms)
+info-query-timeout = Consultas de tiles desta camada que demorarem mais são canceladas e respondidas com 504 Gateway Timeout. Com 0 mantém-se o statement timeout do banco de dados.
+notify-changes = Invalidar com alterações na tabela
+info-notify-changes = Um trigger na tabela informa cada insert, update e delete, e os tiles ao redor das feições alteradas são invalidados em menos de um segundo. Instalar o trigger exige ser dono da tabela.
+priority = Prioridade
+info-priority = Opcional. Coluna ou expressão SQL que ordena as feições mantidas quando o número máximo de registros se aplica; empates e camadas sem prioridade são ordenados pela coluna ID ou pela geometria, então cada requisição mantém as mesmas feições.
+priority-order = Ordem de prioridade
+priority-desc = Maior primeiro
+priority-asc = Menor primeiro
+priority-random = Aleatória (muda a cada requisição)
+cluster-zoom = Agrupar abaixo do zoom
+info-cluster-zoom = Opcional, apenas camadas de pontos. Os tiles de zooms menores trazem um ponto por grupo de feições próximas, com o atributo point_count; deixe vazio para nunca agrupar.
+cluster-radius = Raio de agrupamento (px)
+cluster-aggregates = Agregados dos grupos
+info-cluster-aggregates = Opcional. Atributos dos pontos agrupados além de point_count, p. ex. 'sum(victims), max(severity)', publicados como sum_victims e max_severity. Funções: sum, avg, min, max.
+bin-shape = Agregação em grade
+info-bin-shape = Opcional. Transforma a camada em uma grade de densidade: cada tile traz células hexagonais ou quadradas com a contagem de feições que caem nelas, como polígonos.
+bin-none = Nenhuma
+bin-hexagon = Hexágonos
+bin-square = Quadrados
+bin-size = Tamanho da célula (px)
+bin-aggregates = Agregados das células
+info-bin-aggregates = Opcional. Atributos das células além de count, p. ex. 'sum(victims), avg(severity)', publicados como sum_victims e avg_severity. Funções: sum, avg, min, max.
+source-kind = Origem
+static-file = Arquivo de tiles
+info-static-file = Arquivos colocados no diretório de tiles estáticos. Novos arquivos são listados sem reiniciar o servidor.
+function = Função PostGIS
+info-function = Funções com a assinatura fn(z integer, x integer, y integer, query_params json) RETURNS bytea. Os parâmetros da requisição de tiles são passados como JSON.
+sql-query = Consulta SQL
+info-sql-query = Um SELECT que retorna a coluna de geometria e os campos a publicar. Use !bbox! para o envelope do tile (no SRID da camada), !zoom!, !x! e !y! para o tile, e !nome! para cada parâmetro declarado. A consulta é validada no banco de dados ao salvar a camada.
+sql-params = Parâmetros SQL
+info-sql-params = Nomes, separados por vírgula, dos parâmetros da requisição usados na consulta como !nome!. São passados como texto (NULL quando ausentes); converta-os na consulta, ex. !min_area!::numeric.
+zoom-fields = Campos por zoom
+info-zoom-fields = Opcional. Uma linha 'zmin-zmax: campo, campo' por faixa de zoom, entre os campos selecionados; zooms não cobertos recebem todos os campos selecionados.
+generalization = Generalização
+info-generalization = Opcional. Uma linha 'zmin-zmax: simplify=1, min_area=4, min_length=2' por faixa de zoom, tamanhos em pixels de um tile de 256 px: tolerância de simplificação, área mínima de polígonos e comprimento mínimo de linhas mantidos.
 published = Publicado
 allowed-groups = Grupos Permitidos
 info-empty-allowed-groups = Se estiver vazio, todos os grupos são permitidos
@@ -199,3 +243,20 @@ plugins-dir-hint = Coloque arquivos .lua no diretório de plugins para começar.
 plugin-layer = Camada
 plugin-category = Categoria
 show-code = Ver código
+
+# Cache
+cache-stats = Cache de tiles
+cache-backend = Backend
+info-cache-stats = Tiles armazenados por camada, lidos do backend do cache no máximo a cada 30 segundos; tiles expirados não são contados. Acertos e falhas são contados por esta instância desde que iniciou, para as requisições que podem ser servidas pelo cache.
+cached-tiles = Tiles
+cache-size = Tamanho
+memory-tier = Em memória
+hit-ratio = Taxa de acertos
+cache-hits-misses = Acertos / falhas
+oldest-tile = Mais antigo
+newest-tile = Mais recente
+browse-tiles = Explorar tiles
+all-zooms = Todos os zooms
+tile-age = Idade
+no-cached-tiles = Nenhum tile em cache
+cache-stats-error = Não foi possível ler os tiles em cache
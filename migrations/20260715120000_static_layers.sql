@@ -0,0 +1,2 @@
+ALTER TABLE layers ADD COLUMN source_kind TEXT NOT NULL DEFAULT 'postgis';
+ALTER TABLE layers ADD COLUMN static_file TEXT;
@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN tile_matrix_set TEXT;
@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN function_name TEXT;
@@ -0,0 +1,2 @@
+ALTER TABLE layers ADD COLUMN sql_query TEXT;
+ALTER TABLE layers ADD COLUMN sql_params TEXT;
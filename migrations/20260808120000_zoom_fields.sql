@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN zoom_fields TEXT;
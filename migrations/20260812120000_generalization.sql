@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN generalization TEXT;
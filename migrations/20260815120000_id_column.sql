@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN id_column TEXT;
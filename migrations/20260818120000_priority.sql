@@ -0,0 +1,2 @@
+ALTER TABLE layers ADD COLUMN priority TEXT;
+ALTER TABLE layers ADD COLUMN priority_order TEXT;
@@ -0,0 +1,3 @@
+ALTER TABLE layers ADD COLUMN cluster_zoom INTEGER;
+ALTER TABLE layers ADD COLUMN cluster_radius INTEGER;
+ALTER TABLE layers ADD COLUMN cluster_aggregates TEXT;
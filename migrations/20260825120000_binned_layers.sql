@@ -0,0 +1,3 @@
+ALTER TABLE layers ADD COLUMN bin_shape TEXT;
+ALTER TABLE layers ADD COLUMN bin_size INTEGER;
+ALTER TABLE layers ADD COLUMN bin_aggregates TEXT;
@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN query_timeout_ms INTEGER;
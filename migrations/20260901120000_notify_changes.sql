@@ -0,0 +1 @@
+ALTER TABLE layers ADD COLUMN notify_changes BOOLEAN;
@@ -0,0 +1,46 @@
+use salvo::prelude::*;
+
+use crate::{
+    error::{AppError, AppResult},
+    get_catalog,
+    models::catalog::{Layer, StateLayer},
+    services::cache_stats::{cache_stats, layer_cache_stats, layer_cached_tiles},
+};
+
+async fn find_layer(req: &mut Request) -> AppResult<Layer> {
+    let id = req
+        .param::<String>("id")
+        .ok_or(AppError::RequestParamError("id".to_string()))?;
+    let catalog = get_catalog().await.read().await;
+    catalog
+        .find_layer_by_id(&id, StateLayer::Any)
+        .ok_or_else(|| AppError::NotFound(format!("Layer {id} not found")))
+        .cloned()
+}
+
+/// Cache statistics of every layer: stored tiles, bytes, oldest and newest
+/// tile, and this instance's hits and misses.
+#[handler]
+pub async fn list(res: &mut Response) -> AppResult<()> {
+    res.render(Json(cache_stats().await));
+    Ok(())
+}
+
+#[handler]
+pub async fn layer_stats(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let layer = find_layer(req).await?;
+    res.render(Json(layer_cache_stats(&layer).await));
+    Ok(())
+}
+
+/// Cached tiles of a layer with their size and age, by matrix set, z, x
+/// and y: `?z=` keeps one zoom level, `?page=` and `?page_size=` page them.
+#[handler]
+pub async fn tiles(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let layer = find_layer(req).await?;
+    let z = req.query::<u32>("z");
+    let page = req.query::<usize>("page").unwrap_or(1);
+    let page_size = req.query::<usize>("page_size");
+    res.render(Json(layer_cached_tiles(&layer, z, page, page_size).await?));
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use salvo::prelude::*;
+use salvo::{fs::NamedFile, http::mime, prelude::*};
 use serde::{Deserialize, Serialize};
 
 use crate::{
@@ -6,8 +6,15 @@ use crate::{
     error::{AppError, AppResult},
     get_auth, get_cache_wrapper, get_catalog,
     models::{
-        catalog::{Layer, StateLayer},
+        catalog::{FieldAggregate, GeneralizationRule, Layer, SourceKind, StateLayer, ZoomFields},
         category::Category,
+        tile_matrix_set::{WEB_MERCATOR_QUAD, find_tile_matrix_set},
+    },
+    services::{
+        invalidate::{InvalidateOptions, invalidate_layer_region},
+        seed::{SeedOptions, seed_job_status, start_seed_job},
+        tilejson::{build_tilejson, layer_metadata, mbtiles_metadata},
+        tiles::scheme::TileScheme,
     },
 };
 
@@ -39,17 +46,31 @@ pub async fn list(req: &mut Request, res: &mut Response) -> AppResult<()> {
 #[salvo(extract(default_source(from = "body")))]
 struct NewLayerRequest {
     category: String,
+    #[serde(default)]
+    source_kind: SourceKind,
+    static_file: Option<String>,
+    function_name: Option<String>,
+    sql_query: Option<String>,
+    sql_params: Option<Vec<String>>,
+    #[serde(default)]
     database_id: String,
     geometry: String,
     name: String,
     alias: String,
     description: String,
+    #[serde(default)]
     schema: String,
+    #[serde(default)]
     table: String,
+    #[serde(default)]
     fields: Vec<String>,
+    zoom_fields: Option<Vec<ZoomFields>>,
+    generalization: Option<Vec<GeneralizationRule>>,
     filter: Option<String>,
     srid: Option<u32>,
+    tile_matrix_set: Option<String>,
     geom: Option<String>,
+    id_column: Option<String>,
     sql_mode: Option<String>,
     buffer: Option<u32>,
     extent: Option<u32>,
@@ -62,6 +83,16 @@ struct NewLayerRequest {
     delete_cache_on_start: Option<bool>,
     max_cache_age: Option<u64>,
     max_records: Option<u64>,
+    priority: Option<String>,
+    priority_order: Option<String>,
+    cluster_zoom: Option<u32>,
+    cluster_radius: Option<u32>,
+    cluster_aggregates: Option<Vec<FieldAggregate>>,
+    bin_shape: Option<String>,
+    bin_size: Option<u32>,
+    bin_aggregates: Option<Vec<FieldAggregate>>,
+    query_timeout_ms: Option<u64>,
+    notify_changes: Option<bool>,
     published: bool,
     groups: Option<Vec<String>>,
 }
@@ -83,6 +114,11 @@ pub async fn create_layer(res: &mut Response, layer_form: NewLayerRequest) -> Ap
     let layer = Layer {
         id: uuid::Uuid::new_v4().simple().to_string(),
         category,
+        source_kind: layer_form.source_kind,
+        static_file: layer_form.static_file,
+        function_name: layer_form.function_name,
+        sql_query: layer_form.sql_query,
+        sql_params: layer_form.sql_params,
         database_id: layer_form.database_id,
         geometry: layer_form.geometry,
         name,
@@ -91,9 +127,13 @@ pub async fn create_layer(res: &mut Response, layer_form: NewLayerRequest) -> Ap
         schema: layer_form.schema,
         table_name: layer_form.table,
         fields: layer_form.fields,
+        zoom_fields: layer_form.zoom_fields,
+        generalization: layer_form.generalization,
         filter: layer_form.filter,
         srid: layer_form.srid,
+        tile_matrix_set: layer_form.tile_matrix_set,
         geom: layer_form.geom,
+        id_column: layer_form.id_column,
         sql_mode: layer_form.sql_mode,
         buffer: layer_form.buffer,
         extent: layer_form.extent,
@@ -106,6 +146,16 @@ pub async fn create_layer(res: &mut Response, layer_form: NewLayerRequest) -> Ap
         delete_cache_on_start: layer_form.delete_cache_on_start,
         max_cache_age: layer_form.max_cache_age,
         max_records: layer_form.max_records,
+        priority: layer_form.priority,
+        priority_order: layer_form.priority_order,
+        cluster_zoom: layer_form.cluster_zoom,
+        cluster_radius: layer_form.cluster_radius,
+        cluster_aggregates: layer_form.cluster_aggregates,
+        bin_shape: layer_form.bin_shape,
+        bin_size: layer_form.bin_size,
+        bin_aggregates: layer_form.bin_aggregates,
+        query_timeout_ms: layer_form.query_timeout_ms,
+        notify_changes: layer_form.notify_changes,
         published: layer_form.published,
         url: None,
         groups: Some(groups),
@@ -123,17 +173,31 @@ struct UpdateLayerRequest {
     #[salvo(extract(source(from = "param")))]
     id: String,
     category: String,
+    #[serde(default)]
+    source_kind: SourceKind,
+    static_file: Option<String>,
+    function_name: Option<String>,
+    sql_query: Option<String>,
+    sql_params: Option<Vec<String>>,
+    #[serde(default)]
     database_id: String,
     geometry: String,
     name: String,
     alias: String,
     description: String,
+    #[serde(default)]
     schema: String,
+    #[serde(default)]
     table: String,
+    #[serde(default)]
     fields: Vec<String>,
+    zoom_fields: Option<Vec<ZoomFields>>,
+    generalization: Option<Vec<GeneralizationRule>>,
     filter: Option<String>,
     srid: Option<u32>,
+    tile_matrix_set: Option<String>,
     geom: Option<String>,
+    id_column: Option<String>,
     sql_mode: Option<String>,
     buffer: Option<u32>,
     extent: Option<u32>,
@@ -146,6 +210,16 @@ struct UpdateLayerRequest {
     delete_cache_on_start: Option<bool>,
     max_cache_age: Option<u64>,
     max_records: Option<u64>,
+    priority: Option<String>,
+    priority_order: Option<String>,
+    cluster_zoom: Option<u32>,
+    cluster_radius: Option<u32>,
+    cluster_aggregates: Option<Vec<FieldAggregate>>,
+    bin_shape: Option<String>,
+    bin_size: Option<u32>,
+    bin_aggregates: Option<Vec<FieldAggregate>>,
+    query_timeout_ms: Option<u64>,
+    notify_changes: Option<bool>,
     published: bool,
     groups: Option<Vec<String>>,
 }
@@ -179,6 +253,11 @@ pub async fn update_layer(res: &mut Response, layer_form: UpdateLayerRequest) ->
     let layer = Layer {
         id: layer_form.id,
         category,
+        source_kind: layer_form.source_kind,
+        static_file: layer_form.static_file,
+        function_name: layer_form.function_name,
+        sql_query: layer_form.sql_query,
+        sql_params: layer_form.sql_params,
         database_id: layer_form.database_id,
         geometry: layer_form.geometry,
         name,
@@ -187,9 +266,13 @@ pub async fn update_layer(res: &mut Response, layer_form: UpdateLayerRequest) ->
         schema: layer_form.schema,
         table_name: layer_form.table,
         fields: layer_form.fields,
+        zoom_fields: layer_form.zoom_fields,
+        generalization: layer_form.generalization,
         filter: layer_form.filter,
         srid: layer_form.srid,
+        tile_matrix_set: layer_form.tile_matrix_set,
         geom: layer_form.geom,
+        id_column: layer_form.id_column,
         sql_mode: layer_form.sql_mode,
         buffer: layer_form.buffer,
         extent: layer_form.extent,
@@ -202,6 +285,16 @@ pub async fn update_layer(res: &mut Response, layer_form: UpdateLayerRequest) ->
         delete_cache_on_start: layer_form.delete_cache_on_start,
         max_cache_age: layer_form.max_cache_age,
         max_records: layer_form.max_records,
+        priority: layer_form.priority,
+        priority_order: layer_form.priority_order,
+        cluster_zoom: layer_form.cluster_zoom,
+        cluster_radius: layer_form.cluster_radius,
+        cluster_aggregates: layer_form.cluster_aggregates,
+        bin_shape: layer_form.bin_shape,
+        bin_size: layer_form.bin_size,
+        bin_aggregates: layer_form.bin_aggregates,
+        query_timeout_ms: layer_form.query_timeout_ms,
+        notify_changes: layer_form.notify_changes,
         published: layer_form.published,
         url: None,
         groups: Some(groups),
@@ -270,3 +363,148 @@ pub async fn delete_layer_cache(req: &mut Request, res: &mut Response) -> AppRes
     res.render(Json(serde_json::json!({ "deleted": true })));
     Ok(())
 }
+
+#[derive(Deserialize, Extractible, Debug)]
+#[salvo(extract(default_source(from = "body")))]
+struct InvalidateRegionRequest {
+    zmin: Option<u32>,
+    zmax: Option<u32>,
+    bbox: Option<[f64; 4]>,
+    geometry: Option<serde_json::Value>,
+}
+
+/// Drops only the layer's cached tiles inside a bbox or GeoJSON geometry
+/// over a zoom range; those tiles get a new ETag, the others keep theirs.
+#[handler]
+pub async fn invalidate_layer_cache_region(
+    req: &mut Request,
+    res: &mut Response,
+    region_form: InvalidateRegionRequest,
+) -> AppResult<()> {
+    let id = req
+        .param::<String>("id")
+        .ok_or(AppError::RequestParamError("id".to_string()))?;
+
+    let layer = {
+        let catalog = get_catalog().await.read().await;
+        catalog
+            .find_layer_by_id(&id, StateLayer::Any)
+            .ok_or_else(|| AppError::NotFound(format!("Layer {id} not found")))?
+            .clone()
+    };
+
+    let summary = invalidate_layer_region(
+        &layer,
+        &InvalidateOptions {
+            zmin: region_form.zmin,
+            zmax: region_form.zmax,
+            bbox: region_form.bbox,
+            geometry: region_form.geometry,
+        },
+    )
+    .await?;
+    res.render(Json(summary));
+    Ok(())
+}
+
+/// Downloads the layer's cached WebMercatorQuad tiles as an offline MBTiles
+/// file. Only available when the cache is stored as MBTiles.
+#[handler]
+pub async fn download_layer_cache(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let id = req
+        .param::<String>("id")
+        .ok_or(AppError::RequestParamError("id".to_string()))?;
+
+    let layer = {
+        let catalog = get_catalog().await.read().await;
+        catalog
+            .find_layer_by_id(&id, StateLayer::Any)
+            .ok_or_else(|| AppError::NotFound(format!("Layer {id} not found")))?
+            .clone()
+    };
+    let layer_name = format!("{}_{}", layer.category.name, layer.name);
+    let tms = find_tile_matrix_set(WEB_MERCATOR_QUAD)
+        .ok_or_else(|| AppError::NotFound(WEB_MERCATOR_QUAD.to_string()))?;
+
+    let (layer, bounds, fields) = layer_metadata(layer).await;
+    let tilejson = build_tilejson(&layer, bounds, fields, "", TileScheme::Xyz, tms);
+    let path = get_cache_wrapper()
+        .export_mbtiles(&layer_name, &mbtiles_metadata(&tilejson))
+        .await?;
+
+    NamedFile::builder(path)
+        .attached_name(format!("{layer_name}.mbtiles"))
+        .content_type("application/vnd.sqlite3".parse().unwrap_or(mime::APPLICATION_OCTET_STREAM))
+        .send(req.headers(), res)
+        .await;
+    Ok(())
+}
+
+#[derive(Deserialize, Extractible, Debug)]
+#[salvo(extract(default_source(from = "body")))]
+struct SeedLayerRequest {
+    zmin: Option<u32>,
+    zmax: Option<u32>,
+    bbox: Option<[f64; 4]>,
+    concurrency: Option<usize>,
+    #[serde(default)]
+    skip_empty: bool,
+    #[serde(default)]
+    resume: bool,
+}
+
+/// Starts seeding the layer's tile cache in the background; progress is
+/// read back with `seed_layer_cache_status`.
+#[handler]
+pub async fn seed_layer_cache(
+    req: &mut Request,
+    res: &mut Response,
+    seed_form: SeedLayerRequest,
+) -> AppResult<()> {
+    let id = req
+        .param::<String>("id")
+        .ok_or(AppError::RequestParamError("id".to_string()))?;
+
+    let layer = {
+        let catalog = get_catalog().await.read().await;
+        catalog
+            .find_layer_by_id(&id, StateLayer::Any)
+            .ok_or_else(|| AppError::NotFound(format!("Layer {id} not found")))?
+            .clone()
+    };
+
+    let status = start_seed_job(
+        layer,
+        SeedOptions {
+            zmin: seed_form.zmin,
+            zmax: seed_form.zmax,
+            bbox: seed_form.bbox,
+            concurrency: seed_form.concurrency,
+            skip_empty: seed_form.skip_empty,
+            resume: seed_form.resume,
+        },
+    )?;
+    res.status_code(StatusCode::ACCEPTED);
+    res.render(Json(status));
+    Ok(())
+}
+
+#[handler]
+pub async fn seed_layer_cache_status(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let id = req
+        .param::<String>("id")
+        .ok_or(AppError::RequestParamError("id".to_string()))?;
+
+    let layer_key = {
+        let catalog = get_catalog().await.read().await;
+        let layer = catalog
+            .find_layer_by_id(&id, StateLayer::Any)
+            .ok_or_else(|| AppError::NotFound(format!("Layer {id} not found")))?;
+        format!("{}_{}", layer.category.name, layer.name)
+    };
+
+    let status = seed_job_status(&layer_key)
+        .ok_or_else(|| AppError::NotFound(format!("No seeding job for layer {id}")))?;
+    res.render(Json(status));
+    Ok(())
+}
@@ -1,7 +1,8 @@
 use salvo::prelude::*;
 
 use crate::db::metadata::{
-    Field, Schema, Srid, Table, query_fields, query_schemas, query_srid, query_tables,
+    Field, Function, Schema, Srid, Table, query_fields, query_functions, query_schemas,
+    query_srid, query_tables,
 };
 use crate::error::AppResult;
 
@@ -22,6 +23,15 @@ pub async fn tables(req: &mut Request) -> AppResult<Json<Vec<Table>>> {
     Ok(Json(query_tables(&db_id, schema).await?))
 }
 
+#[handler]
+pub async fn functions(req: &mut Request) -> AppResult<Json<Vec<Function>>> {
+    let db_id = req
+        .query::<String>("database_id")
+        .unwrap_or_else(|| "default".to_string());
+    let schema = req.query::<String>("schema").unwrap_or_default();
+    Ok(Json(query_functions(&db_id, schema).await?))
+}
+
 #[handler]
 pub async fn fields(req: &mut Request) -> AppResult<Json<Vec<Field>>> {
     let db_id = req
@@ -6,7 +6,7 @@ mod tests {
     use super::super::utils::decode_basic_auth;
     use argon2::{Argon2, PasswordHash, PasswordVerifier};
     use base64::{Engine as _, engine::general_purpose};
-    use crate::models::catalog::Layer;
+    use crate::models::catalog::{Layer, SourceKind};
     use crate::models::category::Category;
 
     fn create_test_auth() -> Auth {
@@ -83,13 +83,22 @@ mod tests {
             name: "layer".to_string(),
             alias: "Layer".to_string(),
             description: String::new(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
             database_id: "default".to_string(),
             schema: "public".to_string(),
             table_name: "t".to_string(),
             fields: vec![],
+            zoom_fields: None,
+            generalization: None,
             filter: None,
             srid: None,
+            tile_matrix_set: None,
             geom: None,
+            id_column: None,
             sql_mode: None,
             buffer: None,
             extent: None,
@@ -102,6 +111,16 @@ mod tests {
             delete_cache_on_start: None,
             max_cache_age: None,
             max_records: None,
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
             published: true,
             url: None,
             groups,
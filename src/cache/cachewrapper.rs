@@ -1,71 +1,278 @@
 use super::disk::DiskCache;
+use super::encoding::{EncodedTile, TileEncoding};
+use super::key::TileKey;
+use super::mbtiles::MbtilesCache;
+use super::memory::MemoryCache;
+use super::objectstore::ObjectStoreCache;
 use super::redis::RedisCache;
-use crate::{Catalog, error::AppResult};
+use super::region::{RegionStamps, TileSpan, TileVersion};
+use super::stats::{LayerUsage, TileListing, TilePage};
+use crate::{
+    Catalog,
+    config::settings::CacheConfig,
+    error::{AppError, AppResult},
+};
 use bytes::Bytes;
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// How long the invalidated regions of a layer are trusted before they are
+/// read again, as for the layer versions of the shared backends.
+const REGION_REFRESH: Duration = Duration::from_secs(2);
+/// Tiles a single region invalidation may cover, over all its zoom levels
+/// and matrix sets. Larger areas are cheaper to invalidate as a whole layer.
+pub const MAX_REGION_TILES: u64 = 250_000;
+
+/// Invalidated regions last read per layer, and when.
+type RegionCache = Arc<Mutex<HashMap<String, (Arc<RegionStamps>, Instant)>>>;
 
 #[derive(Debug, Clone)]
 pub enum CacheMode {
     Redis(RedisCache),
     Disk(DiskCache),
+    ObjectStore(ObjectStoreCache),
+    Mbtiles(MbtilesCache),
     Disabled,
 }
 
+/// Where a cached tile was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheTier {
+    Memory,
+    Disk,
+    Redis,
+    ObjectStore,
+    Mbtiles,
+}
+
+impl CacheTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CacheTier::Memory => "memory",
+            CacheTier::Disk => "disk",
+            CacheTier::Redis => "redis",
+            CacheTier::ObjectStore => "object_store",
+            CacheTier::Mbtiles => "mbtiles",
+        }
+    }
+
+    /// `X-Cache` header value of a hit in this tier.
+    pub fn header(&self) -> &'static str {
+        match self {
+            CacheTier::Memory => "HIT-MEMORY",
+            CacheTier::Disk => "HIT-DISK",
+            CacheTier::Redis => "HIT-REDIS",
+            CacheTier::ObjectStore => "HIT-OBJECT-STORE",
+            CacheTier::Mbtiles => "HIT-MBTILES",
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CacheWrapper {
     mode: CacheMode,
+    /// Encoding tiles are stored in; fixed for the lifetime of the process.
+    compression: TileEncoding,
+    /// In-process tier in front of `mode`; `None` when disabled.
+    memory: Option<MemoryCache>,
+    regions: RegionCache,
+    /// Serializes this instance's region invalidations.
+    invalidating: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl CacheWrapper {
-    pub fn new_redis(redis_cache: RedisCache) -> Self {
+    pub fn new_redis(redis_cache: RedisCache, compression: TileEncoding) -> Self {
         CacheWrapper {
             mode: CacheMode::Redis(redis_cache),
+            compression,
+            memory: None,
+            regions: Default::default(),
+            invalidating: Default::default(),
         }
     }
 
-    pub fn new_disk(disk_cache: DiskCache) -> Self {
+    pub fn new_disk(disk_cache: DiskCache, compression: TileEncoding) -> Self {
         CacheWrapper {
             mode: CacheMode::Disk(disk_cache),
+            compression,
+            memory: None,
+            regions: Default::default(),
+            invalidating: Default::default(),
         }
     }
 
+    pub fn new_object_store(object_store: ObjectStoreCache, compression: TileEncoding) -> Self {
+        CacheWrapper {
+            mode: CacheMode::ObjectStore(object_store),
+            compression,
+            memory: None,
+            regions: Default::default(),
+            invalidating: Default::default(),
+        }
+    }
+
+    pub fn new_mbtiles(cache_dir: PathBuf, compression: TileEncoding) -> Self {
+        CacheWrapper {
+            mode: CacheMode::Mbtiles(MbtilesCache::new(cache_dir, compression)),
+            compression,
+            memory: None,
+            regions: Default::default(),
+            invalidating: Default::default(),
+        }
+    }
+
+    /// Nothing is stored, so nothing is compressed either.
     pub fn new_disabled() -> Self {
         CacheWrapper {
             mode: CacheMode::Disabled,
+            compression: TileEncoding::Identity,
+            memory: None,
+            regions: Default::default(),
+            invalidating: Default::default(),
         }
     }
 
+    /// Keeps up to `capacity` bytes of tiles in memory in front of the
+    /// disk, MBTiles, Redis or object store cache. A capacity of 0 leaves the tier off.
+    pub fn with_memory(mut self, capacity: usize) -> Self {
+        if capacity > 0 && !matches!(self.mode, CacheMode::Disabled) {
+            self.memory = Some(MemoryCache::new(capacity));
+        }
+        self
+    }
+
+    /// Opens the configured backend. With `flush_on_start`, the tiles of the
+    /// layers marked `delete_cache_on_start` are dropped first.
     pub async fn initialize_cache(
         redis_conn: Option<String>,
         disk_cache_dir: PathBuf,
         catalog: Catalog,
         disabled: bool,
+        flush_on_start: bool,
+        config: &CacheConfig,
     ) -> AppResult<CacheWrapper> {
         if disabled {
             return Ok(CacheWrapper::new_disabled());
         }
+        let compression = config.compression;
+        let memory_capacity = config.memory_capacity();
 
+        // Versioned backends are flushed through `delete_cache`, which bumps
+        // the layer versions before the old tiles are removed.
         if let Some(redis_conn) = redis_conn
             && !redis_conn.is_empty()
         {
             let redis_cache = RedisCache::new(redis_conn).await?;
-            redis_cache.delete_cache(catalog.clone()).await?;
-            return Ok(CacheWrapper::new_redis(redis_cache));
+            let wrapper = CacheWrapper::new_redis(redis_cache, compression);
+            if flush_on_start {
+                wrapper.delete_cache(catalog).await?;
+            }
+            return Ok(wrapper.with_memory(memory_capacity));
+        }
+
+        if let Some(object_store) = &config.object_store {
+            let object_store = ObjectStoreCache::new(object_store)?;
+            let wrapper = CacheWrapper::new_object_store(object_store, compression);
+            if flush_on_start {
+                wrapper.delete_cache(catalog).await?;
+            }
+            return Ok(wrapper.with_memory(memory_capacity));
+        }
+
+        if config.mbtiles {
+            let wrapper = CacheWrapper::new_mbtiles(disk_cache_dir, compression);
+            if flush_on_start {
+                wrapper.delete_cache(catalog).await?;
+            }
+            return Ok(wrapper.with_memory(memory_capacity));
         }
 
         let disk_cache = DiskCache::new(disk_cache_dir);
-        disk_cache.delete_cache_dir(catalog).await;
-        Ok(CacheWrapper::new_disk(disk_cache))
+        if flush_on_start {
+            disk_cache.delete_cache_dir(catalog).await;
+        }
+        Ok(CacheWrapper::new_disk(disk_cache, compression).with_memory(memory_capacity))
     }
 
     pub fn cache_dir(&self) -> PathBuf {
         match &self.mode {
             CacheMode::Disk(disk_cache) => disk_cache.cache_dir.clone(),
-            CacheMode::Redis(_) => PathBuf::new(),
+            CacheMode::Mbtiles(mbtiles) => mbtiles.cache_dir.clone(),
+            CacheMode::Redis(_) | CacheMode::ObjectStore(_) => PathBuf::new(),
             CacheMode::Disabled => PathBuf::new(),
         }
     }
 
+    pub fn is_disabled(&self) -> bool {
+        matches!(self.mode, CacheMode::Disabled)
+    }
+
+    /// Name of the backend tiles are stored in.
+    pub fn backend(&self) -> &'static str {
+        match &self.mode {
+            CacheMode::Redis(_) => CacheTier::Redis.as_str(),
+            CacheMode::Disk(_) => CacheTier::Disk.as_str(),
+            CacheMode::ObjectStore(_) => CacheTier::ObjectStore.as_str(),
+            CacheMode::Mbtiles(_) => CacheTier::Mbtiles.as_str(),
+            CacheMode::Disabled => "disabled",
+        }
+    }
+
+    /// Unexpired tiles the backend holds for a layer, in the current storage
+    /// encoding and, with Redis, of the current layer version.
+    pub async fn layer_usage(&self, layer_name: &str, max_cache_age: u64) -> AppResult<LayerUsage> {
+        let suffix = self.compression.key_suffix();
+        match &self.mode {
+            CacheMode::Redis(redis_cache) => {
+                redis_cache.layer_usage(layer_name, suffix, max_cache_age).await
+            }
+            CacheMode::Disk(disk_cache) => {
+                Ok(disk_cache.layer_usage(layer_name, suffix, max_cache_age).await)
+            }
+            CacheMode::ObjectStore(object_store) => {
+                object_store.layer_usage(layer_name, suffix, max_cache_age).await
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.layer_usage(layer_name, max_cache_age).await,
+            CacheMode::Disabled => Ok(LayerUsage::default()),
+        }
+    }
+
+    /// Tiles of a layer held by the memory tier; `None` when it is off.
+    pub fn memory_usage(&self, layer_name: &str) -> Option<LayerUsage> {
+        self.memory
+            .as_ref()
+            .map(|memory| memory.layer_usage(layer_name))
+    }
+
+    /// A page of the tiles `layer_usage` counts, by matrix set, z, x and y.
+    pub async fn list_tiles(
+        &self,
+        layer_name: &str,
+        max_cache_age: u64,
+        page: &TilePage,
+    ) -> AppResult<TileListing> {
+        let suffix = self.compression.key_suffix();
+        match &self.mode {
+            CacheMode::Redis(redis_cache) => {
+                redis_cache
+                    .list_tiles(layer_name, suffix, max_cache_age, page)
+                    .await
+            }
+            CacheMode::Disk(disk_cache) => Ok(disk_cache
+                .list_tiles(layer_name, suffix, max_cache_age, page)
+                .await),
+            CacheMode::ObjectStore(object_store) => {
+                object_store
+                    .list_tiles(layer_name, suffix, max_cache_age, page)
+                    .await
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.list_tiles(layer_name, max_cache_age, page).await,
+            CacheMode::Disabled => Ok(TileListing::default()),
+        }
+    }
+
     pub async fn delete_cache(&self, catalog: Catalog) -> AppResult<()> {
         if matches!(self.mode, CacheMode::Disabled) {
             return Ok(());
@@ -80,6 +287,8 @@ impl CacheWrapper {
         }
         match &self.mode {
             CacheMode::Redis(redis_cache) => redis_cache.delete_cache(catalog).await,
+            CacheMode::ObjectStore(object_store) => object_store.delete_cache(catalog).await,
+            CacheMode::Mbtiles(mbtiles) => mbtiles.delete_cache(catalog).await,
             CacheMode::Disk(disk_cache) => {
                 disk_cache.delete_cache_dir(catalog).await;
                 Ok(())
@@ -88,19 +297,35 @@ impl CacheWrapper {
         }
     }
 
+    /// Starts the periodic sweep of stale tiles of backends that need one.
+    pub fn start_sweeper(&self) {
+        if let CacheMode::Redis(redis_cache) = &self.mode {
+            redis_cache.start_sweeper();
+        }
+    }
+
     pub async fn delete_layer_cache(&self, layer_name: &String) -> AppResult<()> {
         if matches!(self.mode, CacheMode::Disabled) {
             return Ok(());
         }
         self.increment_layer_version(layer_name).await;
-        match &self.mode {
+        let deleted = match &self.mode {
             CacheMode::Redis(redis_cache) => redis_cache.delete_layer_cache(layer_name).await,
+            CacheMode::ObjectStore(object_store) => {
+                object_store.delete_layer_cache(layer_name).await
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.delete_layer_cache(layer_name).await,
             CacheMode::Disk(disk_cache) => {
                 disk_cache.delete_layer_cache(layer_name).await;
                 Ok(())
             }
             CacheMode::Disabled => Ok(()),
+        };
+        // Again, for tiles written while the backend was being cleared.
+        if let Some(memory) = &self.memory {
+            memory.remove_layer(layer_name);
         }
+        deleted
     }
 
     /// Returns the current version counter for a layer.
@@ -108,78 +333,288 @@ impl CacheWrapper {
         match &self.mode {
             CacheMode::Redis(redis_cache) => redis_cache.get_layer_version(layer_name).await,
             CacheMode::Disk(disk_cache) => disk_cache.get_layer_version(layer_name).await,
+            CacheMode::ObjectStore(object_store) => {
+                object_store.get_layer_version(layer_name).await
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.get_layer_version(layer_name).await,
             CacheMode::Disabled => 0,
         }
     }
 
-    /// Increments the version counter for a layer (called on cache invalidation).
+    /// Increments the version counter for a layer (called on cache invalidation)
+    /// and drops the layer's tiles from the memory tier.
     pub async fn increment_layer_version(&self, layer_name: &str) {
         match &self.mode {
             CacheMode::Redis(redis_cache) => redis_cache.increment_layer_version(layer_name).await,
             CacheMode::Disk(disk_cache) => disk_cache.increment_layer_version(layer_name).await,
+            CacheMode::ObjectStore(object_store) => {
+                object_store.increment_layer_version(layer_name).await
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.increment_layer_version(layer_name).await,
+            CacheMode::Disabled => {}
+        }
+        // A new layer version changes every ETag; the regions are moot.
+        match &self.mode {
+            CacheMode::Redis(redis_cache) => redis_cache.clear_regions(layer_name).await,
+            CacheMode::Disk(disk_cache) => disk_cache.clear_regions(layer_name).await,
+            CacheMode::ObjectStore(object_store) => object_store.clear_regions(layer_name).await,
+            CacheMode::Mbtiles(mbtiles) => mbtiles.clear_regions(layer_name).await,
+            CacheMode::Disabled => {}
+        }
+        self.remember_regions(layer_name, Arc::default());
+        if let Some(memory) = &self.memory {
+            memory.remove_layer(layer_name);
+        }
+    }
+
+    /// Version of one tile: its layer version, and the stamp of the latest
+    /// region invalidated over it since. Tile ETags are derived from it.
+    pub async fn get_tile_version(&self, key: &TileKey) -> TileVersion {
+        TileVersion {
+            layer: self.get_layer_version(&key.layer).await,
+            region: self.region_stamps(&key.layer).await.stamp(key),
+        }
+    }
+
+    /// Drops the layer's cached tiles inside the spans, from the backend
+    /// and the memory tier, and records the region so those tiles, and only
+    /// those, get a new version. Returns the number of tiles covered.
+    pub async fn invalidate_region(&self, layer_name: &str, spans: Vec<TileSpan>) -> AppResult<u64> {
+        let tiles: u64 = spans.iter().map(TileSpan::count).sum();
+        if tiles > MAX_REGION_TILES {
+            return Err(AppError::InvalidInput(format!(
+                "the region covers {tiles} tiles, more than {MAX_REGION_TILES}; \
+                 narrow the zoom range or clear the whole layer cache"
+            )));
+        }
+        if spans.is_empty() || matches!(self.mode, CacheMode::Disabled) {
+            return Ok(tiles);
+        }
+        let _invalidating = self.invalidating.lock().await;
+
+        // Tiles first: a request served in between renders the new data
+        // under the old ETag, which the recorded region then changes.
+        let suffix = self.compression.key_suffix();
+        match &self.mode {
+            CacheMode::Redis(redis_cache) => {
+                redis_cache.delete_tiles(layer_name, &spans, suffix).await?
+            }
+            CacheMode::Disk(disk_cache) => {
+                disk_cache.delete_tiles(layer_name, &spans, suffix).await
+            }
+            CacheMode::ObjectStore(object_store) => {
+                object_store.delete_tiles(layer_name, &spans, suffix).await?
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.delete_tiles(layer_name, &spans).await?,
             CacheMode::Disabled => {}
         }
+        let regions = match &self.mode {
+            CacheMode::Redis(redis_cache) => redis_cache.push_region(layer_name, spans.clone()).await?,
+            CacheMode::Disk(disk_cache) => disk_cache.push_region(layer_name, spans.clone()).await?,
+            CacheMode::ObjectStore(object_store) => {
+                object_store.push_region(layer_name, spans.clone()).await?
+            }
+            CacheMode::Mbtiles(mbtiles) => mbtiles.push_region(layer_name, spans.clone()).await?,
+            CacheMode::Disabled => RegionStamps::default(),
+        };
+        self.remember_regions(layer_name, Arc::new(regions));
+        if let Some(memory) = &self.memory {
+            for key in spans.iter().flat_map(|span| span.keys(layer_name)) {
+                memory.remove(&key.to_string());
+            }
+        }
+        Ok(tiles)
     }
 
+    /// Regions invalidated over a layer, read from the backend at most
+    /// `REGION_REFRESH` ago.
+    async fn region_stamps(&self, layer_name: &str) -> Arc<RegionStamps> {
+        let cached = self
+            .regions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(layer_name)
+            .cloned();
+        if let Some((regions, read_at)) = cached
+            && read_at.elapsed() < REGION_REFRESH
+        {
+            return regions;
+        }
+        let regions = Arc::new(match &self.mode {
+            CacheMode::Redis(redis_cache) => redis_cache.get_regions(layer_name).await,
+            CacheMode::Disk(disk_cache) => disk_cache.get_regions(layer_name).await,
+            CacheMode::ObjectStore(object_store) => object_store.get_regions(layer_name).await,
+            CacheMode::Mbtiles(mbtiles) => mbtiles.get_regions(layer_name).await,
+            CacheMode::Disabled => RegionStamps::default(),
+        });
+        self.remember_regions(layer_name, regions.clone());
+        regions
+    }
+
+    fn remember_regions(&self, layer_name: &str, regions: Arc<RegionStamps>) {
+        self.regions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(layer_name.to_string(), (regions, Instant::now()));
+    }
+
+    /// Tile version memory tier entries are stamped with. With Redis or an
+    /// object store the versions are shared, so a tile invalidated by another
+    /// instance is not served from this one's memory; the disk and MBTiles
+    /// caches belong to this instance alone, which clears the tier itself.
+    async fn memory_stamp(&self, key: &TileKey) -> TileVersion {
+        match &self.mode {
+            CacheMode::Redis(_) | CacheMode::ObjectStore(_) => self.get_tile_version(key).await,
+            CacheMode::Disk(_) | CacheMode::Mbtiles(_) | CacheMode::Disabled => {
+                TileVersion::default()
+            }
+        }
+    }
+
+    pub fn compression(&self) -> TileEncoding {
+        self.compression
+    }
+
+    /// Compresses a freshly rendered tile into the storage encoding.
+    pub fn encode_tile(&self, raw: Bytes) -> AppResult<EncodedTile> {
+        Ok(EncodedTile {
+            data: self.compression.encode(&raw)?,
+            encoding: self.compression,
+        })
+    }
+
+    /// Stored tile in the storage encoding, and the tier it came from;
+    /// callers decode or pass it on. Backend hits are kept in memory.
     pub async fn get_tile(
         &self,
-        name: &str,
-        z: u32,
-        x: u32,
-        y: u32,
+        key: &TileKey,
         max_cache_age: u64,
-    ) -> Option<Bytes> {
-        match &self.mode {
+    ) -> Option<(EncodedTile, CacheTier)> {
+        let encoded = |data| EncodedTile {
+            data,
+            encoding: self.compression,
+        };
+        let stamp = match &self.memory {
+            Some(memory) => {
+                let stamp = self.memory_stamp(key).await;
+                if let Some(data) = memory.get(&key.to_string(), stamp, max_cache_age) {
+                    return Some((encoded(data), CacheTier::Memory));
+                }
+                stamp
+            }
+            None => TileVersion::default(),
+        };
+
+        let suffix = self.compression.key_suffix();
+        let (data, age, tier) = match &self.mode {
             CacheMode::Redis(redis_cache) => {
-                let key = format!("{name}:{z}:{x}:{y}");
-                redis_cache.get_cache(key).await.ok()
+                let (data, ttl) = redis_cache.get_cache(key, suffix).await.ok()?;
+                let age = ttl.map_or(0, |ttl| max_cache_age.saturating_sub(ttl));
+                (data, Duration::from_secs(age), CacheTier::Redis)
             }
             CacheMode::Disk(disk_cache) => {
-                let tilefolder = disk_cache
-                    .cache_dir
-                    .join(name)
-                    .join(z.to_string())
-                    .join(x.to_string());
-                let tilepath = tilefolder.join(y.to_string()).with_extension("pbf");
-                disk_cache.get_cache(tilepath, max_cache_age).await.ok()
+                let tilepath = disk_cache.tile_path(key, suffix);
+                let (data, age) = disk_cache.get_cache(tilepath, max_cache_age).await.ok()?;
+                (data, age, CacheTier::Disk)
             }
-            CacheMode::Disabled => None,
+            CacheMode::ObjectStore(object_store) => {
+                let path = object_store.tile_path(key, suffix);
+                let (data, age) = object_store.get_cache(&path, max_cache_age).await.ok()?;
+                (data, age, CacheTier::ObjectStore)
+            }
+            CacheMode::Mbtiles(mbtiles) => {
+                let (data, age) = mbtiles.get_cache(key, max_cache_age).await.ok()?;
+                (data, age, CacheTier::Mbtiles)
+            }
+            CacheMode::Disabled => return None,
+        };
+        if let Some(memory) = &self.memory {
+            memory.insert(key.to_string(), data.clone(), stamp, age);
         }
+        Some((encoded(data), tier))
     }
 
-    pub async fn write_tile(
+    /// Compresses and stores a raw tile.
+    pub async fn write_tile(&self, key: &TileKey, tile: &[u8], max_cache_age: u64) -> AppResult<()> {
+        if matches!(self.mode, CacheMode::Disabled) {
+            return Ok(());
+        }
+        let tile = self.encode_tile(Bytes::copy_from_slice(tile))?;
+        self.write_backend(key, &tile, max_cache_age).await
+    }
+
+    /// Stores a tile already in the storage encoding (see `encode_tile`),
+    /// keeping it in memory too.
+    pub async fn write_encoded_tile(
         &self,
-        name: &str,
-        z: u32,
-        x: u32,
-        y: u32,
-        tile: &[u8],
+        key: &TileKey,
+        tile: &EncodedTile,
         max_cache_age: u64,
     ) -> AppResult<()> {
+        self.write_backend(key, tile, max_cache_age).await?;
+        if let Some(memory) = &self.memory {
+            let stamp = self.memory_stamp(key).await;
+            memory.insert(key.to_string(), tile.data.clone(), stamp, Duration::ZERO);
+        }
+        Ok(())
+    }
+
+    /// Writes to the disk or Redis cache only; seeded tiles do not crowd
+    /// out the ones being served from memory.
+    async fn write_backend(
+        &self,
+        key: &TileKey,
+        tile: &EncodedTile,
+        max_cache_age: u64,
+    ) -> AppResult<()> {
+        let suffix = tile.encoding.key_suffix();
         match &self.mode {
             CacheMode::Redis(redis_cache) => {
-                let key = format!("{name}:{z}:{x}:{y}");
                 redis_cache
-                    .write_tile_to_cache(key, tile, max_cache_age)
+                    .write_tile_to_cache(key, suffix, &tile.data, max_cache_age)
                     .await
             }
             CacheMode::Disk(disk_cache) => {
-                let tilefolder = disk_cache
-                    .cache_dir
-                    .join(name)
-                    .join(z.to_string())
-                    .join(x.to_string());
-                let tilepath = tilefolder.join(y.to_string()).with_extension("pbf");
-                disk_cache.write_tile_to_file(&tilepath, tile).await
+                let tilepath = disk_cache.tile_path(key, suffix);
+                disk_cache.write_tile_to_file(&tilepath, &tile.data).await
+            }
+            CacheMode::ObjectStore(object_store) => {
+                let path = object_store.tile_path(key, suffix);
+                object_store
+                    .write_tile(&path, &tile.data, tile.encoding, max_cache_age)
+                    .await
+            }
+            CacheMode::Mbtiles(mbtiles) => {
+                mbtiles.write_tile(key, &tile.data, max_cache_age).await
             }
             CacheMode::Disabled => Ok(()),
         }
     }
 
-    pub async fn exists_key(&self, key: String) -> AppResult<bool> {
+    /// Offline MBTiles copy of a layer's cache; only the MBTiles mode keeps
+    /// one. See `MbtilesCache::export_layer`.
+    pub async fn export_mbtiles(
+        &self,
+        layer_name: &str,
+        metadata: &[(&str, String)],
+    ) -> AppResult<PathBuf> {
+        match &self.mode {
+            CacheMode::Mbtiles(mbtiles) => mbtiles.export_layer(layer_name, metadata).await,
+            _ => Err(AppError::NotFound(
+                "the tile cache is not stored as MBTiles (set cache.mbtiles)".to_string(),
+            )),
+        }
+    }
+
+    /// Whether Redis holds the tile under the layer's current version.
+    pub async fn exists_key(&self, key: &TileKey) -> AppResult<bool> {
         match &self.mode {
-            CacheMode::Redis(redis_cache) => redis_cache.exists_key(key).await,
-            CacheMode::Disk(_) => Ok(false),
+            CacheMode::Redis(redis_cache) => {
+                redis_cache
+                    .exists_key(key, self.compression.key_suffix())
+                    .await
+            }
+            CacheMode::Disk(_) | CacheMode::ObjectStore(_) | CacheMode::Mbtiles(_) => Ok(false),
             CacheMode::Disabled => Ok(false),
         }
     }
@@ -188,6 +623,9 @@ impl CacheWrapper {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::objectstore::KeyLayout;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
 
     fn empty_catalog() -> Catalog {
         Catalog { layers: vec![] }
@@ -197,11 +635,11 @@ mod tests {
     async fn disabled_mode_write_then_get_returns_none() {
         let wrapper = CacheWrapper::new_disabled();
         wrapper
-            .write_tile("layer", 1, 2, 3, b"tile-bytes", 0)
+            .write_tile(&TileKey::new("layer", 1, 2, 3), b"tile-bytes", 0)
             .await
             .expect("write_tile should be a no-op success");
 
-        let tile = wrapper.get_tile("layer", 1, 2, 3, 0).await;
+        let tile = wrapper.get_tile(&TileKey::new("layer", 1, 2, 3), 0).await;
         assert!(tile.is_none());
     }
 
@@ -222,7 +660,7 @@ mod tests {
         wrapper.increment_layer_version("layer").await;
         assert_eq!(wrapper.get_layer_version("layer").await, 0);
 
-        assert!(!wrapper.exists_key("key".to_string()).await.unwrap());
+        assert!(!wrapper.exists_key(&TileKey::new("layer", 1, 2, 3)).await.unwrap());
         assert_eq!(wrapper.cache_dir(), PathBuf::new());
     }
 
@@ -236,6 +674,8 @@ mod tests {
             untouched_dir.clone(),
             empty_catalog(),
             true,
+            true,
+            &CacheConfig::default(),
         )
         .await
         .expect("disabled cache should initialize without a backend");
@@ -243,4 +683,211 @@ mod tests {
         assert_eq!(wrapper.cache_dir(), PathBuf::new());
         assert!(!untouched_dir.exists());
     }
+
+    /// Runs against the Redis server of `MVT_TEST_REDIS_URL`, when set.
+    #[tokio::test]
+    async fn redis_startup_flush_drops_the_current_tiles() {
+        let Ok(redis_url) = std::env::var("MVT_TEST_REDIS_URL") else {
+            return;
+        };
+        let layer = crate::models::catalog::Layer {
+            name: format!("flush_{}", uuid::Uuid::new_v4().simple()),
+            delete_cache_on_start: Some(true),
+            ..crate::config::test_support::test_layer("flush")
+        };
+        let key = TileKey::new(&format!("{}_{}", layer.category.name, layer.name), 3, 2, 1);
+        let catalog = Catalog {
+            layers: vec![layer],
+        };
+
+        let redis_cache = RedisCache::new(redis_url.clone()).await.unwrap();
+        let wrapper = CacheWrapper::new_redis(redis_cache, TileEncoding::Identity);
+        wrapper.write_tile(&key, b"tile", 0).await.unwrap();
+        assert!(wrapper.exists_key(&key).await.unwrap());
+
+        let restarted = CacheWrapper::initialize_cache(
+            Some(redis_url),
+            PathBuf::new(),
+            catalog,
+            false,
+            true,
+            &CacheConfig::default(),
+        )
+        .await
+        .unwrap();
+        assert!(!restarted.exists_key(&key).await.unwrap());
+        assert!(restarted.get_tile(&key, 0).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn disk_mode_stores_compressed_tiles() {
+        let dir = std::env::temp_dir().join(format!(
+            "mvt-rs-test-encoded-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let wrapper = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Gzip);
+
+        let key = TileKey::new("cat_layer", 3, 2, 1);
+        wrapper.write_tile(&key, b"tile-bytes", 0).await.unwrap();
+
+        let stored = std::fs::read(dir.join("cat_layer/3/2/1.pbf.gz")).unwrap();
+        assert_ne!(stored, b"tile-bytes");
+
+        let (tile, tier) = wrapper.get_tile(&key, 0).await.unwrap();
+        assert_eq!(tier, CacheTier::Disk);
+        assert_eq!(tile.encoding, TileEncoding::Gzip);
+        assert_eq!(tile.into_identity().unwrap(), Bytes::from_static(b"tile-bytes"));
+
+        // Entries of another encoding are not picked up.
+        let brotli = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Brotli);
+        assert!(brotli.get_tile(&key, 0).await.is_none());
+
+        // Other matrix sets live under the layer directory.
+        let crs84 = key.clone().with_matrix_set("WorldCRS84Quad");
+        assert!(wrapper.get_tile(&crs84, 0).await.is_none());
+        wrapper.write_tile(&crs84, b"crs84", 0).await.unwrap();
+        assert!(dir.join("cat_layer/WorldCRS84Quad/3/2/1.pbf.gz").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn disk_mode_reports_the_layer_tiles_in_the_current_encoding() {
+        let dir = std::env::temp_dir().join(format!(
+            "mvt-rs-test-usage-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let wrapper = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Identity)
+            .with_memory(1024 * 1024);
+        let key = TileKey::new("cat_layer", 3, 2, 1);
+        let crs84 = TileKey::new("cat_layer", 4, 0, 0).with_matrix_set("WorldCRS84Quad");
+        wrapper.write_tile(&key, b"tile", 0).await.unwrap();
+        wrapper.write_tile(&crs84, b"crs84", 0).await.unwrap();
+        wrapper.write_tile(&TileKey::new("cat_layer2", 3, 2, 1), b"other", 0).await.unwrap();
+        // Stored under another encoding: never served, not counted.
+        let gzip = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Gzip);
+        gzip.write_tile(&TileKey::new("cat_layer", 5, 0, 0), b"gzip", 0).await.unwrap();
+
+        let usage = wrapper.layer_usage("cat_layer", 0).await.unwrap();
+        assert_eq!((usage.tiles, usage.bytes), (2, 9));
+        assert_eq!(usage.newest_age, Some(0));
+        assert_eq!(wrapper.memory_usage("cat_layer").unwrap().tiles, 0);
+        wrapper.get_tile(&key, 0).await.unwrap();
+        assert_eq!(wrapper.memory_usage("cat_layer").unwrap().bytes, 4);
+
+        let page = TilePage {
+            z: None,
+            offset: 1,
+            limit: 5,
+        };
+        let listing = wrapper.list_tiles("cat_layer", 0, &page).await.unwrap();
+        assert_eq!(listing.total, 2);
+        assert_eq!(listing.tiles.len(), 1);
+        assert_eq!(listing.tiles[0].matrix_set.as_deref(), Some("WorldCRS84Quad"));
+        assert_eq!((listing.tiles[0].z, listing.tiles[0].bytes), (4, 5));
+
+        assert_eq!(CacheWrapper::new_disabled().backend(), "disabled");
+        assert_eq!(wrapper.backend(), "disk");
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn object_store_mode_stores_compressed_tiles() {
+        let layout = KeyLayout::parse("{layer}/{tms}/{z}/{x}/{y}.pbf").unwrap();
+        let store = ObjectStoreCache::with_store(Arc::new(InMemory::new()), layout);
+        let wrapper = CacheWrapper::new_object_store(store, TileEncoding::Brotli);
+
+        let key = TileKey::new("cat_layer", 3, 2, 1);
+        wrapper.write_tile(&key, b"tile-bytes", 60).await.unwrap();
+        let (tile, tier) = wrapper.get_tile(&key, 60).await.unwrap();
+        assert_eq!(tier, CacheTier::ObjectStore);
+        assert_eq!(tile.into_identity().unwrap(), Bytes::from_static(b"tile-bytes"));
+
+        wrapper.delete_layer_cache(&"cat_layer".to_string()).await.unwrap();
+        assert!(wrapper.get_tile(&key, 60).await.is_none());
+        assert_eq!(wrapper.get_layer_version("cat_layer").await, 1);
+    }
+
+    #[tokio::test]
+    async fn memory_tier_serves_hits_until_the_layer_is_invalidated() {
+        let dir = std::env::temp_dir().join(format!(
+            "mvt-rs-test-memory-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let wrapper = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Gzip)
+            .with_memory(1024 * 1024);
+
+        // Seeded tiles go to disk only and are promoted on their first hit.
+        let seeded = TileKey::new("cat_layer", 3, 2, 1);
+        wrapper.write_tile(&seeded, b"seeded", 0).await.unwrap();
+        assert_eq!(wrapper.get_tile(&seeded, 0).await.unwrap().1, CacheTier::Disk);
+        assert_eq!(wrapper.get_tile(&seeded, 0).await.unwrap().1, CacheTier::Memory);
+
+        let key = TileKey::new("cat_layer", 3, 2, 2);
+        let tile = wrapper.encode_tile(Bytes::from_static(b"rendered")).unwrap();
+        wrapper.write_encoded_tile(&key, &tile, 0).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        let (tile, tier) = wrapper.get_tile(&key, 0).await.unwrap();
+        assert_eq!(tier, CacheTier::Memory);
+        assert_eq!(tile.into_identity().unwrap(), Bytes::from_static(b"rendered"));
+
+        wrapper.delete_layer_cache(&"cat_layer".to_string()).await.unwrap();
+        assert!(wrapper.get_tile(&key, 0).await.is_none());
+        assert!(wrapper.get_tile(&seeded, 0).await.is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn region_invalidation_drops_and_restamps_only_its_tiles() {
+        let dir = std::env::temp_dir().join(format!(
+            "mvt-rs-test-region-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let wrapper = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Gzip)
+            .with_memory(1024 * 1024);
+        let inside = TileKey::new("cat_layer", 10, 300, 600);
+        let outside = TileKey::new("cat_layer", 10, 310, 600);
+        for key in [&inside, &outside] {
+            let tile = wrapper.encode_tile(Bytes::from_static(b"tile")).unwrap();
+            wrapper.write_encoded_tile(key, &tile, 0).await.unwrap();
+        }
+        let before = wrapper.get_tile_version(&inside).await;
+
+        let span = TileSpan {
+            matrix_set: None,
+            z: 10,
+            xmin: 299,
+            xmax: 301,
+            ymin: 600,
+            ymax: 600,
+        };
+        assert_eq!(wrapper.invalidate_region("cat_layer", vec![span]).await.unwrap(), 3);
+        assert!(wrapper.get_tile(&inside, 0).await.is_none());
+        assert_eq!(wrapper.get_tile(&outside, 0).await.unwrap().1, CacheTier::Memory);
+        assert_ne!(wrapper.get_tile_version(&inside).await, before);
+        assert_eq!(wrapper.get_tile_version(&outside).await, before);
+
+        // Read back from the backend, not only this instance's copy.
+        let reopened = CacheWrapper::new_disk(DiskCache::new(dir.clone()), TileEncoding::Gzip);
+        assert_eq!(reopened.get_tile_version(&inside).await.region, 1);
+
+        let wide = TileSpan {
+            matrix_set: None,
+            z: 20,
+            xmin: 0,
+            xmax: 999,
+            ymin: 0,
+            ymax: 999,
+        };
+        assert!(wrapper.invalidate_region("cat_layer", vec![wide]).await.is_err());
+
+        // A full invalidation forgets the regions.
+        wrapper.delete_layer_cache(&"cat_layer".to_string()).await.unwrap();
+        assert_eq!(wrapper.get_tile_version(&inside).await.region, 0);
+        assert_eq!(wrapper.get_tile_version(&inside).await.layer, 1);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
@@ -1,6 +1,9 @@
 use std::path::{Path, PathBuf};
 use std::time::{Duration, SystemTime};
 
+use super::key::TileKey;
+use super::region::{RegionStamps, TileSpan};
+use super::stats::{CachedTile, LayerUsage, PageCollector, TileListing, TilePage, age_of, expired};
 use crate::{
     Catalog,
     error::{AppError, AppResult},
@@ -51,7 +54,138 @@ impl DiskCache {
         }
     }
 
-    pub async fn get_cache(&self, tilepath: PathBuf, max_cache_age: u64) -> AppResult<Bytes> {
+    /// `{cache_dir}/{name}/{z}/{x}/{y}.pbf{suffix}`
+    pub fn tile_path(&self, key: &TileKey, suffix: &str) -> PathBuf {
+        let mut path = self.cache_dir.join(&key.layer);
+        if let Some(set) = &key.matrix_set {
+            path.push(set);
+        }
+        path.join(key.z.to_string())
+            .join(key.x.to_string())
+            .join(format!("{}.pbf{suffix}", key.y))
+    }
+
+    /// Removes the layer's tiles inside the spans; missing ones are skipped.
+    pub async fn delete_tiles(&self, layer_name: &str, spans: &[TileSpan], suffix: &str) {
+        let paths: Vec<PathBuf> = spans
+            .iter()
+            .flat_map(|span| span.keys(layer_name))
+            .map(|key| self.tile_path(&key, suffix))
+            .collect();
+        let mut deleted = 0;
+        for path in paths {
+            if fs::remove_file(path).await.is_ok() {
+                deleted += 1;
+            }
+        }
+        tracing::debug!("Deleted {deleted} cached tiles of {layer_name}.");
+    }
+
+    /// Zoom directories of a layer: `{layer}/{z}` for WebMercatorQuad and
+    /// `{layer}/{set}/{z}` for other matrix sets.
+    async fn zoom_dirs(&self, layer_name: &str) -> Vec<(Option<String>, u32, PathBuf)> {
+        let mut zooms = Vec::new();
+        let Ok(mut entries) = fs::read_dir(self.cache_dir.join(layer_name)).await else {
+            return zooms;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Ok(z) = name.parse() {
+                zooms.push((None, z, entry.path()));
+                continue;
+            }
+            let Ok(mut sets) = fs::read_dir(entry.path()).await else {
+                continue;
+            };
+            while let Ok(Some(zoom)) = sets.next_entry().await {
+                if let Ok(z) = zoom.file_name().to_string_lossy().parse() {
+                    zooms.push((Some(name.clone()), z, zoom.path()));
+                }
+            }
+        }
+        zooms
+    }
+
+    /// Calls `visit` with every unexpired tile of a layer stored with
+    /// `suffix`, optionally of one zoom level only.
+    async fn walk_tiles(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+        only_z: Option<u32>,
+        mut visit: impl FnMut(CachedTile),
+    ) {
+        let extension = format!(".pbf{suffix}");
+        for (matrix_set, z, dir) in self.zoom_dirs(layer_name).await {
+            if only_z.is_some_and(|only| only != z) {
+                continue;
+            }
+            let Ok(mut columns) = fs::read_dir(&dir).await else {
+                continue;
+            };
+            while let Ok(Some(column)) = columns.next_entry().await {
+                let Ok(x) = column.file_name().to_string_lossy().parse() else {
+                    continue;
+                };
+                let Ok(mut files) = fs::read_dir(column.path()).await else {
+                    continue;
+                };
+                while let Ok(Some(file)) = files.next_entry().await {
+                    let name = file.file_name().to_string_lossy().into_owned();
+                    let Some(Ok(y)) = name.strip_suffix(&extension).map(str::parse) else {
+                        continue;
+                    };
+                    let Ok(metadata) = file.metadata().await else {
+                        continue;
+                    };
+                    let age = metadata.modified().map(age_of).unwrap_or_default();
+                    if expired(age, max_cache_age) {
+                        continue;
+                    }
+                    visit(CachedTile {
+                        matrix_set: matrix_set.clone(),
+                        z,
+                        x,
+                        y,
+                        bytes: metadata.len(),
+                        age: Some(age),
+                    });
+                }
+            }
+        }
+    }
+
+    pub async fn layer_usage(&self, layer_name: &str, suffix: &str, max_cache_age: u64) -> LayerUsage {
+        let mut usage = LayerUsage::default();
+        self.walk_tiles(layer_name, suffix, max_cache_age, None, |tile| {
+            usage.add(tile.bytes, tile.age)
+        })
+        .await;
+        usage
+    }
+
+    pub async fn list_tiles(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+        page: &TilePage,
+    ) -> TileListing {
+        let mut collector = PageCollector::new(page);
+        self.walk_tiles(layer_name, suffix, max_cache_age, page.z, |tile| {
+            collector.push(tile)
+        })
+        .await;
+        collector.finish()
+    }
+
+    /// Cached tile and how long ago it was written.
+    pub async fn get_cache(
+        &self,
+        tilepath: PathBuf,
+        max_cache_age: u64,
+    ) -> AppResult<(Bytes, Duration)> {
         if let Ok(metadata) = fs::metadata(&tilepath).await {
             let cache_modified = match metadata.modified() {
                 Ok(modified_time) => modified_time,
@@ -68,7 +202,7 @@ impl DiskCache {
                 let mut tile = Vec::new();
                 let mut file = File::open(&tilepath).await?;
                 file.read_to_end(&mut tile).await?;
-                return Ok(tile.into());
+                return Ok((tile.into(), cache_age));
             }
         }
 
@@ -120,4 +254,39 @@ impl DiskCache {
             tracing::warn!("Failed to write version for layer {layer_name}: {e}");
         }
     }
+
+    /// Regions invalidated over a layer since its version was last bumped.
+    /// Stored at `{cache_dir}/.regions/{layer_name}.json`, next to the versions.
+    pub async fn get_regions(&self, layer_name: &str) -> RegionStamps {
+        match fs::read(self.regions_path(layer_name)).await {
+            Ok(json) => RegionStamps::from_json(&json),
+            Err(_) => RegionStamps::default(),
+        }
+    }
+
+    /// Records a region; only this instance writes the file.
+    pub async fn push_region(
+        &self,
+        layer_name: &str,
+        spans: Vec<TileSpan>,
+    ) -> AppResult<RegionStamps> {
+        let mut regions = self.get_regions(layer_name).await;
+        regions.push(spans);
+        let path = self.regions_path(layer_name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        fs::write(&path, regions.to_json()).await?;
+        Ok(regions)
+    }
+
+    pub async fn clear_regions(&self, layer_name: &str) {
+        let _ = fs::remove_file(self.regions_path(layer_name)).await;
+    }
+
+    fn regions_path(&self, layer_name: &str) -> PathBuf {
+        self.cache_dir
+            .join(".regions")
+            .join(format!("{layer_name}.json"))
+    }
 }
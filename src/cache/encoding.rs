@@ -0,0 +1,244 @@
+use bytes::Bytes;
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::Deserialize;
+use std::io::{Read, Write};
+
+use crate::{db::static_tiles::is_gzip, error::AppResult};
+
+/// Brotli quality used when storing tiles: tiles are compressed once and
+/// served many times, so a mid-high level pays off.
+const BROTLI_QUALITY: u32 = 6;
+const BROTLI_WINDOW: u32 = 22;
+
+/// How tile bytes are stored in the cache and sent to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TileEncoding {
+    #[serde(rename = "none")]
+    Identity,
+    #[default]
+    Gzip,
+    Brotli,
+}
+
+impl TileEncoding {
+    /// `Content-Encoding` token, `None` for uncompressed bytes.
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Self::Identity => None,
+            Self::Gzip => Some("gzip"),
+            Self::Brotli => Some("br"),
+        }
+    }
+
+    /// Appended to cache keys and file names so entries written with another
+    /// encoding are never misread after a config change.
+    pub fn key_suffix(&self) -> &'static str {
+        match self {
+            Self::Identity => "",
+            Self::Gzip => ".gz",
+            Self::Brotli => ".br",
+        }
+    }
+
+    pub fn encode(&self, raw: &[u8]) -> AppResult<Bytes> {
+        let mut out = Vec::new();
+        match self {
+            Self::Identity => return Ok(Bytes::copy_from_slice(raw)),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(out, Compression::default());
+                encoder.write_all(raw)?;
+                out = encoder.finish()?;
+            }
+            Self::Brotli => {
+                let mut encoder =
+                    brotli::CompressorWriter::new(&mut out, 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                encoder.write_all(raw)?;
+                encoder.flush()?;
+                drop(encoder);
+            }
+        }
+        Ok(out.into())
+    }
+
+    pub fn decode(&self, data: Bytes) -> AppResult<Bytes> {
+        let mut out = Vec::new();
+        match self {
+            Self::Identity => return Ok(data),
+            Self::Gzip => {
+                GzDecoder::new(data.as_ref()).read_to_end(&mut out)?;
+            }
+            Self::Brotli => {
+                brotli::Decompressor::new(data.as_ref(), 4096).read_to_end(&mut out)?;
+            }
+        }
+        Ok(out.into())
+    }
+}
+
+/// True when an `Accept-Encoding` header value allows `coding`, honouring
+/// `q=0` exclusions and the `*` wildcard.
+pub fn accepts_encoding(accept_encoding: Option<&str>, coding: &str) -> bool {
+    let Some(header) = accept_encoding else {
+        return false;
+    };
+    let mut wildcard = false;
+    for item in header.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or("").trim();
+        let allowed = parts
+            .filter_map(|p| p.trim().strip_prefix("q="))
+            .all(|q| q.trim().parse::<f32>().map_or(true, |q| q > 0.0));
+        if name.eq_ignore_ascii_case(coding) {
+            return allowed;
+        }
+        if name == "*" {
+            wildcard = allowed;
+        }
+    }
+    wildcard
+}
+
+/// Tile bytes together with the encoding they are in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodedTile {
+    pub data: Bytes,
+    pub encoding: TileEncoding,
+}
+
+impl EncodedTile {
+    pub fn identity(data: Bytes) -> Self {
+        Self {
+            data,
+            encoding: TileEncoding::Identity,
+        }
+    }
+
+    /// Static tile files may hold gzip tiles; anything else is raw protobuf.
+    pub fn sniff(data: Bytes) -> Self {
+        let encoding = if is_gzip(&data) {
+            TileEncoding::Gzip
+        } else {
+            TileEncoding::Identity
+        };
+        Self { data, encoding }
+    }
+
+    pub fn into_identity(self) -> AppResult<Bytes> {
+        self.encoding.decode(self.data)
+    }
+
+    /// Compresses raw tile bytes in `encoding` when the client accepts it,
+    /// and leaves them as plain protobuf otherwise.
+    pub fn encode_for(
+        raw: Bytes,
+        encoding: TileEncoding,
+        accept_encoding: Option<&str>,
+    ) -> AppResult<Self> {
+        match encoding.content_encoding() {
+            Some(coding) if accepts_encoding(accept_encoding, coding) => Ok(Self {
+                data: encoding.encode(&raw)?,
+                encoding,
+            }),
+            _ => Ok(Self::identity(raw)),
+        }
+    }
+
+    /// Keeps the stored encoding when the client accepts it, otherwise
+    /// decodes to plain protobuf.
+    pub fn negotiate(self, accept_encoding: Option<&str>) -> AppResult<Self> {
+        match self.encoding.content_encoding() {
+            Some(coding) if !accepts_encoding(accept_encoding, coding) => {
+                Ok(Self::identity(self.into_identity()?))
+            }
+            _ => Ok(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RAW: &[u8] = b"\x1a\x0bvector tile vector tile vector tile";
+
+    #[test]
+    fn encodings_round_trip() {
+        for encoding in [
+            TileEncoding::Identity,
+            TileEncoding::Gzip,
+            TileEncoding::Brotli,
+        ] {
+            let encoded = encoding.encode(RAW).unwrap();
+            assert_eq!(encoding.decode(encoded).unwrap(), Bytes::from_static(RAW));
+        }
+        assert!(is_gzip(&TileEncoding::Gzip.encode(RAW).unwrap()));
+    }
+
+    #[test]
+    fn encoding_parses_from_config_values() {
+        let parse = |v: &str| serde_json::from_str::<TileEncoding>(&format!("\"{v}\""));
+        assert_eq!(parse("none").unwrap(), TileEncoding::Identity);
+        assert_eq!(parse("gzip").unwrap(), TileEncoding::Gzip);
+        assert_eq!(parse("brotli").unwrap(), TileEncoding::Brotli);
+        assert!(parse("zstd").is_err());
+        assert_eq!(TileEncoding::default(), TileEncoding::Gzip);
+    }
+
+    #[test]
+    fn accept_encoding_honours_q_values_and_wildcard() {
+        assert!(accepts_encoding(Some("gzip, deflate, br"), "br"));
+        assert!(accepts_encoding(Some("GZIP"), "gzip"));
+        assert!(!accepts_encoding(Some("gzip;q=0, br"), "gzip"));
+        assert!(accepts_encoding(Some("gzip;q=0.5"), "gzip"));
+        assert!(accepts_encoding(Some("*"), "br"));
+        assert!(!accepts_encoding(Some("*, br;q=0"), "br"));
+        assert!(!accepts_encoding(Some("deflate"), "gzip"));
+        assert!(!accepts_encoding(None, "gzip"));
+    }
+
+    #[test]
+    fn negotiate_decodes_only_for_clients_without_support() {
+        let tile = EncodedTile {
+            data: TileEncoding::Gzip.encode(RAW).unwrap(),
+            encoding: TileEncoding::Gzip,
+        };
+
+        let kept = tile.clone().negotiate(Some("gzip, br")).unwrap();
+        assert_eq!(kept, tile);
+
+        let decoded = tile.negotiate(Some("br")).unwrap();
+        assert_eq!(decoded, EncodedTile::identity(Bytes::from_static(RAW)));
+
+        let raw = EncodedTile::identity(Bytes::from_static(RAW));
+        assert_eq!(raw.clone().negotiate(None).unwrap(), raw);
+    }
+
+    #[test]
+    fn encode_for_compresses_only_for_clients_with_support() {
+        let raw = Bytes::from_static(RAW);
+
+        let tile =
+            EncodedTile::encode_for(raw.clone(), TileEncoding::Brotli, Some("gzip, br")).unwrap();
+        assert_eq!(tile.encoding, TileEncoding::Brotli);
+        assert_eq!(tile.into_identity().unwrap(), raw);
+
+        let tile =
+            EncodedTile::encode_for(raw.clone(), TileEncoding::Brotli, Some("gzip")).unwrap();
+        assert_eq!(tile, EncodedTile::identity(raw.clone()));
+
+        let tile =
+            EncodedTile::encode_for(raw.clone(), TileEncoding::Identity, Some("gzip")).unwrap();
+        assert_eq!(tile, EncodedTile::identity(raw));
+    }
+
+    #[test]
+    fn sniff_detects_gzip_static_tiles() {
+        let gz = TileEncoding::Gzip.encode(RAW).unwrap();
+        assert_eq!(EncodedTile::sniff(gz).encoding, TileEncoding::Gzip);
+        assert_eq!(
+            EncodedTile::sniff(Bytes::from_static(RAW)).encoding,
+            TileEncoding::Identity
+        );
+    }
+}
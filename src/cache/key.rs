@@ -0,0 +1,60 @@
+use std::fmt;
+
+use crate::models::tile_matrix_set::WEB_MERCATOR_QUAD;
+
+/// Address of one cached tile. WebMercatorQuad tiles keep the plain
+/// `{layer}:{z}:{x}:{y}` layout; tiles of other matrix sets are namespaced
+/// under the layer (`{layer}:{set}:{z}:{x}:{y}`), so layer-wide
+/// invalidation still covers them.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TileKey {
+    /// `{category}_{name}` of the layer.
+    pub layer: String,
+    /// `None` for WebMercatorQuad.
+    pub matrix_set: Option<String>,
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileKey {
+    pub fn new(layer: &str, z: u32, x: u32, y: u32) -> Self {
+        Self {
+            layer: layer.to_string(),
+            matrix_set: None,
+            z,
+            x,
+            y,
+        }
+    }
+
+    pub fn with_matrix_set(mut self, id: &str) -> Self {
+        self.matrix_set = (id != WEB_MERCATOR_QUAD).then(|| id.to_string());
+        self
+    }
+}
+
+impl fmt::Display for TileKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:", self.layer)?;
+        if let Some(set) = &self.matrix_set {
+            write!(f, "{set}:")?;
+        }
+        write!(f, "{}:{}:{}", self.z, self.x, self.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_matrix_set_keeps_plain_key() {
+        let key = TileKey::new("cat_layer", 3, 2, 1).with_matrix_set(WEB_MERCATOR_QUAD);
+        assert_eq!(key.matrix_set, None);
+        assert_eq!(key.to_string(), "cat_layer:3:2:1");
+
+        let key = TileKey::new("cat_layer", 3, 2, 1).with_matrix_set("WorldCRS84Quad");
+        assert_eq!(key.to_string(), "cat_layer:WorldCRS84Quad:3:2:1");
+    }
+}
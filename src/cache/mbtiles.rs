@@ -0,0 +1,674 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+use sqlx::{
+    Row,
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteSynchronous},
+};
+use tokio::sync::Mutex;
+
+use super::disk::DiskCache;
+use super::encoding::TileEncoding;
+use super::key::TileKey;
+use super::region::{RegionStamps, TileSpan};
+use super::stats::{CachedTile, LayerUsage, PageCollector, TileListing, TilePage};
+use crate::{
+    Catalog,
+    db::static_tiles::xyz_to_tms_y,
+    error::{AppError, AppResult},
+};
+
+/// Metadata entry recording the encoding of the stored tiles, so a change of
+/// `cache.compression` starts the file afresh instead of misreading it.
+const ENCODING_METADATA: &str = "tile_encoding";
+
+/// Tiles re-encoded per transaction when exporting a layer.
+const EXPORT_BATCH_SIZE: i64 = 512;
+
+const SCHEMA: [&str; 2] = [
+    "CREATE TABLE IF NOT EXISTS metadata (name TEXT PRIMARY KEY, value TEXT)",
+    // `created_at` and `expires_at` (unix seconds, NULL for never) are extra
+    // columns MBTiles readers ignore.
+    "CREATE TABLE IF NOT EXISTS tiles (
+        zoom_level INTEGER NOT NULL,
+        tile_column INTEGER NOT NULL,
+        tile_row INTEGER NOT NULL,
+        tile_data BLOB NOT NULL,
+        created_at INTEGER NOT NULL,
+        expires_at INTEGER,
+        PRIMARY KEY (zoom_level, tile_column, tile_row)
+    )",
+];
+
+fn now_secs() -> i64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    i64::try_from(now.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// Tiles stored in one MBTiles file per layer under the cache directory:
+/// `{layer}.mbtiles` for WebMercatorQuad, `{layer}@{set}.mbtiles` for other
+/// matrix sets (whose rows keep XYZ order). Files use WAL so readers never
+/// wait for the writer.
+#[derive(Debug, Clone)]
+pub struct MbtilesCache {
+    pub cache_dir: PathBuf,
+    encoding: TileEncoding,
+    pools: Arc<Mutex<HashMap<String, SqlitePool>>>,
+    /// Layer versions live in the same `.versions` files as the disk cache.
+    versions: DiskCache,
+}
+
+impl MbtilesCache {
+    pub fn new(cache_dir: PathBuf, encoding: TileEncoding) -> Self {
+        MbtilesCache {
+            versions: DiskCache::new(cache_dir.clone()),
+            cache_dir,
+            encoding,
+            pools: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn file_name(key: &TileKey) -> String {
+        match &key.matrix_set {
+            Some(set) => format!("{}@{set}.mbtiles", key.layer),
+            None => format!("{}.mbtiles", key.layer),
+        }
+    }
+
+    /// `tile_row` of a tile: flipped to TMS order for WebMercatorQuad files.
+    fn tile_row(key: &TileKey) -> i64 {
+        match key.matrix_set {
+            Some(_) => key.y as i64,
+            None => xyz_to_tms_y(key.z, key.y) as i64,
+        }
+    }
+
+    /// Pool of a cache file, creating the file and its schema on first use.
+    async fn pool(&self, file: &str) -> AppResult<SqlitePool> {
+        let mut pools = self.pools.lock().await;
+        if let Some(pool) = pools.get(file) {
+            return Ok(pool.clone());
+        }
+
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        let options = SqliteConnectOptions::new()
+            .filename(self.cache_dir.join(file))
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_secs(5));
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_with(options)
+            .await?;
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&pool).await?;
+        }
+
+        let encoding = format!("{:?}", self.encoding).to_lowercase();
+        let stored: Option<String> = sqlx::query_scalar("SELECT value FROM metadata WHERE name = ?")
+            .bind(ENCODING_METADATA)
+            .fetch_optional(&pool)
+            .await?;
+        if stored.as_deref() != Some(encoding.as_str()) {
+            sqlx::query("DELETE FROM tiles").execute(&pool).await?;
+            sqlx::query("INSERT OR REPLACE INTO metadata (name, value) VALUES (?, ?)")
+                .bind(ENCODING_METADATA)
+                .bind(&encoding)
+                .execute(&pool)
+                .await?;
+        }
+        let layer = file.trim_end_matches(".mbtiles");
+        for (name, value) in [("name", layer), ("format", "pbf")] {
+            sqlx::query("INSERT OR IGNORE INTO metadata (name, value) VALUES (?, ?)")
+                .bind(name)
+                .bind(value)
+                .execute(&pool)
+                .await?;
+        }
+
+        pools.insert(file.to_string(), pool.clone());
+        Ok(pool)
+    }
+
+    /// Cache files of a layer, one per matrix set it was requested in.
+    async fn layer_files(&self, layer_name: &str) -> Vec<String> {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.cache_dir).await else {
+            return Vec::new();
+        };
+        let default = format!("{layer_name}.mbtiles");
+        let other_sets = format!("{layer_name}@");
+        let mut files = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if let Ok(name) = entry.file_name().into_string()
+                && (name == default || (name.starts_with(&other_sets) && name.ends_with(".mbtiles")))
+            {
+                files.push(name);
+            }
+        }
+        files
+    }
+
+    /// Matrix set a cache file of the layer holds, `None` for WebMercatorQuad.
+    fn file_matrix_set(layer_name: &str, file: &str) -> Option<String> {
+        file.strip_prefix(&format!("{layer_name}@"))?
+            .strip_suffix(".mbtiles")
+            .map(str::to_string)
+    }
+
+    /// Oldest `created_at` of the tiles still within `max_cache_age`.
+    fn created_since(max_cache_age: u64) -> i64 {
+        match max_cache_age {
+            0 => i64::MIN,
+            age => now_secs().saturating_sub(i64::try_from(age).unwrap_or(i64::MAX)),
+        }
+    }
+
+    /// Unexpired tiles of the layer, over all its files.
+    pub async fn layer_usage(&self, layer_name: &str, max_cache_age: u64) -> AppResult<LayerUsage> {
+        let now = now_secs();
+        let mut usage = LayerUsage::default();
+        for file in self.layer_files(layer_name).await {
+            let pool = self.pool(&file).await?;
+            let (tiles, bytes, oldest, newest): (i64, i64, Option<i64>, Option<i64>) =
+                sqlx::query_as(
+                    "SELECT count(*), coalesce(sum(length(tile_data)), 0),
+                     min(created_at), max(created_at) FROM tiles
+                     WHERE (expires_at IS NULL OR expires_at > ?) AND created_at >= ?",
+                )
+                .bind(now)
+                .bind(Self::created_since(max_cache_age))
+                .fetch_one(&pool)
+                .await?;
+            let age = |created_at: i64| u64::try_from(now - created_at).unwrap_or(0);
+            usage.merge(LayerUsage {
+                tiles: u64::try_from(tiles).unwrap_or(0),
+                bytes: u64::try_from(bytes).unwrap_or(0),
+                oldest_age: oldest.map(age),
+                newest_age: newest.map(age),
+            });
+        }
+        Ok(usage)
+    }
+
+    /// A page of the layer's unexpired tiles, rows flipped back to XYZ.
+    pub async fn list_tiles(
+        &self,
+        layer_name: &str,
+        max_cache_age: u64,
+        page: &TilePage,
+    ) -> AppResult<TileListing> {
+        let now = now_secs();
+        let mut collector = PageCollector::new(page);
+        for file in self.layer_files(layer_name).await {
+            let matrix_set = Self::file_matrix_set(layer_name, &file);
+            let pool = self.pool(&file).await?;
+            let mut rows = sqlx::query(
+                "SELECT zoom_level, tile_column, tile_row, length(tile_data) AS bytes, created_at
+                 FROM tiles WHERE (expires_at IS NULL OR expires_at > ?) AND created_at >= ?
+                 AND (? IS NULL OR zoom_level = ?)",
+            )
+            .bind(now)
+            .bind(Self::created_since(max_cache_age))
+            .bind(page.z.map(i64::from))
+            .bind(page.z.map(i64::from))
+            .fetch(&pool);
+            while let Some(row) = rows.try_next().await? {
+                let z = u32::try_from(row.get::<i64, _>("zoom_level")).unwrap_or(0);
+                let row_y = u32::try_from(row.get::<i64, _>("tile_row")).unwrap_or(0);
+                collector.push(CachedTile {
+                    matrix_set: matrix_set.clone(),
+                    z,
+                    x: u32::try_from(row.get::<i64, _>("tile_column")).unwrap_or(0),
+                    // The TMS flip is its own inverse.
+                    y: match matrix_set {
+                        Some(_) => row_y,
+                        None => xyz_to_tms_y(z, row_y),
+                    },
+                    bytes: u64::try_from(row.get::<i64, _>("bytes")).unwrap_or(0),
+                    age: u64::try_from(now - row.get::<i64, _>("created_at")).ok(),
+                });
+            }
+        }
+        Ok(collector.finish())
+    }
+
+    pub async fn delete_cache(&self, catalog: Catalog) -> AppResult<()> {
+        for layer in catalog.layers.iter() {
+            if layer.delete_cache_on_start.unwrap_or(false) {
+                let key = format!("{}_{}", layer.category.name, layer.name);
+                self.delete_layer_cache(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the layer's rows; the files stay, and their free pages are
+    /// reused by the next tiles written.
+    pub async fn delete_layer_cache(&self, layer_name: &str) -> AppResult<()> {
+        for file in self.layer_files(layer_name).await {
+            let pool = self.pool(&file).await?;
+            let deleted = sqlx::query("DELETE FROM tiles").execute(&pool).await?;
+            tracing::info!("Deleted {} cached tiles from {file}.", deleted.rows_affected());
+        }
+        Ok(())
+    }
+
+    /// Drops the layer's rows inside the spans, in the files that exist.
+    pub async fn delete_tiles(&self, layer_name: &str, spans: &[TileSpan]) -> AppResult<()> {
+        let files = self.layer_files(layer_name).await;
+        let mut deleted = 0;
+        for span in spans {
+            let key = TileKey {
+                layer: layer_name.to_string(),
+                matrix_set: span.matrix_set.clone(),
+                z: span.z,
+                x: span.xmin,
+                y: span.ymin,
+            };
+            let file = Self::file_name(&key);
+            if !files.contains(&file) {
+                continue;
+            }
+            // Flipping to TMS order swaps which row bound is the lowest.
+            let first = Self::tile_row(&key);
+            let last = Self::tile_row(&TileKey { y: span.ymax, ..key });
+            let pool = self.pool(&file).await?;
+            deleted += sqlx::query(
+                "DELETE FROM tiles WHERE zoom_level = ?
+                 AND tile_column BETWEEN ? AND ? AND tile_row BETWEEN ? AND ?",
+            )
+            .bind(span.z as i64)
+            .bind(span.xmin as i64)
+            .bind(span.xmax as i64)
+            .bind(first.min(last))
+            .bind(first.max(last))
+            .execute(&pool)
+            .await?
+            .rows_affected();
+        }
+        tracing::debug!("Deleted {deleted} cached tiles of {layer_name}.");
+        Ok(())
+    }
+
+    /// Cached tile and how long ago it was written. Expired tiles are
+    /// deleted: past their `expires_at` or older than `max_cache_age`.
+    pub async fn get_cache(&self, key: &TileKey, max_cache_age: u64) -> AppResult<(Bytes, Duration)> {
+        let pool = self.pool(&Self::file_name(key)).await?;
+        let row = sqlx::query(
+            "SELECT tile_data, created_at, expires_at FROM tiles
+             WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?",
+        )
+        .bind(key.z as i64)
+        .bind(key.x as i64)
+        .bind(Self::tile_row(key))
+        .fetch_optional(&pool)
+        .await?
+        .ok_or_else(|| AppError::CacheNotFound("Tile not found in cache".to_string()))?;
+
+        let now = now_secs();
+        let age = u64::try_from(now - row.get::<i64, _>("created_at")).unwrap_or(0);
+        let expires_at: Option<i64> = row.get("expires_at");
+        if expires_at.is_some_and(|at| at <= now) || (max_cache_age != 0 && age > max_cache_age) {
+            sqlx::query("DELETE FROM tiles WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?")
+                .bind(key.z as i64)
+                .bind(key.x as i64)
+                .bind(Self::tile_row(key))
+                .execute(&pool)
+                .await?;
+            return Err(AppError::CacheNotFound(
+                "Tile not found in cache or expired".to_string(),
+            ));
+        }
+        Ok((
+            Bytes::from(row.get::<Vec<u8>, _>("tile_data")),
+            Duration::from_secs(age),
+        ))
+    }
+
+    pub async fn write_tile(&self, key: &TileKey, tile: &[u8], max_cache_age: u64) -> AppResult<()> {
+        let pool = self.pool(&Self::file_name(key)).await?;
+        let now = now_secs();
+        let expires_at = (max_cache_age != 0)
+            .then(|| now.saturating_add(i64::try_from(max_cache_age).unwrap_or(i64::MAX)));
+        sqlx::query(
+            "INSERT OR REPLACE INTO tiles
+             (zoom_level, tile_column, tile_row, tile_data, created_at, expires_at)
+             VALUES (?, ?, ?, ?, ?, ?)",
+        )
+        .bind(key.z as i64)
+        .bind(key.x as i64)
+        .bind(Self::tile_row(key))
+        .bind(tile)
+        .bind(now)
+        .bind(expires_at)
+        .execute(&pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Returns the current version counter for a layer.
+    pub async fn get_layer_version(&self, layer_name: &str) -> u64 {
+        self.versions.get_layer_version(layer_name).await
+    }
+
+    /// Increments the version counter for a layer.
+    pub async fn increment_layer_version(&self, layer_name: &str) {
+        self.versions.increment_layer_version(layer_name).await
+    }
+
+    /// Invalidated regions live in the disk cache's `.regions` files too.
+    pub async fn get_regions(&self, layer_name: &str) -> RegionStamps {
+        self.versions.get_regions(layer_name).await
+    }
+
+    pub async fn push_region(
+        &self,
+        layer_name: &str,
+        spans: Vec<TileSpan>,
+    ) -> AppResult<RegionStamps> {
+        self.versions.push_region(layer_name, spans).await
+    }
+
+    pub async fn clear_regions(&self, layer_name: &str) {
+        self.versions.clear_regions(layer_name).await
+    }
+
+    /// Writes a standalone copy of the layer's WebMercatorQuad file to
+    /// `{cache_dir}/.exports/{layer}.mbtiles`, ready to be used offline:
+    /// expired tiles left out, tiles gzip-compressed as MBTiles readers
+    /// expect, and `metadata` (name, bounds, zooms, `json`...) filled in.
+    /// The copy is built in a file of its own and renamed into place, so
+    /// concurrent exports never see each other's half-written files.
+    pub async fn export_layer(
+        &self,
+        layer_name: &str,
+        metadata: &[(&str, String)],
+    ) -> AppResult<PathBuf> {
+        let file = format!("{layer_name}.mbtiles");
+        let opened = self.pools.lock().await.contains_key(&file);
+        if !opened && tokio::fs::metadata(self.cache_dir.join(&file)).await.is_err() {
+            return Err(AppError::NotFound(format!(
+                "layer '{layer_name}' has no cached tiles"
+            )));
+        }
+        let pool = self.pool(&file).await?;
+        let dir = self.cache_dir.join(".exports");
+        tokio::fs::create_dir_all(&dir).await?;
+        let path = dir.join(&file);
+        let partial = dir.join(format!(
+            "{layer_name}.{}.partial",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let built = self.build_export(&pool, &partial, metadata).await;
+        let renamed = match built {
+            Ok(()) => tokio::fs::rename(&partial, &path).await.map_err(AppError::from),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = renamed {
+            let _ = tokio::fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        Ok(path)
+    }
+
+    /// Fills `target` with the export of the cache file behind `pool`.
+    async fn build_export(
+        &self,
+        pool: &SqlitePool,
+        target: &Path,
+        metadata: &[(&str, String)],
+    ) -> AppResult<()> {
+        let target_str = target
+            .to_str()
+            .ok_or_else(|| AppError::InternalServerError("invalid export path".to_string()))?;
+        sqlx::query("VACUUM INTO ?").bind(target_str).execute(pool).await?;
+
+        let options = SqliteConnectOptions::new()
+            .filename(target)
+            .journal_mode(SqliteJournalMode::Delete);
+        let export = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+        sqlx::query("DELETE FROM tiles WHERE expires_at IS NOT NULL AND expires_at <= ?")
+            .bind(now_secs())
+            .execute(&export)
+            .await?;
+        if self.encoding != TileEncoding::Gzip {
+            // Re-encoded in rowid order, a batch at a time, so large caches
+            // are never held in memory.
+            let mut last_rowid = 0i64;
+            loop {
+                let rows = sqlx::query(
+                    "SELECT rowid, tile_data FROM tiles WHERE rowid > ? ORDER BY rowid LIMIT ?",
+                )
+                .bind(last_rowid)
+                .bind(EXPORT_BATCH_SIZE)
+                .fetch_all(&export)
+                .await?;
+                let Some(last) = rows.last() else {
+                    break;
+                };
+                last_rowid = last.get::<i64, _>("rowid");
+                let mut tx = export.begin().await?;
+                for row in rows {
+                    let data = Bytes::from(row.get::<Vec<u8>, _>("tile_data"));
+                    let gzip = TileEncoding::Gzip.encode(&self.encoding.decode(data)?)?;
+                    sqlx::query("UPDATE tiles SET tile_data = ? WHERE rowid = ?")
+                        .bind(gzip.as_ref())
+                        .bind(row.get::<i64, _>("rowid"))
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                tx.commit().await?;
+            }
+        }
+        sqlx::query("DELETE FROM metadata WHERE name = ?")
+            .bind(ENCODING_METADATA)
+            .execute(&export)
+            .await?;
+        for (name, value) in metadata {
+            sqlx::query("INSERT OR REPLACE INTO metadata (name, value) VALUES (?, ?)")
+                .bind(name)
+                .bind(value)
+                .execute(&export)
+                .await?;
+        }
+        sqlx::query("VACUUM").execute(&export).await?;
+        export.close().await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(encoding: TileEncoding) -> (MbtilesCache, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "mvt-rs-test-mbtiles-{}",
+            uuid::Uuid::new_v4().simple()
+        ));
+        (MbtilesCache::new(dir.clone(), encoding), dir)
+    }
+
+    #[tokio::test]
+    async fn tiles_are_rows_in_tms_order_with_expiry() {
+        let (cache, dir) = cache(TileEncoding::Identity);
+        let key = TileKey::new("cat_roads", 3, 2, 1);
+        cache.write_tile(&key, b"tile", 60).await.unwrap();
+
+        let (tile, _) = cache.get_cache(&key, 60).await.unwrap();
+        assert_eq!(tile, Bytes::from_static(b"tile"));
+
+        let pool = cache.pool("cat_roads.mbtiles").await.unwrap();
+        let (row, expires_at): (i64, Option<i64>) =
+            sqlx::query_as("SELECT tile_row, expires_at FROM tiles WHERE zoom_level = 3")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!(row, 6);
+        assert!(expires_at.is_some());
+        let journal: String = sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&pool).await.unwrap();
+        assert_eq!(journal, "wal");
+
+        // Past its expiry: deleted on read.
+        sqlx::query("UPDATE tiles SET expires_at = 1").execute(&pool).await.unwrap();
+        assert!(cache.get_cache(&key, 60).await.is_err());
+        let count: i64 = sqlx::query_scalar("SELECT count(*) FROM tiles").fetch_one(&pool).await.unwrap();
+        assert_eq!(count, 0);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn layer_invalidation_drops_rows_of_every_matrix_set() {
+        let (cache, dir) = cache(TileEncoding::Identity);
+        let key = TileKey::new("cat_roads", 3, 2, 1);
+        let crs84 = key.clone().with_matrix_set("WorldCRS84Quad");
+        let other = TileKey::new("cat_roads2", 3, 2, 1);
+        for key in [&key, &crs84, &other] {
+            cache.write_tile(key, b"tile", 0).await.unwrap();
+        }
+        assert!(dir.join("cat_roads@WorldCRS84Quad.mbtiles").exists());
+
+        cache.delete_layer_cache("cat_roads").await.unwrap();
+        assert!(cache.get_cache(&key, 0).await.is_err());
+        assert!(cache.get_cache(&crs84, 0).await.is_err());
+        assert!(cache.get_cache(&other, 0).await.is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn region_invalidation_drops_only_rows_inside_the_spans() {
+        let (cache, dir) = cache(TileEncoding::Identity);
+        let inside = TileKey::new("cat_roads", 3, 2, 1);
+        let below = TileKey::new("cat_roads", 3, 2, 3);
+        let crs84 = TileKey::new("cat_roads", 3, 2, 1).with_matrix_set("WorldCRS84Quad");
+        for key in [&inside, &below, &crs84] {
+            cache.write_tile(key, b"tile", 0).await.unwrap();
+        }
+
+        let span = TileSpan {
+            matrix_set: None,
+            z: 3,
+            xmin: 1,
+            xmax: 2,
+            ymin: 0,
+            ymax: 2,
+        };
+        cache.delete_tiles("cat_roads", &[span]).await.unwrap();
+        assert!(cache.get_cache(&inside, 0).await.is_err());
+        assert!(cache.get_cache(&below, 0).await.is_ok());
+        assert!(cache.get_cache(&crs84, 0).await.is_ok());
+        // No file is created for matrix sets the layer has no tiles in.
+        let span = TileSpan {
+            matrix_set: Some("Custom".to_string()),
+            z: 3,
+            xmin: 0,
+            xmax: 7,
+            ymin: 0,
+            ymax: 7,
+        };
+        cache.delete_tiles("cat_roads", &[span]).await.unwrap();
+        assert!(!dir.join("cat_roads@Custom.mbtiles").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn stats_and_listings_cover_every_file_of_the_layer() {
+        let (cache, dir) = cache(TileEncoding::Identity);
+        cache.write_tile(&TileKey::new("cat_roads", 3, 2, 1), b"tile", 0).await.unwrap();
+        cache.write_tile(&TileKey::new("cat_roads", 4, 0, 0), b"tile-4", 60).await.unwrap();
+        let crs84 = TileKey::new("cat_roads", 3, 2, 1).with_matrix_set("WorldCRS84Quad");
+        cache.write_tile(&crs84, b"t", 0).await.unwrap();
+        cache.write_tile(&TileKey::new("cat_roads2", 3, 2, 1), b"other", 0).await.unwrap();
+
+        let usage = cache.layer_usage("cat_roads", 0).await.unwrap();
+        assert_eq!((usage.tiles, usage.bytes), (3, 11));
+        assert!(usage.oldest_age.is_some());
+
+        let page = TilePage {
+            z: Some(3),
+            offset: 0,
+            limit: 10,
+        };
+        let listing = cache.list_tiles("cat_roads", 0, &page).await.unwrap();
+        assert_eq!(listing.total, 2);
+        let tiles: Vec<_> = listing
+            .tiles
+            .iter()
+            .map(|tile| (tile.matrix_set.as_deref(), tile.z, tile.x, tile.y, tile.bytes))
+            .collect();
+        assert_eq!(tiles, [(None, 3, 2, 1, 4), (Some("WorldCRS84Quad"), 3, 2, 1, 1)]);
+
+        // Expired tiles are not counted.
+        let pool = cache.pool("cat_roads.mbtiles").await.unwrap();
+        sqlx::query("UPDATE tiles SET expires_at = 1 WHERE zoom_level = 4")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(cache.layer_usage("cat_roads", 0).await.unwrap().tiles, 2);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn export_is_a_gzip_mbtiles_with_metadata() {
+        let (cache, dir) = cache(TileEncoding::Brotli);
+        let key = TileKey::new("cat_roads", 1, 0, 0);
+        let stored = TileEncoding::Brotli.encode(b"tile").unwrap();
+        cache.write_tile(&key, &stored, 0).await.unwrap();
+        cache.write_tile(&TileKey::new("cat_roads", 1, 1, 0), &stored, 60).await.unwrap();
+        let pool = cache.pool("cat_roads.mbtiles").await.unwrap();
+        sqlx::query("UPDATE tiles SET expires_at = 1 WHERE tile_column = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let path = cache
+            .export_layer("cat_roads", &[("minzoom", "0".to_string()), ("json", "{}".to_string())])
+            .await
+            .unwrap();
+        let export = SqlitePool::connect(&format!("sqlite:{}", path.display())).await.unwrap();
+        let tiles: Vec<(i64, i64, i64, Vec<u8>)> =
+            sqlx::query_as("SELECT zoom_level, tile_column, tile_row, tile_data FROM tiles")
+                .fetch_all(&export)
+                .await
+                .unwrap();
+        assert_eq!(tiles.len(), 1);
+        let (z, x, row, data) = &tiles[0];
+        assert_eq!((*z, *x, *row), (1, 0, 1));
+        let data = TileEncoding::Gzip.decode(Bytes::from(data.clone())).unwrap();
+        assert_eq!(data, Bytes::from_static(b"tile"));
+
+        let metadata: Vec<(String, String)> =
+            sqlx::query_as("SELECT name, value FROM metadata ORDER BY name")
+                .fetch_all(&export)
+                .await
+                .unwrap();
+        let names: Vec<&str> = metadata.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["format", "json", "minzoom", "name"]);
+        export.close().await;
+
+        let exports: Vec<_> = std::fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(exports, ["cat_roads.mbtiles"]);
+        assert!(matches!(
+            cache.export_layer("cat_lakes", &[]).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(!dir.join("cat_lakes.mbtiles").exists());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,230 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use super::region::TileVersion;
+use super::stats::LayerUsage;
+use crate::monitor::metrics::set_memory_cache_bytes;
+
+/// Least-recently-used tiles kept in process memory, bounded by their total
+/// size in bytes. Sits in front of the disk and Redis caches.
+#[derive(Debug, Clone)]
+pub struct MemoryCache {
+    inner: Arc<Mutex<MemoryTier>>,
+}
+
+#[derive(Debug)]
+struct MemoryTier {
+    capacity: usize,
+    size: usize,
+    /// Bumped on every access; orders `recency` from least to most recent.
+    tick: u64,
+    entries: HashMap<String, Entry>,
+    recency: BTreeMap<u64, String>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Bytes,
+    /// When the tile was cached in the backend it came from, so the tier
+    /// never outlives the layer's `max_cache_age`.
+    stored_at: Instant,
+    /// Version the tile was cached under.
+    version: TileVersion,
+    tick: u64,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        MemoryCache {
+            inner: Arc::new(Mutex::new(MemoryTier {
+                capacity,
+                size: 0,
+                tick: 0,
+                entries: HashMap::new(),
+                recency: BTreeMap::new(),
+            })),
+        }
+    }
+
+    fn tier(&self) -> std::sync::MutexGuard<'_, MemoryTier> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Cached tile, unless it expired or was cached under another version.
+    pub fn get(&self, key: &str, version: TileVersion, max_cache_age: u64) -> Option<Bytes> {
+        let mut tier = self.tier();
+        let entry = tier.entries.get(key)?;
+        let expired = max_cache_age != 0
+            && entry.stored_at.elapsed() > Duration::from_secs(max_cache_age);
+        if expired || entry.version != version {
+            tier.remove(key);
+            set_memory_cache_bytes(tier.size);
+            return None;
+        }
+        let data = entry.data.clone();
+        tier.touch(key);
+        Some(data)
+    }
+
+    /// Stores a tile that has been cached for `age` already, evicting the
+    /// least recently used tiles to make room. Tiles larger than the whole
+    /// tier are not kept.
+    pub fn insert(&self, key: String, data: Bytes, version: TileVersion, age: Duration) {
+        let mut tier = self.tier();
+        if data.len() > tier.capacity {
+            return;
+        }
+        tier.remove(&key);
+        while tier.size + data.len() > tier.capacity {
+            let Some((_, oldest)) = tier.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = tier.entries.remove(&oldest) {
+                tier.size -= entry.data.len();
+            }
+        }
+        tier.tick += 1;
+        let tick = tier.tick;
+        tier.size += data.len();
+        tier.recency.insert(tick, key.clone());
+        let stored_at = Instant::now().checked_sub(age).unwrap_or_else(Instant::now);
+        tier.entries.insert(
+            key,
+            Entry {
+                data,
+                stored_at,
+                version,
+                tick,
+            },
+        );
+        set_memory_cache_bytes(tier.size);
+    }
+
+    /// Drops one tile.
+    pub fn remove(&self, key: &str) {
+        let mut tier = self.tier();
+        tier.remove(key);
+        set_memory_cache_bytes(tier.size);
+    }
+
+    /// Drops every tile of a layer (`{category}_{name}`).
+    pub fn remove_layer(&self, layer_name: &str) {
+        let prefix = format!("{layer_name}:");
+        let mut tier = self.tier();
+        let keys: Vec<String> = tier
+            .entries
+            .keys()
+            .filter(|key| key.starts_with(&prefix))
+            .cloned()
+            .collect();
+        for key in keys {
+            tier.remove(&key);
+        }
+        set_memory_cache_bytes(tier.size);
+    }
+
+    /// Tiles of a layer (`{category}_{name}`) held in memory.
+    pub fn layer_usage(&self, layer_name: &str) -> LayerUsage {
+        let prefix = format!("{layer_name}:");
+        let tier = self.tier();
+        let mut usage = LayerUsage::default();
+        for (_, entry) in tier.entries.iter().filter(|(key, _)| key.starts_with(&prefix)) {
+            usage.add(entry.data.len() as u64, Some(entry.stored_at.elapsed().as_secs()));
+        }
+        usage
+    }
+
+    /// Total size of the cached tiles in bytes.
+    #[cfg(test)]
+    fn size(&self) -> usize {
+        self.tier().size
+    }
+}
+
+impl MemoryTier {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.size -= entry.data.len();
+            self.recency.remove(&entry.tick);
+        }
+    }
+
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recency.remove(&entry.tick);
+            entry.tick = tick;
+            self.recency.insert(tick, key.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(len: usize) -> Bytes {
+        Bytes::from(vec![0u8; len])
+    }
+
+    fn version(layer: u64) -> TileVersion {
+        TileVersion { layer, region: 0 }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_within_the_byte_limit() {
+        let cache = MemoryCache::new(30);
+        cache.insert("l:0:0:0".into(), tile(10), version(0), Duration::ZERO);
+        cache.insert("l:1:0:0".into(), tile(10), version(0), Duration::ZERO);
+        cache.insert("l:1:1:0".into(), tile(10), version(0), Duration::ZERO);
+        // Reading the first tile makes the second the least recently used.
+        assert!(cache.get("l:0:0:0", version(0), 0).is_some());
+
+        cache.insert("l:1:1:1".into(), tile(10), version(0), Duration::ZERO);
+        assert!(cache.get("l:1:0:0", version(0), 0).is_none());
+        assert!(cache.get("l:0:0:0", version(0), 0).is_some());
+        assert!(cache.get("l:1:1:1", version(0), 0).is_some());
+        assert_eq!(cache.size(), 30);
+
+        cache.insert("l:2:0:0".into(), tile(31), version(0), Duration::ZERO);
+        assert!(cache.get("l:2:0:0", version(0), 0).is_none());
+        assert_eq!(cache.size(), 30);
+    }
+
+    #[test]
+    fn respects_max_cache_age_and_layer_version() {
+        let cache = MemoryCache::new(100);
+        // Cached in the backend two minutes ago.
+        cache.insert("l:0:0:0".into(), tile(1), version(3), Duration::from_secs(120));
+        assert!(cache.get("l:0:0:0", version(3), 0).is_some());
+        assert!(cache.get("l:0:0:0", version(3), 300).is_some());
+        assert!(cache.get("l:0:0:0", version(3), 60).is_none());
+        assert_eq!(cache.size(), 0);
+
+        cache.insert("l:0:0:0".into(), tile(1), version(3), Duration::ZERO);
+        assert!(cache.get("l:0:0:0", version(4), 0).is_none());
+
+        // A region invalidated over the tile is a new version too.
+        cache.insert("l:0:0:0".into(), tile(1), version(4), Duration::ZERO);
+        let stamped = TileVersion { layer: 4, region: 1 };
+        assert!(cache.get("l:0:0:0", stamped, 0).is_none());
+    }
+
+    #[test]
+    fn removes_only_the_given_layer() {
+        let cache = MemoryCache::new(100);
+        cache.insert("cat_roads:0:0:0".into(), tile(5), version(0), Duration::ZERO);
+        cache.insert("cat_roads:WorldCRS84Quad:0:0:0".into(), tile(5), version(0), Duration::ZERO);
+        cache.insert("cat_roads2:0:0:0".into(), tile(5), version(0), Duration::ZERO);
+
+        cache.remove_layer("cat_roads");
+        assert!(cache.get("cat_roads:0:0:0", version(0), 0).is_none());
+        assert!(cache.get("cat_roads:WorldCRS84Quad:0:0:0", version(0), 0).is_none());
+        assert!(cache.get("cat_roads2:0:0:0", version(0), 0).is_some());
+        assert_eq!(cache.size(), 5);
+    }
+}
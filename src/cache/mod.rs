@@ -1,3 +1,10 @@
 pub mod cachewrapper;
 mod disk;
+pub mod encoding;
+pub mod key;
+mod mbtiles;
+mod memory;
+pub mod objectstore;
 mod redis;
+pub mod region;
+pub mod stats;
@@ -0,0 +1,622 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use regex::Regex;
+use object_store::{
+    Attribute, Attributes, ObjectStore, ObjectStoreExt, PutMode, PutOptions, PutPayload,
+    UpdateVersion, aws::AmazonS3Builder, path::Path,
+};
+
+use super::encoding::TileEncoding;
+use super::key::TileKey;
+use super::region::{RegionStamps, TileSpan};
+use super::stats::{
+    CachedTile, LayerUsage, PageCollector, TileListing, TilePage, expired, now_secs,
+};
+use crate::{
+    Catalog,
+    config::settings::ObjectStoreConfig,
+    error::{AppError, AppResult},
+    models::tile_matrix_set::WEB_MERCATOR_QUAD,
+};
+
+const MVT_CONTENT_TYPE: &str = "application/vnd.mapbox-vector-tile";
+/// User metadata holding the unix time a tile expires at.
+const EXPIRES_METADATA: &str = "expires";
+/// How long a layer version read from the bucket is trusted before it is
+/// read again. Shorter than the cluster invalidation delay, so peers see a
+/// bumped version before the tiles are deleted.
+const VERSION_REFRESH: Duration = Duration::from_secs(2);
+
+/// Where tiles go in the bucket, e.g. `tiles/{layer}/{tms}/{z}/{x}/{y}.pbf`.
+/// `{layer}` is a whole path segment with only fixed text before it, so
+/// every tile of a layer shares one prefix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyLayout {
+    template: String,
+}
+
+impl KeyLayout {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let template = template.trim().trim_matches('/');
+        for placeholder in ["{layer}", "{tms}", "{z}", "{x}", "{y}"] {
+            if template.matches(placeholder).count() != 1 {
+                return Err(format!("the key layout must contain {placeholder} once"));
+            }
+        }
+        let start = template.find("{layer}").unwrap_or_default();
+        let before = &template[..start];
+        if before.contains('{') || !(before.is_empty() || before.ends_with('/')) {
+            return Err("{layer} must be the first placeholder and a whole path segment".into());
+        }
+        if !template[start + "{layer}".len()..].starts_with('/') {
+            return Err("{layer} must be followed by '/'".into());
+        }
+        Ok(KeyLayout {
+            template: template.to_string(),
+        })
+    }
+
+    /// Object key of a tile, ending in the storage encoding's suffix.
+    pub fn tile_path(&self, key: &TileKey, suffix: &str) -> Path {
+        let key_path = self
+            .template
+            .replace("{layer}", &key.layer)
+            .replace("{tms}", key.matrix_set.as_deref().unwrap_or(WEB_MERCATOR_QUAD))
+            .replace("{z}", &key.z.to_string())
+            .replace("{x}", &key.x.to_string())
+            .replace("{y}", &key.y.to_string());
+        Path::from(format!("{key_path}{suffix}"))
+    }
+
+    /// Prefix shared by every tile of a layer.
+    pub fn layer_prefix(&self, layer_name: &str) -> Path {
+        let start = self.template.find("{layer}").unwrap_or_default();
+        Path::from(format!("{}{layer_name}", &self.template[..start]))
+    }
+
+    /// Pattern of the layer's tile keys ending in `suffix`, capturing `tms`,
+    /// `z`, `x` and `y`; the inverse of `tile_path`.
+    pub fn tile_matcher(&self, layer_name: &str, suffix: &str) -> Regex {
+        let mut pattern = String::from("^");
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            pattern.push_str(&regex::escape(&rest[..start]));
+            let end = rest[start..].find('}').map_or(rest.len(), |end| start + end + 1);
+            match &rest[start..end] {
+                "{layer}" => pattern.push_str(&regex::escape(layer_name)),
+                "{tms}" => pattern.push_str("(?P<tms>[^/]+)"),
+                "{z}" => pattern.push_str(r"(?P<z>\d+)"),
+                "{x}" => pattern.push_str(r"(?P<x>\d+)"),
+                "{y}" => pattern.push_str(r"(?P<y>\d+)"),
+                other => pattern.push_str(&regex::escape(other)),
+            }
+            rest = &rest[end..];
+        }
+        pattern.push_str(&regex::escape(rest));
+        pattern.push_str(&regex::escape(suffix));
+        pattern.push('$');
+        Regex::new(&pattern).expect("escaped tile key pattern")
+    }
+}
+
+/// Tiles stored in an S3-compatible bucket, shared by every instance.
+#[derive(Debug, Clone)]
+pub struct ObjectStoreCache {
+    store: Arc<dyn ObjectStore>,
+    layout: KeyLayout,
+    /// Layer versions last read from the bucket, and when.
+    versions: Arc<Mutex<HashMap<String, (u64, Instant)>>>,
+}
+
+impl ObjectStoreCache {
+    pub fn new(config: &ObjectStoreConfig) -> AppResult<Self> {
+        let mut builder = AmazonS3Builder::from_env()
+            .with_bucket_name(&config.bucket)
+            .with_allow_http(config.allow_http);
+        if let Some(endpoint) = &config.endpoint {
+            // MinIO and most S3-compatible servers expect path-style requests.
+            builder = builder
+                .with_endpoint(endpoint)
+                .with_virtual_hosted_style_request(false);
+        }
+        if let Some(region) = &config.region {
+            builder = builder.with_region(region);
+        }
+        if let Some(access_key_id) = &config.access_key_id {
+            builder = builder.with_access_key_id(access_key_id);
+        }
+        if let Some(secret_access_key) = &config.secret_access_key {
+            builder = builder.with_secret_access_key(secret_access_key);
+        }
+        let layout = KeyLayout::parse(&config.key_layout).map_err(AppError::ConfigurationError)?;
+        Ok(Self::with_store(Arc::new(builder.build()?), layout))
+    }
+
+    pub fn with_store(store: Arc<dyn ObjectStore>, layout: KeyLayout) -> Self {
+        ObjectStoreCache {
+            store,
+            layout,
+            versions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn tile_path(&self, key: &TileKey, suffix: &str) -> Path {
+        self.layout.tile_path(key, suffix)
+    }
+
+    pub async fn delete_cache(&self, catalog: Catalog) -> AppResult<()> {
+        for layer in catalog.layers.iter() {
+            if layer.delete_cache_on_start.unwrap_or(false) {
+                let key = format!("{}_{}", layer.category.name, layer.name);
+                self.delete_layer_cache(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Deletes every object under the layer's prefix, in batches.
+    pub async fn delete_layer_cache(&self, layer_name: &str) -> AppResult<()> {
+        let prefix = self.layout.layer_prefix(layer_name);
+        let locations = self
+            .store
+            .list(Some(&prefix))
+            .map_ok(|meta| meta.location)
+            .boxed();
+        let deleted = self.store.delete_stream(locations).try_collect::<Vec<_>>().await?;
+        tracing::info!("Deleted {} cached tiles under {prefix}.", deleted.len());
+        Ok(())
+    }
+
+    /// Calls `visit` with every unexpired tile under the layer's prefix
+    /// stored with `suffix`, aged by the objects' last modification.
+    async fn list_objects(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+        mut visit: impl FnMut(CachedTile),
+    ) -> AppResult<()> {
+        let matcher = self.layout.tile_matcher(layer_name, suffix);
+        let prefix = self.layout.layer_prefix(layer_name);
+        let now = now_secs();
+        let mut objects = self.store.list(Some(&prefix));
+        while let Some(meta) = objects.try_next().await? {
+            let Some(captures) = matcher.captures(meta.location.as_ref()) else {
+                continue;
+            };
+            let number = |name: &str| captures[name].parse::<u32>().ok();
+            let (Some(z), Some(x), Some(y)) = (number("z"), number("x"), number("y")) else {
+                continue;
+            };
+            let modified = u64::try_from(meta.last_modified.timestamp()).unwrap_or_default();
+            let age = now.saturating_sub(modified);
+            if expired(age, max_cache_age) {
+                continue;
+            }
+            let tms = &captures["tms"];
+            visit(CachedTile {
+                matrix_set: (tms != WEB_MERCATOR_QUAD).then(|| tms.to_string()),
+                z,
+                x,
+                y,
+                bytes: meta.size,
+                age: Some(age),
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn layer_usage(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+    ) -> AppResult<LayerUsage> {
+        let mut usage = LayerUsage::default();
+        self.list_objects(layer_name, suffix, max_cache_age, |tile| {
+            usage.add(tile.bytes, tile.age)
+        })
+        .await?;
+        Ok(usage)
+    }
+
+    pub async fn list_tiles(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+        page: &TilePage,
+    ) -> AppResult<TileListing> {
+        let mut collector = PageCollector::new(page);
+        self.list_objects(layer_name, suffix, max_cache_age, |tile| {
+            collector.push(tile)
+        })
+        .await?;
+        Ok(collector.finish())
+    }
+
+    /// Deletes the layer's tiles inside the spans, in batches; objects that
+    /// were never written are skipped.
+    pub async fn delete_tiles(
+        &self,
+        layer_name: &str,
+        spans: &[TileSpan],
+        suffix: &str,
+    ) -> AppResult<()> {
+        let paths: Vec<_> = spans
+            .iter()
+            .flat_map(|span| span.keys(layer_name))
+            .map(|key| Ok(self.tile_path(&key, suffix)))
+            .collect();
+        let mut results = self.store.delete_stream(futures::stream::iter(paths).boxed());
+        let mut deleted = 0;
+        while let Some(result) = results.next().await {
+            match result {
+                Ok(_) => deleted += 1,
+                Err(object_store::Error::NotFound { .. }) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        tracing::debug!("Deleted {deleted} cached tiles of {layer_name}.");
+        Ok(())
+    }
+
+    /// Cached tile and how long ago it was written. Expired tiles are
+    /// deleted: past their `expires` metadata or older than `max_cache_age`.
+    pub async fn get_cache(&self, path: &Path, max_cache_age: u64) -> AppResult<(Bytes, Duration)> {
+        let result = self.store.get(path).await?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let modified = u64::try_from(result.meta.last_modified.timestamp()).unwrap_or_default();
+        let age = now.saturating_sub(modified);
+        let expires_at = result
+            .attributes
+            .get(&Attribute::Metadata(EXPIRES_METADATA.into()))
+            .and_then(|value| value.parse::<u64>().ok());
+
+        if expires_at.is_some_and(|at| at <= now) || (max_cache_age != 0 && age > max_cache_age) {
+            self.store.delete(path).await?;
+            return Err(AppError::CacheNotFound(
+                "Tile not found in cache or expired".to_string(),
+            ));
+        }
+        Ok((result.bytes().await?, Duration::from_secs(age)))
+    }
+
+    pub async fn write_tile(
+        &self,
+        path: &Path,
+        tile: &[u8],
+        encoding: TileEncoding,
+        max_cache_age: u64,
+    ) -> AppResult<()> {
+        let mut attributes = Attributes::new();
+        attributes.insert(Attribute::ContentType, MVT_CONTENT_TYPE.into());
+        if let Some(content_encoding) = encoding.content_encoding() {
+            attributes.insert(Attribute::ContentEncoding, content_encoding.into());
+        }
+        if max_cache_age != 0 {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+            let expires_at = now.as_secs() + max_cache_age;
+            attributes.insert(
+                Attribute::Metadata(EXPIRES_METADATA.into()),
+                expires_at.to_string().into(),
+            );
+            attributes.insert(Attribute::CacheControl, format!("max-age={max_cache_age}").into());
+        }
+        let options = PutOptions {
+            attributes,
+            ..Default::default()
+        };
+        self.store
+            .put_opts(path, PutPayload::from(Bytes::copy_from_slice(tile)), options)
+            .await?;
+        Ok(())
+    }
+
+    /// `.versions/{layer_name}` — outside the layer prefix, so it survives
+    /// the deletion of the layer's tiles.
+    fn version_path(layer_name: &str) -> Path {
+        Path::from(format!(".versions/{layer_name}"))
+    }
+
+    /// Returns the current version counter for a layer.
+    pub async fn get_layer_version(&self, layer_name: &str) -> u64 {
+        let cached = self
+            .versions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(layer_name)
+            .copied();
+        if let Some((version, read_at)) = cached
+            && read_at.elapsed() < VERSION_REFRESH
+        {
+            return version;
+        }
+        let version = self.read_layer_version(layer_name).await;
+        self.remember_version(layer_name, version);
+        version
+    }
+
+    /// Increments the version counter for a layer.
+    pub async fn increment_layer_version(&self, layer_name: &str) {
+        match self.bump_layer_version(layer_name).await {
+            Ok(version) => self.remember_version(layer_name, version),
+            Err(e) => tracing::warn!("Failed to write version for layer {layer_name}: {e}"),
+        }
+    }
+
+    /// Writes the next version with a conditional put, retried when another
+    /// instance bumped it meanwhile, so concurrent invalidations are never
+    /// folded into one. Stores without conditional puts get a plain
+    /// overwrite, and may lose one of two simultaneous bumps.
+    async fn bump_layer_version(&self, layer_name: &str) -> AppResult<u64> {
+        let path = Self::version_path(layer_name);
+        loop {
+            let (current, mode) = match self.store.get(&path).await {
+                Ok(result) => {
+                    let version = UpdateVersion {
+                        e_tag: result.meta.e_tag.clone(),
+                        version: result.meta.version.clone(),
+                    };
+                    let bytes = result.bytes().await?;
+                    let current = String::from_utf8_lossy(&bytes).trim().parse().unwrap_or(0);
+                    (current, PutMode::Update(version))
+                }
+                Err(object_store::Error::NotFound { .. }) => (0, PutMode::Create),
+                Err(e) => return Err(e.into()),
+            };
+            let version: u64 = current + 1;
+            let payload = PutPayload::from(version.to_string());
+            match self.store.put_opts(&path, payload.clone(), mode.into()).await {
+                Ok(_) => return Ok(version),
+                Err(
+                    object_store::Error::Precondition { .. }
+                    | object_store::Error::AlreadyExists { .. },
+                ) => continue,
+                Err(object_store::Error::NotImplemented { .. }) => {
+                    self.store.put(&path, payload).await?;
+                    return Ok(version);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn read_layer_version(&self, layer_name: &str) -> u64 {
+        match self.store.get(&Self::version_path(layer_name)).await {
+            Ok(result) => match result.bytes().await {
+                Ok(bytes) => String::from_utf8_lossy(&bytes).trim().parse().unwrap_or(0),
+                Err(_) => 0,
+            },
+            Err(_) => 0,
+        }
+    }
+
+    /// `.regions/{layer_name}`, beside the versions.
+    fn regions_path(layer_name: &str) -> Path {
+        Path::from(format!(".regions/{layer_name}"))
+    }
+
+    /// Regions invalidated over a layer since its version was last bumped.
+    pub async fn get_regions(&self, layer_name: &str) -> RegionStamps {
+        match self.store.get(&Self::regions_path(layer_name)).await {
+            Ok(result) => match result.bytes().await {
+                Ok(json) => RegionStamps::from_json(&json),
+                Err(_) => RegionStamps::default(),
+            },
+            Err(_) => RegionStamps::default(),
+        }
+    }
+
+    /// Records a region with a conditional put, retried when another
+    /// instance changed the regions meanwhile. Stores without conditional
+    /// puts get a plain overwrite.
+    pub async fn push_region(
+        &self,
+        layer_name: &str,
+        spans: Vec<TileSpan>,
+    ) -> AppResult<RegionStamps> {
+        let path = Self::regions_path(layer_name);
+        loop {
+            let (mut regions, mode) = match self.store.get(&path).await {
+                Ok(result) => {
+                    let version = UpdateVersion {
+                        e_tag: result.meta.e_tag.clone(),
+                        version: result.meta.version.clone(),
+                    };
+                    (RegionStamps::from_json(&result.bytes().await?), PutMode::Update(version))
+                }
+                Err(object_store::Error::NotFound { .. }) => {
+                    (RegionStamps::default(), PutMode::Create)
+                }
+                Err(e) => return Err(e.into()),
+            };
+            regions.push(spans.clone());
+            let payload = PutPayload::from(regions.to_json());
+            match self.store.put_opts(&path, payload.clone(), mode.into()).await {
+                Ok(_) => return Ok(regions),
+                Err(
+                    object_store::Error::Precondition { .. }
+                    | object_store::Error::AlreadyExists { .. },
+                ) => continue,
+                Err(object_store::Error::NotImplemented { .. }) => {
+                    self.store.put(&path, payload).await?;
+                    return Ok(regions);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub async fn clear_regions(&self, layer_name: &str) {
+        if let Err(e) = self.store.delete(&Self::regions_path(layer_name)).await
+            && !matches!(e, object_store::Error::NotFound { .. })
+        {
+            tracing::warn!("Failed to clear invalidated regions of {layer_name}: {e}");
+        }
+    }
+
+    fn remember_version(&self, layer_name: &str, version: u64) {
+        self.versions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(layer_name.to_string(), (version, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn cache() -> ObjectStoreCache {
+        let layout = KeyLayout::parse("tiles/{layer}/{tms}/{z}/{x}/{y}.pbf").unwrap();
+        ObjectStoreCache::with_store(Arc::new(InMemory::new()), layout)
+    }
+
+    #[test]
+    fn key_layout_is_checked() {
+        let layout = KeyLayout::parse("/tiles/{layer}/{tms}/{z}/{x}/{y}.pbf").unwrap();
+        let key = TileKey::new("cat_roads", 3, 2, 1);
+        assert_eq!(layout.tile_path(&key, ".gz").as_ref(), "tiles/cat_roads/WebMercatorQuad/3/2/1.pbf.gz");
+        let key = key.with_matrix_set("WorldCRS84Quad");
+        assert_eq!(layout.tile_path(&key, "").as_ref(), "tiles/cat_roads/WorldCRS84Quad/3/2/1.pbf");
+        assert_eq!(layout.layer_prefix("cat_roads").as_ref(), "tiles/cat_roads");
+
+        let matcher = layout.tile_matcher("cat_roads", ".gz");
+        let captures = matcher.captures("tiles/cat_roads/WorldCRS84Quad/3/2/1.pbf.gz").unwrap();
+        let parts = (&captures["tms"], &captures["z"], &captures["x"], &captures["y"]);
+        assert_eq!(parts, ("WorldCRS84Quad", "3", "2", "1"));
+        assert!(!matcher.is_match("tiles/cat_roads/WorldCRS84Quad/3/2/1.pbf"));
+        assert!(!matcher.is_match("tiles/cat_roads2/WebMercatorQuad/3/2/1.pbf.gz"));
+
+        assert!(KeyLayout::parse("{layer}/{z}/{x}/{y}.pbf").is_err());
+        assert!(KeyLayout::parse("{tms}/{layer}/{z}/{x}/{y}.pbf").is_err());
+        assert!(KeyLayout::parse("tiles-{layer}/{tms}/{z}/{x}/{y}.pbf").is_err());
+        assert!(KeyLayout::parse("{layer}-{tms}/{z}/{x}/{y}.pbf").is_err());
+    }
+
+    #[tokio::test]
+    async fn tiles_carry_encoding_and_expiry_metadata() {
+        let cache = cache();
+        let path = cache.tile_path(&TileKey::new("cat_roads", 3, 2, 1), ".gz");
+        cache.write_tile(&path, b"tile", TileEncoding::Gzip, 600).await.unwrap();
+
+        let stored = cache.store.get(&path).await.unwrap();
+        assert_eq!(stored.attributes.get(&Attribute::ContentType).unwrap().as_ref(), MVT_CONTENT_TYPE);
+        assert_eq!(stored.attributes.get(&Attribute::ContentEncoding).unwrap().as_ref(), "gzip");
+        assert!(stored.attributes.get(&Attribute::Metadata(EXPIRES_METADATA.into())).is_some());
+
+        let (tile, _) = cache.get_cache(&path, 600).await.unwrap();
+        assert_eq!(tile, Bytes::from_static(b"tile"));
+
+        // Already expired by its metadata: deleted on read.
+        cache.write_tile(&path, b"tile", TileEncoding::Gzip, 0).await.unwrap();
+        let mut attributes = Attributes::new();
+        attributes.insert(Attribute::Metadata(EXPIRES_METADATA.into()), "1".into());
+        let options = PutOptions { attributes, ..Default::default() };
+        cache.store.put_opts(&path, PutPayload::from_static(b"old"), options).await.unwrap();
+        assert!(cache.get_cache(&path, 0).await.is_err());
+        assert!(cache.store.head(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn layer_invalidation_deletes_the_prefix_and_bumps_the_version() {
+        let cache = cache();
+        let roads = cache.tile_path(&TileKey::new("cat_roads", 3, 2, 1), "");
+        let roads2 = cache.tile_path(&TileKey::new("cat_roads2", 3, 2, 1), "");
+        cache.write_tile(&roads, b"a", TileEncoding::Identity, 0).await.unwrap();
+        cache.write_tile(&roads2, b"b", TileEncoding::Identity, 0).await.unwrap();
+
+        assert_eq!(cache.get_layer_version("cat_roads").await, 0);
+        cache.increment_layer_version("cat_roads").await;
+        cache.delete_layer_cache("cat_roads").await.unwrap();
+
+        assert!(cache.get_cache(&roads, 0).await.is_err());
+        assert!(cache.get_cache(&roads2, 0).await.is_ok());
+        assert_eq!(cache.get_layer_version("cat_roads").await, 1);
+        assert_eq!(cache.read_layer_version("cat_roads").await, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_version_bumps_all_count() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let layout = KeyLayout::parse("tiles/{layer}/{tms}/{z}/{x}/{y}.pbf").unwrap();
+        let instances: Vec<ObjectStoreCache> = (0..4)
+            .map(|_| ObjectStoreCache::with_store(store.clone(), layout.clone()))
+            .collect();
+
+        futures::future::join_all(
+            instances
+                .iter()
+                .map(|cache| cache.increment_layer_version("cat_roads")),
+        )
+        .await;
+        assert_eq!(instances[0].read_layer_version("cat_roads").await, 4);
+    }
+
+    #[tokio::test]
+    async fn listings_read_the_tiles_back_from_their_keys() {
+        let cache = cache();
+        for key in [
+            TileKey::new("cat_roads", 3, 2, 1),
+            TileKey::new("cat_roads", 4, 0, 0).with_matrix_set("WorldCRS84Quad"),
+            TileKey::new("cat_roads2", 3, 2, 1),
+        ] {
+            let path = cache.tile_path(&key, ".gz");
+            cache.write_tile(&path, b"tile", TileEncoding::Gzip, 0).await.unwrap();
+        }
+        // Left over from another encoding.
+        let identity = cache.tile_path(&TileKey::new("cat_roads", 5, 0, 0), "");
+        cache.write_tile(&identity, b"tile", TileEncoding::Identity, 0).await.unwrap();
+
+        let usage = cache.layer_usage("cat_roads", ".gz", 0).await.unwrap();
+        assert_eq!((usage.tiles, usage.bytes), (2, 8));
+
+        let page = TilePage {
+            z: None,
+            offset: 0,
+            limit: 10,
+        };
+        let listing = cache.list_tiles("cat_roads", ".gz", 0, &page).await.unwrap();
+        let tiles: Vec<_> = listing
+            .tiles
+            .iter()
+            .map(|tile| (tile.matrix_set.as_deref(), tile.z, tile.x, tile.y))
+            .collect();
+        assert_eq!(tiles, [(None, 3, 2, 1), (Some("WorldCRS84Quad"), 4, 0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn region_invalidation_deletes_the_tiles_and_records_the_region() {
+        let cache = cache();
+        let inside = cache.tile_path(&TileKey::new("cat_roads", 3, 2, 1), "");
+        let outside = cache.tile_path(&TileKey::new("cat_roads", 3, 5, 1), "");
+        cache.write_tile(&inside, b"a", TileEncoding::Identity, 0).await.unwrap();
+        cache.write_tile(&outside, b"b", TileEncoding::Identity, 0).await.unwrap();
+
+        let span = TileSpan {
+            matrix_set: None,
+            z: 3,
+            xmin: 1,
+            xmax: 3,
+            ymin: 0,
+            ymax: 2,
+        };
+        cache.delete_tiles("cat_roads", std::slice::from_ref(&span), "").await.unwrap();
+        assert!(cache.get_cache(&inside, 0).await.is_err());
+        assert!(cache.get_cache(&outside, 0).await.is_ok());
+
+        assert_eq!(cache.push_region("cat_roads", vec![span.clone()]).await.unwrap().last, 1);
+        assert_eq!(cache.push_region("cat_roads", vec![span]).await.unwrap().last, 2);
+        let regions = cache.get_regions("cat_roads").await;
+        assert_eq!(regions.stamp(&TileKey::new("cat_roads", 3, 2, 1)), 2);
+
+        cache.clear_regions("cat_roads").await;
+        assert_eq!(cache.get_regions("cat_roads").await, RegionStamps::default());
+    }
+}
@@ -1,100 +1,597 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::key::TileKey;
+use super::region::{RegionStamps, TileSpan};
+use super::stats::{CachedTile, LayerUsage, PageCollector, TileListing, TilePage};
 use crate::{Catalog, error::AppResult};
 use bb8_redis::{
     RedisConnectionManager,
     bb8,
     // redis::{AsyncCommands, RedisResult},
-    redis::AsyncCommands,
+    redis::{
+        self, AsyncCommands, ConnectionAddr, ConnectionInfo, IntoConnectionInfo,
+        aio::MultiplexedConnection,
+    },
 };
 use bytes::Bytes;
 
+/// How long a layer version read from Redis is trusted before it is read
+/// again. Shorter than the cluster invalidation delay, so peers see a bumped
+/// version before the old tiles are swept.
+const VERSION_REFRESH: Duration = Duration::from_secs(2);
+/// Keys asked for per SCAN call, and deleted per pipeline, by the sweeper.
+const SWEEP_BATCH: usize = 1000;
+/// How often every layer's tiles of older versions are swept, for those a
+/// failed sweep or a restart mid-sweep left behind.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Tile keys embed the layer version: `{cat_layer}:v3:z:x:y[.gz]`, plus the
+/// matrix set before `z` for other grids. Invalidating a layer only bumps
+/// its version, so old tiles are never read again; their TTL or the
+/// background sweeper reclaims them. The `{cat_layer}` hash tag keeps a
+/// layer's tiles in one Redis Cluster slot.
+pub fn tile_key(key: &TileKey, version: u64, suffix: &str) -> String {
+    let mut redis_key = format!("{{{}}}:v{version}:", key.layer);
+    if let Some(set) = &key.matrix_set {
+        redis_key.push_str(set);
+        redis_key.push(':');
+    }
+    redis_key.push_str(&format!("{}:{}:{}{suffix}", key.z, key.x, key.y));
+    redis_key
+}
+
+/// Version counter of a layer. The `{cat_layer}` hash tag puts it in the
+/// slot of the layer's tiles.
+fn version_key(layer_name: &str) -> String {
+    format!("ver:{{{layer_name}}}")
+}
+
+/// Version counter of a layer before its key got a hash tag.
+fn legacy_version_key(layer_name: &str) -> String {
+    format!("ver:{layer_name}")
+}
+
+/// Regions invalidated over a layer, in the slot of its tiles.
+fn regions_key(layer_name: &str) -> String {
+    format!("regions:{{{layer_name}}}")
+}
+
+/// `(host, port)` of the reachable primaries in a `CLUSTER NODES` reply.
+fn cluster_primaries(nodes: &str) -> Vec<(String, u16)> {
+    nodes
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags = fields.get(2)?;
+            if !flags.split(',').any(|flag| flag == "master") || flags.contains("fail") {
+                return None;
+            }
+            // ip:port@cport[,hostname]
+            let address = fields.get(1)?.split(['@', ',']).next()?;
+            let (host, port) = address.rsplit_once(':')?;
+            let port = port.parse().ok().filter(|&port| port != 0)?;
+            (!host.is_empty()).then(|| (host.to_string(), port))
+        })
+        .collect()
+}
+
+/// Escapes the glob characters of a layer name for SCAN MATCH.
+fn escape_glob(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Matrix set, z, x and y of a key read back with `tile_key`'s `prefix`
+/// (`{cat_layer}:v3:`) and `suffix`; `None` for keys of other encodings.
+fn parse_tile_key(
+    redis_key: &str,
+    prefix: &str,
+    suffix: &str,
+) -> Option<(Option<String>, u32, u32, u32)> {
+    let rest = redis_key.strip_prefix(prefix)?.strip_suffix(suffix)?;
+    let mut parts: Vec<&str> = rest.split(':').collect();
+    let y = parts.pop()?.parse().ok()?;
+    let x = parts.pop()?.parse().ok()?;
+    let z = parts.pop()?.parse().ok()?;
+    match parts.as_slice() {
+        [] => Some((None, z, x, y)),
+        [set] => Some((Some(set.to_string()), z, x, y)),
+        _ => None,
+    }
+}
+
+/// SCAN patterns of a layer's tiles: the versioned keys, and those of the
+/// former `cat_layer:z:x:y` layout still waiting for their TTL.
+fn sweep_patterns(layer_name: &str) -> [String; 2] {
+    let layer = escape_glob(layer_name);
+    [format!("{{{layer}}}:v*"), format!("{layer}:*")]
+}
+
+/// Unlinks the tiles of a layer held by one node, except those of the
+/// `current` version prefix.
+async fn sweep_node(
+    conn: &mut MultiplexedConnection,
+    layer_name: &str,
+    current: &str,
+) -> AppResult<usize> {
+    let mut deleted = 0;
+    for pattern in sweep_patterns(layer_name) {
+        let mut cursor = 0u64;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .arg(cursor)
+                .arg("MATCH")
+                .arg(&pattern)
+                .arg("COUNT")
+                .arg(SWEEP_BATCH)
+                .query_async(conn)
+                .await?;
+            let mut pipe = redis::pipe();
+            let mut batch = 0;
+            for key in keys.iter().filter(|key| !key.starts_with(current)) {
+                pipe.unlink(key).ignore();
+                batch += 1;
+            }
+            if batch > 0 {
+                pipe.query_async::<()>(conn).await?;
+                deleted += batch;
+            }
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+    }
+    Ok(deleted)
+}
+
 #[derive(Debug, Clone)]
 pub struct RedisCache {
-    // pub conn_info: String,
+    /// Server, credentials and TLS settings, reused to reach each node of a
+    /// Redis Cluster when scanning.
+    conn_info: ConnectionInfo,
     pool: bb8::Pool<RedisConnectionManager>,
+    /// Layer versions last read from Redis, and when.
+    versions: Arc<Mutex<HashMap<String, (u64, Instant)>>>,
 }
 
 impl RedisCache {
     pub async fn new(conn_info: String) -> AppResult<Self> {
         let manager = RedisConnectionManager::new(conn_info.clone())?;
         let pool = bb8::Pool::builder().build(manager).await?;
-        Ok(RedisCache { pool })
+        Ok(RedisCache {
+            conn_info: conn_info.into_connection_info()?,
+            pool,
+            versions: Arc::new(Mutex::new(HashMap::new())),
+        })
     }
 
+    /// Sweeps the layers flushed on start. Their versions must have been
+    /// bumped first (see `CacheWrapper::delete_cache`): the sweep keeps the
+    /// tiles of the current version.
     pub async fn delete_cache(&self, catalog: Catalog) -> AppResult<()> {
         for layer in catalog.layers.iter() {
             if layer.delete_cache_on_start.unwrap_or(false) {
-                let mut conn = self.pool.get().await?;
-                let key_pattern = format!("{}_{}:*", layer.category.name, layer.name);
-                let keys: Vec<String> = conn.keys(key_pattern).await?;
+                let key = format!("{}_{}", layer.category.name, layer.name);
+                self.delete_layer_cache(&key).await?;
+            }
+        }
+        Ok(())
+    }
 
-                if !keys.is_empty() {
-                    for key in keys {
-                        conn.del::<&str, ()>(&key).await?;
+    /// Sweeps the catalog's layers every `SWEEP_INTERVAL`. Tiles of older
+    /// versions are otherwise only reclaimed by the sweep that follows an
+    /// invalidation, or by their TTL.
+    pub fn start_sweeper(&self) {
+        let cache = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SWEEP_INTERVAL).await;
+                let layers: Vec<String> = {
+                    let catalog = crate::get_catalog().await.read().await;
+                    catalog
+                        .layers
+                        .iter()
+                        .map(|layer| format!("{}_{}", layer.category.name, layer.name))
+                        .collect()
+                };
+                for layer_name in layers {
+                    match cache.sweep_layer(&layer_name).await {
+                        Ok(0) => {}
+                        Ok(deleted) => {
+                            tracing::debug!("Swept {deleted} stale cached tiles of {layer_name}.")
+                        }
+                        Err(e) => tracing::warn!("Failed to sweep cached tiles of {layer_name}: {e}"),
                     }
                 }
             }
+        });
+    }
+
+    /// Connections to every primary of a Redis Cluster, or to the server
+    /// itself when it is not clustered. SCAN only walks the node it is sent to.
+    async fn scan_connections(&self) -> AppResult<Vec<MultiplexedConnection>> {
+        let mut conn = self.pool.get().await?;
+        let nodes: redis::RedisResult<String> =
+            redis::cmd("CLUSTER").arg("NODES").query_async(&mut *conn).await;
+        let Ok(nodes) = nodes else {
+            return Ok(vec![(*conn).clone()]);
+        };
+        let mut connections = Vec::new();
+        for (host, port) in cluster_primaries(&nodes) {
+            let addr = match self.conn_info.addr() {
+                ConnectionAddr::TcpTls {
+                    insecure,
+                    tls_params,
+                    ..
+                } => ConnectionAddr::TcpTls {
+                    host,
+                    port,
+                    insecure: *insecure,
+                    tls_params: tls_params.clone(),
+                },
+                _ => ConnectionAddr::Tcp(host, port),
+            };
+            let client = redis::Client::open(self.conn_info.clone().set_addr(addr))?;
+            connections.push(client.get_multiplexed_async_connection().await?);
         }
+        Ok(connections)
+    }
+
+    /// The layer version has already been bumped, which makes its cached
+    /// tiles unreachable; they are removed in the background.
+    pub async fn delete_layer_cache(&self, layer_name: &str) -> AppResult<()> {
+        let cache = self.clone();
+        let layer_name = layer_name.to_string();
+        tokio::spawn(async move {
+            match cache.sweep_layer(&layer_name).await {
+                Ok(deleted) => tracing::info!("Swept {deleted} stale cached tiles of {layer_name}."),
+                Err(e) => tracing::warn!("Failed to sweep cached tiles of {layer_name}: {e}"),
+            }
+        });
         Ok(())
     }
 
-    pub async fn delete_layer_cache(&self, layer_name: &String) -> AppResult<()> {
-        let mut conn = self.pool.get().await?;
-        let key_pattern = format!("{layer_name}:*");
-        let keys: Vec<String> = conn.keys(key_pattern).await?;
+    /// Deletes the layer's tiles of older versions with SCAN and pipelined
+    /// UNLINKs, so Redis is never blocked for long, on every node.
+    pub async fn sweep_layer(&self, layer_name: &str) -> AppResult<usize> {
+        let current = format!("{{{layer_name}}}:v{}:", self.read_layer_version(layer_name).await);
+        let mut deleted = 0;
+        for mut conn in self.scan_connections().await? {
+            deleted += sweep_node(&mut conn, layer_name, &current).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Calls `visit` with every tile of the layer's current version stored
+    /// with `suffix`, read with SCAN and pipelined STRLEN and TTL. Redis
+    /// keeps no write time: the age of a tile is derived from its TTL, and
+    /// unknown for layers without `max_cache_age`.
+    async fn scan_tiles(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+        mut visit: impl FnMut(CachedTile),
+    ) -> AppResult<()> {
+        let prefix = format!("{{{layer_name}}}:v{}:", self.get_layer_version(layer_name).await);
+        let pattern = format!("{}*", escape_glob(&prefix));
+        for mut conn in self.scan_connections().await? {
+            let mut cursor = 0u64;
+            loop {
+                let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                    .arg(cursor)
+                    .arg("MATCH")
+                    .arg(&pattern)
+                    .arg("COUNT")
+                    .arg(SWEEP_BATCH)
+                    .query_async(&mut conn)
+                    .await?;
+                let tiles: Vec<_> = keys
+                    .iter()
+                    .filter_map(|key| Some((key, parse_tile_key(key, &prefix, suffix)?)))
+                    .collect();
+                if !tiles.is_empty() {
+                    let mut pipe = redis::pipe();
+                    for (key, _) in &tiles {
+                        pipe.strlen(*key).ttl(*key);
+                    }
+                    let sizes: Vec<(u64, i64)> = pipe.query_async(&mut conn).await?;
+                    for ((_, (matrix_set, z, x, y)), (bytes, ttl)) in tiles.into_iter().zip(sizes) {
+                        // Swept or expired since the SCAN.
+                        if ttl == -2 {
+                            continue;
+                        }
+                        let age = u64::try_from(ttl)
+                            .ok()
+                            .filter(|_| max_cache_age != 0)
+                            .map(|ttl| max_cache_age.saturating_sub(ttl));
+                        visit(CachedTile {
+                            matrix_set,
+                            z,
+                            x,
+                            y,
+                            bytes,
+                            age,
+                        });
+                    }
+                }
+                if next == 0 {
+                    break;
+                }
+                cursor = next;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn layer_usage(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+    ) -> AppResult<LayerUsage> {
+        let mut usage = LayerUsage::default();
+        self.scan_tiles(layer_name, suffix, max_cache_age, |tile| {
+            usage.add(tile.bytes, tile.age)
+        })
+        .await?;
+        Ok(usage)
+    }
+
+    pub async fn list_tiles(
+        &self,
+        layer_name: &str,
+        suffix: &str,
+        max_cache_age: u64,
+        page: &TilePage,
+    ) -> AppResult<TileListing> {
+        let mut collector = PageCollector::new(page);
+        self.scan_tiles(layer_name, suffix, max_cache_age, |tile| {
+            collector.push(tile)
+        })
+        .await?;
+        Ok(collector.finish())
+    }
 
-        if !keys.is_empty() {
-            for key in keys {
-                conn.del::<&str, ()>(&key).await?;
+    /// Unlinks the layer's tiles of the current version inside the spans,
+    /// in pipelined batches.
+    pub async fn delete_tiles(
+        &self,
+        layer_name: &str,
+        spans: &[TileSpan],
+        suffix: &str,
+    ) -> AppResult<()> {
+        let version = self.get_layer_version(layer_name).await;
+        let keys: Vec<String> = spans
+            .iter()
+            .flat_map(|span| span.keys(layer_name))
+            .map(|key| tile_key(&key, version, suffix))
+            .collect();
+        let mut conn = self.pool.get().await?;
+        let mut deleted = 0;
+        for batch in keys.chunks(SWEEP_BATCH) {
+            let mut pipe = redis::pipe();
+            for key in batch {
+                pipe.unlink(key);
             }
+            let unlinked: Vec<usize> = pipe.query_async(&mut *conn).await?;
+            deleted += unlinked.iter().sum::<usize>();
         }
+        tracing::debug!("Deleted {deleted} cached tiles of {layer_name}.");
         Ok(())
     }
 
-    pub async fn exists_key(&self, key: String) -> AppResult<bool> {
+    pub async fn exists_key(&self, key: &TileKey, suffix: &str) -> AppResult<bool> {
+        let redis_key = tile_key(key, self.get_layer_version(&key.layer).await, suffix);
         let mut conn = self.pool.get().await?;
-        let ret: bool = conn.exists(&key).await?;
+        let ret: bool = conn.exists(&redis_key).await?;
         Ok(ret)
     }
 
-    pub async fn get_cache(&self, key: String) -> AppResult<Bytes> {
+    /// Cached tile of the layer's current version and its remaining time to
+    /// live in seconds, if it expires.
+    pub async fn get_cache(&self, key: &TileKey, suffix: &str) -> AppResult<(Bytes, Option<u64>)> {
+        let redis_key = tile_key(key, self.get_layer_version(&key.layer).await, suffix);
         let mut conn = self.pool.get().await?;
-        let retrieved_data: Bytes = conn.get(&key).await?;
-        Ok(retrieved_data)
+        let (retrieved_data, ttl): (Bytes, i64) = redis::pipe()
+            .get(&redis_key)
+            .ttl(&redis_key)
+            .query_async(&mut *conn)
+            .await?;
+        Ok((retrieved_data, u64::try_from(ttl).ok()))
     }
 
     pub async fn write_tile_to_cache(
         &self,
-        key: String,
+        key: &TileKey,
+        suffix: &str,
         tile: &[u8],
         max_cache_age: u64,
     ) -> AppResult<()> {
+        let redis_key = tile_key(key, self.get_layer_version(&key.layer).await, suffix);
         let mut conn = self.pool.get().await?;
-        conn.set::<&str, Vec<u8>, ()>(&key, tile.to_vec()).await?;
         if max_cache_age != 0 {
-            conn.expire::<&str, ()>(&key, max_cache_age.try_into()?)
+            conn.set_ex::<&str, &[u8], ()>(&redis_key, tile, max_cache_age)
                 .await?;
+        } else {
+            conn.set::<&str, &[u8], ()>(&redis_key, tile).await?;
         }
 
         Ok(())
     }
 
-    /// Returns the current version counter for a layer. Uses the prefix "ver:" to
-    /// avoid collisions with tile keys (which use "{layer_name}:z:x:y").
+    /// Returns the current version counter for a layer, as read from Redis
+    /// at most `VERSION_REFRESH` ago, from `ver:{cat_layer}`.
     pub async fn get_layer_version(&self, layer_name: &str) -> u64 {
+        let cached = self
+            .versions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(layer_name)
+            .copied();
+        if let Some((version, read_at)) = cached
+            && read_at.elapsed() < VERSION_REFRESH
+        {
+            return version;
+        }
+        let version = self.read_layer_version(layer_name).await;
+        self.remember_version(layer_name, version);
+        version
+    }
+
+    async fn read_layer_version(&self, layer_name: &str) -> u64 {
         let Ok(mut conn) = self.pool.get().await else {
             return 0;
         };
-        let key = format!("ver:{layer_name}");
-        conn.get::<_, u64>(&key).await.unwrap_or(0)
+        if let Ok(Some(version)) = conn.get::<_, Option<u64>>(version_key(layer_name)).await {
+            return version;
+        }
+        conn.get::<_, Option<u64>>(legacy_version_key(layer_name))
+            .await
+            .ok()
+            .flatten()
+            .unwrap_or(0)
     }
 
     /// Increments the version counter for a layer. Called whenever the layer's
-    /// tile cache is invalidated so that existing client ETags become stale.
+    /// tile cache is invalidated so that existing client ETags become stale
+    /// and the cached tiles are no longer read.
     pub async fn increment_layer_version(&self, layer_name: &str) {
         let Ok(mut conn) = self.pool.get().await else {
             return;
         };
-        let key = format!("ver:{layer_name}");
-        let _: Result<u64, _> = conn.incr(&key, 1u64).await;
+        let key = version_key(layer_name);
+        // Carry a version kept under the former key over, so the tiles of
+        // older versions stay unreachable.
+        if let Ok(Some(legacy)) = conn
+            .get::<_, Option<u64>>(legacy_version_key(layer_name))
+            .await
+        {
+            let _: Result<bool, _> = conn.set_nx(&key, legacy).await;
+        }
+        if let Ok(version) = conn.incr::<_, _, u64>(&key, 1u64).await {
+            self.remember_version(layer_name, version);
+        }
+    }
+
+    /// Regions invalidated over a layer since its version was last bumped,
+    /// shared by every instance under `regions:{cat_layer}`.
+    pub async fn get_regions(&self, layer_name: &str) -> RegionStamps {
+        let Ok(mut conn) = self.pool.get().await else {
+            return RegionStamps::default();
+        };
+        let key = regions_key(layer_name);
+        match conn.get::<_, Option<Vec<u8>>>(&key).await {
+            Ok(Some(json)) => RegionStamps::from_json(&json),
+            _ => RegionStamps::default(),
+        }
+    }
+
+    /// Records a region. The key is WATCHed, so a region pushed by another
+    /// instance meanwhile makes this one retry rather than overwrite it.
+    pub async fn push_region(
+        &self,
+        layer_name: &str,
+        spans: Vec<TileSpan>,
+    ) -> AppResult<RegionStamps> {
+        let key = regions_key(layer_name);
+        let mut conn = self.pool.get().await?;
+        loop {
+            redis::cmd("WATCH").arg(&key).query_async::<()>(&mut *conn).await?;
+            let mut regions = match conn.get::<_, Option<Vec<u8>>>(&key).await? {
+                Some(json) => RegionStamps::from_json(&json),
+                None => RegionStamps::default(),
+            };
+            regions.push(spans.clone());
+            let committed: Option<()> = redis::pipe()
+                .atomic()
+                .set(&key, regions.to_json())
+                .ignore()
+                .query_async(&mut *conn)
+                .await?;
+            if committed.is_some() {
+                return Ok(regions);
+            }
+        }
+    }
+
+    pub async fn clear_regions(&self, layer_name: &str) {
+        if let Ok(mut conn) = self.pool.get().await {
+            let _: Result<(), _> = conn.del(regions_key(layer_name)).await;
+        }
+    }
+
+    fn remember_version(&self, layer_name: &str, version: u64) {
+        self.versions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(layer_name.to_string(), (version, Instant::now()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_keys_carry_the_version_and_a_hash_tag() {
+        let key = TileKey::new("cat_roads", 3, 2, 1);
+        assert_eq!(tile_key(&key, 0, ""), "{cat_roads}:v0:3:2:1");
+        assert_eq!(tile_key(&key, 7, ".gz"), "{cat_roads}:v7:3:2:1.gz");
+
+        let key = key.with_matrix_set("WorldCRS84Quad");
+        assert_eq!(tile_key(&key, 7, ".br"), "{cat_roads}:v7:WorldCRS84Quad:3:2:1.br");
+    }
+
+    #[test]
+    fn tile_keys_are_read_back_for_the_current_encoding_only() {
+        let key = TileKey::new("cat_roads", 3, 2, 1);
+        let prefix = "{cat_roads}:v7:";
+        assert_eq!(
+            parse_tile_key(&tile_key(&key, 7, ".gz"), prefix, ".gz"),
+            Some((None, 3, 2, 1))
+        );
+        let crs84 = key.clone().with_matrix_set("WorldCRS84Quad");
+        assert_eq!(
+            parse_tile_key(&tile_key(&crs84, 7, ""), prefix, ""),
+            Some((Some("WorldCRS84Quad".to_string()), 3, 2, 1))
+        );
+        assert_eq!(parse_tile_key(&tile_key(&key, 7, ".gz"), prefix, ""), None);
+        assert_eq!(parse_tile_key(&tile_key(&key, 6, ""), prefix, ""), None);
+    }
+
+    #[test]
+    fn metadata_keys_share_the_hash_tag_of_the_tiles() {
+        assert_eq!(version_key("cat_roads"), "ver:{cat_roads}");
+        assert_eq!(regions_key("cat_roads"), "regions:{cat_roads}");
+    }
+
+    #[test]
+    fn cluster_scans_reach_the_live_primaries() {
+        let nodes = "\
+07c37dfeb235213a872192d90877d0cd55635b91 127.0.0.1:30004@31004,node-4 slave e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 0 1426238317239 4 connected
+67ed2db8d677e59ec4a4cefb06858cf2a1a89fa1 127.0.0.1:30002@31002 master - 0 1426238316232 2 connected 5461-10922
+292f8b365bb7edb5e285caf0b7e6ddc7265d2f4f 127.0.0.1:30003@31003 master,fail - 1426238318243 1426238318243 3 disconnected 10923-16383
+e7d1eecce10fd6bb5eb35b9f99a514335d9ba9ca 127.0.0.1:30001@31001 myself,master - 0 0 1 connected 0-5460
+";
+        assert_eq!(
+            cluster_primaries(nodes),
+            [("127.0.0.1".to_string(), 30002), ("127.0.0.1".to_string(), 30001)]
+        );
+    }
+
+    #[test]
+    fn sweep_patterns_match_only_the_layer() {
+        assert_eq!(sweep_patterns("cat_roads"), ["{cat_roads}:v*", "cat_roads:*"]);
+        assert_eq!(sweep_patterns("cat_r*[1]"), ["{cat_r\\*\\[1\\]}:v*", "cat_r\\*\\[1\\]:*"]);
     }
 }
@@ -0,0 +1,193 @@
+//! Partial invalidation: the regions of a layer whose cached tiles were
+//! dropped without invalidating the whole layer. Each region gets a stamp,
+//! and a tile's version carries the stamp of the latest region covering it,
+//! so only those tiles change ETag. Bumping the layer version forgets them.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use super::key::TileKey;
+
+/// Regions kept per layer. Past this, the oldest is folded into the next
+/// one, which can only widen the tiles that next region covers.
+const MAX_REGIONS: usize = 64;
+
+/// Tiles of one zoom level of a matrix set, bounds inclusive.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct TileSpan {
+    /// `None` for WebMercatorQuad, as in `TileKey`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub matrix_set: Option<String>,
+    pub z: u32,
+    pub xmin: u32,
+    pub xmax: u32,
+    pub ymin: u32,
+    pub ymax: u32,
+}
+
+impl TileSpan {
+    pub fn count(&self) -> u64 {
+        u64::from(self.xmax - self.xmin + 1) * u64::from(self.ymax - self.ymin + 1)
+    }
+
+    pub fn contains(&self, key: &TileKey) -> bool {
+        self.z == key.z
+            && self.matrix_set == key.matrix_set
+            && (self.xmin..=self.xmax).contains(&key.x)
+            && (self.ymin..=self.ymax).contains(&key.y)
+    }
+
+    /// Every tile of the span, for the layer `{category}_{name}`.
+    pub fn keys<'a>(&'a self, layer: &'a str) -> impl Iterator<Item = TileKey> + 'a {
+        (self.xmin..=self.xmax).flat_map(move |x| {
+            (self.ymin..=self.ymax).map(move |y| TileKey {
+                layer: layer.to_string(),
+                matrix_set: self.matrix_set.clone(),
+                z: self.z,
+                x,
+                y,
+            })
+        })
+    }
+
+    fn extend(&mut self, other: &TileSpan) {
+        self.xmin = self.xmin.min(other.xmin);
+        self.xmax = self.xmax.max(other.xmax);
+        self.ymin = self.ymin.min(other.ymin);
+        self.ymax = self.ymax.max(other.ymax);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Region {
+    pub stamp: u64,
+    pub spans: Vec<TileSpan>,
+}
+
+/// Regions invalidated since the layer version was last bumped.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RegionStamps {
+    /// Stamp of the latest region; stamps are never reused within a version.
+    pub last: u64,
+    pub regions: Vec<Region>,
+}
+
+impl RegionStamps {
+    /// Stamp of the latest region covering the tile, 0 if none does.
+    pub fn stamp(&self, key: &TileKey) -> u64 {
+        self.regions
+            .iter()
+            .filter(|region| region.spans.iter().any(|span| span.contains(key)))
+            .map(|region| region.stamp)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Records a new region and returns its stamp.
+    pub fn push(&mut self, spans: Vec<TileSpan>) -> u64 {
+        self.last += 1;
+        self.regions.push(Region {
+            stamp: self.last,
+            spans,
+        });
+        while self.regions.len() > MAX_REGIONS {
+            let oldest = self.regions.remove(0);
+            let next = &mut self.regions[0];
+            for span in oldest.spans {
+                match next
+                    .spans
+                    .iter_mut()
+                    .find(|s| s.z == span.z && s.matrix_set == span.matrix_set)
+                {
+                    Some(merged) => merged.extend(&span),
+                    None => next.spans.push(span),
+                }
+            }
+        }
+        self.last
+    }
+
+    /// Stored form of the regions.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Regions read back from storage; anything unreadable counts as none.
+    pub fn from_json(json: &[u8]) -> Self {
+        serde_json::from_slice(json).unwrap_or_default()
+    }
+}
+
+/// Version of one cached tile: the layer version, and the stamp of the
+/// latest region invalidated over the tile since. Tile ETags hash it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TileVersion {
+    pub layer: u64,
+    pub region: u64,
+}
+
+impl fmt::Display for TileVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Tiles outside every region keep the ETag input they always had.
+        match self.region {
+            0 => write!(f, "{}", self.layer),
+            region => write!(f, "{}.{region}", self.layer),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(z: u32, xmin: u32, xmax: u32, ymin: u32, ymax: u32) -> TileSpan {
+        TileSpan {
+            matrix_set: None,
+            z,
+            xmin,
+            xmax,
+            ymin,
+            ymax,
+        }
+    }
+
+    #[test]
+    fn tiles_take_the_stamp_of_the_latest_region_covering_them() {
+        let mut stamps = RegionStamps::default();
+        assert_eq!(stamps.push(vec![span(10, 4, 6, 4, 6)]), 1);
+        assert_eq!(stamps.push(vec![span(10, 6, 8, 6, 8), span(11, 12, 16, 12, 16)]), 2);
+
+        assert_eq!(stamps.stamp(&TileKey::new("l", 10, 4, 5)), 1);
+        assert_eq!(stamps.stamp(&TileKey::new("l", 10, 6, 6)), 2);
+        assert_eq!(stamps.stamp(&TileKey::new("l", 11, 4, 5)), 0);
+        assert_eq!(stamps.stamp(&TileKey::new("l", 10, 9, 9)), 0);
+        // Other matrix sets have spans of their own.
+        let crs84 = TileKey::new("l", 10, 4, 5).with_matrix_set("WorldCRS84Quad");
+        assert_eq!(stamps.stamp(&crs84), 0);
+
+        assert_eq!(span(11, 12, 16, 12, 16).keys("l").count() as u64, 25);
+        assert_eq!(RegionStamps::from_json(stamps.to_json().as_bytes()), stamps);
+        assert_eq!(RegionStamps::from_json(b"garbage"), RegionStamps::default());
+    }
+
+    #[test]
+    fn old_regions_are_folded_into_newer_ones() {
+        let mut stamps = RegionStamps::default();
+        for i in 0..MAX_REGIONS as u32 + 2 {
+            stamps.push(vec![span(12, i, i, 0, 0)]);
+        }
+        assert_eq!(stamps.regions.len(), MAX_REGIONS);
+        // The first tiles are still covered, by a later stamp.
+        assert_eq!(stamps.stamp(&TileKey::new("l", 12, 0, 0)), 3);
+        assert_eq!(stamps.stamp(&TileKey::new("l", 12, 2, 0)), 3);
+        assert_eq!(stamps.stamp(&TileKey::new("l", 12, 3, 0)), 4);
+        assert_eq!(stamps.last, MAX_REGIONS as u64 + 2);
+    }
+
+    #[test]
+    fn versions_outside_regions_format_as_the_layer_version() {
+        assert_eq!(TileVersion { layer: 3, region: 0 }.to_string(), "3");
+        assert_eq!(TileVersion { layer: 3, region: 2 }.to_string(), "3.2");
+    }
+}
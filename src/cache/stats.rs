@@ -0,0 +1,197 @@
+//! Inspection of what the cache holds for a layer: its tile count, size and
+//! oldest and newest tiles, and the tiles themselves page by page. Every
+//! backend walks its own storage; expired tiles are left out.
+
+use std::collections::BinaryHeap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/// Tiles of a layer in one cache tier.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LayerUsage {
+    pub tiles: u64,
+    pub bytes: u64,
+    /// Seconds since the oldest and the newest tiles were cached; `None`
+    /// when no tile records it (Redis tiles without a cache time).
+    pub oldest_age: Option<u64>,
+    pub newest_age: Option<u64>,
+}
+
+impl LayerUsage {
+    pub fn add(&mut self, bytes: u64, age: Option<u64>) {
+        self.tiles += 1;
+        self.bytes += bytes;
+        if let Some(age) = age {
+            self.oldest_age = Some(self.oldest_age.map_or(age, |oldest| oldest.max(age)));
+            self.newest_age = Some(self.newest_age.map_or(age, |newest| newest.min(age)));
+        }
+    }
+
+    /// Adds the tiles of another file or prefix of the same layer.
+    pub fn merge(&mut self, other: LayerUsage) {
+        self.tiles += other.tiles;
+        self.bytes += other.bytes;
+        self.oldest_age = self.oldest_age.max(other.oldest_age);
+        self.newest_age = match (self.newest_age, other.newest_age) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        };
+    }
+}
+
+/// One cached tile. Tiles sort by matrix set, then z, x and y.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct CachedTile {
+    /// `None` for WebMercatorQuad, as in `TileKey`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matrix_set: Option<String>,
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+    pub bytes: u64,
+    /// Seconds since the tile was cached, when known.
+    pub age: Option<u64>,
+}
+
+/// Which tiles of a layer to list.
+#[derive(Debug, Clone, Default)]
+pub struct TilePage {
+    /// Only this zoom level.
+    pub z: Option<u32>,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TileListing {
+    /// Tiles matching the page's filter, over every page.
+    pub total: u64,
+    pub tiles: Vec<CachedTile>,
+}
+
+/// Builds a page out of tiles listed in any order, holding no more than
+/// the tiles up to the end of the page.
+pub struct PageCollector<'a> {
+    page: &'a TilePage,
+    total: u64,
+    first: BinaryHeap<CachedTile>,
+}
+
+impl<'a> PageCollector<'a> {
+    pub fn new(page: &'a TilePage) -> Self {
+        PageCollector {
+            page,
+            total: 0,
+            first: BinaryHeap::new(),
+        }
+    }
+
+    pub fn push(&mut self, tile: CachedTile) {
+        if self.page.z.is_some_and(|z| z != tile.z) {
+            return;
+        }
+        self.total += 1;
+        let end = self.page.offset.saturating_add(self.page.limit);
+        if self.first.len() < end {
+            self.first.push(tile);
+        } else if self.first.peek().is_some_and(|last| tile < *last) {
+            self.first.pop();
+            self.first.push(tile);
+        }
+    }
+
+    pub fn finish(self) -> TileListing {
+        let tiles = self.first.into_sorted_vec();
+        TileListing {
+            total: self.total,
+            tiles: tiles
+                .into_iter()
+                .skip(self.page.offset)
+                .take(self.page.limit)
+                .collect(),
+        }
+    }
+}
+
+/// Seconds since a tile written at `modified` was cached.
+pub fn age_of(modified: SystemTime) -> u64 {
+    modified.elapsed().unwrap_or_default().as_secs()
+}
+
+pub fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Whether a tile of this age is past the layer's `max_cache_age`.
+pub fn expired(age: u64, max_cache_age: u64) -> bool {
+    max_cache_age != 0 && age > max_cache_age
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(matrix_set: Option<&str>, z: u32, x: u32, y: u32) -> CachedTile {
+        CachedTile {
+            matrix_set: matrix_set.map(str::to_string),
+            z,
+            x,
+            y,
+            bytes: 10,
+            age: Some(u64::from(x)),
+        }
+    }
+
+    #[test]
+    fn pages_come_out_in_tile_order() {
+        let tiles = [
+            tile(Some("WorldCRS84Quad"), 1, 0, 0),
+            tile(None, 2, 3, 1),
+            tile(None, 1, 1, 0),
+            tile(None, 2, 0, 2),
+            tile(None, 1, 0, 1),
+        ];
+        let page = TilePage {
+            z: None,
+            offset: 1,
+            limit: 2,
+        };
+        let mut collector = PageCollector::new(&page);
+        tiles.iter().cloned().for_each(|tile| collector.push(tile));
+        let listing = collector.finish();
+        assert_eq!(listing.total, 5);
+        assert_eq!(listing.tiles, [tile(None, 1, 1, 0), tile(None, 2, 0, 2)]);
+
+        let page = TilePage {
+            z: Some(2),
+            offset: 0,
+            limit: 10,
+        };
+        let mut collector = PageCollector::new(&page);
+        tiles.iter().cloned().for_each(|tile| collector.push(tile));
+        let listing = collector.finish();
+        assert_eq!(listing.total, 2);
+        assert_eq!(listing.tiles, [tile(None, 2, 0, 2), tile(None, 2, 3, 1)]);
+    }
+
+    #[test]
+    fn usage_tracks_the_oldest_and_newest_tiles() {
+        let mut usage = LayerUsage::default();
+        usage.add(100, Some(30));
+        usage.add(50, None);
+        usage.add(25, Some(5));
+        assert_eq!(
+            usage,
+            LayerUsage {
+                tiles: 3,
+                bytes: 175,
+                oldest_age: Some(30),
+                newest_age: Some(5),
+            }
+        );
+    }
+}
@@ -1,7 +1,10 @@
 use crate::auth::Group;
 use crate::get_cf_pool;
 use crate::config::system_settings::bump_config_version;
-use crate::models::{catalog::Layer, category::Category};
+use crate::models::{
+    catalog::{Layer, SourceKind},
+    category::Category,
+};
 use sqlx::{Row, sqlite::SqlitePool};
 
 pub async fn get_layers(pool: Option<&SqlitePool>) -> Result<Vec<Layer>, sqlx::Error> {
@@ -46,12 +49,21 @@ pub async fn get_layers(pool: Option<&SqlitePool>) -> Result<Vec<Layer>, sqlx::E
         let name: String = row.get("name");
         let alias: String = row.get("alias");
         let description: String = row.get("description");
+        let source_kind: String = row.get("source_kind");
+        let static_file: Option<String> = row.get("static_file");
+        let function_name: Option<String> = row.get("function_name");
+        let sql_query: Option<String> = row.get("sql_query");
+        let sql_params: Option<String> = row.get("sql_params");
         let schema: String = row.get("schema");
         let table_name: String = row.get("table_name");
         let fields: String = row.get("fields");
+        let zoom_fields: Option<String> = row.get("zoom_fields");
+        let generalization: Option<String> = row.get("generalization");
         let filter: Option<String> = row.get("filter");
         let srid: Option<i32> = row.get("srid");
+        let tile_matrix_set: Option<String> = row.get("tile_matrix_set");
         let geom: Option<String> = row.get("geom");
+        let id_column: Option<String> = row.get("id_column");
         let sql_mode: Option<String> = row.get("sql_mode");
         let buffer: Option<i32> = row.get("buffer");
         let extent: Option<i32> = row.get("extent");
@@ -64,6 +76,16 @@ pub async fn get_layers(pool: Option<&SqlitePool>) -> Result<Vec<Layer>, sqlx::E
         let delete_cache_on_start: Option<bool> = row.get("delete_cache_on_start");
         let max_cache_age: Option<i64> = row.get("max_cache_age");
         let max_records: Option<i64> = row.get("max_records");
+        let priority: Option<String> = row.get("priority");
+        let priority_order: Option<String> = row.get("priority_order");
+        let cluster_zoom: Option<i32> = row.get("cluster_zoom");
+        let cluster_radius: Option<i32> = row.get("cluster_radius");
+        let cluster_aggregates: Option<String> = row.get("cluster_aggregates");
+        let bin_shape: Option<String> = row.get("bin_shape");
+        let bin_size: Option<i32> = row.get("bin_size");
+        let bin_aggregates: Option<String> = row.get("bin_aggregates");
+        let query_timeout_ms: Option<i64> = row.get("query_timeout_ms");
+        let notify_changes: Option<bool> = row.get("notify_changes");
         let published: bool = row.get("published");
         let database_id: String = row.get("database_id");
         let url: Option<String> = row.get("url");
@@ -105,12 +127,22 @@ pub async fn get_layers(pool: Option<&SqlitePool>) -> Result<Vec<Layer>, sqlx::E
             name,
             alias,
             description,
+            source_kind: SourceKind::from_str(&source_kind),
+            static_file,
+            function_name,
+            sql_query,
+            sql_params: sql_params
+                .map(|params| params.split(',').map(|s| s.trim().to_string()).collect()),
             schema,
             table_name,
             fields: fields_vec,
+            zoom_fields: zoom_fields.and_then(|json| serde_json::from_str(&json).ok()),
+            generalization: generalization.and_then(|json| serde_json::from_str(&json).ok()),
             filter,
             srid: srid.map(|v| v as u32),
+            tile_matrix_set,
             geom,
+            id_column,
             sql_mode,
             buffer: buffer.map(|v| v as u32),
             extent: extent.map(|v| v as u32),
@@ -123,6 +155,16 @@ pub async fn get_layers(pool: Option<&SqlitePool>) -> Result<Vec<Layer>, sqlx::E
             delete_cache_on_start,
             max_cache_age: max_cache_age.map(|v| v as u64),
             max_records: max_records.map(|v| v as u64),
+            priority,
+            priority_order,
+            cluster_zoom: cluster_zoom.map(|v| v as u32),
+            cluster_radius: cluster_radius.map(|v| v as u32),
+            cluster_aggregates: cluster_aggregates.and_then(|json| serde_json::from_str(&json).ok()),
+            bin_shape,
+            bin_size: bin_size.map(|v| v as u32),
+            bin_aggregates: bin_aggregates.and_then(|json| serde_json::from_str(&json).ok()),
+            query_timeout_ms: query_timeout_ms.map(|v| v as u64),
+            notify_changes,
             published,
             database_id,
             url,
@@ -137,15 +179,44 @@ pub async fn create_layer(pool: Option<&SqlitePool>, layer: Layer) -> Result<(),
     let pool = pool.unwrap_or_else(|| get_cf_pool());
 
     let fields = layer.fields.join(",");
+    let zoom_fields = layer
+        .zoom_fields
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let generalization = layer
+        .generalization
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let cluster_aggregates = layer
+        .cluster_aggregates
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let bin_aggregates = layer
+        .bin_aggregates
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
 
     sqlx::query(
         "INSERT INTO layers (
             id, category, geometry, name, alias, description, schema, table_name, fields, filter, srid, geom,
             sql_mode, buffer, extent, zmin, zmax, zmax_do_not_simplify,
             buffer_do_not_simplify, extent_do_not_simplify, clip_geom,
-            delete_cache_on_start, max_cache_age, max_records, published, database_id, url, groups
+            delete_cache_on_start, max_cache_age, max_records, published, database_id, url, groups,
+            source_kind, static_file, tile_matrix_set, function_name, sql_query, sql_params,
+            zoom_fields, generalization, id_column, priority, priority_order,
+            cluster_zoom, cluster_radius, cluster_aggregates, bin_shape, bin_size, bin_aggregates,
+            query_timeout_ms, notify_changes
         ) VALUES (
-            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
+            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+            ?, ?, ?, ?, ?, ?, ?, ?, ?, ?
         )",
     )
     .bind(&layer.id)
@@ -188,6 +259,25 @@ pub async fn create_layer(pool: Option<&SqlitePool>, layer: Layer) -> Result<(),
             })
             .unwrap_or_default(),
     )
+    .bind(layer.source_kind.as_str())
+    .bind(&layer.static_file)
+    .bind(&layer.tile_matrix_set)
+    .bind(&layer.function_name)
+    .bind(&layer.sql_query)
+    .bind(layer.sql_params.as_ref().map(|params| params.join(",")))
+    .bind(zoom_fields.as_deref())
+    .bind(generalization.as_deref())
+    .bind(&layer.id_column)
+    .bind(&layer.priority)
+    .bind(&layer.priority_order)
+    .bind(layer.cluster_zoom)
+    .bind(layer.cluster_radius)
+    .bind(cluster_aggregates.as_deref())
+    .bind(&layer.bin_shape)
+    .bind(layer.bin_size)
+    .bind(bin_aggregates.as_deref())
+    .bind(layer.query_timeout_ms.map(|v| v as i64))
+    .bind(layer.notify_changes)
     .execute(pool)
     .await?;
 
@@ -200,6 +290,30 @@ pub async fn update_layer(pool: Option<&SqlitePool>, layer: Layer) -> Result<(),
     let pool = pool.unwrap_or_else(|| get_cf_pool());
 
     let fields = layer.fields.join(",");
+    let zoom_fields = layer
+        .zoom_fields
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let generalization = layer
+        .generalization
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let cluster_aggregates = layer
+        .cluster_aggregates
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+    let bin_aggregates = layer
+        .bin_aggregates
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()
+        .map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
     let group_ids = layer
         .groups
         .as_ref() // Convierte Option<Vec<Group>> en Option<&Vec<Group>>
@@ -218,7 +332,13 @@ pub async fn update_layer(pool: Option<&SqlitePool>, layer: Layer) -> Result<(),
             filter = ?, srid = ?, geom = ?, sql_mode = ?, buffer = ?, extent = ?, zmin = ?,
             zmax = ?, zmax_do_not_simplify = ?, buffer_do_not_simplify = ?,
             extent_do_not_simplify = ?, clip_geom = ?, delete_cache_on_start = ?,
-            max_cache_age = ?, max_records = ?, published = ?, database_id = ?, url = ?, groups = ? WHERE id = ?",
+            max_cache_age = ?, max_records = ?, published = ?, database_id = ?, url = ?, groups = ?,
+            source_kind = ?, static_file = ?, tile_matrix_set = ?, function_name = ?,
+            sql_query = ?, sql_params = ?, zoom_fields = ?,
+            generalization = ?, id_column = ?, priority = ?, priority_order = ?,
+            cluster_zoom = ?, cluster_radius = ?, cluster_aggregates = ?,
+            bin_shape = ?, bin_size = ?, bin_aggregates = ?, query_timeout_ms = ?,
+            notify_changes = ? WHERE id = ?",
     )
     .bind(&layer.category.id)
     .bind(&layer.geometry)
@@ -247,6 +367,25 @@ pub async fn update_layer(pool: Option<&SqlitePool>, layer: Layer) -> Result<(),
     .bind(&layer.database_id)
     .bind(&layer.url)
     .bind(group_ids)
+    .bind(layer.source_kind.as_str())
+    .bind(&layer.static_file)
+    .bind(&layer.tile_matrix_set)
+    .bind(&layer.function_name)
+    .bind(&layer.sql_query)
+    .bind(layer.sql_params.as_ref().map(|params| params.join(",")))
+    .bind(zoom_fields.as_deref())
+    .bind(generalization.as_deref())
+    .bind(&layer.id_column)
+    .bind(&layer.priority)
+    .bind(&layer.priority_order)
+    .bind(layer.cluster_zoom)
+    .bind(layer.cluster_radius)
+    .bind(cluster_aggregates.as_deref())
+    .bind(&layer.bin_shape)
+    .bind(layer.bin_size)
+    .bind(bin_aggregates.as_deref())
+    .bind(layer.query_timeout_ms.map(|v| v as i64))
+    .bind(layer.notify_changes)
     .bind(&layer.id)
     .execute(pool)
     .await?;
@@ -319,13 +458,22 @@ mod tests {
             name: "layer".to_string(),
             alias: "Layer".to_string(),
             description: String::new(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
             database_id: "default".to_string(),
             schema: "public".to_string(),
             table_name: "t".to_string(),
             fields: vec![],
+            zoom_fields: None,
+            generalization: None,
             filter: None,
             srid: None,
+            tile_matrix_set: None,
             geom: None,
+            id_column: None,
             sql_mode: None,
             buffer: None,
             extent: None,
@@ -338,6 +486,16 @@ mod tests {
             delete_cache_on_start: None,
             max_cache_age: None,
             max_records: None,
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
             published: true,
             url: None,
             groups: None,
@@ -369,4 +527,97 @@ mod tests {
         let layers = get_layers(Some(&pool)).await.unwrap();
         assert_eq!(layers[0].max_records, Some(0));
     }
+
+    #[tokio::test]
+    async fn source_kind_round_trips_and_defaults_to_postgis() {
+        let pool = in_memory_pool().await;
+        create_layer(Some(&pool), test_layer("l1")).await.unwrap();
+
+        let mut layer = test_layer("l2");
+        layer.name = "basemap".to_string();
+        layer.source_kind = SourceKind::Mbtiles;
+        layer.static_file = Some("basemap.mbtiles".to_string());
+        create_layer(Some(&pool), layer).await.unwrap();
+
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        let postgis = layers.iter().find(|l| l.id == "l1").unwrap();
+        assert_eq!(postgis.source_kind, SourceKind::Postgis);
+        assert!(postgis.static_file.is_none());
+        let mbtiles = layers.iter().find(|l| l.id == "l2").unwrap();
+        assert_eq!(mbtiles.source_kind, SourceKind::Mbtiles);
+        assert_eq!(mbtiles.static_file.as_deref(), Some("basemap.mbtiles"));
+    }
+
+    #[tokio::test]
+    async fn tile_matrix_set_round_trips() {
+        let pool = in_memory_pool().await;
+        let mut layer = test_layer("l1");
+        layer.tile_matrix_set = Some("WorldCRS84Quad".to_string());
+        create_layer(Some(&pool), layer.clone()).await.unwrap();
+
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert_eq!(layers[0].tile_matrix_set.as_deref(), Some("WorldCRS84Quad"));
+
+        layer.tile_matrix_set = None;
+        update_layer(Some(&pool), layer).await.unwrap();
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert!(layers[0].tile_matrix_set.is_none());
+    }
+
+    #[tokio::test]
+    async fn function_name_round_trips() {
+        let pool = in_memory_pool().await;
+        let mut layer = test_layer("l1");
+        layer.source_kind = SourceKind::Function;
+        layer.function_name = Some("parcels_tile".to_string());
+        create_layer(Some(&pool), layer.clone()).await.unwrap();
+
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert_eq!(layers[0].source_kind, SourceKind::Function);
+        assert_eq!(layers[0].function_name.as_deref(), Some("parcels_tile"));
+
+        layer.function_name = Some("parcels_tile_v2".to_string());
+        update_layer(Some(&pool), layer).await.unwrap();
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert_eq!(layers[0].function_name.as_deref(), Some("parcels_tile_v2"));
+    }
+
+    #[tokio::test]
+    async fn sql_query_and_params_round_trip() {
+        let pool = in_memory_pool().await;
+        let mut layer = test_layer("l1");
+        layer.source_kind = SourceKind::Sql;
+        layer.sql_query = Some("SELECT gid, geom FROM parcels WHERE zone = !zone!".to_string());
+        layer.sql_params = Some(vec!["zone".to_string(), "min_area".to_string()]);
+        create_layer(Some(&pool), layer.clone()).await.unwrap();
+
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert_eq!(layers[0].source_kind, SourceKind::Sql);
+        assert_eq!(layers[0].sql_query, layer.sql_query);
+        assert_eq!(layers[0].get_sql_params(), vec!["zone", "min_area"]);
+
+        layer.sql_params = None;
+        update_layer(Some(&pool), layer).await.unwrap();
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert!(layers[0].sql_params.is_none());
+    }
+
+    #[tokio::test]
+    async fn zoom_fields_round_trip() {
+        let pool = in_memory_pool().await;
+        let mut layer = test_layer("l1");
+        layer.zoom_fields = Some(vec![crate::models::catalog::ZoomFields {
+            zmin: 0,
+            zmax: 8,
+            fields: vec!["name".to_string(), "class".to_string()],
+        }]);
+        create_layer(Some(&pool), layer.clone()).await.unwrap();
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert_eq!(layers[0].zoom_fields, layer.zoom_fields);
+
+        layer.zoom_fields = None;
+        update_layer(Some(&pool), layer).await.unwrap();
+        let layers = get_layers(Some(&pool)).await.unwrap();
+        assert!(layers[0].zoom_fields.is_none());
+    }
 }
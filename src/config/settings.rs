@@ -1,7 +1,11 @@
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use serde::Deserialize;
 use std::collections::HashMap;
 
+use crate::cache::encoding::TileEncoding;
+use crate::cache::objectstore::KeyLayout;
+use crate::models::tile_matrix_set::{TileMatrixSet, build_registry};
+
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
 pub struct CliArgs {
@@ -13,6 +17,53 @@ pub struct CliArgs {
     pub port: Option<u16>,
     #[arg(long)]
     pub no_cache: bool,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Render tiles ahead of time and store them in the tile cache
+    Seed(SeedArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct SeedArgs {
+    /// Layer to seed, as `category:name`
+    #[arg(long, required_unless_present = "category", conflicts_with = "category")]
+    pub layer: Option<String>,
+    /// Seed every cached layer of this category
+    #[arg(long)]
+    pub category: Option<String>,
+    /// First zoom (defaults to the layer zmin)
+    #[arg(long)]
+    pub zmin: Option<u32>,
+    /// Last zoom (defaults to the layer zmax)
+    #[arg(long)]
+    pub zmax: Option<u32>,
+    /// west,south,east,north in EPSG:4326 (defaults to the layer extent)
+    #[arg(long, value_parser = parse_bbox, allow_hyphen_values = true)]
+    pub bbox: Option<[f64; 4]>,
+    /// Tiles rendered at the same time
+    #[arg(long, default_value_t = crate::services::seed::DEFAULT_CONCURRENCY)]
+    pub concurrency: usize,
+    /// Do not store tiles without features
+    #[arg(long)]
+    pub skip_empty: bool,
+    /// Continue an interrupted run from its checkpoint
+    #[arg(long)]
+    pub resume: bool,
+}
+
+fn parse_bbox(value: &str) -> Result<[f64; 4], String> {
+    let parts = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    parts
+        .try_into()
+        .map_err(|_| "expected west,south,east,north".to_string())
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -86,12 +137,50 @@ pub struct PathConfig {
     #[serde(default = "default_cache_path")]   pub cache: String,
     #[serde(default = "default_assets_path")]  pub assets: String,
     #[serde(default = "default_plugins_path")] pub plugins: String,
+    #[serde(default = "default_static_tiles_path")] pub static_tiles: String,
 }
 
 fn default_config_path() -> String { "config".to_string() }
 fn default_cache_path() -> String { "cache".to_string() }
 fn default_assets_path() -> String { "map_assets".to_string() }
 fn default_plugins_path() -> String { "plugins".to_string() }
+fn default_static_tiles_path() -> String { "static_tiles".to_string() }
+fn default_key_layout() -> String { "{layer}/{tms}/{z}/{x}/{y}.pbf".to_string() }
+
+/// S3-compatible bucket used as the tile cache. Credentials and region not
+/// set here are read from the usual `AWS_*` environment variables.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ObjectStoreConfig {
+    pub bucket: String,
+    /// Server URL for S3-compatible stores such as MinIO; AWS when unset.
+    #[serde(default)] pub endpoint: Option<String>,
+    #[serde(default)] pub region: Option<String>,
+    #[serde(default)] pub access_key_id: Option<String>,
+    #[serde(default)] pub secret_access_key: Option<String>,
+    /// Object key of a tile: `{layer}`, `{tms}`, `{z}`, `{x}` and `{y}` are replaced.
+    #[serde(default = "default_key_layout")] pub key_layout: String,
+    /// Allows a plain `http://` endpoint.
+    #[serde(default)] pub allow_http: bool,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct CacheConfig {
+    /// Encoding cached tiles are stored and served in: gzip, brotli or none.
+    #[serde(default)] pub compression: TileEncoding,
+    /// Megabytes of tiles kept in memory in front of the disk or Redis cache; 0 disables it.
+    #[serde(default)] pub memory_mb: u64,
+    /// Stores tiles in one MBTiles file per layer under `paths.cache` instead
+    /// of one file per tile.
+    #[serde(default)] pub mbtiles: bool,
+    /// Stores tiles in an S3-compatible bucket instead of `paths.cache`.
+    #[serde(default)] pub object_store: Option<ObjectStoreConfig>,
+}
+
+impl CacheConfig {
+    pub fn memory_capacity(&self) -> usize {
+        usize::try_from(self.memory_mb.saturating_mul(1024 * 1024)).unwrap_or(usize::MAX)
+    }
+}
 
 #[derive(Debug, Deserialize, Default)]
 pub struct Settings {
@@ -101,7 +190,11 @@ pub struct Settings {
     #[serde(default)] pub security: SecurityConfig,
     #[serde(default)] pub paths: PathConfig,
     #[serde(default)] pub cluster: ClusterConfig,
+    #[serde(default)] pub cache: CacheConfig,
+    /// Custom tile grids, in addition to WebMercatorQuad and WorldCRS84Quad.
+    #[serde(default)] pub tile_matrix_sets: Vec<TileMatrixSet>,
     #[serde(skip)] pub no_cache: bool,
+    #[serde(skip)] pub command: Option<Command>,
 }
 
 impl Settings {
@@ -122,9 +215,12 @@ impl Settings {
             .set_default("paths.cache", "cache")?
             .set_default("paths.assets", "map_assets")?
             .set_default("paths.plugins", "plugins")?
+            .set_default("paths.static_tiles", "static_tiles")?
             .set_default("cluster.mode", "standalone")?
             .set_default("cluster.config_watch_interval_secs", 10)?
             .set_default("cluster.cache_invalidation_extra_delay_secs", 5)?
+            .set_default("cache.compression", "gzip")?
+            .set_default("cache.memory_mb", 64)?
             .add_source(
                 config::File::new(&config_path, config::FileFormat::Yaml).required(false),
             )
@@ -153,6 +249,7 @@ impl Settings {
             e
         })?;
         settings.no_cache = no_cache;
+        settings.command = args.command;
 
         tracing::debug!("Loaded settings: {:?}", settings);
 
@@ -217,15 +314,30 @@ impl Settings {
             }
         }
 
-        // Every clustered instance must share one Redis cache. With a per-host
-        // disk cache, a config change on one node cannot invalidate the cached
-        // tiles of the others, so peers keep serving stale tiles forever.
+        build_registry(&self.tile_matrix_sets)
+            .map_err(|e| format!("Configuration error: {e}"))?;
+
+        if let Some(object_store) = &self.cache.object_store {
+            if object_store.bucket.trim().is_empty() {
+                return Err("Configuration error: 'cache.object_store.bucket' is empty."
+                    .to_string());
+            }
+            KeyLayout::parse(&object_store.key_layout).map_err(|e| {
+                format!("Configuration error: invalid 'cache.object_store.key_layout': {e}")
+            })?;
+        }
+
+        // Every clustered instance must share one Redis or object store cache.
+        // With a per-host disk cache, a config change on one node cannot
+        // invalidate the cached tiles of the others, so peers keep serving
+        // stale tiles forever.
         if self.cluster.mode != "standalone"
             && self.database.redis_url.as_deref().unwrap_or("").is_empty()
+            && self.cache.object_store.is_none()
         {
             return Err(format!(
-                "Configuration error: cluster.mode = {} requires a shared Redis cache \
-                 (set database.redis_url)",
+                "Configuration error: cluster.mode = {} requires a shared Redis or object \
+                 store cache (set database.redis_url or cache.object_store)",
                 self.cluster.mode
             ));
         }
@@ -265,6 +377,7 @@ mod tests {
                 cache: "cache".to_string(),
                 assets: "map_assets".to_string(),
                 plugins: "plugins".to_string(),
+                static_tiles: "static_tiles".to_string(),
             },
             cluster: ClusterConfig {
                 mode: "standalone".to_string(),
@@ -273,7 +386,10 @@ mod tests {
                 owner_url: None,
                 shared_secret: None,
             },
+            cache: CacheConfig::default(),
+            tile_matrix_sets: Vec::new(),
             no_cache: false,
+            command: None,
         }
     }
 
@@ -289,6 +405,17 @@ mod tests {
         assert!(s.validate().is_ok());
     }
 
+    #[test]
+    fn invalid_tile_matrix_set_fails() {
+        let mut s = valid_settings();
+        let mut tms = TileMatrixSet::web_mercator_quad();
+        tms.id = "Custom".to_string();
+        tms.matrix_height = 3;
+        s.tile_matrix_sets.push(tms);
+        let err = s.validate().unwrap_err();
+        assert!(err.contains("powers of two"));
+    }
+
     #[test]
     fn missing_default_db_fails() {
         let mut s = valid_settings();
@@ -379,6 +506,26 @@ mod tests {
         }
     }
 
+    #[test]
+    fn cluster_accepts_an_object_store_cache() {
+        let mut s = valid_settings();
+        s.cluster.mode = "shared".to_string();
+        s.cache.object_store = Some(ObjectStoreConfig {
+            bucket: "tiles".to_string(),
+            endpoint: Some("http://localhost:9000".to_string()),
+            region: None,
+            access_key_id: None,
+            secret_access_key: None,
+            key_layout: default_key_layout(),
+            allow_http: true,
+        });
+        assert!(s.validate().is_ok());
+
+        s.cache.object_store.as_mut().unwrap().key_layout = "{z}/{x}/{y}.pbf".to_string();
+        let err = s.validate().unwrap_err();
+        assert!(err.contains("key_layout"));
+    }
+
     #[test]
     fn standalone_does_not_require_redis() {
         let s = valid_settings();
@@ -386,6 +533,11 @@ mod tests {
         assert!(s.validate().is_ok());
     }
 
+    #[test]
+    fn static_tiles_path_default() {
+        assert_eq!(default_static_tiles_path(), "static_tiles");
+    }
+
     #[test]
     fn no_cache_flag_defaults_to_false() {
         let args = CliArgs::parse_from(["mvt-rs"]);
@@ -397,4 +549,35 @@ mod tests {
         let args = CliArgs::parse_from(["mvt-rs", "--no-cache"]);
         assert!(args.no_cache);
     }
+
+    #[test]
+    fn seed_subcommand_parses_layer_zooms_and_bbox() {
+        let args = CliArgs::parse_from([
+            "mvt-rs", "seed", "--layer", "base:roads", "--zmin", "2", "--zmax", "10",
+            "--bbox", "-60,-40,-50,-30", "--skip-empty", "--resume",
+        ]);
+        let Some(Command::Seed(seed)) = args.command else {
+            panic!("expected seed subcommand");
+        };
+        assert_eq!(seed.layer.as_deref(), Some("base:roads"));
+        assert_eq!((seed.zmin, seed.zmax), (Some(2), Some(10)));
+        assert_eq!(seed.bbox, Some([-60.0, -40.0, -50.0, -30.0]));
+        assert_eq!(seed.concurrency, crate::services::seed::DEFAULT_CONCURRENCY);
+        assert!(seed.skip_empty && seed.resume);
+    }
+
+    #[test]
+    fn seed_subcommand_requires_layer_or_category() {
+        assert!(CliArgs::try_parse_from(["mvt-rs", "seed"]).is_err());
+        assert!(
+            CliArgs::try_parse_from(["mvt-rs", "seed", "--category", "a", "--bbox", "1,2,3"])
+                .is_err()
+        );
+        assert!(
+            CliArgs::try_parse_from(["mvt-rs", "seed", "--layer", "a:b", "--category", "a"])
+                .is_err()
+        );
+        let args = CliArgs::try_parse_from(["mvt-rs", "seed", "--category", "base"]).unwrap();
+        assert!(matches!(args.command, Some(Command::Seed(seed)) if seed.category.as_deref() == Some("base")));
+    }
 }
@@ -2,6 +2,11 @@
 
 use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
 
+use crate::models::{
+    catalog::{Layer, SourceKind},
+    category::Category,
+};
+
 /// In-memory SQLite pool with all migrations applied. `max_connections(1)` is
 /// required: each connection to `sqlite::memory:` gets its own database, so a
 /// multi-connection pool would migrate one DB and query another.
@@ -14,3 +19,61 @@ pub async fn in_memory_pool() -> SqlitePool {
     sqlx::migrate!().run(&pool).await.unwrap();
     pool
 }
+
+/// Published PostGIS layer on `public.t` with every optional setting unset;
+/// tests override what they need with struct update syntax.
+pub fn test_layer(id: &str) -> Layer {
+    Layer {
+        id: id.to_string(),
+        category: Category {
+            id: "cat-1".to_string(),
+            name: "public".to_string(),
+            description: String::new(),
+        },
+        geometry: "polygons".to_string(),
+        name: "layer".to_string(),
+        alias: "Layer".to_string(),
+        description: String::new(),
+        source_kind: SourceKind::Postgis,
+        static_file: None,
+        function_name: None,
+        sql_query: None,
+        sql_params: None,
+        database_id: "default".to_string(),
+        schema: "public".to_string(),
+        table_name: "t".to_string(),
+        fields: vec![],
+        zoom_fields: None,
+        generalization: None,
+        filter: None,
+        srid: None,
+        tile_matrix_set: None,
+        geom: None,
+        id_column: None,
+        sql_mode: None,
+        buffer: None,
+        extent: None,
+        zmin: None,
+        zmax: None,
+        zmax_do_not_simplify: None,
+        buffer_do_not_simplify: None,
+        extent_do_not_simplify: None,
+        clip_geom: None,
+        delete_cache_on_start: None,
+        max_cache_age: None,
+        max_records: None,
+        priority: None,
+        priority_order: None,
+        cluster_zoom: None,
+        cluster_radius: None,
+        cluster_aggregates: None,
+        bin_shape: None,
+        bin_size: None,
+        bin_aggregates: None,
+        query_timeout_ms: None,
+        notify_changes: None,
+        published: true,
+        url: None,
+        groups: None,
+    }
+}
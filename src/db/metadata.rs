@@ -1,10 +1,13 @@
 use crate::{
     error::{AppError, AppResult},
     get_db_registry,
-    models::catalog::Layer,
+    models::catalog::{Layer, SourceKind},
 };
 use serde::Serialize;
-use sqlx::{FromRow, PgPool};
+use sqlx::{
+    AssertSqlSafe, Column, Executor, FromRow, PgPool, SqlSafeStr, Statement, TypeInfo,
+    postgres::PgTypeInfo,
+};
 
 #[derive(FromRow, Serialize, Debug)]
 pub struct Schema {
@@ -17,12 +20,24 @@ pub struct Table {
     pub geometry: String,
 }
 
+#[derive(FromRow, Serialize, Debug)]
+pub struct Function {
+    pub name: String,
+}
+
 #[derive(FromRow, Serialize, Debug)]
 pub struct Field {
     pub name: String,
     pub udt: String,
 }
 
+impl Field {
+    /// Whether the column can be an MVT feature id.
+    pub fn is_integer(&self) -> bool {
+        matches!(self.udt.as_str(), "int2" | "int4" | "int8")
+    }
+}
+
 #[derive(FromRow, Serialize, Debug)]
 pub struct FieldWithComment {
     pub name: String,
@@ -91,6 +106,38 @@ pub async fn query_tables(database_id: &str, schema: String) -> AppResult<Vec<Ta
     Ok(data)
 }
 
+/// Functions of `schema` that can back a function layer:
+/// `fn(z integer, x integer, y integer, query_params json) RETURNS bytea`.
+pub async fn query_functions(database_id: &str, schema: String) -> AppResult<Vec<Function>> {
+    let pg_pool: PgPool = get_db_registry()
+        .get_pool(database_id)
+        .ok_or(AppError::DatabaseError("DB not found".to_string()))?
+        .clone();
+
+    let sql = r#"
+        SELECT DISTINCT p.proname as name
+        FROM pg_proc p
+        JOIN pg_namespace n
+            ON n.oid = p.pronamespace
+        WHERE n.nspname = $1
+          AND p.prokind = 'f'
+          AND p.prorettype = 'bytea'::regtype
+          AND p.pronargs = 4
+          AND p.proargtypes[0] = 'integer'::regtype
+          AND p.proargtypes[1] = 'integer'::regtype
+          AND p.proargtypes[2] = 'integer'::regtype
+          AND p.proargtypes[3] = 'json'::regtype
+        ORDER BY p.proname;
+    "#;
+
+    let data = sqlx::query_as::<_, Function>(sql)
+        .bind(schema)
+        .fetch_all(&pg_pool)
+        .await?;
+
+    Ok(data)
+}
+
 pub async fn query_fields(
     database_id: &str,
     schema: String,
@@ -125,6 +172,31 @@ pub async fn query_fields(
     Ok(data)
 }
 
+/// Whether the relation stores its own rows (a table or a materialized
+/// view), so every row has a `ctid`. Views and foreign tables do not.
+pub async fn query_is_table(database_id: &str, schema: &str, table: &str) -> AppResult<bool> {
+    let pg_pool: PgPool = get_db_registry()
+        .get_pool(database_id)
+        .ok_or(AppError::DatabaseError("DB not found".to_string()))?
+        .clone();
+
+    let sql = r#"
+        SELECT c.relkind IN ('r', 'm')
+        FROM pg_class c
+        JOIN pg_namespace n ON c.relnamespace = n.oid
+        WHERE n.nspname = $1
+          AND c.relname = $2;
+    "#;
+
+    let is_table = sqlx::query_scalar::<_, bool>(sql)
+        .bind(schema)
+        .bind(table)
+        .fetch_optional(&pg_pool)
+        .await?;
+
+    Ok(is_table.unwrap_or(false))
+}
+
 pub async fn query_fields_with_comments(
     database_id: &str,
     schema: String,
@@ -160,6 +232,32 @@ pub async fn query_fields_with_comments(
     Ok(data)
 }
 
+/// Result columns of a query, read from its prepared statement without
+/// running it. `param_types` types each `$n` parameter, used or not.
+pub async fn query_result_fields(
+    database_id: &str,
+    sql: String,
+    param_types: &[PgTypeInfo],
+) -> AppResult<Vec<Field>> {
+    let pg_pool: PgPool = get_db_registry()
+        .get_pool(database_id)
+        .ok_or(AppError::DatabaseError("DB not found".to_string()))?
+        .clone();
+
+    let statement = pg_pool
+        .prepare_with(AssertSqlSafe(sql).into_sql_str(), param_types)
+        .await?;
+
+    Ok(statement
+        .columns()
+        .iter()
+        .map(|column| Field {
+            name: column.name().to_string(),
+            udt: column.type_info().name().to_lowercase(),
+        })
+        .collect())
+}
+
 pub async fn query_srid(
     database_id: &str,
     schema: String,
@@ -190,6 +288,16 @@ pub async fn query_srid(
 }
 
 pub async fn query_extent(layer: &Layer) -> AppResult<Extent> {
+    // A function's or a query's output has no table to measure.
+    if matches!(layer.source_kind, SourceKind::Function | SourceKind::Sql) {
+        return Ok(Extent {
+            xmin: -180.0,
+            ymin: -85.051_128_779_806_6,
+            xmax: 180.0,
+            ymax: 85.051_128_779_806_6,
+        });
+    }
+
     let pg_pool: PgPool = get_db_registry()
         .get_pool(&layer.database_id)
         .ok_or(AppError::DatabaseError("DB not found".to_string()))?
@@ -241,3 +349,33 @@ pub async fn query_extent(layer: &Layer) -> AppResult<Extent> {
 
     Ok(extent)
 }
+
+/// Bounds of a WGS84 bbox in `srid`, for matrix sets in other projections.
+/// The edges are densified first, so the bounds cover the whole projected
+/// area and not only its corners.
+pub async fn transform_bbox(database_id: &str, bbox: [f64; 4], srid: u32) -> AppResult<Extent> {
+    let pg_pool: PgPool = get_db_registry()
+        .get_pool(database_id)
+        .ok_or(AppError::DatabaseError("DB not found".to_string()))?
+        .clone();
+
+    let sql = r#"
+        SELECT
+            ST_XMin(box) as xmin, ST_YMin(box) as ymin,
+            ST_XMax(box) as xmax, ST_YMax(box) as ymax
+        FROM (
+            SELECT ST_Transform(ST_Segmentize(ST_MakeEnvelope($1, $2, $3, $4, 4326), 0.1), $5) as box
+        ) as sub
+    "#;
+
+    let extent = sqlx::query_as::<_, Extent>(sql)
+        .bind(bbox[0])
+        .bind(bbox[1])
+        .bind(bbox[2])
+        .bind(bbox[3])
+        .bind(srid as i32)
+        .fetch_one(&pg_pool)
+        .await?;
+
+    Ok(extent)
+}
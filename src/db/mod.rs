@@ -1,5 +1,13 @@
 pub mod connection;
 pub mod metadata;
+pub mod pmtiles;
+pub mod static_tiles;
+pub mod triggers;
+
+/// Double-quoted SQL identifier, with embedded quotes doubled.
+pub fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
 
 #[cfg(test)]
 mod tests;
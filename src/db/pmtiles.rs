@@ -0,0 +1,553 @@
+//! Reader for PMTiles v3 archives.
+//!
+//! Only the byte ranges a request needs are read from disk: the header and
+//! root directory once when the archive is opened, leaf directories on
+//! demand (kept in memory afterwards) and the tile itself.
+//! See <https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md>.
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use std::collections::HashMap;
+use std::io::{Read, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::{
+    db::static_tiles::{TilesetMetadata, parse_vector_layer_fields},
+    error::{AppError, AppResult},
+};
+
+pub const HEADER_LEN: usize = 127;
+const MAGIC: &[u8; 7] = b"PMTiles";
+/// Root plus at most three levels of leaves, as allowed by the spec.
+const MAX_DIRECTORY_DEPTH: usize = 4;
+/// Leaf directories kept in memory per archive before the cache is reset.
+const LEAF_CACHE_ENTRIES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Unknown,
+    None,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl Compression {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::None,
+            2 => Self::Gzip,
+            3 => Self::Brotli,
+            4 => Self::Zstd,
+            _ => Self::Unknown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub root_offset: u64,
+    pub root_length: u64,
+    pub metadata_offset: u64,
+    pub metadata_length: u64,
+    pub leaf_offset: u64,
+    pub leaf_length: u64,
+    pub data_offset: u64,
+    pub data_length: u64,
+    pub internal_compression: Compression,
+    pub tile_compression: Compression,
+    pub tile_type: u8,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    /// west, south, east, north in degrees.
+    pub bounds: [f64; 4],
+}
+
+impl Header {
+    pub fn parse(buf: &[u8]) -> AppResult<Self> {
+        if buf.len() < HEADER_LEN || &buf[..7] != MAGIC {
+            return Err(AppError::InvalidInput("not a PMTiles archive".to_string()));
+        }
+        if buf[7] != 3 {
+            return Err(AppError::InvalidInput(format!(
+                "unsupported PMTiles version {}",
+                buf[7]
+            )));
+        }
+        // Tiles are served as stored or inflated here: zstd would reach
+        // clients still compressed.
+        if buf[97] == 4 || buf[98] == 4 {
+            return Err(AppError::InvalidInput(
+                "zstd-compressed PMTiles archives are not supported".to_string(),
+            ));
+        }
+        let u64_at = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+        let coord_at =
+            |at: usize| i32::from_le_bytes(buf[at..at + 4].try_into().unwrap()) as f64 / 1e7;
+
+        Ok(Self {
+            root_offset: u64_at(8),
+            root_length: u64_at(16),
+            metadata_offset: u64_at(24),
+            metadata_length: u64_at(32),
+            leaf_offset: u64_at(40),
+            leaf_length: u64_at(48),
+            data_offset: u64_at(56),
+            data_length: u64_at(64),
+            internal_compression: Compression::from_u8(buf[97]),
+            tile_compression: Compression::from_u8(buf[98]),
+            tile_type: buf[99],
+            min_zoom: buf[100],
+            max_zoom: buf[101],
+            bounds: [coord_at(102), coord_at(106), coord_at(110), coord_at(114)],
+        })
+    }
+}
+
+/// One directory entry. `run_length == 0` points to a leaf directory,
+/// otherwise `run_length` consecutive tile ids share the same tile data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    pub tile_id: u64,
+    pub offset: u64,
+    pub length: u32,
+    pub run_length: u32,
+}
+
+/// Hilbert-curve tile id: tiles of lower zooms come first, then the
+/// position of (x, y) along the curve at zoom `z`.
+pub fn zxy_to_tile_id(z: u8, x: u32, y: u32) -> u64 {
+    let base: u64 = (0..z).map(|level| 1u64 << (2 * level)).sum();
+    let n = 1u64 << z;
+    let (mut x, mut y) = (x as u64, y as u64);
+    let mut d = 0u64;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    base + d
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> AppResult<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *buf
+            .get(*pos)
+            .ok_or_else(|| AppError::InvalidInput("truncated PMTiles directory".to_string()))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(AppError::InvalidInput(
+        "malformed varint in PMTiles directory".to_string(),
+    ))
+}
+
+/// Decodes an (already decompressed) directory: the entry count followed
+/// by columns of delta-encoded tile ids, run lengths, lengths and offsets.
+pub fn parse_directory(buf: &[u8]) -> AppResult<Vec<Entry>> {
+    let mut pos = 0;
+    let count = read_varint(buf, &mut pos)? as usize;
+    // Entries take at least one byte per column; bounds the allocation.
+    if count > buf.len() {
+        return Err(AppError::InvalidInput(
+            "malformed PMTiles directory".to_string(),
+        ));
+    }
+    let mut entries = vec![
+        Entry {
+            tile_id: 0,
+            offset: 0,
+            length: 0,
+            run_length: 0,
+        };
+        count
+    ];
+
+    let malformed = || AppError::InvalidInput("malformed PMTiles directory".to_string());
+    let mut last_id = 0u64;
+    for entry in entries.iter_mut() {
+        last_id = last_id
+            .checked_add(read_varint(buf, &mut pos)?)
+            .ok_or_else(malformed)?;
+        entry.tile_id = last_id;
+    }
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(buf, &mut pos)? as u32;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(buf, &mut pos)? as u32;
+    }
+    for i in 0..count {
+        let value = read_varint(buf, &mut pos)?;
+        entries[i].offset = if value == 0 && i > 0 {
+            entries[i - 1]
+                .offset
+                .checked_add(u64::from(entries[i - 1].length))
+                .ok_or_else(malformed)?
+        } else {
+            value.saturating_sub(1)
+        };
+    }
+    Ok(entries)
+}
+
+/// Entry covering `tile_id`: either a tile run containing it or the leaf
+/// directory that may contain it.
+pub fn find_entry(entries: &[Entry], tile_id: u64) -> Option<Entry> {
+    let idx = entries.partition_point(|entry| entry.tile_id <= tile_id);
+    let entry = *entries.get(idx.checked_sub(1)?)?;
+    (entry.run_length == 0 || tile_id - entry.tile_id < u64::from(entry.run_length))
+        .then_some(entry)
+}
+
+pub fn decompress(data: Vec<u8>, compression: Compression) -> AppResult<Vec<u8>> {
+    let mut out = Vec::new();
+    match compression {
+        Compression::None | Compression::Unknown => return Ok(data),
+        Compression::Gzip => {
+            GzDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+        }
+        Compression::Brotli => {
+            brotli::Decompressor::new(data.as_slice(), 4096).read_to_end(&mut out)?;
+        }
+        Compression::Zstd => {
+            return Err(AppError::InvalidInput(
+                "zstd-compressed PMTiles archives are not supported".to_string(),
+            ));
+        }
+    }
+    Ok(out)
+}
+
+async fn read_range(path: &Path, offset: u64, length: u64) -> AppResult<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut buf = vec![0u8; length as usize];
+    file.read_exact(&mut buf).await?;
+    Ok(buf)
+}
+
+/// An opened archive: header and root directory are read once, leaf
+/// directories are cached as they are visited.
+#[derive(Debug)]
+pub struct PmtilesArchive {
+    path: PathBuf,
+    header: Header,
+    root: Arc<Vec<Entry>>,
+    leaves: RwLock<HashMap<u64, Arc<Vec<Entry>>>>,
+}
+
+impl PmtilesArchive {
+    pub async fn open(path: impl Into<PathBuf>) -> AppResult<Self> {
+        let path = path.into();
+        let header = Header::parse(&read_range(&path, 0, HEADER_LEN as u64).await?)?;
+        let root = read_range(&path, header.root_offset, header.root_length).await?;
+        let root = parse_directory(&decompress(root, header.internal_compression)?)?;
+
+        Ok(Self {
+            path,
+            header,
+            root: Arc::new(root),
+            leaves: RwLock::new(HashMap::new()),
+        })
+    }
+
+    async fn leaf_directory(&self, offset: u64, length: u32) -> AppResult<Arc<Vec<Entry>>> {
+        if let Some(dir) = self
+            .leaves
+            .read()
+            .ok()
+            .and_then(|l| l.get(&offset).cloned())
+        {
+            return Ok(dir);
+        }
+
+        let raw = read_range(
+            &self.path,
+            self.header.leaf_offset + offset,
+            u64::from(length),
+        )
+        .await?;
+        let dir = Arc::new(parse_directory(&decompress(
+            raw,
+            self.header.internal_compression,
+        )?)?);
+
+        if let Ok(mut leaves) = self.leaves.write() {
+            if leaves.len() >= LEAF_CACHE_ENTRIES {
+                leaves.clear();
+            }
+            leaves.insert(offset, dir.clone());
+        }
+        Ok(dir)
+    }
+
+    /// Tile bytes for XYZ coordinates, gzip-compressed when the archive
+    /// stores them that way, or `None` when the archive has no such tile.
+    pub async fn get_tile(&self, z: u32, x: u32, y: u32) -> AppResult<Option<Bytes>> {
+        if z < u32::from(self.header.min_zoom)
+            || z > u32::from(self.header.max_zoom)
+            || x >= 1 << z
+            || y >= 1 << z
+        {
+            return Ok(None);
+        }
+        let tile_id = zxy_to_tile_id(z as u8, x, y);
+
+        let mut dir = self.root.clone();
+        for _ in 0..MAX_DIRECTORY_DEPTH {
+            let Some(entry) = find_entry(&dir, tile_id) else {
+                return Ok(None);
+            };
+            if entry.run_length > 0 {
+                let data = read_range(
+                    &self.path,
+                    self.header.data_offset + entry.offset,
+                    u64::from(entry.length),
+                )
+                .await?;
+                // Gzip is passed through and served with Content-Encoding.
+                let data = match self.header.tile_compression {
+                    Compression::Gzip => data,
+                    other => decompress(data, other)?,
+                };
+                return Ok(Some(data.into()));
+            }
+            dir = self.leaf_directory(entry.offset, entry.length).await?;
+        }
+        Ok(None)
+    }
+
+    /// Bounds and zooms from the header, fields from the `vector_layers`
+    /// entry of the JSON metadata.
+    pub async fn metadata(&self) -> AppResult<TilesetMetadata> {
+        let header = &self.header;
+        let bounds = header.bounds;
+        let fields = if header.metadata_length > 0 {
+            let raw =
+                read_range(&self.path, header.metadata_offset, header.metadata_length).await?;
+            let json = decompress(raw, header.internal_compression)?;
+            parse_vector_layer_fields(&String::from_utf8_lossy(&json))
+        } else {
+            Default::default()
+        };
+
+        Ok(TilesetMetadata {
+            bounds: (bounds[0] < bounds[2] && bounds[1] < bounds[3]).then_some(bounds),
+            minzoom: Some(u32::from(header.min_zoom)),
+            maxzoom: Some(u32::from(header.max_zoom)),
+            fields,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression as GzLevel, write::GzEncoder};
+    use std::io::Write;
+
+    fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            buf.push((value as u8 & 0x7f) | 0x80);
+            value >>= 7;
+        }
+        buf.push(value as u8);
+    }
+
+    fn serialize_directory(entries: &[Entry]) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, entries.len() as u64);
+        let mut last_id = 0;
+        for entry in entries {
+            write_varint(&mut buf, entry.tile_id - last_id);
+            last_id = entry.tile_id;
+        }
+        for entry in entries {
+            write_varint(&mut buf, u64::from(entry.run_length));
+        }
+        for entry in entries {
+            write_varint(&mut buf, u64::from(entry.length));
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let contiguous =
+                i > 0 && entry.offset == entries[i - 1].offset + u64::from(entries[i - 1].length);
+            write_varint(&mut buf, if contiguous { 0 } else { entry.offset + 1 });
+        }
+        buf
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), GzLevel::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn entry(tile_id: u64, offset: u64, length: u32, run_length: u32) -> Entry {
+        Entry {
+            tile_id,
+            offset,
+            length,
+            run_length,
+        }
+    }
+
+    #[test]
+    fn tile_ids_follow_the_hilbert_curve() {
+        assert_eq!(zxy_to_tile_id(0, 0, 0), 0);
+        assert_eq!(zxy_to_tile_id(1, 0, 0), 1);
+        assert_eq!(zxy_to_tile_id(1, 0, 1), 2);
+        assert_eq!(zxy_to_tile_id(1, 1, 1), 3);
+        assert_eq!(zxy_to_tile_id(1, 1, 0), 4);
+        assert_eq!(zxy_to_tile_id(2, 0, 0), 5);
+        assert_eq!(zxy_to_tile_id(12, 3423, 1763), 19_078_479);
+    }
+
+    #[test]
+    fn directory_round_trips_with_contiguous_offsets() {
+        let entries = vec![
+            entry(0, 0, 10, 1),
+            entry(1, 10, 20, 2),
+            entry(5, 100, 7, 1),
+            entry(9, 0, 40, 0),
+        ];
+        assert_eq!(
+            parse_directory(&serialize_directory(&entries)).unwrap(),
+            entries
+        );
+        assert!(parse_directory(&[5, 1]).is_err());
+        assert!(parse_directory(&[]).is_err());
+    }
+
+    #[test]
+    fn find_entry_honours_run_lengths_and_leaves() {
+        let entries = vec![entry(1, 0, 10, 3), entry(10, 0, 50, 0)];
+        assert_eq!(find_entry(&entries, 0), None);
+        assert_eq!(find_entry(&entries, 3).map(|e| e.tile_id), Some(1));
+        assert_eq!(find_entry(&entries, 4), None);
+        assert_eq!(find_entry(&entries, 1000).map(|e| e.tile_id), Some(10));
+    }
+
+    #[test]
+    fn header_rejects_foreign_files() {
+        assert!(Header::parse(b"SQLite format 3").is_err());
+        let mut buf = vec![0u8; HEADER_LEN];
+        buf[..7].copy_from_slice(MAGIC);
+        buf[7] = 2;
+        assert!(Header::parse(&buf).is_err());
+
+        buf[7] = 3;
+        assert!(Header::parse(&buf).is_ok());
+        buf[98] = 4;
+        assert!(Header::parse(&buf).is_err());
+    }
+
+    #[test]
+    fn directory_rejects_overflowing_tile_ids() {
+        let mut buf = Vec::new();
+        write_varint(&mut buf, 2);
+        write_varint(&mut buf, u64::MAX);
+        write_varint(&mut buf, 1);
+        for _ in 0..6 {
+            write_varint(&mut buf, 1);
+        }
+        assert!(matches!(parse_directory(&buf), Err(AppError::InvalidInput(_))));
+    }
+
+    /// Writes an archive whose root points to one gzip leaf directory, the
+    /// layout tools produce for large tilesets.
+    fn write_fixture(path: &Path) {
+        let tiles: [&[u8]; 2] = [b"world", b"shared"];
+        let data: Vec<u8> = tiles.concat();
+        // z1 tiles 1..=4 share one blob via run length.
+        let leaf = gzip(&serialize_directory(&[
+            entry(0, 0, 5, 1),
+            entry(1, 5, 6, 4),
+        ]));
+        let root = gzip(&serialize_directory(&[entry(0, 0, leaf.len() as u32, 0)]));
+        let metadata = gzip(br#"{"vector_layers":[{"id":"roads","fields":{"name":"String"}}]}"#);
+
+        let root_offset = HEADER_LEN as u64;
+        let metadata_offset = root_offset + root.len() as u64;
+        let leaf_offset = metadata_offset + metadata.len() as u64;
+        let data_offset = leaf_offset + leaf.len() as u64;
+
+        let mut header = vec![0u8; HEADER_LEN];
+        header[..7].copy_from_slice(MAGIC);
+        header[7] = 3;
+        for (at, value) in [
+            (8, root_offset),
+            (16, root.len() as u64),
+            (24, metadata_offset),
+            (32, metadata.len() as u64),
+            (40, leaf_offset),
+            (48, leaf.len() as u64),
+            (56, data_offset),
+            (64, data.len() as u64),
+        ] {
+            header[at..at + 8].copy_from_slice(&value.to_le_bytes());
+        }
+        header[97] = 2; // internal gzip
+        header[98] = 1; // tiles uncompressed
+        header[99] = 1; // mvt
+        header[100] = 0;
+        header[101] = 1;
+        for (at, degrees) in [(102, -60.0), (106, -40.0), (110, -50.0), (114, -30.0)] {
+            let value = (degrees * 1e7_f64) as i32;
+            header[at..at + 4].copy_from_slice(&value.to_le_bytes());
+        }
+
+        let archive = [header, root, metadata, leaf, data].concat();
+        std::fs::write(path, archive).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_tiles_through_leaf_directories() {
+        let path =
+            std::env::temp_dir().join(format!("mvt-rs-{}.pmtiles", uuid::Uuid::new_v4().simple()));
+        write_fixture(&path);
+
+        let archive = PmtilesArchive::open(&path).await.unwrap();
+        assert_eq!(archive.header.max_zoom, 1);
+        assert_eq!(
+            archive.get_tile(0, 0, 0).await.unwrap(),
+            Some(Bytes::from_static(b"world"))
+        );
+        for (x, y) in [(0, 0), (0, 1), (1, 1), (1, 0)] {
+            assert_eq!(
+                archive.get_tile(1, x, y).await.unwrap(),
+                Some(Bytes::from_static(b"shared")),
+                "1/{x}/{y}"
+            );
+        }
+        assert_eq!(archive.get_tile(2, 0, 0).await.unwrap(), None);
+        assert_eq!(archive.get_tile(1, 2, 0).await.unwrap(), None);
+        assert_eq!(archive.leaves.read().unwrap().len(), 1);
+
+        let metadata = archive.metadata().await.unwrap();
+        assert_eq!(metadata.bounds, Some([-60.0, -40.0, -50.0, -30.0]));
+        assert_eq!((metadata.minzoom, metadata.maxzoom), (Some(0), Some(1)));
+        assert_eq!(
+            metadata.fields.get("name").map(String::as_str),
+            Some("String")
+        );
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
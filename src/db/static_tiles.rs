@@ -0,0 +1,406 @@
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use sqlx::{
+    Row,
+    sqlite::{SqliteConnectOptions, SqlitePool, SqlitePoolOptions},
+};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::{
+    db::pmtiles::PmtilesArchive,
+    error::{AppError, AppResult},
+    models::catalog::{Layer, SourceKind},
+};
+
+/// Bounds, zooms and fields advertised by a pre-rendered tileset. Every
+/// member is optional: TileJSON falls back to the catalog config for
+/// anything the file does not declare.
+#[derive(Debug, Default, Clone)]
+pub struct TilesetMetadata {
+    pub bounds: Option<[f64; 4]>,
+    pub minzoom: Option<u32>,
+    pub maxzoom: Option<u32>,
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Read-only handles to the tile files deployed under `paths.static_tiles`.
+/// Files are opened lazily on first use and kept open for the lifetime of
+/// the process; a replaced file is picked up on restart.
+#[derive(Debug)]
+pub struct StaticTileRegistry {
+    dir: PathBuf,
+    mbtiles: RwLock<HashMap<String, SqlitePool>>,
+    pmtiles: RwLock<HashMap<String, Arc<PmtilesArchive>>>,
+}
+
+impl StaticTileRegistry {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            mbtiles: RwLock::new(HashMap::new()),
+            pmtiles: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// File names in the static tiles directory with the given extension,
+    /// sorted. A missing directory yields an empty list.
+    pub fn list_files(&self, extension: &str) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut files: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().is_file())
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| {
+                Path::new(name)
+                    .extension()
+                    .is_some_and(|ext| ext.eq_ignore_ascii_case(extension))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    /// Full path of `file` inside the static tiles directory. Only bare file
+    /// names are accepted so a layer can never point outside the directory.
+    pub fn resolve(&self, file: &str) -> AppResult<PathBuf> {
+        if !is_plain_file_name(file) {
+            return Err(AppError::InvalidInput(format!(
+                "static file '{file}' must be a file name inside the static tiles directory"
+            )));
+        }
+        let path = self.dir.join(file);
+        if !path.is_file() {
+            return Err(AppError::NotFound(format!(
+                "static file '{file}' not found"
+            )));
+        }
+        Ok(path)
+    }
+
+    fn mbtiles_pool(&self, file: &str) -> AppResult<SqlitePool> {
+        if let Some(pool) = self.mbtiles.read().ok().and_then(|m| m.get(file).cloned()) {
+            return Ok(pool);
+        }
+
+        let path = self.resolve(file)?;
+        let opts = SqliteConnectOptions::new()
+            .filename(path)
+            .read_only(true)
+            .immutable(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(4)
+            .connect_lazy_with(opts);
+
+        if let Ok(mut pools) = self.mbtiles.write() {
+            pools
+                .entry(file.to_string())
+                .or_insert_with(|| pool.clone());
+        }
+        Ok(pool)
+    }
+
+    async fn pmtiles_archive(&self, file: &str) -> AppResult<Arc<PmtilesArchive>> {
+        if let Some(archive) = self.pmtiles.read().ok().and_then(|m| m.get(file).cloned()) {
+            return Ok(archive);
+        }
+
+        let archive = Arc::new(PmtilesArchive::open(self.resolve(file)?).await?);
+        if let Ok(mut archives) = self.pmtiles.write() {
+            return Ok(archives.entry(file.to_string()).or_insert(archive).clone());
+        }
+        Ok(archive)
+    }
+
+    /// Tile bytes as stored in the file (possibly gzip-compressed), or
+    /// `None` when the file has no tile at that position. `y` is XYZ.
+    pub async fn read_tile(
+        &self,
+        layer: &Layer,
+        z: u32,
+        x: u32,
+        y: u32,
+    ) -> AppResult<Option<Bytes>> {
+        match layer.source_kind {
+            SourceKind::Mbtiles => {
+                let pool = self.mbtiles_pool(&layer.get_static_file())?;
+                read_mbtiles_tile(&pool, z, x, y).await
+            }
+            SourceKind::Pmtiles => {
+                let archive = self.pmtiles_archive(&layer.get_static_file()).await?;
+                archive.get_tile(z, x, y).await
+            }
+            SourceKind::Postgis | SourceKind::Sql | SourceKind::Function => Err(AppError::InvalidInput(format!(
+                "layer '{}' is not a static tile source",
+                layer.name
+            ))),
+        }
+    }
+
+    pub async fn read_metadata(&self, layer: &Layer) -> AppResult<TilesetMetadata> {
+        match layer.source_kind {
+            SourceKind::Mbtiles => {
+                let pool = self.mbtiles_pool(&layer.get_static_file())?;
+                read_mbtiles_metadata(&pool).await
+            }
+            SourceKind::Pmtiles => {
+                let archive = self.pmtiles_archive(&layer.get_static_file()).await?;
+                archive.metadata().await
+            }
+            SourceKind::Postgis | SourceKind::Sql | SourceKind::Function => Err(AppError::InvalidInput(format!(
+                "layer '{}' is not a static tile source",
+                layer.name
+            ))),
+        }
+    }
+}
+
+fn is_plain_file_name(file: &str) -> bool {
+    !file.is_empty() && file != "." && file != ".." && !file.contains(['/', '\\'])
+}
+
+/// MBTiles stores rows in TMS order (origin bottom-left); routes use XYZ.
+pub fn xyz_to_tms_y(z: u32, y: u32) -> u32 {
+    (1u32 << z).saturating_sub(1).saturating_sub(y)
+}
+
+pub fn is_gzip(tile: &[u8]) -> bool {
+    tile.len() >= 2 && tile[0] == 0x1f && tile[1] == 0x8b
+}
+
+/// Inflates a gzip-compressed tile; anything else is returned unchanged.
+pub fn decompress_tile(tile: Bytes) -> AppResult<Bytes> {
+    if !is_gzip(&tile) {
+        return Ok(tile);
+    }
+    let mut out = Vec::new();
+    GzDecoder::new(tile.as_ref()).read_to_end(&mut out)?;
+    Ok(out.into())
+}
+
+/// Parses the MBTiles `bounds` value (`"west,south,east,north"`).
+pub fn parse_bounds(value: &str) -> Option<[f64; 4]> {
+    let parts: Vec<f64> = value
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .ok()?;
+    let bounds: [f64; 4] = parts.try_into().ok()?;
+    (bounds[0] < bounds[2] && bounds[1] < bounds[3]).then_some(bounds)
+}
+
+/// Field map of the first `vector_layers` entry of the MBTiles `json`
+/// metadata value or the PMTiles JSON metadata, the shape written by
+/// tippecanoe and ogr2ogr.
+pub fn parse_vector_layer_fields(json: &str) -> BTreeMap<String, String> {
+    serde_json::from_str::<serde_json::Value>(json)
+        .ok()
+        .and_then(|doc| {
+            doc.get("vector_layers")?
+                .get(0)?
+                .get("fields")?
+                .as_object()
+                .cloned()
+        })
+        .map(|fields| {
+            fields
+                .into_iter()
+                .map(|(name, kind)| {
+                    let kind = kind
+                        .as_str()
+                        .map(str::to_string)
+                        .unwrap_or_else(|| kind.to_string());
+                    (name, kind)
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+async fn read_mbtiles_tile(pool: &SqlitePool, z: u32, x: u32, y: u32) -> AppResult<Option<Bytes>> {
+    let row = sqlx::query(
+        "SELECT tile_data FROM tiles WHERE zoom_level = ? AND tile_column = ? AND tile_row = ?",
+    )
+    .bind(z as i64)
+    .bind(x as i64)
+    .bind(xyz_to_tms_y(z, y) as i64)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(row.map(|row| Bytes::from(row.get::<Vec<u8>, _>("tile_data"))))
+}
+
+async fn read_mbtiles_metadata(pool: &SqlitePool) -> AppResult<TilesetMetadata> {
+    let rows = sqlx::query("SELECT name, value FROM metadata")
+        .fetch_all(pool)
+        .await?;
+
+    let mut metadata = TilesetMetadata::default();
+    for row in rows {
+        let name: String = row.get("name");
+        let value: String = row.get("value");
+        match name.as_str() {
+            "bounds" => metadata.bounds = parse_bounds(&value),
+            "minzoom" => metadata.minzoom = value.trim().parse().ok(),
+            "maxzoom" => metadata.maxzoom = value.trim().parse().ok(),
+            "json" => metadata.fields = parse_vector_layer_fields(&value),
+            _ => {}
+        }
+    }
+    Ok(metadata)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write;
+
+    #[test]
+    fn xyz_to_tms_flips_rows() {
+        assert_eq!(xyz_to_tms_y(0, 0), 0);
+        assert_eq!(xyz_to_tms_y(1, 0), 1);
+        assert_eq!(xyz_to_tms_y(1, 1), 0);
+        assert_eq!(xyz_to_tms_y(14, 5000), (1 << 14) - 1 - 5000);
+    }
+
+    #[test]
+    fn gzip_magic_bytes_are_detected_and_inflated() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"protobuf").unwrap();
+        let gz = encoder.finish().unwrap();
+
+        assert!(is_gzip(&gz));
+        assert!(!is_gzip(b"protobuf"));
+        assert!(!is_gzip(&[0x1f]));
+        assert_eq!(
+            decompress_tile(Bytes::from(gz)).unwrap(),
+            Bytes::from_static(b"protobuf")
+        );
+        assert_eq!(
+            decompress_tile(Bytes::from_static(b"raw")).unwrap(),
+            Bytes::from_static(b"raw")
+        );
+    }
+
+    #[test]
+    fn parse_bounds_accepts_valid_and_rejects_malformed() {
+        assert_eq!(
+            parse_bounds("-60, -40,-50,-30"),
+            Some([-60.0, -40.0, -50.0, -30.0])
+        );
+        assert_eq!(parse_bounds("-60,-40,-50"), None);
+        assert_eq!(parse_bounds("a,b,c,d"), None);
+        assert_eq!(parse_bounds("10,0,-10,5"), None);
+        assert_eq!(parse_bounds(""), None);
+    }
+
+    #[test]
+    fn parse_vector_layer_fields_reads_first_layer() {
+        let json = r#"{"vector_layers":[
+            {"id":"roads","fields":{"name":"String","lanes":"Number"}},
+            {"id":"other","fields":{"ignored":"String"}}
+        ]}"#;
+        let fields = parse_vector_layer_fields(json);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields.get("lanes").map(String::as_str), Some("Number"));
+        assert!(parse_vector_layer_fields("not json").is_empty());
+        assert!(parse_vector_layer_fields(r#"{"vector_layers":[]}"#).is_empty());
+    }
+
+    #[test]
+    fn resolve_rejects_paths_outside_the_directory() {
+        let registry = StaticTileRegistry::new(std::env::temp_dir());
+        for file in [
+            "",
+            "..",
+            "../etc/passwd",
+            "sub/file.mbtiles",
+            "a\\b.mbtiles",
+        ] {
+            assert!(
+                matches!(registry.resolve(file), Err(AppError::InvalidInput(_))),
+                "{file}"
+            );
+        }
+        assert!(matches!(
+            registry.resolve("mvt-rs-missing.mbtiles"),
+            Err(AppError::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn reads_tiles_and_metadata_from_mbtiles_file() {
+        let dir =
+            std::env::temp_dir().join(format!("mvt-rs-mbtiles-{}", uuid::Uuid::new_v4().simple()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("fixture.mbtiles");
+
+        let writer = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(
+                SqliteConnectOptions::new()
+                    .filename(&path)
+                    .create_if_missing(true),
+            )
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE metadata (name TEXT, value TEXT)")
+            .execute(&writer)
+            .await
+            .unwrap();
+        sqlx::query("CREATE TABLE tiles (zoom_level INTEGER, tile_column INTEGER, tile_row INTEGER, tile_data BLOB)")
+            .execute(&writer)
+            .await
+            .unwrap();
+        for (name, value) in [
+            ("bounds", "-60,-40,-50,-30"),
+            ("minzoom", "2"),
+            ("maxzoom", "9"),
+            (
+                "json",
+                r#"{"vector_layers":[{"id":"x","fields":{"name":"String"}}]}"#,
+            ),
+        ] {
+            sqlx::query("INSERT INTO metadata VALUES (?, ?)")
+                .bind(name)
+                .bind(value)
+                .execute(&writer)
+                .await
+                .unwrap();
+        }
+        // XYZ 1/0/0 is TMS row 1.
+        sqlx::query("INSERT INTO tiles VALUES (1, 0, 1, ?)")
+            .bind(b"tile".to_vec())
+            .execute(&writer)
+            .await
+            .unwrap();
+        writer.close().await;
+
+        let registry = StaticTileRegistry::new(&dir);
+        assert_eq!(registry.list_files("mbtiles"), vec!["fixture.mbtiles"]);
+
+        let pool = registry.mbtiles_pool("fixture.mbtiles").unwrap();
+        assert_eq!(
+            read_mbtiles_tile(&pool, 1, 0, 0).await.unwrap(),
+            Some(Bytes::from_static(b"tile"))
+        );
+        assert_eq!(read_mbtiles_tile(&pool, 1, 0, 1).await.unwrap(), None);
+
+        let metadata = read_mbtiles_metadata(&pool).await.unwrap();
+        assert_eq!(metadata.bounds, Some([-60.0, -40.0, -50.0, -30.0]));
+        assert_eq!(metadata.minzoom, Some(2));
+        assert_eq!(metadata.maxzoom, Some(9));
+        assert_eq!(
+            metadata.fields.get("name").map(String::as_str),
+            Some("String")
+        );
+
+        pool.close().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,271 @@
+//! Change triggers of layers with `notify_changes`: each insert, update or
+//! delete statement on the layer's table sends the bbox (in EPSG:4326) of
+//! the rows it touched, before and after, on `CHANGES_CHANNEL`, and a
+//! truncate sends `truncate`. The payload is
+//! `{"layer": id, "old": [w, s, e, n] | null, "new": ... }`. A statement
+//! touching a geometry without SRID also sends `truncate`.
+
+use crate::{
+    db::quote_ident,
+    error::{AppError, AppResult},
+    get_db_registry,
+    models::catalog::Layer,
+};
+use sqlx::{AssertSqlSafe, PgPool};
+use tracing::warn;
+
+pub const CHANGES_CHANNEL: &str = "mvt_server_changes";
+
+/// Function the triggers run, created in the table's schema. Its arguments
+/// are the layer id and the geometry column.
+const NOTIFY_FUNCTION: &str = "mvt_server_notify_change";
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Statement triggers of a layer: name suffix, event and transition
+/// tables. A trigger with transition tables takes a single event.
+const TRIGGER_EVENTS: [(&str, &str, &str); 4] = [
+    ("insert", "INSERT", "REFERENCING NEW TABLE AS new_rows "),
+    (
+        "update",
+        "UPDATE",
+        "REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows ",
+    ),
+    ("delete", "DELETE", "REFERENCING OLD TABLE AS old_rows "),
+    ("truncate", "TRUNCATE", ""),
+];
+
+/// Prefix of the trigger names of a layer.
+pub fn trigger_name(layer_id: &str) -> String {
+    let id: String = layer_id
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || *c == '_')
+        .take(40)
+        .collect();
+    format!("mvt_server_{}", id.to_lowercase())
+}
+
+/// Names of the triggers of a layer, in `TRIGGER_EVENTS` order.
+fn trigger_names(layer_id: &str) -> Vec<String> {
+    let name = trigger_name(layer_id);
+    TRIGGER_EVENTS
+        .iter()
+        .map(|(suffix, _, _)| format!("{name}_{suffix}"))
+        .collect()
+}
+
+fn notify_function_sql(schema: &str) -> String {
+    format!(
+        r#"
+        CREATE OR REPLACE FUNCTION {schema}.{NOTIFY_FUNCTION}() RETURNS trigger
+        LANGUAGE plpgsql AS $fn$
+        DECLARE
+            -- Extent of the rows of a transition table, and whether any of
+            -- them has a geometry without SRID.
+            extent_sql text :=
+                'SELECT CASE WHEN b IS NOT NULL THEN
+                        json_build_array(ST_XMin(b), ST_YMin(b), ST_XMax(b), ST_YMax(b))
+                    END, no_srid
+                 FROM (
+                    SELECT ST_Extent(ST_Transform(g, 4326)) FILTER (WHERE ST_SRID(g) <> 0) AS b,
+                        coalesce(bool_or(ST_SRID(g) = 0), false) AS no_srid
+                    FROM (SELECT %I::geometry AS g FROM %I) AS r
+                    WHERE g IS NOT NULL AND NOT ST_IsEmpty(g)
+                 ) AS t';
+            old_bbox json;
+            new_bbox json;
+            no_srid boolean := false;
+        BEGIN
+            IF TG_OP = 'TRUNCATE' THEN
+                PERFORM pg_notify('{CHANGES_CHANNEL}',
+                    json_build_object('layer', TG_ARGV[0], 'truncate', true)::text);
+                RETURN NULL;
+            END IF;
+            IF TG_OP <> 'INSERT' THEN
+                EXECUTE format(extent_sql, TG_ARGV[1], 'old_rows') INTO old_bbox, no_srid;
+            END IF;
+            IF TG_OP <> 'DELETE' AND NOT no_srid THEN
+                EXECUTE format(extent_sql, TG_ARGV[1], 'new_rows') INTO new_bbox, no_srid;
+            END IF;
+            IF no_srid THEN
+                -- Such a geometry has no bbox in EPSG:4326: the whole layer
+                -- is invalidated.
+                PERFORM pg_notify('{CHANGES_CHANNEL}',
+                    json_build_object('layer', TG_ARGV[0], 'truncate', true)::text);
+                RETURN NULL;
+            END IF;
+            IF old_bbox IS NOT NULL OR new_bbox IS NOT NULL THEN
+                PERFORM pg_notify('{CHANGES_CHANNEL}',
+                    json_build_object('layer', TG_ARGV[0], 'old', old_bbox, 'new', new_bbox)::text);
+            END IF;
+            RETURN NULL;
+        END
+        $fn$
+        "#,
+        schema = quote_ident(schema),
+    )
+}
+
+/// Statements installing the function and the triggers of `layer`.
+pub fn install_trigger_sql(layer: &Layer) -> Vec<String> {
+    let schema = quote_ident(&layer.schema);
+    let table = format!("{schema}.{}", quote_ident(&layer.table_name));
+    let args = format!(
+        "{}, {}",
+        quote_literal(&layer.id),
+        quote_literal(&layer.get_geom())
+    );
+    let function = format!("{schema}.{NOTIFY_FUNCTION}({args})");
+    let mut sql = vec![notify_function_sql(&layer.schema)];
+    for (name, (_, event, transition)) in trigger_names(&layer.id).iter().zip(TRIGGER_EVENTS) {
+        sql.push(format!("DROP TRIGGER IF EXISTS {} ON {table}", quote_ident(name)));
+        sql.push(format!(
+            "CREATE TRIGGER {} AFTER {event} ON {table} {transition}\
+             FOR EACH STATEMENT EXECUTE FUNCTION {function}",
+            quote_ident(name)
+        ));
+    }
+    sql
+}
+
+fn layer_pool(layer: &Layer) -> AppResult<PgPool> {
+    Ok(get_db_registry()
+        .get_pool(&layer.database_id)
+        .ok_or(AppError::DatabaseError("DB not found".to_string()))?
+        .clone())
+}
+
+async fn triggers_exist(pool: &PgPool, layer: &Layer) -> AppResult<bool> {
+    let sql = r#"
+        SELECT count(*) FROM pg_trigger t
+        JOIN pg_class c ON c.oid = t.tgrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE n.nspname = $1 AND c.relname = $2 AND t.tgname = ANY($3)
+    "#;
+    let names = trigger_names(&layer.id);
+    let (count,): (i64,) = sqlx::query_as(sql)
+        .bind(&layer.schema)
+        .bind(&layer.table_name)
+        .bind(&names)
+        .fetch_one(pool)
+        .await?;
+    Ok(count == names.len() as i64)
+}
+
+/// (Re)creates the triggers of `layer`. Creating a trigger takes owning the
+/// table: when mvt-server's role cannot, triggers a table owner installed
+/// with the same name are used instead.
+pub async fn install_change_trigger(layer: &Layer) -> AppResult<()> {
+    let pool = layer_pool(layer)?;
+    let installed = async {
+        let mut tx = pool.begin().await?;
+        for sql in install_trigger_sql(layer) {
+            sqlx::query(AssertSqlSafe(sql)).execute(&mut *tx).await?;
+        }
+        tx.commit().await
+    }
+    .await;
+
+    match installed {
+        Ok(()) => Ok(()),
+        Err(e) if triggers_exist(&pool, layer).await? => {
+            warn!(
+                "change trigger of {}.{} not updated, using the existing one: {e}",
+                layer.schema, layer.table_name
+            );
+            Ok(())
+        }
+        Err(e) => Err(AppError::DatabaseError(format!(
+            "could not install the change trigger on {}.{}: {e}",
+            layer.schema, layer.table_name
+        ))),
+    }
+}
+
+/// Drops the triggers of `layer`, leaving the shared function in place.
+pub async fn drop_change_trigger(layer: &Layer) -> AppResult<()> {
+    let pool = layer_pool(layer)?;
+    let table = format!(
+        "{}.{}",
+        quote_ident(&layer.schema),
+        quote_ident(&layer.table_name)
+    );
+    for trigger in trigger_names(&layer.id) {
+        let sql = format!(
+            "DROP TRIGGER IF EXISTS {} ON {table}",
+            quote_ident(&trigger)
+        );
+        sqlx::query(AssertSqlSafe(sql)).execute(&pool).await?;
+    }
+    Ok(())
+}
+
+/// Follows a layer edit: drops the triggers the previous config left on a
+/// table it no longer reports, and installs the new config's. Either side
+/// is `None` when the layer is created or deleted.
+pub async fn sync_change_trigger(previous: Option<&Layer>, layer: Option<&Layer>) -> AppResult<()> {
+    let watched = layer.filter(|layer| layer.get_notify_changes());
+    if let Some(previous) = previous.filter(|previous| previous.get_notify_changes())
+        && !watched.is_some_and(|layer| {
+            (&layer.database_id, &layer.schema, &layer.table_name)
+                == (
+                    &previous.database_id,
+                    &previous.schema,
+                    &previous.table_name,
+                )
+        })
+        && let Err(e) = drop_change_trigger(previous).await
+    {
+        warn!(
+            "could not drop the change trigger on {}.{}: {e}",
+            previous.schema, previous.table_name
+        );
+    }
+    match watched {
+        Some(layer) => install_change_trigger(layer).await,
+        None => Ok(()),
+    }
+}
+
+/// Undoes `sync_change_trigger(previous, layer)` when the layer edit could
+/// not be saved, so the tables keep the triggers of the saved config.
+pub async fn revert_change_trigger(previous: Option<&Layer>, layer: Option<&Layer>) {
+    if let Err(e) = sync_change_trigger(layer, previous).await {
+        warn!("could not restore the change triggers of an unsaved layer edit: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn trigger_sql_quotes_the_layer_table_and_arguments() {
+        assert_eq!(trigger_name("8f1c-AB_x\"; --"), "mvt_server_8f1cab_x");
+
+        let layer: Layer = serde_json::from_value(json!({
+            "id": "it's",
+            "category": { "id": "c", "name": "public", "description": "" },
+            "geometry": "points", "name": "roads", "alias": "Roads", "description": "",
+            "database_id": "default", "schema": "we\"ird", "table_name": "roads",
+            "fields": [], "geom": "the_geom", "published": true, "groups": null
+        }))
+        .unwrap();
+        let sql = install_trigger_sql(&layer);
+        assert_eq!(sql.len(), 9);
+        assert!(sql[0].contains(r#"FUNCTION "we""ird".mvt_server_notify_change()"#));
+        assert!(sql[0].contains("ST_SRID(g) <> 0"));
+        assert!(!sql[0].contains("EXCEPTION"));
+        assert_eq!(sql[1], r#"DROP TRIGGER IF EXISTS "mvt_server_its_insert" ON "we""ird"."roads""#);
+        assert!(sql[4].starts_with(
+            r#"CREATE TRIGGER "mvt_server_its_update" AFTER UPDATE ON "we""ird"."roads" REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows FOR EACH STATEMENT"#
+        ));
+        assert!(sql[4].ends_with(r#"mvt_server_notify_change('it''s', 'the_geom')"#));
+        assert!(sql[8].starts_with(
+            r#"CREATE TRIGGER "mvt_server_its_truncate" AFTER TRUNCATE ON "we""ird"."roads" FOR EACH STATEMENT"#
+        ));
+    }
+}
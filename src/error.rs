@@ -56,6 +56,9 @@ pub enum AppError {
     #[error("Redis error: {0}")]
     RedisError(#[from] RedisError),
 
+    #[error("Object store error: {0}")]
+    ObjectStoreError(#[from] object_store::Error),
+
     #[error("Conversion error")]
     Conversion(#[from] TryFromIntError),
 
@@ -104,6 +107,9 @@ pub enum AppError {
     #[error("Timeout error")]
     TimeoutError,
 
+    #[error("Query timed out: {0}")]
+    QueryTimeout(String),
+
     #[error("MapLibre legend error: {0}")]
     Legend(#[from] LegendError),
 
@@ -131,6 +137,7 @@ impl AppError {
                 StatusCode::BAD_REQUEST
             }
             Self::TimeoutError => StatusCode::REQUEST_TIMEOUT,
+            Self::QueryTimeout(_) => StatusCode::GATEWAY_TIMEOUT,
             Self::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -175,3 +182,18 @@ impl Writer for AppError {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_timeout_is_a_gateway_timeout_not_a_bad_request() {
+        let error = AppError::QueryTimeout("layer 'roads' took longer than 500 ms".to_string());
+        assert_eq!(error.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(
+            error.to_string(),
+            "Query timed out: layer 'roads' took longer than 500 ms"
+        );
+    }
+}
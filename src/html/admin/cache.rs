@@ -0,0 +1,201 @@
+use askama::Template;
+use salvo::prelude::*;
+
+use crate::{
+    cache::stats::LayerUsage,
+    error::{AppError, AppResult},
+    get_catalog,
+    html::utils::{BaseTemplateData, make_base},
+    models::catalog::StateLayer,
+    services::cache_stats::{DEFAULT_PAGE_SIZE, cache_stats, layer_cached_tiles},
+};
+
+struct LayerRow {
+    id: String,
+    layer: String,
+    alias: String,
+    tiles: String,
+    size: String,
+    memory: String,
+    hits: u64,
+    misses: u64,
+    hit_ratio: String,
+    oldest: String,
+    newest: String,
+    /// Why the stored tiles could not be read.
+    error: Option<String>,
+}
+
+struct TileRow {
+    matrix_set: String,
+    z: u32,
+    x: u32,
+    y: u32,
+    size: String,
+    age: String,
+}
+
+#[derive(Template)]
+#[template(path = "admin/cache/cache.html")]
+struct CacheTemplate {
+    base: BaseTemplateData,
+    backend: &'static str,
+    layers: Vec<LayerRow>,
+}
+
+#[derive(Template)]
+#[template(path = "admin/cache/tiles.html")]
+struct CacheTilesTemplate {
+    base: BaseTemplateData,
+    id: String,
+    alias: String,
+    layer: String,
+    /// Zoom levels of the layer, and whether each is the one listed.
+    zooms: Vec<(u32, bool)>,
+    /// `&z=...` keeping the zoom selection across pages.
+    zoom_query: String,
+    total: u64,
+    page: usize,
+    pages: usize,
+    tiles: Vec<TileRow>,
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KB", "MB", "GB", "TB"];
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn format_age(age: Option<u64>) -> String {
+    match age {
+        None => "—".to_string(),
+        Some(secs) if secs < 60 => format!("{secs} s"),
+        Some(secs) if secs < 3600 => format!("{} min", secs / 60),
+        Some(secs) if secs < 86_400 => format!("{} h", secs / 3600),
+        Some(secs) => format!("{} d", secs / 86_400),
+    }
+}
+
+fn format_memory(memory: Option<&LayerUsage>) -> String {
+    match memory {
+        Some(usage) if usage.tiles > 0 => {
+            format!("{} · {}", usage.tiles, format_bytes(usage.bytes))
+        }
+        Some(_) => "0".to_string(),
+        None => "—".to_string(),
+    }
+}
+
+#[handler]
+pub async fn cache_page(res: &mut Response, depot: &mut Depot) -> AppResult<()> {
+    let (base, _) = make_base(depot).await;
+    let stats = cache_stats().await;
+    let layers = stats
+        .layers
+        .into_iter()
+        .map(|layer| {
+            let usage = layer.usage.as_ref();
+            LayerRow {
+                tiles: usage.map_or("—".to_string(), |usage| usage.tiles.to_string()),
+                size: usage.map_or("—".to_string(), |usage| format_bytes(usage.bytes)),
+                memory: format_memory(layer.memory.as_ref()),
+                hit_ratio: layer
+                    .hit_ratio
+                    .map_or("—".to_string(), |ratio| format!("{:.1} %", ratio * 100.0)),
+                oldest: format_age(usage.and_then(|usage| usage.oldest_age)),
+                newest: format_age(usage.and_then(|usage| usage.newest_age)),
+                id: layer.id,
+                layer: layer.layer,
+                alias: layer.alias,
+                hits: layer.hits,
+                misses: layer.misses,
+                error: layer.error,
+            }
+        })
+        .collect();
+
+    let template = CacheTemplate {
+        base,
+        backend: stats.backend,
+        layers,
+    };
+    res.render(Text::Html(template.render()?));
+    Ok(())
+}
+
+#[handler]
+pub async fn cache_tiles_page(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let (base, _) = make_base(depot).await;
+    let id = req
+        .param::<String>("id")
+        .ok_or(AppError::RequestParamError("id".to_string()))?;
+    let layer = {
+        let catalog = get_catalog().await.read().await;
+        catalog
+            .find_layer_by_id(&id, StateLayer::Any)
+            .ok_or_else(|| AppError::NotFound(format!("Layer {id} not found")))?
+            .clone()
+    };
+    // An empty selection lists every zoom level.
+    let z = req.query::<u32>("z");
+    let page = req.query::<usize>("page").unwrap_or(1).max(1);
+
+    let listing = layer_cached_tiles(&layer, z, page, None).await?;
+    let tiles = listing
+        .tiles
+        .into_iter()
+        .map(|tile| TileRow {
+            matrix_set: tile.matrix_set.unwrap_or_default(),
+            z: tile.z,
+            x: tile.x,
+            y: tile.y,
+            size: format_bytes(tile.bytes),
+            age: format_age(tile.age),
+        })
+        .collect();
+
+    let template = CacheTilesTemplate {
+        base,
+        layer: format!("{}_{}", layer.category.name, layer.name),
+        zooms: (layer.get_zmin()..=layer.get_zmax())
+            .map(|zoom| (zoom, z == Some(zoom)))
+            .collect(),
+        id: layer.id,
+        alias: layer.alias,
+        zoom_query: z.map(|z| format!("&z={z}")).unwrap_or_default(),
+        total: listing.total,
+        page,
+        pages: (listing.total as usize).div_ceil(DEFAULT_PAGE_SIZE).max(1),
+        tiles,
+    };
+    res.render(Text::Html(template.render()?));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_and_ages_are_readable() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0 GB");
+        assert_eq!(format_age(None), "—");
+        assert_eq!(format_age(Some(59)), "59 s");
+        assert_eq!(format_age(Some(7200)), "2 h");
+        assert_eq!(format_age(Some(3 * 86_400 + 5)), "3 d");
+    }
+}
@@ -7,11 +7,13 @@ use crate::{
     auth::{Group, User},
     error::{AppError, AppResult},
     get_auth, get_cache_wrapper, get_catalog, get_categories, get_db_registry,
+    get_tile_matrix_sets,
     html::utils::{BaseTemplateData, make_base},
     models::{
-        catalog::{Layer, StateLayer},
+        catalog::{Layer, SourceKind, StateLayer},
         category::Category,
     },
+    services::invalidate::{InvalidateOptions, invalidate_layer_region},
 };
 
 #[derive(Template)]
@@ -27,6 +29,7 @@ struct NewLayerTemplate {
     categories: Vec<Category>,
     groups: Vec<Group>,
     databases: Vec<(String, String)>,
+    tile_matrix_sets: Vec<String>,
     base: BaseTemplateData,
 }
 
@@ -37,25 +40,58 @@ struct EditLayerTemplate {
     categories: Vec<Category>,
     groups: Vec<Group>,
     databases: Vec<(String, String)>,
+    tile_matrix_sets: Vec<String>,
     base: BaseTemplateData,
 }
 
+fn tile_matrix_set_ids() -> Vec<String> {
+    get_tile_matrix_sets().iter().map(|s| s.id.clone()).collect()
+}
+
+/// The form's cluster zoom input is left empty for unclustered layers.
+fn parse_cluster_zoom(value: Option<&str>) -> AppResult<Option<u32>> {
+    match value.map(str::trim).filter(|v| !v.is_empty()) {
+        None => Ok(None),
+        Some(zoom) => zoom
+            .parse()
+            .map(Some)
+            .map_err(|_| AppError::InvalidInput(format!("invalid cluster zoom '{zoom}'"))),
+    }
+}
+
 #[derive(Serialize, Deserialize, Extractible, Debug)]
 #[salvo(extract(default_source(from = "body")))]
 struct NewLayer<'a> {
     id: String,
     category: String,
+    #[serde(default)]
+    source_kind: SourceKind,
+    static_file: Option<String>,
+    function_name: Option<String>,
+    sql_query: Option<String>,
+    /// sql_params: comma separated parameter names
+    sql_params: Option<String>,
+    #[serde(default)]
     database_id: String,
     geometry: &'a str,
     name: String,
     alias: String,
     description: String,
+    #[serde(default)]
     schema: String,
+    #[serde(default)]
     table: String,
+    #[serde(default)]
     fields: Vec<String>,
+    /// zoom_fields: one `zmin-zmax: field, field` line per range
+    zoom_fields: Option<String>,
+    /// generalization: one `zmin-zmax: simplify=1, min_area=4` line per range
+    generalization: Option<String>,
     filter: Option<String>,
     srid: Option<u32>,
+    tile_matrix_set: Option<String>,
     geom: Option<String>,
+    id_column: Option<String>,
     sql_mode: Option<String>,
     buffer: Option<u32>,
     extent: Option<u32>,
@@ -69,6 +105,20 @@ struct NewLayer<'a> {
     /// max_cache_age: on seconds: default 0 -> infinite
     max_cache_age: Option<u64>,
     max_records: Option<u64>,
+    priority: Option<String>,
+    priority_order: Option<String>,
+    /// cluster_zoom: empty when the layer is not clustered
+    cluster_zoom: Option<String>,
+    cluster_radius: Option<u32>,
+    /// cluster_aggregates: e.g. `sum(victims), max(severity)`
+    cluster_aggregates: Option<String>,
+    /// bin_shape: empty when the layer is not binned
+    bin_shape: Option<String>,
+    bin_size: Option<u32>,
+    bin_aggregates: Option<String>,
+    /// query_timeout_ms: 0 keeps the database's statement timeout
+    query_timeout_ms: Option<u64>,
+    notify_changes: Option<bool>,
     published: bool,
     groups: Option<Vec<String>>,
 }
@@ -102,6 +152,7 @@ pub async fn new_layer_page(res: &mut Response, depot: &mut Depot) -> AppResult<
         categories: (categories).to_vec(),
         groups,
         databases,
+        tile_matrix_sets: tile_matrix_set_ids(),
         base,
     };
     res.render(Text::Html(template.render()?));
@@ -128,6 +179,7 @@ pub async fn edit_layer_page(req: &mut Request, res: &mut Response, depot: &mut
         categories: (categories).to_vec(),
         groups,
         databases,
+        tile_matrix_sets: tile_matrix_set_ids(),
         base,
     };
     res.render(Text::Html(template.render()?));
@@ -163,6 +215,13 @@ pub async fn create_layer<'a>(res: &mut Response, layer_form: NewLayer<'a>) -> A
     let layer = Layer {
         id: hex_string,
         category,
+        source_kind: layer_form.source_kind,
+        static_file: layer_form.static_file,
+        function_name: layer_form.function_name,
+        sql_query: layer_form.sql_query,
+        sql_params: layer_form
+            .sql_params
+            .map(|params| params.split(',').map(|p| p.trim().to_string()).collect()),
         database_id: layer_form.database_id,
         geometry: layer_form.geometry.to_string(),
         name: layer_form.name,
@@ -171,9 +230,15 @@ pub async fn create_layer<'a>(res: &mut Response, layer_form: NewLayer<'a>) -> A
         schema: layer_form.schema,
         table_name: layer_form.table,
         fields: layer_form.fields,
+        zoom_fields: Layer::parse_zoom_fields(&layer_form.zoom_fields.unwrap_or_default())?,
+        generalization: Layer::parse_generalization(
+            &layer_form.generalization.unwrap_or_default(),
+        )?,
         filter: layer_form.filter,
         srid: layer_form.srid,
+        tile_matrix_set: layer_form.tile_matrix_set,
         geom: layer_form.geom,
+        id_column: layer_form.id_column,
         sql_mode: layer_form.sql_mode,
         buffer: layer_form.buffer,
         extent: layer_form.extent,
@@ -186,6 +251,18 @@ pub async fn create_layer<'a>(res: &mut Response, layer_form: NewLayer<'a>) -> A
         delete_cache_on_start: layer_form.delete_cache_on_start,
         max_cache_age: layer_form.max_cache_age,
         max_records: layer_form.max_records,
+        priority: layer_form.priority,
+        priority_order: layer_form.priority_order,
+        cluster_zoom: parse_cluster_zoom(layer_form.cluster_zoom.as_deref())?,
+        cluster_radius: layer_form.cluster_radius,
+        cluster_aggregates: Layer::parse_aggregates(
+            &layer_form.cluster_aggregates.unwrap_or_default(),
+        )?,
+        bin_shape: layer_form.bin_shape,
+        bin_size: layer_form.bin_size,
+        bin_aggregates: Layer::parse_aggregates(&layer_form.bin_aggregates.unwrap_or_default())?,
+        query_timeout_ms: layer_form.query_timeout_ms,
+        notify_changes: layer_form.notify_changes,
         published: layer_form.published,
         url: None,
         groups: Some(selected_groups),
@@ -237,6 +314,13 @@ pub async fn update_layer<'a>(res: &mut Response, layer_form: NewLayer<'a>) -> A
     let layer = Layer {
         id: layer_form.id,
         category,
+        source_kind: layer_form.source_kind,
+        static_file: layer_form.static_file,
+        function_name: layer_form.function_name,
+        sql_query: layer_form.sql_query,
+        sql_params: layer_form
+            .sql_params
+            .map(|params| params.split(',').map(|p| p.trim().to_string()).collect()),
         database_id: layer_form.database_id,
         geometry: layer_form.geometry.to_string(),
         name: layer_form.name,
@@ -245,9 +329,15 @@ pub async fn update_layer<'a>(res: &mut Response, layer_form: NewLayer<'a>) -> A
         schema: layer_form.schema,
         table_name: layer_form.table,
         fields: layer_form.fields,
+        zoom_fields: Layer::parse_zoom_fields(&layer_form.zoom_fields.unwrap_or_default())?,
+        generalization: Layer::parse_generalization(
+            &layer_form.generalization.unwrap_or_default(),
+        )?,
         filter: layer_form.filter,
         srid: layer_form.srid,
+        tile_matrix_set: layer_form.tile_matrix_set,
         geom: layer_form.geom,
+        id_column: layer_form.id_column,
         sql_mode: layer_form.sql_mode,
         buffer: layer_form.buffer,
         extent: layer_form.extent,
@@ -260,6 +350,18 @@ pub async fn update_layer<'a>(res: &mut Response, layer_form: NewLayer<'a>) -> A
         delete_cache_on_start: layer_form.delete_cache_on_start,
         max_cache_age: layer_form.max_cache_age,
         max_records: layer_form.max_records,
+        priority: layer_form.priority,
+        priority_order: layer_form.priority_order,
+        cluster_zoom: parse_cluster_zoom(layer_form.cluster_zoom.as_deref())?,
+        cluster_radius: layer_form.cluster_radius,
+        cluster_aggregates: Layer::parse_aggregates(
+            &layer_form.cluster_aggregates.unwrap_or_default(),
+        )?,
+        bin_shape: layer_form.bin_shape,
+        bin_size: layer_form.bin_size,
+        bin_aggregates: Layer::parse_aggregates(&layer_form.bin_aggregates.unwrap_or_default())?,
+        query_timeout_ms: layer_form.query_timeout_ms,
+        notify_changes: layer_form.notify_changes,
         published: layer_form.published,
         url: None,
         groups: Some(selected_groups),
@@ -347,3 +449,93 @@ pub async fn delete_layer_cache<'a>(res: &mut Response, req: &mut Request) -> Ap
     res.render(Redirect::other("/admin/catalog"));
     Ok(())
 }
+
+/// The invalidate-area form: `bbox` as `west,south,east,north` or a GeoJSON
+/// `geometry`. Empty zoom inputs fall back to the layer's zooms.
+#[derive(Deserialize, Extractible, Debug)]
+#[salvo(extract(default_source(from = "body")))]
+struct InvalidateRegionForm {
+    id: String,
+    #[serde(default)]
+    bbox: String,
+    #[serde(default)]
+    geometry: String,
+    #[serde(default)]
+    zmin: String,
+    #[serde(default)]
+    zmax: String,
+}
+
+fn parse_optional_zoom(value: &str) -> AppResult<Option<u32>> {
+    match value.trim() {
+        "" => Ok(None),
+        zoom => zoom
+            .parse()
+            .map(Some)
+            .map_err(|_| AppError::InvalidInput(format!("invalid zoom '{zoom}'"))),
+    }
+}
+
+fn parse_bbox(value: &str) -> AppResult<Option<[f64; 4]>> {
+    if value.trim().is_empty() {
+        return Ok(None);
+    }
+    let invalid = || AppError::InvalidInput(format!("invalid bbox '{value}'"));
+    let coords = value
+        .split(',')
+        .map(|c| c.trim().parse::<f64>().map_err(|_| invalid()))
+        .collect::<AppResult<Vec<f64>>>()?;
+    coords.try_into().map(Some).map_err(|_| invalid())
+}
+
+#[handler]
+pub async fn invalidate_layer_cache_region(
+    res: &mut Response,
+    region_form: InvalidateRegionForm,
+) -> AppResult<()> {
+    let layer = {
+        let catalog = get_catalog().await.read().await;
+        match catalog.find_layer_by_id(&region_form.id, StateLayer::Any) {
+            Some(layer) => layer.clone(),
+            None => {
+                res.status_code(StatusCode::BAD_REQUEST);
+                return Err(AppError::CacheNotFound(region_form.id));
+            }
+        }
+    };
+
+    let geometry = match region_form.geometry.trim() {
+        "" => None,
+        geojson => Some(serde_json::from_str(geojson)?),
+    };
+    let options = InvalidateOptions {
+        zmin: parse_optional_zoom(&region_form.zmin)?,
+        zmax: parse_optional_zoom(&region_form.zmax)?,
+        bbox: parse_bbox(&region_form.bbox)?,
+        geometry,
+    };
+    invalidate_layer_region(&layer, &options).await?;
+
+    res.render(Redirect::other("/admin/catalog"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn area_form_inputs_are_parsed() {
+        assert_eq!(parse_optional_zoom(" ").unwrap(), None);
+        assert_eq!(parse_optional_zoom("14").unwrap(), Some(14));
+        assert!(parse_optional_zoom("z14").is_err());
+
+        assert_eq!(parse_bbox("").unwrap(), None);
+        assert_eq!(
+            parse_bbox("-58.5, -34.7,-58.3,-34.5").unwrap(),
+            Some([-58.5, -34.7, -58.3, -34.5])
+        );
+        assert!(parse_bbox("-58.5,-34.7,-58.3").is_err());
+        assert!(parse_bbox("a,b,c,d").is_err());
+    }
+}
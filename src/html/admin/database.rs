@@ -4,9 +4,12 @@ use salvo::prelude::*;
 
 use crate::{
     db::metadata::{
-        Field, Schema, Srid, Table, query_fields, query_schemas, query_srid, query_tables,
+        Field, Function, Schema, Srid, Table, query_fields, query_functions, query_schemas,
+        query_srid, query_tables,
     },
     error::{AppError, AppResult},
+    get_static_tile_registry,
+    models::catalog::SourceKind,
 };
 
 #[derive(Template)]
@@ -23,6 +26,13 @@ struct TableTemplate<'a> {
     table_selected: String,
 }
 
+#[derive(Template)]
+#[template(path = "admin/database/functions.html")]
+struct FunctionTemplate<'a> {
+    functions: &'a Vec<Function>,
+    function_selected: String,
+}
+
 #[derive(Template)]
 #[template(path = "admin/database/fields.html")]
 struct FieldTemplate<'a> {
@@ -36,6 +46,13 @@ struct SRIDTemplate<'a> {
     srid: &'a Srid,
 }
 
+#[derive(Template)]
+#[template(path = "admin/database/static_files.html")]
+struct StaticFilesTemplate<'a> {
+    files: &'a Vec<String>,
+    file_selected: String,
+}
+
 #[handler]
 pub async fn schemas(req: &mut Request, res: &mut Response) -> AppResult<()> {
     let schema_selected = req.query::<String>("schema_selected").unwrap_or_default();
@@ -84,6 +101,26 @@ pub async fn tables(req: &mut Request, res: &mut Response) -> AppResult<()> {
     Ok(())
 }
 
+#[handler]
+pub async fn functions(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let db_id = req
+        .query::<String>("database_id")
+        .unwrap_or_else(|| "default".to_string());
+    let schema = req
+        .query::<String>("schema")
+        .ok_or(AppError::RequestParamError("schema".to_string()))?;
+    let function_selected = req.query::<String>("function_selected").unwrap_or_default();
+
+    let rv = query_functions(&db_id, schema).await?;
+    let template = FunctionTemplate {
+        functions: &rv,
+        function_selected,
+    };
+    let html_render = template.render()?;
+    res.render(Text::Html(html_render));
+    Ok(())
+}
+
 #[handler]
 pub async fn fields(req: &mut Request, res: &mut Response) -> AppResult<()> {
     let db_id = req
@@ -141,3 +178,23 @@ pub async fn srid(req: &mut Request, res: &mut Response) -> AppResult<()> {
     res.render(Text::Html(html_render));
     Ok(())
 }
+
+#[handler]
+pub async fn static_files(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let file_selected = req.query::<String>("file_selected").unwrap_or_default();
+    let source_kind = SourceKind::from_str(
+        &req.query::<String>("source_kind").unwrap_or_default(),
+    );
+    let files = source_kind
+        .file_extension()
+        .map(|ext| get_static_tile_registry().list_files(ext))
+        .unwrap_or_default();
+
+    let template = StaticFilesTemplate {
+        files: &files,
+        file_selected,
+    };
+    let html_render = template.render()?;
+    res.render(Text::Html(html_render));
+    Ok(())
+}
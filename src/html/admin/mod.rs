@@ -1,3 +1,4 @@
+pub mod cache;
 pub mod catalog;
 pub mod categories;
 pub mod dashboard;
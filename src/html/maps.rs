@@ -93,11 +93,11 @@ pub async fn page_map_layer(
             })?
             .clone();
 
-        let geometry = match lyr.geometry.as_str() {
+        let geometry = match lyr.output_geometry() {
             "points" => "circle".to_string(),
             "lines" => "line".to_string(),
             "polygons" => "fill".to_string(),
-            _ => lyr.geometry.clone(),
+            other => other.to_string(),
         };
 
         (lyr, geometry)
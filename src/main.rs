@@ -26,10 +26,13 @@ mod routes;
 mod services;
 
 use crate::db::connection::DbRegistry;
+use crate::db::static_tiles::StaticTileRegistry;
 use crate::error::AppResult;
 use auth::Auth;
 use cache::cachewrapper::CacheWrapper;
-use models::{catalog::Catalog, category::Category, styles::Style};
+use models::{
+    catalog::Catalog, category::Category, styles::Style, tile_matrix_set::TileMatrixSet,
+};
 use monitor::start_system_monitor;
 
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -40,6 +43,12 @@ pub fn get_db_registry() -> &'static DbRegistry {
     DB_REGISTRY.get().unwrap()
 }
 
+static STATIC_TILE_REGISTRY: OnceLock<StaticTileRegistry> = OnceLock::new();
+#[inline]
+pub fn get_static_tile_registry() -> &'static StaticTileRegistry {
+    STATIC_TILE_REGISTRY.get().unwrap()
+}
+
 static SQLITE_CONF: OnceLock<SqlitePool> = OnceLock::new();
 #[inline]
 pub fn get_cf_pool() -> &'static SqlitePool {
@@ -85,6 +94,14 @@ pub fn get_cache_invalidation_delay() -> Option<Duration> {
     CACHE_INVALIDATION_DELAY.get().copied().flatten()
 }
 
+/// Built-in and configured tile matrix sets. Falls back to the built-in
+/// sets when the config has not been loaded (tests, tooling).
+static TILE_MATRIX_SETS: OnceLock<Vec<TileMatrixSet>> = OnceLock::new();
+#[inline]
+pub fn get_tile_matrix_sets() -> &'static [TileMatrixSet] {
+    TILE_MATRIX_SETS.get_or_init(TileMatrixSet::builtin)
+}
+
 static CACHE_WRAPPER: OnceLock<CacheWrapper> = OnceLock::new();
 #[inline]
 pub fn get_cache_wrapper() -> &'static CacheWrapper {
@@ -242,7 +259,13 @@ async fn main() -> AppResult<()> {
     }
 
     CONFIG_DIR.set(settings.paths.config.clone()).unwrap();
+    STATIC_TILE_REGISTRY
+        .set(StaticTileRegistry::new(&settings.paths.static_tiles))
+        .unwrap();
     PUBLIC_URL.set(settings.server.public_url.clone()).unwrap();
+    TILE_MATRIX_SETS
+        .set(models::tile_matrix_set::build_registry(&settings.tile_matrix_sets)?)
+        .unwrap();
 
     // In clustered owner/shared modes, defer cache invalidation so every peer
     // reloads the edited config (within its watch interval) before the shared
@@ -257,6 +280,9 @@ async fn main() -> AppResult<()> {
     };
     CACHE_INVALIDATION_DELAY.set(cache_invalidation_delay).unwrap();
 
+    // A seed run shares the cache with the server, so it must not flush it.
+    let seeding = matches!(settings.command, Some(config::settings::Command::Seed(_)));
+
     if settings.cluster.mode == "client" {
         let owner_url = settings.cluster.owner_url.clone().expect("client requires owner_url");
         let secret = settings.cluster.shared_secret.clone().expect("client requires shared_secret");
@@ -281,6 +307,8 @@ async fn main() -> AppResult<()> {
             settings.paths.cache.clone().into(),
             snapshot.catalog.clone(),
             settings.no_cache,
+            !seeding,
+            &settings.cache,
         )
         .await?;
         let plugin_registry = plugins::LuaPluginRegistry::new(&settings.paths.plugins);
@@ -329,6 +357,8 @@ async fn main() -> AppResult<()> {
             settings.paths.cache.clone().into(),
             catalog.clone(),
             settings.no_cache,
+            !seeding,
+            &settings.cache,
         )
         .await?;
 
@@ -359,6 +389,13 @@ async fn main() -> AppResult<()> {
         }
     }
 
+    if let Some(config::settings::Command::Seed(args)) = &settings.command {
+        return services::seed::run_cli(args).await;
+    }
+
+    services::changes::start_change_listeners();
+    get_cache_wrapper().start_sweeper();
+
     let i18n_service = Arc::new(i18n::I18n::new());
 
     let acceptor = TcpListener::new(format!("{}:{}", settings.server.host, settings.server.port))
@@ -3,17 +3,211 @@ use crate::{
     config::layers::{
         create_layer, delete_layer, get_layers, switch_layer_published, update_layer,
     },
-    error::AppResult,
-    models::category::Category,
+    db::triggers::{revert_change_trigger, sync_change_trigger},
+    error::{AppError, AppResult},
+    models::{
+        category::Category,
+        tile_matrix_set::{WEB_MERCATOR_QUAD, find_tile_matrix_set},
+    },
+    services::utils::validate_filter,
 };
 use html_escape::encode_safe;
 use serde::{Deserialize, Serialize};
 
+/// Placeholders of a SQL layer's query bound to the requested tile; the
+/// query may also use `!name!` for each of its declared parameters.
+pub const SQL_TILE_PLACEHOLDERS: [&str; 4] = ["bbox", "zoom", "x", "y"];
+
 pub enum StateLayer {
     Any,
     Published,
 }
 
+/// Where a layer's tiles come from. `Postgis` layers are rendered on the fly
+/// with `ST_AsMVT`, from a table or, for `Sql` layers, from the SELECT in
+/// `Layer::sql_query`; `Function` layers call a PostGIS function
+/// `fn(z, x, y, query_params json) RETURNS bytea` named by
+/// `Layer::function_name`; the other kinds are pre-rendered files deployed
+/// under `paths.static_tiles` and referenced by `Layer::static_file`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    #[default]
+    Postgis,
+    Sql,
+    Function,
+    Mbtiles,
+    Pmtiles,
+}
+
+impl SourceKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Postgis => "postgis",
+            Self::Sql => "sql",
+            Self::Function => "function",
+            Self::Mbtiles => "mbtiles",
+            Self::Pmtiles => "pmtiles",
+        }
+    }
+
+    /// Unknown values fall back to `Postgis`, the column default.
+    pub fn from_str(s: &str) -> Self {
+        match s {
+            "sql" => Self::Sql,
+            "function" => Self::Function,
+            "mbtiles" => Self::Mbtiles,
+            "pmtiles" => Self::Pmtiles,
+            _ => Self::Postgis,
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Postgis => "PostGIS",
+            Self::Sql => "SQL query",
+            Self::Function => "PostGIS function",
+            Self::Mbtiles => "MBTiles",
+            Self::Pmtiles => "PMTiles",
+        }
+    }
+
+    /// Extension of the files this source reads from `paths.static_tiles`.
+    pub fn file_extension(&self) -> Option<&'static str> {
+        match self {
+            Self::Postgis | Self::Sql | Self::Function => None,
+            Self::Mbtiles => Some("mbtiles"),
+            Self::Pmtiles => Some("pmtiles"),
+        }
+    }
+
+    /// True for pre-rendered file sources (no PostGIS pool involved).
+    pub fn is_static(&self) -> bool {
+        matches!(self, Self::Mbtiles | Self::Pmtiles)
+    }
+
+    /// True for sources that produce WebMercatorQuad tiles only: files are
+    /// pre-rendered in it and functions get no grid besides z/x/y.
+    pub fn is_web_mercator_only(&self) -> bool {
+        !matches!(self, Self::Postgis | Self::Sql)
+    }
+}
+
+/// Attributes of a layer's tiles over a zoom range. Zooms no range covers
+/// get `Layer::fields`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct ZoomFields {
+    pub zmin: u32,
+    pub zmax: u32,
+    pub fields: Vec<String>,
+}
+
+/// Generalization applied to a layer's features over a zoom range. Sizes
+/// are in pixels of a 256 px tile at the requested zoom: `simplify` is the
+/// `ST_SimplifyPreserveTopology` tolerance, `min_area` drops smaller
+/// polygons and `min_length` shorter lines.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct GeneralizationRule {
+    pub zmin: u32,
+    pub zmax: u32,
+    pub simplify: Option<f64>,
+    pub min_area: Option<f64>,
+    pub min_length: Option<f64>,
+}
+
+/// Aggregate published on cluster points or grid cells as
+/// `{function}_{field}`; `function` is one of `AGGREGATE_FUNCTIONS`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct FieldAggregate {
+    pub function: String,
+    pub field: String,
+}
+
+impl FieldAggregate {
+    pub fn name(&self) -> String {
+        format!("{}_{}", self.function, self.field)
+    }
+}
+
+pub const AGGREGATE_FUNCTIONS: [&str; 4] = ["sum", "avg", "min", "max"];
+
+/// Cluster cell size, in pixels, when the layer does not set one.
+pub const DEFAULT_CLUSTER_RADIUS: u32 = 64;
+
+/// Grids a binned layer can use: `ST_HexagonGrid` or `ST_SquareGrid`.
+pub const BIN_SHAPES: [&str; 2] = ["hexagon", "square"];
+
+/// Grid cell size, in pixels, when a binned layer does not set one.
+pub const DEFAULT_BIN_SIZE: u32 = 32;
+
+/// How far beyond the tile, in grid cells, binned tile queries read
+/// features, so cells shared with a neighbouring tile agree.
+pub const BIN_READ_MARGIN: f64 = 2.0;
+
+fn aggregates_text(aggregates: &[FieldAggregate]) -> String {
+    aggregates
+        .iter()
+        .map(|aggregate| format!("{}({})", aggregate.function, aggregate.field))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Aggregates must use known functions over the layer's fields; an empty
+/// list is stored as none.
+fn check_aggregates(aggregates: &mut Option<Vec<FieldAggregate>>, fields: &[String]) -> AppResult<()> {
+    let list = aggregates.get_or_insert_default();
+    list.dedup();
+    for aggregate in list.iter() {
+        if !AGGREGATE_FUNCTIONS.contains(&aggregate.function.as_str()) {
+            return Err(AppError::InvalidInput(format!(
+                "unknown aggregate function '{}', expected one of {}",
+                aggregate.function,
+                AGGREGATE_FUNCTIONS.join(", ")
+            )));
+        }
+        if !fields.contains(&aggregate.field) {
+            return Err(AppError::InvalidInput(format!(
+                "aggregate field '{}' is not one of the layer fields",
+                aggregate.field
+            )));
+        }
+    }
+    if list.is_empty() {
+        *aggregates = None;
+    }
+    Ok(())
+}
+
+/// Splits a `zmin-zmax: rest` line of the admin form's per-zoom settings.
+fn parse_zoom_range(line: &str) -> Option<(u32, u32, &str)> {
+    let (zooms, rest) = line.split_once(':')?;
+    let (zmin, zmax) = zooms.split_once('-')?;
+    Some((zmin.trim().parse().ok()?, zmax.trim().parse().ok()?, rest))
+}
+
+/// Zoom ranges, sorted by `zmin`, must not be empty nor overlap.
+fn check_zoom_ranges(ranges: impl Iterator<Item = (u32, u32)>, what: &str) -> AppResult<()> {
+    let mut previous_zmax: Option<u32> = None;
+    for (zmin, zmax) in ranges {
+        if zmin > zmax || previous_zmax.is_some_and(|previous| zmin <= previous) {
+            return Err(AppError::InvalidInput(format!(
+                "{what} range {zmin}-{zmax} is empty or overlaps another"
+            )));
+        }
+        previous_zmax = Some(zmax);
+    }
+    Ok(())
+}
+
+/// Field list as stored: a single element may hold a comma separated list.
+pub fn split_fields(fields: &[String]) -> Vec<String> {
+    if fields.len() == 1 {
+        fields[0].split(',').map(|s| s.trim().to_string()).collect()
+    } else {
+        fields.iter().map(|s| s.trim().to_string()).collect()
+    }
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Layer {
     pub id: String,
@@ -22,13 +216,31 @@ pub struct Layer {
     pub name: String,
     pub alias: String,
     pub description: String,
+    #[serde(default)]
+    pub source_kind: SourceKind,
+    /// static_file: file name relative to `paths.static_tiles`; only used by static sources
+    pub static_file: Option<String>,
+    /// function_name: PostGIS function in `schema`; only used by function sources
+    pub function_name: Option<String>,
+    /// sql_query: SELECT wrapped by the tile template; only used by SQL sources
+    pub sql_query: Option<String>,
+    /// sql_params: request parameters the SQL query declares as `!name!`
+    pub sql_params: Option<Vec<String>>,
     pub database_id: String,
     pub schema: String,
     pub table_name: String,
     pub fields: Vec<String>,
+    /// zoom_fields: field lists replacing `fields` over zoom ranges
+    pub zoom_fields: Option<Vec<ZoomFields>>,
+    /// generalization: simplification and size thresholds over zoom ranges
+    pub generalization: Option<Vec<GeneralizationRule>>,
     pub filter: Option<String>,
     pub srid: Option<u32>,
+    /// tile_matrix_set: id of the tile grid served by default; `None` is WebMercatorQuad
+    pub tile_matrix_set: Option<String>,
     pub geom: Option<String>,
+    /// id_column: integer column emitted as the MVT feature id
+    pub id_column: Option<String>,
     pub sql_mode: Option<String>,
     pub buffer: Option<u32>,
     pub extent: Option<u32>,
@@ -43,6 +255,26 @@ pub struct Layer {
     /// max_cache_age: on seconds: default 0 -> infinite
     pub max_cache_age: Option<u64>,
     pub max_records: Option<u64>,
+    /// priority: column or SQL expression ranking the features kept by `max_records`
+    pub priority: Option<String>,
+    /// priority_order: "desc" (default), "asc", or "random" for `ORDER BY RANDOM()`
+    pub priority_order: Option<String>,
+    /// cluster_zoom: zooms below it serve cluster points instead of features
+    pub cluster_zoom: Option<u32>,
+    /// cluster_radius: cluster cell size in pixels
+    pub cluster_radius: Option<u32>,
+    /// cluster_aggregates: attributes of the cluster points besides `point_count`
+    pub cluster_aggregates: Option<Vec<FieldAggregate>>,
+    /// bin_shape: "hexagon" or "square" makes tiles a grid of feature counts
+    pub bin_shape: Option<String>,
+    /// bin_size: grid cell size in pixels
+    pub bin_size: Option<u32>,
+    /// bin_aggregates: attributes of the grid cells besides `count`
+    pub bin_aggregates: Option<Vec<FieldAggregate>>,
+    /// query_timeout_ms: statement timeout of the layer's tile queries; `None` is the database's
+    pub query_timeout_ms: Option<u64>,
+    /// notify_changes: a table trigger reports edits so their tiles are invalidated
+    pub notify_changes: Option<bool>,
     pub published: bool,
     #[serde(rename = "source")]
     pub url: Option<String>,
@@ -54,6 +286,10 @@ impl Layer {
         self.geom.as_deref().unwrap_or("geom").to_string()
     }
 
+    pub fn get_id_column(&self) -> Option<&str> {
+        self.id_column.as_deref()
+    }
+
     pub fn get_sql_mode(&self) -> String {
         self.sql_mode.as_deref().unwrap_or("CTE").to_string()
     }
@@ -66,6 +302,13 @@ impl Layer {
         self.srid.unwrap_or(4326)
     }
 
+    pub fn get_tile_matrix_set(&self) -> String {
+        self.tile_matrix_set
+            .as_deref()
+            .unwrap_or(WEB_MERCATOR_QUAD)
+            .to_string()
+    }
+
     pub fn get_buffer(&self) -> u32 {
         self.buffer.unwrap_or(256)
     }
@@ -102,6 +345,14 @@ impl Layer {
         self.delete_cache_on_start.unwrap_or(false)
     }
 
+    pub fn get_query_timeout_ms(&self) -> u64 {
+        self.query_timeout_ms.unwrap_or(0)
+    }
+
+    pub fn get_notify_changes(&self) -> bool {
+        self.notify_changes.unwrap_or(false)
+    }
+
     pub fn get_max_cache_age(&self) -> u64 {
         self.max_cache_age.unwrap_or(0)
     }
@@ -110,6 +361,510 @@ impl Layer {
         self.max_records.unwrap_or(0)
     }
 
+    pub fn get_priority(&self) -> Option<&str> {
+        self.priority.as_deref()
+    }
+
+    pub fn get_priority_order(&self) -> String {
+        self.priority_order.as_deref().unwrap_or("desc").to_string()
+    }
+
+    pub fn get_static_file(&self) -> String {
+        self.static_file.as_deref().unwrap_or("").to_string()
+    }
+
+    pub fn get_function_name(&self) -> String {
+        self.function_name.as_deref().unwrap_or("").to_string()
+    }
+
+    pub fn get_sql_query(&self) -> String {
+        self.sql_query.as_deref().unwrap_or("").to_string()
+    }
+
+    pub fn get_sql_params(&self) -> Vec<String> {
+        self.sql_params.clone().unwrap_or_default()
+    }
+
+    /// Fields of the tiles at zoom `z`.
+    pub fn fields_at(&self, z: u32) -> Vec<String> {
+        self.zoom_fields
+            .iter()
+            .flatten()
+            .find(|range| range.zmin <= z && z <= range.zmax)
+            .map_or_else(|| split_fields(&self.fields), |range| split_fields(&range.fields))
+    }
+
+    /// Every field the layer's tiles carry at some zoom of the layer's
+    /// range, with the first zoom it appears at.
+    pub fn field_zooms(&self) -> Vec<(String, u32)> {
+        let mut zooms: Vec<(String, u32)> = Vec::new();
+        for z in self.get_zmin()..=self.get_zmax() {
+            for field in self.fields_at(z) {
+                if !zooms.iter().any(|(name, _)| *name == field) {
+                    zooms.push((field, z));
+                }
+            }
+        }
+        zooms
+    }
+
+    /// `zoom_fields` as edited in the admin form, one `zmin-zmax: a, b`
+    /// line per range.
+    pub fn zoom_fields_text(&self) -> String {
+        self.zoom_fields
+            .iter()
+            .flatten()
+            .map(|range| format!("{}-{}: {}", range.zmin, range.zmax, range.fields.join(", ")))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Parses the admin form's `zoom_fields` text; see `zoom_fields_text`.
+    pub fn parse_zoom_fields(text: &str) -> AppResult<Option<Vec<ZoomFields>>> {
+        let invalid = |line: &str| {
+            AppError::InvalidInput(format!(
+                "invalid zoom fields '{line}', expected 'zmin-zmax: field, field'"
+            ))
+        };
+        let mut ranges = Vec::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (zmin, zmax, fields) = parse_zoom_range(line).ok_or_else(|| invalid(line))?;
+            ranges.push(ZoomFields {
+                zmin,
+                zmax,
+                fields: fields
+                    .split(',')
+                    .map(|f| f.trim().to_string())
+                    .filter(|f| !f.is_empty())
+                    .collect(),
+            });
+        }
+        Ok((!ranges.is_empty()).then_some(ranges))
+    }
+
+    /// Zoom ranges must be ordered, not overlap, and only pick fields the
+    /// layer publishes.
+    pub fn check_zoom_fields(&mut self) -> AppResult<()> {
+        let Some(ranges) = self.zoom_fields.as_mut().filter(|r| !r.is_empty()) else {
+            self.zoom_fields = None;
+            return Ok(());
+        };
+        ranges.sort_by_key(|range| range.zmin);
+        check_zoom_ranges(ranges.iter().map(|r| (r.zmin, r.zmax)), "zoom fields")?;
+        let fields = split_fields(&self.fields);
+        for range in ranges.iter_mut() {
+            range.fields = split_fields(&range.fields);
+            range.fields.retain(|f| !f.is_empty());
+            if range.fields.is_empty() {
+                return Err(AppError::InvalidInput(format!(
+                    "zoom fields range {}-{} has no fields",
+                    range.zmin, range.zmax
+                )));
+            }
+            if let Some(field) = range.fields.iter().find(|f| !fields.contains(f)) {
+                return Err(AppError::InvalidInput(format!(
+                    "zoom fields range {}-{} uses '{field}', which is not a layer field",
+                    range.zmin, range.zmax
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether tiles of zoom `z` hold cluster points.
+    pub fn is_clustered_at(&self, z: u32) -> bool {
+        self.cluster_zoom.is_some_and(|cluster_zoom| z < cluster_zoom)
+    }
+
+    pub fn get_cluster_radius(&self) -> u32 {
+        self.cluster_radius.unwrap_or(DEFAULT_CLUSTER_RADIUS)
+    }
+
+    pub fn get_cluster_aggregates(&self) -> &[FieldAggregate] {
+        self.cluster_aggregates.as_deref().unwrap_or_default()
+    }
+
+    /// `cluster_aggregates` as edited in the admin form: `sum(victims), max(severity)`.
+    pub fn cluster_aggregates_text(&self) -> String {
+        aggregates_text(self.get_cluster_aggregates())
+    }
+
+    /// Whether the layer's tiles hold grid cells instead of features.
+    pub fn is_binned(&self) -> bool {
+        self.bin_shape.is_some()
+    }
+
+    /// Geometry type of the layer's tiles: binned layers publish polygons
+    /// whatever their source `geometry`.
+    pub fn output_geometry(&self) -> &str {
+        if self.is_binned() {
+            "polygons"
+        } else {
+            &self.geometry
+        }
+    }
+
+    pub fn get_bin_size(&self) -> u32 {
+        self.bin_size.unwrap_or(DEFAULT_BIN_SIZE)
+    }
+
+    pub fn get_bin_aggregates(&self) -> &[FieldAggregate] {
+        self.bin_aggregates.as_deref().unwrap_or_default()
+    }
+
+    /// `bin_aggregates` as edited in the admin form; see `cluster_aggregates_text`.
+    pub fn bin_aggregates_text(&self) -> String {
+        aggregates_text(self.get_bin_aggregates())
+    }
+
+    /// Parses the admin form's aggregates text, e.g. `sum(victims), max(severity)`.
+    pub fn parse_aggregates(text: &str) -> AppResult<Option<Vec<FieldAggregate>>> {
+        let mut aggregates = Vec::new();
+        for item in text.split(',').map(str::trim).filter(|i| !i.is_empty()) {
+            let parsed = item
+                .strip_suffix(')')
+                .and_then(|item| item.split_once('('))
+                .map(|(function, field)| FieldAggregate {
+                    function: function.trim().to_lowercase(),
+                    field: field.trim().to_string(),
+                });
+            let Some(aggregate) = parsed else {
+                return Err(AppError::InvalidInput(format!(
+                    "invalid aggregate '{item}', expected e.g. 'sum(field)'"
+                )));
+            };
+            aggregates.push(aggregate);
+        }
+        Ok((!aggregates.is_empty()).then_some(aggregates))
+    }
+
+    /// Clustering needs a point layer; aggregates must use known functions
+    /// over the layer's fields.
+    pub fn check_clustering(&mut self) -> AppResult<()> {
+        if self.cluster_zoom.is_none() {
+            self.cluster_radius = None;
+            self.cluster_aggregates = None;
+            return Ok(());
+        }
+        if self.geometry != "points" {
+            return Err(AppError::InvalidInput(
+                "clustering applies to point layers only".to_string(),
+            ));
+        }
+        if self.cluster_radius == Some(0) {
+            return Err(AppError::InvalidInput(
+                "cluster_radius must be positive".to_string(),
+            ));
+        }
+        let fields = split_fields(&self.fields);
+        check_aggregates(&mut self.cluster_aggregates, &fields)
+    }
+
+    /// Bin shapes must be known and sizes positive; a binned layer cannot
+    /// also be clustered.
+    pub fn check_binning(&mut self) -> AppResult<()> {
+        let Some(shape) = self.bin_shape.take() else {
+            self.bin_size = None;
+            self.bin_aggregates = None;
+            return Ok(());
+        };
+        let shape = shape.trim().to_lowercase();
+        if shape.is_empty() {
+            return self.check_binning();
+        }
+        if !BIN_SHAPES.contains(&shape.as_str()) {
+            return Err(AppError::InvalidInput(format!(
+                "unknown bin shape '{shape}', expected one of {}",
+                BIN_SHAPES.join(", ")
+            )));
+        }
+        if self.cluster_zoom.is_some() {
+            return Err(AppError::InvalidInput(
+                "a binned layer cannot also be clustered".to_string(),
+            ));
+        }
+        if self.bin_size == Some(0) {
+            return Err(AppError::InvalidInput(
+                "bin_size must be positive".to_string(),
+            ));
+        }
+        self.bin_shape = Some(shape);
+        let fields = split_fields(&self.fields);
+        check_aggregates(&mut self.bin_aggregates, &fields)
+    }
+
+    /// Generalization rule of zoom `z`, if any.
+    pub fn generalization_at(&self, z: u32) -> Option<&GeneralizationRule> {
+        self.generalization
+            .iter()
+            .flatten()
+            .find(|rule| rule.zmin <= z && z <= rule.zmax)
+    }
+
+    /// `generalization` as edited in the admin form, one
+    /// `zmin-zmax: simplify=1, min_area=4, min_length=2` line per range.
+    pub fn generalization_text(&self) -> String {
+        self.generalization
+            .iter()
+            .flatten()
+            .map(|rule| {
+                let settings: Vec<String> = [
+                    ("simplify", rule.simplify),
+                    ("min_area", rule.min_area),
+                    ("min_length", rule.min_length),
+                ]
+                .into_iter()
+                .filter_map(|(name, value)| value.map(|v| format!("{name}={v}")))
+                .collect();
+                format!("{}-{}: {}", rule.zmin, rule.zmax, settings.join(", "))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Parses the admin form's `generalization` text; see
+    /// `generalization_text`.
+    pub fn parse_generalization(text: &str) -> AppResult<Option<Vec<GeneralizationRule>>> {
+        let invalid = |line: &str| {
+            AppError::InvalidInput(format!(
+                "invalid generalization '{line}', expected 'zmin-zmax: simplify=1, min_area=4, min_length=2'"
+            ))
+        };
+        let mut rules = Vec::new();
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            let (zmin, zmax, settings) = parse_zoom_range(line).ok_or_else(|| invalid(line))?;
+            let mut rule = GeneralizationRule {
+                zmin,
+                zmax,
+                simplify: None,
+                min_area: None,
+                min_length: None,
+            };
+            for setting in settings.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let (name, value) = setting.split_once('=').ok_or_else(|| invalid(line))?;
+                let value: f64 = value.trim().parse().map_err(|_| invalid(line))?;
+                match name.trim() {
+                    "simplify" => rule.simplify = Some(value),
+                    "min_area" => rule.min_area = Some(value),
+                    "min_length" => rule.min_length = Some(value),
+                    _ => return Err(invalid(line)),
+                }
+            }
+            rules.push(rule);
+        }
+        Ok((!rules.is_empty()).then_some(rules))
+    }
+
+    /// Rules must cover disjoint zoom ranges and hold positive sizes.
+    pub fn check_generalization(&mut self) -> AppResult<()> {
+        let Some(rules) = self.generalization.as_mut().filter(|r| !r.is_empty()) else {
+            self.generalization = None;
+            return Ok(());
+        };
+        rules.sort_by_key(|rule| rule.zmin);
+        check_zoom_ranges(rules.iter().map(|r| (r.zmin, r.zmax)), "generalization")?;
+        for rule in rules.iter() {
+            let sizes = [rule.simplify, rule.min_area, rule.min_length];
+            if sizes.iter().flatten().any(|size| !size.is_finite() || *size <= 0.0) {
+                return Err(AppError::InvalidInput(format!(
+                    "generalization range {}-{} needs positive sizes",
+                    rule.zmin, rule.zmax
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the settings that do not apply to the layer's source kind, so a
+    /// static layer never carries stale PostGIS config (and vice versa).
+    pub fn normalize_source(&mut self) -> AppResult<()> {
+        // Only tables can carry the change trigger.
+        let table = self.source_kind == SourceKind::Postgis;
+        self.notify_changes = self.notify_changes.filter(|&notify| notify && table);
+        if self.source_kind == SourceKind::Function {
+            return self.normalize_function_source();
+        }
+        self.function_name = None;
+        if self.source_kind == SourceKind::Sql {
+            self.normalize_sql_source()?;
+        } else {
+            self.sql_query = None;
+            self.sql_params = None;
+        }
+        if !self.source_kind.is_static() {
+            self.static_file = None;
+            self.query_timeout_ms = self.query_timeout_ms.filter(|&ms| ms > 0);
+            self.id_column = self
+                .id_column
+                .take()
+                .map(|column| column.trim().to_string())
+                .filter(|column| !column.is_empty());
+            self.normalize_priority()?;
+            self.tile_matrix_set = self
+                .tile_matrix_set
+                .take()
+                .map(|id| id.trim().to_string())
+                .filter(|id| !id.is_empty());
+            return Ok(());
+        }
+
+        let file = self.get_static_file().trim().to_string();
+        if file.is_empty() {
+            return Err(AppError::InvalidInput(format!(
+                "static_file is required for {} layers",
+                self.source_kind.label()
+            )));
+        }
+        self.static_file = Some(file);
+        if self.database_id.is_empty() {
+            self.database_id = "default".to_string();
+        }
+        self.schema.clear();
+        self.table_name.clear();
+        self.fields.clear();
+        self.zoom_fields = None;
+        self.generalization = None;
+        self.filter = None;
+        self.srid = None;
+        // Tile files are pre-rendered in WebMercatorQuad.
+        self.tile_matrix_set = None;
+        self.geom = None;
+        self.id_column = None;
+        self.sql_mode = None;
+        self.buffer = None;
+        self.extent = None;
+        self.zmax_do_not_simplify = None;
+        self.buffer_do_not_simplify = None;
+        self.extent_do_not_simplify = None;
+        self.clip_geom = None;
+        self.max_records = None;
+        self.priority = None;
+        self.priority_order = None;
+        self.cluster_zoom = None;
+        self.cluster_radius = None;
+        self.cluster_aggregates = None;
+        self.bin_shape = None;
+        self.bin_size = None;
+        self.bin_aggregates = None;
+        self.query_timeout_ms = None;
+        Ok(())
+    }
+
+    /// The priority is a single expression; its order is `asc`, `desc` or
+    /// the explicit `random`.
+    fn normalize_priority(&mut self) -> AppResult<()> {
+        self.priority = self
+            .priority
+            .take()
+            .map(|priority| priority.trim().to_string())
+            .filter(|priority| !priority.is_empty());
+        if let Some(priority) = &self.priority {
+            validate_filter(priority).map_err(|e| {
+                AppError::InvalidInput(format!("invalid priority '{priority}': {e}"))
+            })?;
+        }
+        self.priority_order = match self.priority_order.as_deref().map(str::trim) {
+            None | Some("") => None,
+            Some(order) => match order.to_lowercase().as_str() {
+                order @ ("asc" | "desc" | "random") => Some(order.to_string()),
+                _ => {
+                    return Err(AppError::InvalidInput(format!(
+                        "unknown priority order '{order}'"
+                    )));
+                }
+            },
+        };
+        Ok(())
+    }
+
+    /// The query replaces the table; everything else about rendering stays.
+    /// Declared parameters must be plain identifiers that do not shadow the
+    /// tile placeholders.
+    fn normalize_sql_source(&mut self) -> AppResult<()> {
+        let query = self
+            .get_sql_query()
+            .trim()
+            .trim_end_matches(';')
+            .trim_end()
+            .to_string();
+        if query.is_empty() {
+            return Err(AppError::InvalidInput(
+                "sql_query is required for SQL layers".to_string(),
+            ));
+        }
+        let mut params: Vec<String> = Vec::new();
+        for param in self.get_sql_params().iter().map(|p| p.trim()) {
+            if param.is_empty() || params.iter().any(|p| p == param) {
+                continue;
+            }
+            let valid = param.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+                && param
+                    .chars()
+                    .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+            if !valid || SQL_TILE_PLACEHOLDERS.contains(&param) {
+                return Err(AppError::InvalidInput(format!(
+                    "invalid SQL layer parameter '{param}'"
+                )));
+            }
+            params.push(param.to_string());
+        }
+        self.sql_query = Some(query);
+        self.sql_params = (!params.is_empty()).then_some(params);
+        self.function_name = None;
+        self.static_file = None;
+        self.query_timeout_ms = self.query_timeout_ms.filter(|&ms| ms > 0);
+        self.table_name.clear();
+        if self.schema.trim().is_empty() {
+            self.schema = "public".to_string();
+        }
+        Ok(())
+    }
+
+    /// The function returns whole tiles: table, field and geometry settings
+    /// do not apply, nor do filters (request parameters reach the function
+    /// as JSON instead).
+    fn normalize_function_source(&mut self) -> AppResult<()> {
+        let function = self.get_function_name().trim().to_string();
+        if function.is_empty() {
+            return Err(AppError::InvalidInput(
+                "function_name is required for function layers".to_string(),
+            ));
+        }
+        self.function_name = Some(function);
+        if self.schema.trim().is_empty() {
+            self.schema = "public".to_string();
+        }
+        self.static_file = None;
+        self.sql_query = None;
+        self.sql_params = None;
+        self.table_name.clear();
+        self.fields.clear();
+        self.zoom_fields = None;
+        self.generalization = None;
+        self.filter = None;
+        self.srid = None;
+        self.tile_matrix_set = None;
+        self.geom = None;
+        self.id_column = None;
+        self.sql_mode = None;
+        self.buffer = None;
+        self.extent = None;
+        self.zmax_do_not_simplify = None;
+        self.buffer_do_not_simplify = None;
+        self.extent_do_not_simplify = None;
+        self.clip_geom = None;
+        self.max_records = None;
+        self.priority = None;
+        self.priority_order = None;
+        self.cluster_zoom = None;
+        self.cluster_radius = None;
+        self.cluster_aggregates = None;
+        self.bin_shape = None;
+        self.bin_size = None;
+        self.bin_aggregates = None;
+        Ok(())
+    }
+
     pub fn database_id_capitalized(&self) -> String {
         let mut c = self.database_id.chars();
         match c.next() {
@@ -145,12 +900,49 @@ impl Layer {
         );
         rows += &row("Name", &encode_safe(&self.name));
         rows += &row("Alias", &encode_safe(&self.alias));
-        rows += &row("Database", &encode_safe(&self.database_id));
+        rows += &row("Source kind", self.source_kind.label());
         rows += &row("Description", &encode_safe(&self.description));
-        rows += &row("Schema", &encode_safe(&self.schema));
-        rows += &row("Table", &encode_safe(&self.table_name));
+        if self.source_kind.is_static() {
+            rows += &row("Static file", &encode_safe(&self.get_static_file()));
+            rows += &row("Zoom min", &self.get_zmin().to_string());
+            rows += &row("Zoom max", &self.get_zmax().to_string());
+            rows += &row("Delete cache on start", &badge(&self.get_delete_cache_on_start().to_string()));
+            rows += &row("Max cache age (s)", &self.get_max_cache_age().to_string());
+            rows += &row("Published", &badge(&self.published.to_string()));
+            rows += &row("Allowed groups", &encode_safe(&self.groups_as_string()));
+            return format!(r#"<dl class="cfg-table">{rows}</dl>"#);
+        }
+        if self.source_kind == SourceKind::Function {
+            rows += &row("Database", &encode_safe(&self.database_id));
+            rows += &row("Schema", &encode_safe(&self.schema));
+            rows += &row("Function", &encode_safe(&self.get_function_name()));
+            rows += &row("Zoom min", &self.get_zmin().to_string());
+            rows += &row("Zoom max", &self.get_zmax().to_string());
+            rows += &row("Delete cache on start", &badge(&self.get_delete_cache_on_start().to_string()));
+            rows += &row("Max cache age (s)", &self.get_max_cache_age().to_string());
+            rows += &row("Published", &badge(&self.published.to_string()));
+            rows += &row("Allowed groups", &encode_safe(&self.groups_as_string()));
+            return format!(r#"<dl class="cfg-table">{rows}</dl>"#);
+        }
+        rows += &row("Database", &encode_safe(&self.database_id));
+        if self.source_kind == SourceKind::Sql {
+            rows += &row("SQL query", &format!("<pre>{}</pre>", encode_safe(&self.get_sql_query())));
+            rows += &row("SQL parameters", &encode_safe(&self.get_sql_params().join(", ")));
+        } else {
+            rows += &row("Schema", &encode_safe(&self.schema));
+            rows += &row("Table", &encode_safe(&self.table_name));
+        }
         rows += &row("Fields", &encode_safe(&self.fields.join(", ")));
+        if self.zoom_fields.is_some() {
+            rows += &row("Zoom fields", &encode_safe(&self.zoom_fields_text()).replace('\n', "<br>"));
+        }
+        if self.generalization.is_some() {
+            rows += &row("Generalization", &encode_safe(&self.generalization_text()).replace('\n', "<br>"));
+        }
         rows += &row("Geom field", &encode_safe(&self.get_geom()));
+        if let Some(id_column) = self.get_id_column() {
+            rows += &row("ID column", &encode_safe(id_column));
+        }
         rows += &row("SQL Mode", &encode_safe(&self.get_sql_mode()));
         rows += &row("SRID", &self.get_srid().to_string());
         rows += &row("Filter", &encode_safe(&self.get_filter()));
@@ -165,6 +957,20 @@ impl Layer {
         rows += &row("Delete cache on start", &badge(&self.get_delete_cache_on_start().to_string()));
         rows += &row("Max cache age (s)", &self.get_max_cache_age().to_string());
         rows += &row("Max records", &self.get_max_records().to_string());
+        if let Some(priority) = self.get_priority() {
+            rows += &row("Priority", &encode_safe(priority));
+        }
+        rows += &row("Priority order", &encode_safe(&self.get_priority_order()));
+        if let Some(cluster_zoom) = self.cluster_zoom {
+            rows += &row("Clustered below zoom", &cluster_zoom.to_string());
+            rows += &row("Cluster radius (px)", &self.get_cluster_radius().to_string());
+            rows += &row("Cluster aggregates", &encode_safe(&self.cluster_aggregates_text()));
+        }
+        if let Some(shape) = &self.bin_shape {
+            rows += &row("Bin shape", &encode_safe(shape));
+            rows += &row("Bin size (px)", &self.get_bin_size().to_string());
+            rows += &row("Bin aggregates", &encode_safe(&self.bin_aggregates_text()));
+        }
         rows += &row("Published", &badge(&self.published.to_string()));
         rows += &row("Allowed groups", &encode_safe(&self.groups_as_string()));
 
@@ -211,6 +1017,23 @@ impl Layer {
     }
 }
 
+/// Normalizes the layer's source settings and, for static layers, checks
+/// that the referenced file is actually deployed.
+fn prepare_source(layer: &mut Layer) -> AppResult<()> {
+    layer.normalize_source()?;
+    if layer.source_kind.is_static() {
+        crate::get_static_tile_registry().resolve(&layer.get_static_file())?;
+    }
+    if let Some(id) = &layer.tile_matrix_set
+        && find_tile_matrix_set(id).is_none()
+    {
+        return Err(AppError::InvalidInput(format!(
+            "unknown tile matrix set '{id}'"
+        )));
+    }
+    Ok(())
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Catalog {
     pub layers: Vec<Layer>,
@@ -320,14 +1143,43 @@ impl Catalog {
 
     pub async fn add_layer(&mut self, mut layer: Layer) -> AppResult<()> {
         layer.name = crate::services::utils::normalize_name(&layer.name)?;
-        create_layer(None, layer.clone()).await?;
+        prepare_source(&mut layer)?;
+        if layer.source_kind == SourceKind::Sql {
+            crate::services::tiles::builder::prepare_sql_layer(&mut layer).await?;
+        }
+        crate::services::tiles::builder::check_id_column(&layer).await?;
+        crate::services::tiles::builder::check_limit_tiebreak(&layer).await?;
+        layer.check_zoom_fields()?;
+        layer.check_generalization()?;
+        layer.check_binning()?;
+        layer.check_clustering()?;
+        sync_change_trigger(None, Some(&layer)).await?;
+        if let Err(e) = create_layer(None, layer.clone()).await {
+            revert_change_trigger(None, Some(&layer)).await;
+            return Err(e.into());
+        }
         self.layers.push(layer);
         Ok(())
     }
 
     pub async fn update_layer(&mut self, mut layer: Layer) -> AppResult<()> {
         layer.name = crate::services::utils::normalize_name(&layer.name)?;
-        update_layer(None, layer.clone()).await?;
+        prepare_source(&mut layer)?;
+        if layer.source_kind == SourceKind::Sql {
+            crate::services::tiles::builder::prepare_sql_layer(&mut layer).await?;
+        }
+        crate::services::tiles::builder::check_id_column(&layer).await?;
+        crate::services::tiles::builder::check_limit_tiebreak(&layer).await?;
+        layer.check_zoom_fields()?;
+        layer.check_generalization()?;
+        layer.check_binning()?;
+        layer.check_clustering()?;
+        let previous = self.layers.iter().find(|lyr| lyr.id == layer.id);
+        sync_change_trigger(previous, Some(&layer)).await?;
+        if let Err(e) = update_layer(None, layer.clone()).await {
+            revert_change_trigger(previous, Some(&layer)).await;
+            return Err(e.into());
+        }
         let position = self.layers.iter().position(|lyr| lyr.id == layer.id);
         match position {
             Some(index) => self.layers[index] = layer,
@@ -339,6 +1191,8 @@ impl Catalog {
 
     pub async fn delete_layer(&mut self, id: String) -> AppResult<()> {
         delete_layer(None, id.as_str()).await?;
+        let previous = self.layers.iter().find(|lyr| lyr.id == id);
+        sync_change_trigger(previous, None).await?;
         self.layers.retain(|lyr| lyr.id != id);
         Ok(())
     }
@@ -355,3 +1209,356 @@ impl Catalog {
         self.layers.retain(|layer| layer.name != target_name);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_layer() -> Layer {
+        Layer {
+            id: "layer-1".to_string(),
+            category: Category {
+                id: "cat-1".to_string(),
+                name: "public".to_string(),
+                description: String::new(),
+            },
+            geometry: "lines".to_string(),
+            name: "roads".to_string(),
+            alias: "Roads".to_string(),
+            description: String::new(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
+            database_id: String::new(),
+            schema: "public".to_string(),
+            table_name: "roads".to_string(),
+            fields: vec!["name".to_string()],
+            zoom_fields: None,
+            generalization: None,
+            filter: Some("lanes > 1".to_string()),
+            srid: Some(4326),
+            tile_matrix_set: None,
+            geom: Some("geom".to_string()),
+            id_column: None,
+            sql_mode: Some("CTE".to_string()),
+            buffer: Some(256),
+            extent: Some(4096),
+            zmin: Some(2),
+            zmax: Some(12),
+            zmax_do_not_simplify: Some(16),
+            buffer_do_not_simplify: Some(256),
+            extent_do_not_simplify: Some(4096),
+            clip_geom: Some(true),
+            delete_cache_on_start: Some(false),
+            max_cache_age: Some(60),
+            max_records: Some(100),
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
+            published: true,
+            url: None,
+            groups: None,
+        }
+    }
+
+    #[test]
+    fn source_kind_serde_is_lowercase_and_defaults_to_postgis() {
+        assert_eq!(serde_json::to_string(&SourceKind::Mbtiles).unwrap(), "\"mbtiles\"");
+        assert_eq!(
+            serde_json::from_str::<SourceKind>("\"postgis\"").unwrap(),
+            SourceKind::Postgis
+        );
+        assert_eq!(SourceKind::default(), SourceKind::Postgis);
+        assert_eq!(SourceKind::from_str("bogus"), SourceKind::Postgis);
+        assert_eq!(SourceKind::from_str("pmtiles"), SourceKind::Pmtiles);
+        assert_eq!(SourceKind::from_str("function"), SourceKind::Function);
+        assert_eq!(SourceKind::from_str("sql"), SourceKind::Sql);
+        assert!(!SourceKind::Sql.is_static() && !SourceKind::Sql.is_web_mercator_only());
+        assert!(!SourceKind::Function.is_static() && SourceKind::Function.is_web_mercator_only());
+        assert_eq!(SourceKind::Pmtiles.file_extension(), Some("pmtiles"));
+        assert_eq!(SourceKind::Postgis.file_extension(), None);
+
+        let mut json = serde_json::to_value(test_layer()).unwrap();
+        json.as_object_mut().unwrap().remove("source_kind");
+        let layer: Layer = serde_json::from_value(json).unwrap();
+        assert_eq!(layer.source_kind, SourceKind::Postgis);
+    }
+
+    #[test]
+    fn normalize_source_keeps_postgis_config_and_drops_static_file() {
+        let mut layer = test_layer();
+        layer.static_file = Some("stale.mbtiles".to_string());
+        layer.id_column = Some(" gid ".to_string());
+        layer.normalize_source().unwrap();
+        assert!(layer.static_file.is_none());
+        assert_eq!(layer.table_name, "roads");
+        assert_eq!(layer.max_records, Some(100));
+        assert_eq!(layer.get_id_column(), Some("gid"));
+
+        layer.id_column = Some(" ".to_string());
+        layer.normalize_source().unwrap();
+        assert!(layer.id_column.is_none());
+
+        layer.query_timeout_ms = Some(0);
+        layer.notify_changes = Some(true);
+        layer.normalize_source().unwrap();
+        assert!(layer.query_timeout_ms.is_none());
+        assert!(layer.get_notify_changes());
+
+        layer.notify_changes = Some(false);
+        layer.normalize_source().unwrap();
+        assert!(layer.notify_changes.is_none());
+    }
+
+    #[test]
+    fn normalize_source_clears_postgis_config_for_static_layers() {
+        let mut layer = test_layer();
+        layer.source_kind = SourceKind::Mbtiles;
+        layer.static_file = Some(" roads.mbtiles ".to_string());
+        layer.id_column = Some("gid".to_string());
+        layer.query_timeout_ms = Some(500);
+        layer.notify_changes = Some(true);
+        layer.normalize_source().unwrap();
+
+        assert_eq!(layer.static_file.as_deref(), Some("roads.mbtiles"));
+        assert_eq!(layer.database_id, "default");
+        assert!(layer.table_name.is_empty() && layer.fields.is_empty());
+        assert!(layer.filter.is_none() && layer.max_records.is_none());
+        assert!(layer.id_column.is_none() && layer.query_timeout_ms.is_none());
+        assert!(layer.notify_changes.is_none());
+        assert_eq!((layer.zmin, layer.zmax, layer.max_cache_age), (Some(2), Some(12), Some(60)));
+    }
+
+    #[test]
+    fn normalize_source_checks_the_priority() {
+        let mut layer = test_layer();
+        layer.priority = Some("  ".to_string());
+        layer.priority_order = Some(" DESC ".to_string());
+        layer.normalize_source().unwrap();
+        assert!(layer.priority.is_none());
+        assert_eq!(layer.priority_order.as_deref(), Some("desc"));
+
+        layer.priority_order = Some("".to_string());
+        layer.normalize_source().unwrap();
+        assert_eq!(layer.get_priority_order(), "desc");
+
+        layer.priority_order = Some("sideways".to_string());
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+        layer.priority_order = None;
+        layer.priority = Some("rank; DROP TABLE roads".to_string());
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+        layer.priority = Some("rank /* */".to_string());
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+        layer.priority = Some("length(name) * 2".to_string());
+        layer.normalize_source().unwrap();
+    }
+
+    #[test]
+    fn normalize_source_requires_a_static_file() {
+        let mut layer = test_layer();
+        layer.source_kind = SourceKind::Mbtiles;
+        layer.static_file = Some("   ".to_string());
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn normalize_source_keeps_only_the_function_for_function_layers() {
+        let mut layer = test_layer();
+        layer.source_kind = SourceKind::Function;
+        layer.function_name = Some(" roads_tile ".to_string());
+        layer.tile_matrix_set = Some("WorldCRS84Quad".to_string());
+        layer.normalize_source().unwrap();
+
+        assert_eq!(layer.get_function_name(), "roads_tile");
+        assert_eq!(layer.schema, "public");
+        assert!(layer.table_name.is_empty() && layer.fields.is_empty());
+        assert!(layer.filter.is_none() && layer.tile_matrix_set.is_none());
+        assert_eq!((layer.zmin, layer.zmax, layer.max_cache_age), (Some(2), Some(12), Some(60)));
+
+        layer.function_name = None;
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+
+        let mut layer = test_layer();
+        layer.function_name = Some("stale".to_string());
+        layer.normalize_source().unwrap();
+        assert!(layer.function_name.is_none());
+    }
+
+    #[test]
+    fn normalize_source_checks_sql_layers() {
+        let mut layer = test_layer();
+        layer.source_kind = SourceKind::Sql;
+        layer.sql_query = Some(" SELECT gid, geom FROM roads WHERE class = !class!; ".to_string());
+        layer.sql_params = Some(vec!["class".to_string(), " ".to_string(), "class".to_string()]);
+        layer.normalize_source().unwrap();
+
+        assert_eq!(layer.get_sql_query(), "SELECT gid, geom FROM roads WHERE class = !class!");
+        assert_eq!(layer.get_sql_params(), vec!["class"]);
+        assert!(layer.table_name.is_empty());
+        assert_eq!(layer.get_geom(), "geom");
+
+        layer.sql_params = Some(vec!["bbox".to_string()]);
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+        layer.sql_params = Some(vec!["min-area".to_string()]);
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+        layer.sql_params = None;
+        layer.sql_query = Some(";".to_string());
+        assert!(matches!(layer.normalize_source(), Err(AppError::InvalidInput(_))));
+
+        let mut layer = test_layer();
+        layer.sql_query = Some("stale".to_string());
+        layer.normalize_source().unwrap();
+        assert!(layer.sql_query.is_none());
+    }
+
+    #[test]
+    fn zoom_fields_pick_the_list_per_zoom() {
+        let mut layer = test_layer();
+        layer.fields = vec!["name, class, surface".to_string()];
+        layer.zmin = Some(0);
+        layer.zmax = Some(14);
+        layer.zoom_fields = Layer::parse_zoom_fields("9-12: name, class, surface\n 0-8 : name,class\n").unwrap();
+        layer.check_zoom_fields().unwrap();
+
+        assert_eq!(layer.fields_at(3), vec!["name", "class"]);
+        assert_eq!(layer.fields_at(10), vec!["name", "class", "surface"]);
+        assert_eq!(layer.fields_at(14), vec!["name", "class", "surface"]);
+        assert_eq!(
+            layer.field_zooms(),
+            vec![("name".to_string(), 0), ("class".to_string(), 0), ("surface".to_string(), 9)]
+        );
+        assert_eq!(layer.zoom_fields_text(), "0-8: name, class\n9-12: name, class, surface");
+        assert_eq!(
+            Layer::parse_zoom_fields(&layer.zoom_fields_text()).unwrap(),
+            layer.zoom_fields
+        );
+    }
+
+    #[test]
+    fn zoom_fields_are_checked() {
+        let mut layer = test_layer();
+        layer.fields = vec!["name".to_string(), "class".to_string()];
+        assert!(matches!(Layer::parse_zoom_fields("0-8 name"), Err(AppError::InvalidInput(_))));
+        assert_eq!(Layer::parse_zoom_fields(" \n").unwrap(), None);
+
+        for text in ["0-8: name\n8-10: class", "5-2: name", "0-8: owner", "0-8: ,"] {
+            layer.zoom_fields = Layer::parse_zoom_fields(text).unwrap();
+            assert!(
+                matches!(layer.check_zoom_fields(), Err(AppError::InvalidInput(_))),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn generalization_is_parsed_and_checked() {
+        let mut layer = test_layer();
+        let text = "8-10: simplify=0.5\n0-7: simplify=2, min_area=16, min_length=8";
+        layer.generalization = Layer::parse_generalization(text).unwrap();
+        layer.check_generalization().unwrap();
+        assert_eq!(
+            layer.generalization_text(),
+            "0-7: simplify=2, min_area=16, min_length=8\n8-10: simplify=0.5"
+        );
+        assert_eq!(layer.generalization_at(9).unwrap().simplify, Some(0.5));
+        assert!(layer.generalization_at(11).is_none());
+        assert_eq!(Layer::parse_generalization("\n").unwrap(), None);
+
+        for text in ["0-8 simplify=1", "0-8: tolerance=1", "0-8: simplify=fast"] {
+            assert!(matches!(Layer::parse_generalization(text), Err(AppError::InvalidInput(_))));
+        }
+        for text in ["0-8: simplify=1\n8-9: min_area=2", "0-8: min_length=-1", "4-2: simplify=1"] {
+            layer.generalization = Layer::parse_generalization(text).unwrap();
+            assert!(
+                matches!(layer.check_generalization(), Err(AppError::InvalidInput(_))),
+                "{text}"
+            );
+        }
+    }
+
+    #[test]
+    fn clustering_is_parsed_and_checked() {
+        let mut layer = test_layer();
+        layer.geometry = "points".to_string();
+        layer.fields = vec!["victims, severity".to_string()];
+        layer.cluster_zoom = Some(12);
+        layer.cluster_aggregates =
+            Layer::parse_aggregates(" SUM(victims), max( severity ) ").unwrap();
+        layer.check_clustering().unwrap();
+        assert_eq!(layer.cluster_aggregates_text(), "sum(victims), max(severity)");
+        assert!(layer.is_clustered_at(11) && !layer.is_clustered_at(12));
+        assert_eq!(layer.get_cluster_radius(), DEFAULT_CLUSTER_RADIUS);
+        assert!(matches!(
+            Layer::parse_aggregates("sum victims"),
+            Err(AppError::InvalidInput(_))
+        ));
+
+        for text in ["median(victims)", "sum(owner)"] {
+            layer.cluster_aggregates = Layer::parse_aggregates(text).unwrap();
+            assert!(matches!(layer.check_clustering(), Err(AppError::InvalidInput(_))), "{text}");
+        }
+        layer.cluster_aggregates = None;
+        layer.geometry = "polygons".to_string();
+        assert!(matches!(layer.check_clustering(), Err(AppError::InvalidInput(_))));
+
+        layer.cluster_zoom = None;
+        layer.cluster_radius = Some(16);
+        layer.check_clustering().unwrap();
+        assert!(layer.cluster_radius.is_none() && !layer.is_clustered_at(0));
+    }
+
+    #[test]
+    fn binning_is_checked() {
+        let mut layer = test_layer();
+        layer.fields = vec!["victims".to_string()];
+        layer.bin_shape = Some(" Hexagon ".to_string());
+        layer.geometry = "points".to_string();
+        layer.bin_aggregates = Layer::parse_aggregates("sum(victims)").unwrap();
+        layer.check_binning().unwrap();
+        assert_eq!(layer.bin_shape.as_deref(), Some("hexagon"));
+        assert_eq!(layer.geometry, "points");
+        assert_eq!(layer.output_geometry(), "polygons");
+        assert_eq!(layer.get_bin_size(), DEFAULT_BIN_SIZE);
+        assert_eq!(layer.bin_aggregates_text(), "sum(victims)");
+
+        layer.bin_aggregates = Layer::parse_aggregates("max(owner)").unwrap();
+        assert!(matches!(layer.check_binning(), Err(AppError::InvalidInput(_))));
+        layer.bin_aggregates = None;
+
+        layer.bin_shape = Some("triangle".to_string());
+        assert!(matches!(layer.check_binning(), Err(AppError::InvalidInput(_))));
+        layer.bin_shape = Some("square".to_string());
+        layer.cluster_zoom = Some(8);
+        assert!(matches!(layer.check_binning(), Err(AppError::InvalidInput(_))));
+
+        layer.bin_shape = Some("".to_string());
+        layer.bin_size = Some(8);
+        layer.check_binning().unwrap();
+        assert!(!layer.is_binned() && layer.bin_size.is_none() && layer.bin_aggregates.is_none());
+        assert_eq!(layer.output_geometry(), "points");
+    }
+
+    #[test]
+    fn prepare_source_checks_the_tile_matrix_set() {
+        let mut layer = test_layer();
+        layer.tile_matrix_set = Some(" WorldCRS84Quad ".to_string());
+        prepare_source(&mut layer).unwrap();
+        assert_eq!(layer.get_tile_matrix_set(), "WorldCRS84Quad");
+
+        layer.tile_matrix_set = Some("".to_string());
+        prepare_source(&mut layer).unwrap();
+        assert_eq!(layer.get_tile_matrix_set(), "WebMercatorQuad");
+
+        layer.tile_matrix_set = Some("NoSuchGrid".to_string());
+        assert!(matches!(prepare_source(&mut layer), Err(AppError::InvalidInput(_))));
+    }
+}
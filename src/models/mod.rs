@@ -1,3 +1,4 @@
 pub mod catalog;
 pub mod category;
 pub mod styles;
+pub mod tile_matrix_set;
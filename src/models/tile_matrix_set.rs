@@ -0,0 +1,390 @@
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AppError, AppResult};
+
+/// Id of the default matrix set: the XYZ grid every web map client uses.
+pub const WEB_MERCATOR_QUAD: &str = "WebMercatorQuad";
+pub const WORLD_CRS84_QUAD: &str = "WorldCRS84Quad";
+
+/// Half of the Web Mercator world width, in metres.
+const WEB_MERCATOR_HALF: f64 = 20037508.342789244;
+/// Web Mercator latitude limit.
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_6;
+/// Metres per degree at the equator of the WGS84 ellipsoid.
+const METERS_PER_DEGREE: f64 = 2.0 * std::f64::consts::PI * 6378137.0 / 360.0;
+/// OGC standardized rendering pixel size, in metres.
+const STANDARD_PIXEL_SIZE: f64 = 0.00028;
+/// Tile size in pixels advertised in matrix definitions.
+pub const TILE_SIZE: u32 = 256;
+/// Deepest zoom level listed in matrix set definitions.
+pub const MAX_TILE_MATRIX: u32 = 24;
+
+fn one() -> u32 {
+    1
+}
+
+/// One zoom level of a matrix set, as described by OGC TileMatrixSet 2.0.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileMatrix {
+    pub id: String,
+    pub scale_denominator: f64,
+    pub cell_size: f64,
+    pub corner_of_origin: &'static str,
+    pub point_of_origin: [f64; 2],
+    pub matrix_width: u32,
+    pub matrix_height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+}
+
+/// A tile grid in the OGC TileMatrixSet sense, restricted to what
+/// `ST_TileEnvelope` can express: square tiles that halve at every zoom
+/// level, with the origin at the top-left corner of `bounds`.
+///
+/// Custom sets are declared under `tile_matrix_sets` in the config file.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct TileMatrixSet {
+    pub id: String,
+    #[serde(default)]
+    pub title: String,
+    /// SRID tiles are rendered in.
+    pub srid: u32,
+    /// Extent of the grid in `srid` units: xmin, ymin, xmax, ymax.
+    pub bounds: [f64; 4],
+    /// Tile columns at zoom 0.
+    #[serde(default = "one")]
+    pub matrix_width: u32,
+    /// Tile rows at zoom 0.
+    #[serde(default = "one")]
+    pub matrix_height: u32,
+}
+
+impl TileMatrixSet {
+    pub fn web_mercator_quad() -> Self {
+        Self {
+            id: WEB_MERCATOR_QUAD.to_string(),
+            title: "Google Maps Compatible for the World".to_string(),
+            srid: 3857,
+            bounds: [
+                -WEB_MERCATOR_HALF,
+                -WEB_MERCATOR_HALF,
+                WEB_MERCATOR_HALF,
+                WEB_MERCATOR_HALF,
+            ],
+            matrix_width: 1,
+            matrix_height: 1,
+        }
+    }
+
+    pub fn world_crs84_quad() -> Self {
+        Self {
+            id: WORLD_CRS84_QUAD.to_string(),
+            title: "CRS84 for the World".to_string(),
+            srid: 4326,
+            bounds: [-180.0, -90.0, 180.0, 90.0],
+            matrix_width: 2,
+            matrix_height: 1,
+        }
+    }
+
+    /// Sets available without any configuration.
+    pub fn builtin() -> Vec<Self> {
+        vec![Self::web_mercator_quad(), Self::world_crs84_quad()]
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.id == WEB_MERCATOR_QUAD
+    }
+
+    /// Tile columns at zoom `z`.
+    pub fn matrix_width_at(&self, z: u32) -> u32 {
+        self.matrix_width << z
+    }
+
+    /// Tile rows at zoom `z`.
+    pub fn matrix_height_at(&self, z: u32) -> u32 {
+        self.matrix_height << z
+    }
+
+    /// Whether the tile is on the grid. The zoom is checked first, so the
+    /// matrix size is never computed past the last `ST_TileEnvelope` level.
+    pub fn contains(&self, z: u32, x: u32, y: u32) -> bool {
+        z <= 30 - self.level_offset()
+            && x < self.matrix_width_at(z)
+            && y < self.matrix_height_at(z)
+    }
+
+    /// Zoom levels between this set and the square `ST_TileEnvelope` grid
+    /// it is addressed in: a 2x1 set is the second level of a 1x1 grid.
+    fn level_offset(&self) -> u32 {
+        self.matrix_width.max(self.matrix_height).ilog2()
+    }
+
+    /// `ST_TileEnvelope` zoom for zoom `z` of this set.
+    pub fn envelope_zoom(&self, z: u32) -> u32 {
+        z + self.level_offset()
+    }
+
+    /// Bounds passed to `ST_TileEnvelope`: the square grid anchored at the
+    /// set's top-left corner. Equal to `bounds` for 1x1 sets.
+    pub fn envelope_bounds(&self) -> [f64; 4] {
+        let [xmin, _, xmax, ymax] = self.bounds;
+        let side = (xmax - xmin) / self.matrix_width as f64
+            * (1u32 << self.level_offset()) as f64;
+        [xmin, ymax - side, xmin + side, ymax]
+    }
+
+    /// Registered OGC URI of the built-in sets.
+    pub fn uri(&self) -> Option<String> {
+        matches!(self.id.as_str(), WEB_MERCATOR_QUAD | WORLD_CRS84_QUAD)
+            .then(|| format!("http://www.opengis.net/def/tilematrixset/OGC/1.0/{}", self.id))
+    }
+
+    /// CRS URI; EPSG:4326 tiles are rendered lon/lat, which is OGC CRS84.
+    pub fn crs_uri(&self) -> String {
+        match self.srid {
+            4326 => "http://www.opengis.net/def/crs/OGC/1.3/CRS84".to_string(),
+            srid => format!("http://www.opengis.net/def/crs/EPSG/0/{srid}"),
+        }
+    }
+
+    pub fn ordered_axes(&self) -> [&'static str; 2] {
+        match self.srid {
+            4326 => ["Lon", "Lat"],
+            _ => ["X", "Y"],
+        }
+    }
+
+    /// Geographic sets are in degrees, every other one is assumed metric.
+    fn meters_per_unit(&self) -> f64 {
+        match self.srid {
+            4326 => METERS_PER_DEGREE,
+            _ => 1.0,
+        }
+    }
+
+    /// Deepest zoom level this set can address.
+    pub fn max_zoom(&self) -> u32 {
+        MAX_TILE_MATRIX.min(30 - self.level_offset())
+    }
+
+    pub fn tile_matrix(&self, z: u32) -> TileMatrix {
+        let [xmin, _, xmax, ymax] = self.bounds;
+        let cell_size = (xmax - xmin) / self.matrix_width_at(z) as f64 / TILE_SIZE as f64;
+        TileMatrix {
+            id: z.to_string(),
+            scale_denominator: cell_size * self.meters_per_unit() / STANDARD_PIXEL_SIZE,
+            cell_size,
+            corner_of_origin: "topLeft",
+            point_of_origin: [xmin, ymax],
+            matrix_width: self.matrix_width_at(z),
+            matrix_height: self.matrix_height_at(z),
+            tile_width: TILE_SIZE,
+            tile_height: TILE_SIZE,
+        }
+    }
+
+    /// Tiles at zoom `z` overlapping `bbox` (xmin, ymin, xmax, ymax in
+    /// `srid` units), as first column, first row, last column, last row;
+    /// `None` when the bbox is outside the grid.
+    pub fn tile_range(&self, bbox: [f64; 4], z: u32) -> Option<[u32; 4]> {
+        let [xmin, ymin, xmax, ymax] = self.bounds;
+        let [west, south, east, north] = bbox;
+        if !(west <= east && south <= north)
+            || east < xmin
+            || west > xmax
+            || north < ymin
+            || south > ymax
+        {
+            return None;
+        }
+        let (width, height) = (self.matrix_width_at(z), self.matrix_height_at(z));
+        let size = (xmax - xmin) / width as f64;
+        let column = |x: f64| (((x - xmin) / size).floor().max(0.0) as u32).min(width - 1);
+        let row = |y: f64| (((ymax - y) / size).floor().max(0.0) as u32).min(height - 1);
+        Some([column(west), row(north), column(east), row(south)])
+    }
+
+    pub fn validate(&self) -> AppResult<()> {
+        let invalid = |msg: String| Err(AppError::InvalidInput(msg));
+        if self.id.is_empty()
+            || !self
+                .id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return invalid(format!(
+                "tile matrix set id '{}' may only use letters, digits, '_' and '-'",
+                self.id
+            ));
+        }
+        if self.srid == 0 {
+            return invalid(format!("tile matrix set '{}' needs an srid", self.id));
+        }
+        for size in [self.matrix_width, self.matrix_height] {
+            if !size.is_power_of_two() || size > 1024 {
+                return invalid(format!(
+                    "tile matrix set '{}': matrix_width and matrix_height must be \
+                     powers of two",
+                    self.id
+                ));
+            }
+        }
+        let [xmin, ymin, xmax, ymax] = self.bounds;
+        if !(xmin < xmax && ymin < ymax) {
+            return invalid(format!(
+                "tile matrix set '{}': bounds must be xmin, ymin, xmax, ymax",
+                self.id
+            ));
+        }
+        let tile_width = (xmax - xmin) / self.matrix_width as f64;
+        let tile_height = (ymax - ymin) / self.matrix_height as f64;
+        if ((tile_width - tile_height) / tile_width).abs() > 1e-9 {
+            return invalid(format!(
+                "tile matrix set '{}': tiles must be square \
+                 (bounds do not match matrix_width/matrix_height)",
+                self.id
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// WGS84 position in EPSG:3857 metres, clamped to the Web Mercator limits.
+pub fn lonlat_to_web_mercator(lon: f64, lat: f64) -> (f64, f64) {
+    let lat = lat.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    let x = lon.clamp(-180.0, 180.0) / 180.0 * WEB_MERCATOR_HALF;
+    let y = (std::f64::consts::FRAC_PI_4 + lat / 2.0).tan().ln() / std::f64::consts::PI
+        * WEB_MERCATOR_HALF;
+    (x, y)
+}
+
+/// Built-in sets followed by the configured ones. Custom ids must be unique
+/// and must not shadow a built-in set.
+pub fn build_registry(custom: &[TileMatrixSet]) -> AppResult<Vec<TileMatrixSet>> {
+    let mut sets = TileMatrixSet::builtin();
+    for set in custom {
+        set.validate()?;
+        if sets.iter().any(|s| s.id == set.id) {
+            return Err(AppError::InvalidInput(format!(
+                "tile matrix set '{}' is defined twice",
+                set.id
+            )));
+        }
+        sets.push(set.clone());
+    }
+    Ok(sets)
+}
+
+/// WebMercatorQuad: the registry always starts with the built-in sets.
+pub fn default_tile_matrix_set() -> &'static TileMatrixSet {
+    &crate::get_tile_matrix_sets()[0]
+}
+
+/// Looks up a set by id among the registered ones.
+pub fn find_tile_matrix_set(id: &str) -> Option<&'static TileMatrixSet> {
+    crate::get_tile_matrix_sets().iter().find(|s| s.id == id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_sets_are_valid_and_registered() {
+        for set in TileMatrixSet::builtin() {
+            set.validate().unwrap();
+        }
+        assert!(find_tile_matrix_set(WEB_MERCATOR_QUAD).unwrap().is_default());
+        assert_eq!(find_tile_matrix_set(WORLD_CRS84_QUAD).unwrap().srid, 4326);
+        assert!(find_tile_matrix_set("Unknown").is_none());
+        assert!(default_tile_matrix_set().is_default());
+    }
+
+    #[test]
+    fn web_mercator_uses_plain_tile_envelope_grid() {
+        let set = TileMatrixSet::web_mercator_quad();
+        assert_eq!(set.envelope_zoom(5), 5);
+        assert_eq!(set.envelope_bounds(), set.bounds);
+        assert!(set.contains(2, 3, 3));
+        assert!(!set.contains(2, 4, 0));
+    }
+
+    #[test]
+    fn world_crs84_quad_maps_to_next_level_of_square_grid() {
+        let set = TileMatrixSet::world_crs84_quad();
+        assert_eq!(set.matrix_width_at(0), 2);
+        assert_eq!(set.matrix_height_at(3), 8);
+        assert_eq!(set.envelope_zoom(0), 1);
+        assert_eq!(set.envelope_bounds(), [-180.0, -270.0, 180.0, 90.0]);
+        assert!(set.contains(1, 3, 1));
+        assert!(!set.contains(1, 3, 2));
+        assert!(set.contains(29, 0, 0));
+        assert!(!set.contains(30, 0, 0));
+        assert!(!set.contains(u32::MAX, 0, 0));
+    }
+
+    #[test]
+    fn tile_matrices_match_the_ogc_definitions() {
+        let mercator = TileMatrixSet::web_mercator_quad().tile_matrix(0);
+        assert!((mercator.scale_denominator - 559082264.0287178).abs() < 1e-3);
+        assert!((mercator.cell_size - 156543.03392804097).abs() < 1e-6);
+        assert_eq!(mercator.point_of_origin, [-WEB_MERCATOR_HALF, WEB_MERCATOR_HALF]);
+
+        let crs84 = TileMatrixSet::world_crs84_quad();
+        let level = crs84.tile_matrix(1);
+        assert!((level.scale_denominator - 139770566.0071794).abs() < 1e-3);
+        assert_eq!((level.matrix_width, level.matrix_height), (4, 2));
+        assert_eq!(crs84.crs_uri(), "http://www.opengis.net/def/crs/OGC/1.3/CRS84");
+        assert_eq!(
+            crs84.uri().as_deref(),
+            Some("http://www.opengis.net/def/tilematrixset/OGC/1.0/WorldCRS84Quad")
+        );
+    }
+
+    #[test]
+    fn tile_ranges_are_clamped_to_the_grid() {
+        let crs84 = TileMatrixSet::world_crs84_quad();
+        assert_eq!(crs84.tile_range([-58.5, -34.7, -58.3, -34.5], 0), Some([0, 0, 0, 0]));
+        assert_eq!(crs84.tile_range([-58.5, -34.7, -58.3, -34.5], 3), Some([5, 5, 5, 5]));
+        assert_eq!(crs84.tile_range([-200.0, -100.0, 200.0, 100.0], 1), Some([0, 0, 3, 1]));
+        assert_eq!(crs84.tile_range([190.0, 0.0, 200.0, 10.0], 1), None);
+
+        let (x, y) = lonlat_to_web_mercator(180.0, 90.0);
+        assert!((x - WEB_MERCATOR_HALF).abs() < 1e-6 && (y - WEB_MERCATOR_HALF).abs() < 1e-3);
+        let (x, y) = lonlat_to_web_mercator(-90.0, 0.0);
+        assert!((x + WEB_MERCATOR_HALF / 2.0).abs() < 1e-6 && y.abs() < 1e-6);
+    }
+
+    #[test]
+    fn custom_sets_are_validated() {
+        let custom = TileMatrixSet {
+            id: "Posgar07Faja5".to_string(),
+            title: String::new(),
+            srid: 5347,
+            bounds: [4_000_000.0, 3_500_000.0, 6_000_000.0, 7_500_000.0],
+            matrix_width: 1,
+            matrix_height: 2,
+        };
+        let sets = build_registry(std::slice::from_ref(&custom)).unwrap();
+        assert_eq!(sets.len(), 3);
+        assert!(custom.uri().is_none());
+        assert_eq!(custom.crs_uri(), "http://www.opengis.net/def/crs/EPSG/0/5347");
+        assert_eq!(custom.envelope_zoom(4), 5);
+        assert_eq!(
+            custom.envelope_bounds(),
+            [4_000_000.0, 3_500_000.0, 8_000_000.0, 7_500_000.0]
+        );
+
+        let mut not_square = custom.clone();
+        not_square.matrix_height = 1;
+        assert!(not_square.validate().is_err());
+
+        let mut bad_id = custom.clone();
+        bad_id.id = "../x".to_string();
+        assert!(bad_id.validate().is_err());
+
+        assert!(build_registry(&[TileMatrixSet::world_crs84_quad()]).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use prometheus::{Counter, Gauge, Opts, Registry};
+use prometheus::{Counter, CounterVec, Gauge, Opts, Registry};
 use std::sync::LazyLock;
 
 // Registro central
@@ -25,6 +25,22 @@ pub static CACHE_HITS: LazyLock<Counter> =
 pub static CACHE_MISSES: LazyLock<Counter> =
     LazyLock::new(|| register_counter("cache_misses_total", "Total cache misses"));
 
+pub static CACHE_TIER_HITS: LazyLock<CounterVec> = LazyLock::new(|| {
+    register_counter_vec("cache_tier_hits_total", "Cache hits by cache tier", &["tier"])
+});
+
+pub static LAYER_CACHE_LOOKUPS: LazyLock<CounterVec> = LazyLock::new(|| {
+    register_counter_vec(
+        "layer_cache_lookups_total",
+        "Tile cache lookups by layer and result",
+        &["layer", "result"],
+    )
+});
+
+pub static MEMORY_CACHE_BYTES: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge("memory_cache_bytes", "Size of the tiles in the in-memory cache")
+});
+
 pub static LAST_LATENCY: LazyLock<Gauge> = LazyLock::new(|| {
     register_gauge(
         "last_request_latency_seconds",
@@ -35,6 +51,20 @@ pub static LAST_LATENCY: LazyLock<Gauge> = LazyLock::new(|| {
 pub static AVG_LATENCY: LazyLock<Gauge> =
     LazyLock::new(|| register_gauge("avg_request_latency_seconds", "Average request latency"));
 
+pub static TILE_REQUESTS_COALESCED: LazyLock<Counter> = LazyLock::new(|| {
+    register_counter(
+        "tile_requests_coalesced_total",
+        "Tile requests served by another request's in-flight query",
+    )
+});
+
+pub static TILE_QUERIES_IN_FLIGHT: LazyLock<Gauge> = LazyLock::new(|| {
+    register_gauge(
+        "tile_queries_in_flight",
+        "Distinct tile queries currently running",
+    )
+});
+
 // Helpers privados para reducir boilerplate
 fn register_gauge(name: &str, help: &str) -> Gauge {
     let g = Gauge::with_opts(Opts::new(name, help)).unwrap();
@@ -52,18 +82,51 @@ fn register_counter(name: &str, help: &str) -> Counter {
     c
 }
 
+fn register_counter_vec(name: &str, help: &str, labels: &[&str]) -> CounterVec {
+    let c = CounterVec::new(Opts::new(name, help), labels).unwrap();
+    REGISTRY
+        .register(Box::new(c.clone()))
+        .expect("metric registration failed");
+    c
+}
+
 pub fn record_request() {
     REQUESTS_TOTAL.inc();
 }
 
-pub fn record_cache_hit() {
+pub fn record_cache_hit(tier: &str) {
     CACHE_HITS.inc();
+    CACHE_TIER_HITS.with_label_values(&[tier]).inc();
 }
 
 pub fn record_cache_miss() {
     CACHE_MISSES.inc();
 }
 
+/// Counts cache lookups only: filtered and plugin tiles never consult the cache.
+pub fn record_layer_cache_lookup(layer: &str, hit: bool) {
+    let result = if hit { "hit" } else { "miss" };
+    LAYER_CACHE_LOOKUPS.with_label_values(&[layer, result]).inc();
+}
+
+/// Hits and misses of a layer's cache lookups since this instance started.
+pub fn layer_cache_lookups(layer: &str) -> (u64, u64) {
+    let count = |result: &str| LAYER_CACHE_LOOKUPS.with_label_values(&[layer, result]).get() as u64;
+    (count("hit"), count("miss"))
+}
+
+pub fn set_memory_cache_bytes(bytes: usize) {
+    MEMORY_CACHE_BYTES.set(bytes as f64);
+}
+
+pub fn record_coalesced() {
+    TILE_REQUESTS_COALESCED.inc();
+}
+
+pub fn set_tile_queries_in_flight(count: usize) {
+    TILE_QUERIES_IN_FLIGHT.set(count as f64);
+}
+
 pub fn record_latency(secs: f64) {
     LAST_LATENCY.set(secs);
     let current_avg = AVG_LATENCY.get();
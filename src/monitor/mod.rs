@@ -3,4 +3,6 @@ pub mod handlers;
 pub mod metrics;
 
 pub use collector::start_system_monitor;
-pub use metrics::{record_cache_hit, record_cache_miss, record_latency, record_request};
+pub use metrics::{
+    record_cache_hit, record_cache_miss, record_latency, record_layer_cache_lookup, record_request,
+};
@@ -1,9 +1,9 @@
 use include_dir::{Dir, include_dir};
 use mime_guess::from_path;
-use salvo::cache::{Cache, MokaStore, RequestIssuer};
+use salvo::cache::{Cache, CacheIssuer, MokaStore, RequestIssuer};
 use salvo::catcher::Catcher;
 use salvo::cors::{self as cors, Cors};
-use salvo::http::header::CONTENT_DISPOSITION;
+use salvo::http::header::{ACCEPT_ENCODING, CONTENT_DISPOSITION, HeaderValue, VARY};
 use salvo::logging::Logger;
 use salvo::prelude::*;
 use salvo::rate_limiter::{BasicQuota, FixedGuard, MokaStore as RateMokaStore, RateLimiter, RemoteIpIssuer};
@@ -12,10 +12,10 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use crate::{
-    api, auth, config::settings::Settings, html,
+    api, auth, cache::encoding::accepts_encoding, config::settings::Settings, html,
     i18n::{I18n, i18n_middleware},
     monitor,
-    services::{health, legends, styles, tilejson, tiles::handlers as tiles},
+    services::{health, legends, ogc, styles, tilejson, tiles::handlers as tiles, tiles::scheme as tiles_scheme, wmts},
 };
 
 const STATIC_DIR: Dir = include_dir!("$CARGO_MANIFEST_DIR/static");
@@ -64,12 +64,36 @@ fn build_session_handler(settings: &Settings) -> SessionHandler<CookieStore> {
         .expect("Failed to build session handler")
 }
 
-fn build_cache_middleware(ttl_secs: u64) -> Cache<MokaStore<String>, RequestIssuer> {
+/// Keys responses on the request URI and the tile codings the client
+/// accepts: a tile is sent gzip or brotli encoded only to clients that
+/// accept it. salvo-cache never stores a response carrying `Vary`, so
+/// `vary_tile_encoding` adds that header outside the cache.
+struct EncodingIssuer(RequestIssuer);
+
+impl CacheIssuer for EncodingIssuer {
+    type Key = String;
+    async fn issue(&self, req: &mut Request, depot: &Depot) -> Option<Self::Key> {
+        let mut key = self.0.issue(req, depot).await?;
+        let accept_encoding = req
+            .headers()
+            .get(ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok());
+        for coding in ["gzip", "br"] {
+            if accepts_encoding(accept_encoding, coding) {
+                key.push('|');
+                key.push_str(coding);
+            }
+        }
+        Some(key)
+    }
+}
+
+fn build_cache_middleware(ttl_secs: u64) -> Cache<MokaStore<String>, EncodingIssuer> {
     Cache::new(
         MokaStore::builder()
             .time_to_live(Duration::from_secs(ttl_secs))
             .build(),
-        RequestIssuer::default(),
+        EncodingIssuer(RequestIssuer::default()),
     )
 }
 
@@ -207,14 +231,27 @@ fn build_admin_catalog_routes() -> Router {
             Router::with_path("layers/delete_cache/{id}")
                 .get(html::admin::catalog::delete_layer_cache),
         )
+        .push(
+            Router::with_path("layers/invalidate_cache")
+                .post(html::admin::catalog::invalidate_layer_cache_region),
+        )
 }
 
 fn build_admin_database_routes() -> Router {
     Router::with_path("database")
         .push(Router::with_path("schemas").get(html::admin::database::schemas))
         .push(Router::with_path("tables").get(html::admin::database::tables))
+        .push(Router::with_path("functions").get(html::admin::database::functions))
         .push(Router::with_path("fields").get(html::admin::database::fields))
         .push(Router::with_path("srid").get(html::admin::database::srid))
+        .push(Router::with_path("static_files").get(html::admin::database::static_files))
+}
+
+fn build_admin_cache_routes() -> Router {
+    Router::with_path("cache")
+        .hoop(auth::require_user_admin)
+        .get(html::admin::cache::cache_page)
+        .push(Router::with_path("{id}/tiles").get(html::admin::cache::cache_tiles_page))
 }
 
 fn build_admin_monitor_routes() -> Router {
@@ -232,6 +269,7 @@ fn build_admin_routes() -> Router {
         .push(build_admin_styles_routes())
         .push(build_admin_groups_routes())
         .push(build_admin_catalog_routes())
+        .push(build_admin_cache_routes())
         .push(build_admin_database_routes())
         .push(build_admin_monitor_routes())
         .push(Router::with_path("plugins").get(html::admin::plugins::index))
@@ -285,6 +323,7 @@ fn build_api_database_routes() -> Router {
     Router::with_path("database")
         .push(Router::with_path("schemas").get(api::database::schemas))
         .push(Router::with_path("tables/{schema}").get(api::database::tables))
+        .push(Router::with_path("functions/{schema}").get(api::database::functions))
         .push(Router::with_path("fields/{schema}/{table}").get(api::database::fields))
         .push(Router::with_path("srid/{schema}/{table}/{geometry}").get(api::database::srid))
 }
@@ -298,10 +337,34 @@ fn build_api_catalog_routes() -> Router {
                 .put(api::catalog::update_layer)
                 .delete(api::catalog::delete_layer)
                 .push(Router::with_path("publish").patch(api::catalog::toggle_published))
-                .push(Router::with_path("cache").delete(api::catalog::delete_layer_cache)),
+                .push(
+                    Router::with_path("cache")
+                        .delete(api::catalog::delete_layer_cache)
+                        .push(
+                            Router::with_path("invalidate")
+                                .post(api::catalog::invalidate_layer_cache_region),
+                        )
+                        .push(
+                            Router::with_path("mbtiles")
+                                .get(api::catalog::download_layer_cache),
+                        )
+                        .push(
+                            Router::with_path("seed")
+                                .post(api::catalog::seed_layer_cache)
+                                .get(api::catalog::seed_layer_cache_status),
+                        ),
+                ),
         )
 }
 
+fn build_api_cache_routes() -> Router {
+    Router::with_path("cache").get(api::cache::list).push(
+        Router::with_path("{id}")
+            .get(api::cache::layer_stats)
+            .push(Router::with_path("tiles").get(api::cache::tiles)),
+    )
+}
+
 fn build_api_routes() -> Router {
     Router::with_path("api")
         .push(
@@ -321,22 +384,34 @@ fn build_api_routes() -> Router {
                 .push(build_api_categories_routes())
                 .push(build_api_styles_routes())
                 .push(build_api_database_routes())
-                .push(build_api_catalog_routes()),
+                .push(build_api_catalog_routes())
+                .push(build_api_cache_routes()),
         )
 }
 
 fn build_tiles_routes() -> Router {
     Router::new()
         .push(
-            Router::with_path("tiles/{layer_name}/{z}/{x}/{y}.pbf")
+            Router::with_path("tiles/tms")
+                .hoop(tiles_scheme::tms_scheme)
+                .push(build_tile_endpoints()),
+        )
+        .push(Router::with_path("tiles").push(build_tile_endpoints()))
+}
+
+/// Tile endpoints, relative to `tiles/` or `tiles/tms/`.
+fn build_tile_endpoints() -> Router {
+    Router::new()
+        .push(
+            Router::with_path("{layer_name}/{z}/{x}/{y}.pbf")
                 .get(tiles::get_single_layer_tile),
         )
         .push(
-            Router::with_path("tiles/multi/{layers}/{z}/{x}/{y}.pbf")
+            Router::with_path("multi/{layers}/{z}/{x}/{y}.pbf")
                 .get(tiles::get_composite_layers_tile),
         )
         .push(
-            Router::with_path("tiles/category/{category}/{z}/{x}/{y}.pbf")
+            Router::with_path("category/{category}/{z}/{x}/{y}.pbf")
                 .get(tiles::get_category_layers_tile),
         )
 }
@@ -359,6 +434,47 @@ fn build_services_routes(settings: &Settings, cache: impl Handler) -> Router {
         )
 }
 
+/// OGC API - Tiles. Not behind the services response cache: documents
+/// depend on the caller's groups.
+fn build_ogc_routes() -> Router {
+    Router::with_path("ogc")
+        .get(ogc::landing)
+        .push(Router::with_path("conformance").get(ogc::conformance))
+        .push(
+            Router::with_path("collections")
+                .get(ogc::list_collections)
+                .push(
+                    Router::with_path("{collectionId}")
+                        .get(ogc::get_collection)
+                        .push(
+                            Router::with_path("tiles")
+                                .get(ogc::list_tilesets)
+                                .push(
+                                    Router::with_path("{tileMatrixSetId}")
+                                        .get(ogc::get_tileset)
+                                        .push(
+                                            Router::with_path("{tileMatrix}/{tileRow}/{tileCol}")
+                                                .get(ogc::get_collection_tile),
+                                        ),
+                                ),
+                        ),
+                ),
+        )
+        .push(
+            Router::with_path("tileMatrixSets")
+                .get(ogc::list_tile_matrix_sets)
+                .push(Router::with_path("{tileMatrixSetId}").get(ogc::get_tile_matrix_set)),
+        )
+}
+
+/// WMTS capabilities. Mounted beside `services` rather than inside it so
+/// the response cache never hands one user's layer list to another.
+fn build_wmts_routes() -> Router {
+    Router::with_path("services/wmts")
+        .get(wmts::kvp)
+        .push(Router::with_path("1.0.0/WMTSCapabilities.xml").get(wmts::capabilities))
+}
+
 fn build_public_routes() -> Router {
     Router::new()
         .hoop(i18n_middleware)
@@ -372,6 +488,10 @@ fn build_public_routes() -> Router {
 // MAIN ROUTER
 // ============================================================================
 
+fn is_tile_path(path: &str) -> bool {
+    path.starts_with("/services/tiles/") || path.starts_with("/ogc/collections/")
+}
+
 /// Strips `Set-Cookie` from tile responses at the Service level, which runs
 /// its after-phase AFTER the router (including session_handler) has finished.
 /// This lets browsers cache tiles while keeping session auth intact for tiles
@@ -384,11 +504,28 @@ async fn strip_tile_cookie(
     ctrl: &mut FlowCtrl,
 ) {
     ctrl.call_next(req, depot, res).await;
-    if req.uri().path().starts_with("/services/tiles/") {
+    if is_tile_path(req.uri().path()) {
         res.headers_mut().remove("set-cookie");
     }
 }
 
+/// Tile bodies follow the client's Accept-Encoding. The header is added
+/// here, outside the services response cache, which would not store the
+/// tile otherwise; that cache keys on the accepted codings instead.
+#[handler]
+async fn vary_tile_encoding(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    ctrl.call_next(req, depot, res).await;
+    if is_tile_path(req.uri().path()) {
+        res.headers_mut()
+            .insert(VARY, HeaderValue::from_static("Accept-Encoding"));
+    }
+}
+
 /// Reduced router for `cluster.mode = client`: no SQLite, so only memory-served
 /// reads (tiles/styles/legends), health, and static assets are mounted. Admin,
 /// write API, and `/internal` are intentionally absent (nginx routes those to
@@ -405,10 +542,13 @@ pub fn client_app_router(settings: &Settings, i18n_service: Arc<I18n>) -> Servic
         .hoop(session_handler)
         .push(Router::with_path("health").get(health::get_health))
         .push(build_services_routes(settings, cache_5s))
+        .push(build_wmts_routes())
+        .push(build_ogc_routes())
         .push(Router::with_path("static/{**path}").get(serve_static));
 
     Service::new(router)
         .hoop(strip_tile_cookie)
+        .hoop(vary_tile_encoding)
         .hoop(cors_handler)
         .catcher(Catcher::default().hoop(html::errors::handle_errors))
 }
@@ -431,6 +571,8 @@ pub fn app_router(settings: &Settings, i18n_service: Arc<I18n>) -> Service {
         .push(build_api_routes())
         .push(Router::with_path("health").get(health::get_health))
         .push(build_services_routes(settings, cache_5s))
+        .push(build_wmts_routes())
+        .push(build_ogc_routes())
         .push(Router::with_path("static/{**path}").get(serve_static));
 
     if settings.cluster.mode == "owner" {
@@ -439,6 +581,147 @@ pub fn app_router(settings: &Settings, i18n_service: Arc<I18n>) -> Service {
 
     Service::new(router)
         .hoop(strip_tile_cookie) // outermost: after-phase runs after session_handler
+        .hoop(vary_tile_encoding)
         .hoop(cors_handler)
         .catcher(Catcher::default().hoop(html::errors::handle_errors))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use salvo::http::header::CONTENT_ENCODING;
+    use salvo::test::{ResponseExt, TestClient};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{
+        cache::{cachewrapper::CacheWrapper, encoding::TileEncoding},
+        db::static_tiles::StaticTileRegistry,
+        models::catalog::{Catalog, Layer, SourceKind},
+    };
+
+    static RENDERED: AtomicUsize = AtomicUsize::new(0);
+
+    #[handler]
+    async fn negotiated_tile(req: &mut Request, res: &mut Response) {
+        RENDERED.fetch_add(1, Ordering::SeqCst);
+        let accept_encoding = req.headers().get(ACCEPT_ENCODING).and_then(|v| v.to_str().ok());
+        if accepts_encoding(accept_encoding, "gzip") {
+            res.render("gzip");
+        } else {
+            res.render("identity");
+        }
+    }
+
+    #[tokio::test]
+    async fn tiles_are_cached_per_accepted_encoding_and_vary_on_it() {
+        let router = Router::with_path("services")
+            .hoop(build_cache_middleware(5))
+            .push(Router::with_path("tiles/{**rest}").get(negotiated_tile));
+        let service = Service::new(router).hoop(vary_tile_encoding);
+        let url = "http://localhost/services/tiles/cat:layer/0/0/0.pbf";
+
+        for (accept_encoding, body, rendered) in [
+            (Some("gzip, br"), "gzip", 1),
+            (Some("gzip"), "gzip", 2),
+            (Some("gzip, br"), "gzip", 2),
+            (None, "identity", 3),
+            (None, "identity", 3),
+        ] {
+            let mut req = TestClient::get(url);
+            if let Some(value) = accept_encoding {
+                req = req.add_header(ACCEPT_ENCODING, value, true);
+            }
+            let mut res = req.send(&service).await;
+            assert_eq!(
+                res.headers().get(VARY).and_then(|v| v.to_str().ok()),
+                Some("Accept-Encoding")
+            );
+            assert_eq!(res.take_string().await.unwrap(), body);
+            assert_eq!(RENDERED.load(Ordering::SeqCst), rendered);
+        }
+    }
+
+    /// `tests/fixtures/static_layer.mbtiles` holds two tiles: a gzip tile
+    /// with a `world` layer at 0/0/0, and a plain one with a `north_west`
+    /// layer at the top-left tile of zoom 1, stored as TMS row 1.
+    const WORLD_TILE: &[u8] = b"\x1a\x0c\x78\x02\x0a\x05world\x28\x80\x20";
+    const NORTH_WEST_TILE: &[u8] = b"\x1a\x11\x78\x02\x0a\x0anorth_west\x28\x80\x20";
+
+    /// The tile routes over the fixture MBTiles file, published as
+    /// `public:static_layer` with the tile cache off.
+    async fn fixture_tile_service() -> Service {
+        let _ = crate::STATIC_TILE_REGISTRY.set(StaticTileRegistry::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures"
+        )));
+        let _ = crate::CACHE_WRAPPER.set(CacheWrapper::new_disabled());
+        crate::CATALOG
+            .get_or_init(|| async {
+                let layer = Layer {
+                    name: "static_layer".to_string(),
+                    source_kind: SourceKind::Mbtiles,
+                    static_file: Some("static_layer.mbtiles".to_string()),
+                    zmin: Some(0),
+                    zmax: Some(1),
+                    ..crate::config::test_support::test_layer("static-1")
+                };
+                tokio::sync::RwLock::new(Catalog { layers: vec![layer] })
+            })
+            .await;
+        Service::new(Router::with_path("services").push(build_tiles_routes()))
+    }
+
+    async fn get_tile(service: &Service, path: &str, accept_encoding: Option<&str>) -> Response {
+        let mut req = TestClient::get(format!("http://localhost/services/tiles/{path}"));
+        if let Some(value) = accept_encoding {
+            req = req.add_header(ACCEPT_ENCODING, value, true);
+        }
+        req.send(service).await
+    }
+
+    #[tokio::test]
+    async fn mbtiles_layers_serve_their_tiles_end_to_end() {
+        let service = fixture_tile_service().await;
+
+        // XYZ row 0 of zoom 1 is TMS row 1 in the file.
+        let mut res = get_tile(&service, "public:static_layer/1/0/0.pbf", None).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_bytes(None).await.unwrap().as_ref(), NORTH_WEST_TILE);
+
+        // TMS clients ask for that row as is.
+        let mut res = get_tile(&service, "tms/public:static_layer/1/0/1.pbf", None).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(res.take_bytes(None).await.unwrap().as_ref(), NORTH_WEST_TILE);
+        let mut res = get_tile(&service, "public:static_layer/1/0/1.pbf?scheme=tms", None).await;
+        assert_eq!(res.take_bytes(None).await.unwrap().as_ref(), NORTH_WEST_TILE);
+
+        // A tile the file does not hold is an empty 204.
+        let mut res = get_tile(&service, "public:static_layer/1/0/1.pbf", None).await;
+        assert_eq!(res.status_code, Some(StatusCode::NO_CONTENT));
+        assert!(res.take_bytes(None).await.unwrap().is_empty());
+        let res = get_tile(&service, "tms/public:static_layer/1/0/0.pbf", None).await;
+        assert_eq!(res.status_code, Some(StatusCode::NO_CONTENT));
+
+        // Zooms outside the layer range are rejected.
+        let res = get_tile(&service, "public:static_layer/2/0/0.pbf", None).await;
+        assert_eq!(res.status_code, Some(StatusCode::BAD_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn gzip_mbtiles_tiles_pass_through_to_clients_accepting_gzip() {
+        let service = fixture_tile_service().await;
+
+        let mut res = get_tile(&service, "public:static_layer/0/0/0.pbf", Some("gzip")).await;
+        assert_eq!(res.status_code, Some(StatusCode::OK));
+        assert_eq!(
+            res.headers().get(CONTENT_ENCODING).and_then(|v| v.to_str().ok()),
+            Some("gzip")
+        );
+        let body = res.take_bytes(None).await.unwrap();
+        assert_eq!(TileEncoding::Gzip.decode(body).unwrap().as_ref(), WORLD_TILE);
+
+        let mut res = get_tile(&service, "public:static_layer/0/0/0.pbf", None).await;
+        assert!(res.headers().get(CONTENT_ENCODING).is_none());
+        assert_eq!(res.take_bytes(None).await.unwrap().as_ref(), WORLD_TILE);
+    }
+}
@@ -0,0 +1,182 @@
+//! Per-layer cache statistics for the admin API and pages: what the backend
+//! and the memory tier hold for each layer, and how often its tiles were
+//! found in the cache. Hits and misses are counted by this instance since
+//! it started; the stored tiles are read from the backend, at most once
+//! every `USAGE_TTL` per layer.
+
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::{
+    cache::stats::{LayerUsage, TileListing, TilePage},
+    error::AppResult,
+    get_cache_wrapper, get_catalog,
+    models::catalog::Layer,
+    monitor::metrics::layer_cache_lookups,
+};
+
+/// Tiles listed per page when none is asked for, and at most.
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// How long the stored tiles of a layer are reused. Reading them walks the
+/// whole layer (a Redis `SCAN`, an object store listing...), so reloading
+/// the page does not repeat it.
+const USAGE_TTL: Duration = Duration::from_secs(30);
+/// Layers read from the backend at once.
+const STATS_CONCURRENCY: usize = 4;
+
+static USAGES: LazyLock<Mutex<HashMap<String, (Instant, LayerUsage)>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LayerCacheStats {
+    pub id: String,
+    /// `{category}_{name}`, as in the tile URLs.
+    pub layer: String,
+    pub alias: String,
+    /// Stored tiles; `None` when the backend could not be read.
+    #[serde(flatten)]
+    pub usage: Option<LayerUsage>,
+    /// Tiles also held in memory; `None` when the memory tier is off.
+    pub memory: Option<LayerUsage>,
+    pub hits: u64,
+    pub misses: u64,
+    /// Share of the lookups served from the cache, `None` before the first.
+    pub hit_ratio: Option<f64>,
+    /// Why the stored tiles are missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub backend: &'static str,
+    pub layers: Vec<LayerCacheStats>,
+}
+
+fn layer_key(layer: &Layer) -> String {
+    format!("{}_{}", layer.category.name, layer.name)
+}
+
+fn hit_ratio(hits: u64, misses: u64) -> Option<f64> {
+    let lookups = hits + misses;
+    (lookups > 0).then(|| hits as f64 / lookups as f64)
+}
+
+/// Stored tiles of a layer, read again once `USAGE_TTL` has passed.
+async fn layer_usage(key: &str, max_cache_age: u64) -> AppResult<LayerUsage> {
+    let recent = USAGES
+        .lock()
+        .ok()
+        .and_then(|usages| usages.get(key).cloned())
+        .filter(|(read_at, _)| read_at.elapsed() < USAGE_TTL);
+    if let Some((_, usage)) = recent {
+        return Ok(usage);
+    }
+    let usage = get_cache_wrapper().layer_usage(key, max_cache_age).await?;
+    if let Ok(mut usages) = USAGES.lock() {
+        usages.insert(key.to_string(), (Instant::now(), usage.clone()));
+    }
+    Ok(usage)
+}
+
+/// Statistics of one layer. A backend error is reported in `error` rather
+/// than failing the call.
+pub async fn layer_cache_stats(layer: &Layer) -> LayerCacheStats {
+    let key = layer_key(layer);
+    let (usage, error) = match layer_usage(&key, layer.get_max_cache_age()).await {
+        Ok(usage) => (Some(usage), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let (hits, misses) = layer_cache_lookups(&key);
+    LayerCacheStats {
+        id: layer.id.clone(),
+        memory: get_cache_wrapper().memory_usage(&key),
+        alias: layer.alias.clone(),
+        layer: key,
+        usage,
+        hits,
+        misses,
+        hit_ratio: hit_ratio(hits, misses),
+        error,
+    }
+}
+
+/// Statistics of every layer in the catalog, by layer name.
+pub async fn cache_stats() -> CacheStats {
+    let mut layers: Vec<Layer> = get_catalog().await.read().await.layers.clone();
+    layers.sort_by_key(layer_key);
+    let stats = futures::stream::iter(&layers)
+        .map(layer_cache_stats)
+        .buffered(STATS_CONCURRENCY)
+        .collect()
+        .await;
+    CacheStats {
+        backend: get_cache_wrapper().backend(),
+        layers: stats,
+    }
+}
+
+/// A page of the layer's cached tiles; the page size is capped at
+/// `MAX_PAGE_SIZE`.
+pub async fn layer_cached_tiles(
+    layer: &Layer,
+    z: Option<u32>,
+    page: usize,
+    page_size: Option<usize>,
+) -> AppResult<TileListing> {
+    let limit = page_size
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let page = TilePage {
+        z,
+        offset: page.saturating_sub(1).saturating_mul(limit),
+        limit,
+    };
+    get_cache_wrapper()
+        .list_tiles(&layer_key(layer), layer.get_max_cache_age(), &page)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hit_ratio_waits_for_the_first_lookup() {
+        assert_eq!(hit_ratio(0, 0), None);
+        assert_eq!(hit_ratio(3, 1), Some(0.75));
+        assert_eq!(hit_ratio(0, 5), Some(0.0));
+    }
+
+    #[test]
+    fn unreadable_layers_report_the_error_instead_of_usage() {
+        let stats = LayerCacheStats {
+            id: "layer-1".to_string(),
+            layer: "cat_roads".to_string(),
+            alias: "Roads".to_string(),
+            usage: None,
+            memory: None,
+            hits: 0,
+            misses: 0,
+            hit_ratio: None,
+            error: Some("connection refused".to_string()),
+        };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert!(json.get("tiles").is_none());
+        assert_eq!(json["error"], "connection refused");
+
+        let stats = LayerCacheStats {
+            usage: Some(LayerUsage::default()),
+            error: None,
+            ..stats
+        };
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["tiles"], 0);
+        assert!(json.get("error").is_none());
+    }
+}
@@ -0,0 +1,309 @@
+//! Database-driven invalidation: listens on `CHANGES_CHANNEL` of every
+//! database with a `notify_changes` layer, and drops the cached tiles around
+//! the rows the layers' triggers report (see `db::triggers`). Notices are
+//! applied in short batches, one region per layer.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use serde::Deserialize;
+use sqlx::postgres::PgListener;
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender, unbounded_channel};
+use tokio::time::{Instant, sleep, timeout_at};
+use tracing::{info, warn};
+
+use crate::{
+    db::triggers::CHANGES_CHANNEL,
+    error::{AppError, AppResult},
+    get_catalog, get_db_registry,
+    models::catalog::Layer,
+    services::invalidate::invalidate_layer_bboxes,
+};
+
+/// Notices arriving this long after the first one of a batch join it.
+const BATCH_WINDOW: Duration = Duration::from_millis(500);
+const MAX_BATCH: usize = 10_000;
+/// Bboxes of a layer in one batch past which their union is dropped instead.
+const MAX_BBOXES: usize = 256;
+/// How often new `notify_changes` databases are picked up.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Payload of the change triggers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChangeNotice {
+    pub layer: String,
+    #[serde(default)]
+    pub old: Option<[f64; 4]>,
+    #[serde(default)]
+    pub new: Option<[f64; 4]>,
+    #[serde(default)]
+    pub truncate: bool,
+}
+
+#[derive(Debug)]
+enum Event {
+    Notice(ChangeNotice),
+    /// The connection dropped: notices sent meanwhile are gone.
+    Lost,
+}
+
+/// What a batch does to one layer's cache.
+#[derive(Debug, PartialEq)]
+enum Invalidation {
+    Layer,
+    Bboxes(Vec<[f64; 4]>),
+}
+
+/// Invalidations of the watched `layers` for a batch of events; notices of
+/// other layers are ignored.
+fn plan<'a>(events: &[Event], layers: &'a [Layer]) -> Vec<(&'a Layer, Invalidation)> {
+    let lost = events.iter().any(|event| matches!(event, Event::Lost));
+    let mut plans: Vec<(&Layer, Invalidation)> = Vec::new();
+    for layer in layers {
+        let mut invalidation = if lost {
+            Invalidation::Layer
+        } else {
+            Invalidation::Bboxes(Vec::new())
+        };
+        let notices = events.iter().filter_map(|event| match event {
+            Event::Notice(notice) if notice.layer == layer.id => Some(notice),
+            _ => None,
+        });
+        for notice in notices {
+            let Invalidation::Bboxes(bboxes) = &mut invalidation else {
+                break;
+            };
+            if notice.truncate {
+                invalidation = Invalidation::Layer;
+                continue;
+            }
+            for bbox in [notice.old, notice.new].into_iter().flatten() {
+                if !bboxes.contains(&bbox) {
+                    bboxes.push(bbox);
+                }
+            }
+        }
+        match invalidation {
+            Invalidation::Bboxes(bboxes) if bboxes.is_empty() => {}
+            Invalidation::Bboxes(bboxes) if bboxes.len() > MAX_BBOXES => {
+                let union = bboxes.iter().skip(1).fold(bboxes[0], |[w, s, e, n], b| {
+                    [w.min(b[0]), s.min(b[1]), e.max(b[2]), n.max(b[3])]
+                });
+                plans.push((layer, Invalidation::Bboxes(vec![union])));
+            }
+            invalidation => plans.push((layer, invalidation)),
+        }
+    }
+    plans
+}
+
+/// Spawns a listener for each database with `notify_changes` layers, now and
+/// whenever the catalog gains one. A database whose first `LISTEN` fails is
+/// tried again on the next pass.
+pub fn start_change_listeners() {
+    tokio::spawn(async {
+        let mut listening: HashSet<String> = HashSet::new();
+        loop {
+            let databases: HashSet<String> = {
+                let catalog = get_catalog().await.read().await;
+                catalog
+                    .layers
+                    .iter()
+                    .filter(|layer| layer.get_notify_changes())
+                    .map(|layer| layer.database_id.clone())
+                    .collect()
+            };
+            for database_id in databases {
+                if listening.contains(&database_id) {
+                    continue;
+                }
+                match connect_listener(&database_id).await {
+                    Ok(listener) => {
+                        info!("changes: listening on database {database_id}");
+                        listening.insert(database_id.clone());
+                        let (sender, receiver) = unbounded_channel();
+                        tokio::spawn(apply_changes(database_id.clone(), receiver));
+                        tokio::spawn(listen(database_id, listener, sender));
+                    }
+                    Err(e) => warn!("changes: cannot listen on database {database_id}: {e}"),
+                }
+            }
+            sleep(WATCH_INTERVAL).await;
+        }
+    });
+}
+
+/// Connects to a database and listens on `CHANGES_CHANNEL`.
+async fn connect_listener(database_id: &str) -> AppResult<PgListener> {
+    let pool = get_db_registry()
+        .get_pool(database_id)
+        .cloned()
+        .ok_or_else(|| AppError::DatabaseError(format!("database {database_id} not found")))?;
+    let mut listener = PgListener::connect_with(&pool).await?;
+    listener.listen(CHANGES_CHANNEL).await?;
+    Ok(listener)
+}
+
+/// Forwards the notices of one database, reconnecting when it goes away.
+async fn listen(database_id: String, mut listener: PgListener, events: UnboundedSender<Event>) {
+    loop {
+        loop {
+            let event = match listener.try_recv().await {
+                Ok(Some(notification)) => {
+                    match serde_json::from_str::<ChangeNotice>(notification.payload()) {
+                        Ok(notice) => Event::Notice(notice),
+                        Err(e) => {
+                            warn!(
+                                "changes: unreadable notice '{}': {e}",
+                                notification.payload()
+                            );
+                            continue;
+                        }
+                    }
+                }
+                // Reconnected by the listener itself.
+                Ok(None) => Event::Lost,
+                Err(e) => {
+                    warn!("changes: connection to database {database_id} lost: {e}");
+                    break;
+                }
+            };
+            if events.send(event).is_err() {
+                return;
+            }
+        }
+
+        listener = loop {
+            sleep(RETRY_DELAY).await;
+            match connect_listener(&database_id).await {
+                Ok(listener) => break listener,
+                Err(e) => warn!("changes: cannot listen on database {database_id}: {e}"),
+            }
+        };
+        info!("changes: listening on database {database_id}");
+        if events.send(Event::Lost).is_err() {
+            return;
+        }
+    }
+}
+
+/// Batches the events of one database and invalidates the layers' tiles.
+async fn apply_changes(database_id: String, mut events: UnboundedReceiver<Event>) {
+    while let Some(event) = events.recv().await {
+        let mut batch = vec![event];
+        let deadline = Instant::now() + BATCH_WINDOW;
+        while batch.len() < MAX_BATCH {
+            match timeout_at(deadline, events.recv()).await {
+                Ok(Some(event)) => batch.push(event),
+                _ => break,
+            }
+        }
+
+        let layers: Vec<Layer> = {
+            let catalog = get_catalog().await.read().await;
+            catalog
+                .layers
+                .iter()
+                .filter(|layer| layer.get_notify_changes() && layer.database_id == database_id)
+                .cloned()
+                .collect()
+        };
+        for (layer, invalidation) in plan(&batch, &layers) {
+            invalidate(layer, invalidation).await;
+        }
+    }
+}
+
+async fn invalidate(layer: &Layer, invalidation: Invalidation) {
+    let key = format!("{}_{}", layer.category.name, layer.name);
+    let result = match invalidation {
+        Invalidation::Layer => crate::invalidate_layer_tile_cache(&key).await.map(|_| None),
+        Invalidation::Bboxes(bboxes) => match invalidate_layer_bboxes(layer, &bboxes).await {
+            Ok(tiles) => Ok(Some((bboxes.len(), tiles))),
+            // Too many tiles for a region: the whole layer goes instead.
+            Err(AppError::InvalidInput(_)) => {
+                crate::invalidate_layer_tile_cache(&key).await.map(|_| None)
+            }
+            Err(e) => Err(e),
+        },
+    };
+    match result {
+        Ok(Some((changes, tiles))) => {
+            info!(layer = %key, changes, tiles, "changes: tiles invalidated")
+        }
+        Ok(None) => info!(layer = %key, "changes: layer cache invalidated"),
+        Err(e) => warn!(layer = %key, error = ?e, "changes: invalidation failed"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn layer(id: &str) -> Layer {
+        serde_json::from_value(json!({
+            "id": id,
+            "category": { "id": "c", "name": "public", "description": "" },
+            "geometry": "points", "name": id, "alias": id, "description": "",
+            "database_id": "default", "schema": "public", "table_name": id,
+            "fields": [], "notify_changes": true, "published": true, "groups": null
+        }))
+        .unwrap()
+    }
+
+    fn notice(payload: serde_json::Value) -> Event {
+        Event::Notice(serde_json::from_value(payload).unwrap())
+    }
+
+    #[test]
+    fn batches_are_planned_per_layer() {
+        let layers = [layer("a"), layer("b"), layer("c")];
+        let events = [
+            notice(json!({ "layer": "a", "old": null, "new": [1.0, 1.0, 2.0, 2.0] })),
+            notice(
+                json!({ "layer": "a", "old": [1.0, 1.0, 2.0, 2.0], "new": [3.0, 3.0, 3.0, 3.0] }),
+            ),
+            notice(json!({ "layer": "b", "old": [0.0, 0.0, 1.0, 1.0], "new": null })),
+            notice(json!({ "layer": "b", "truncate": true })),
+            notice(json!({ "layer": "unknown", "new": [0.0, 0.0, 1.0, 1.0] })),
+        ];
+        let plans = plan(&events, &layers);
+        assert_eq!(plans.len(), 2);
+        assert_eq!(plans[0].0.id, "a");
+        assert_eq!(
+            plans[0].1,
+            Invalidation::Bboxes(vec![[1.0, 1.0, 2.0, 2.0], [3.0, 3.0, 3.0, 3.0]])
+        );
+        assert_eq!(
+            (plans[1].0.id.as_str(), &plans[1].1),
+            ("b", &Invalidation::Layer)
+        );
+
+        // A lost connection may have hidden any change.
+        let plans = plan(&[Event::Lost], &layers);
+        assert!(
+            plans
+                .iter()
+                .all(|(_, invalidation)| *invalidation == Invalidation::Layer)
+        );
+        assert_eq!(plans.len(), 3);
+    }
+
+    #[test]
+    fn large_batches_drop_the_union_of_their_bboxes() {
+        let layers = [layer("a")];
+        let events: Vec<Event> = (0..=MAX_BBOXES)
+            .map(|i| {
+                let x = i as f64 / 100.0;
+                notice(json!({ "layer": "a", "new": [x, -x, x, -x] }))
+            })
+            .collect();
+        let last = MAX_BBOXES as f64 / 100.0;
+        assert_eq!(
+            plan(&events, &layers)[0].1,
+            Invalidation::Bboxes(vec![[0.0, -last, last, 0.0]])
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! Partial cache invalidation: drops the cached tiles of a layer inside a
+//! bbox or a GeoJSON geometry over a zoom range, in every matrix set, with
+//! `CacheWrapper::invalidate_region`. Only those tiles get a new ETag.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    cache::region::TileSpan,
+    db::metadata::transform_bbox,
+    error::{AppError, AppResult},
+    get_cache_wrapper, get_tile_matrix_sets,
+    models::{
+        catalog::{BIN_READ_MARGIN, Layer},
+        tile_matrix_set::{TILE_SIZE, TileMatrixSet, lonlat_to_web_mercator},
+    },
+    services::seed::validate_seedable,
+};
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct InvalidateOptions {
+    /// Defaults to the layer's zmin; never below it.
+    pub zmin: Option<u32>,
+    /// Defaults to the layer's zmax; never above it.
+    pub zmax: Option<u32>,
+    /// west, south, east, north in EPSG:4326.
+    pub bbox: Option<[f64; 4]>,
+    /// GeoJSON geometry, Feature or FeatureCollection in EPSG:4326, used
+    /// instead of `bbox`. Tiles intersecting its bounding box are dropped.
+    pub geometry: Option<Value>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InvalidateSummary {
+    pub layer: String,
+    pub zmin: u32,
+    pub zmax: u32,
+    pub bbox: [f64; 4],
+    /// Tiles covered over every zoom level and matrix set, cached or not.
+    pub tiles: u64,
+}
+
+/// Bounding box (west, south, east, north) of the positions of a GeoJSON
+/// geometry, Feature or FeatureCollection.
+pub fn geometry_bbox(geojson: &Value) -> AppResult<[f64; 4]> {
+    let mut bbox: Option<[f64; 4]> = None;
+    extend_bbox(geojson, &mut bbox);
+    bbox.ok_or_else(|| AppError::InvalidInput("the GeoJSON has no coordinates".to_string()))
+}
+
+fn extend_bbox(value: &Value, bbox: &mut Option<[f64; 4]>) {
+    match value.get("type").and_then(Value::as_str) {
+        Some("FeatureCollection") => {
+            for feature in value["features"].as_array().into_iter().flatten() {
+                extend_bbox(feature, bbox);
+            }
+        }
+        Some("Feature") => extend_bbox(&value["geometry"], bbox),
+        Some("GeometryCollection") => {
+            for geometry in value["geometries"].as_array().into_iter().flatten() {
+                extend_bbox(geometry, bbox);
+            }
+        }
+        Some(_) => extend_positions(&value["coordinates"], bbox),
+        None => {}
+    }
+}
+
+fn extend_positions(coordinates: &Value, bbox: &mut Option<[f64; 4]>) {
+    let Some(items) = coordinates.as_array() else {
+        return;
+    };
+    match (
+        items.first().and_then(Value::as_f64),
+        items.get(1).and_then(Value::as_f64),
+    ) {
+        (Some(x), Some(y)) => {
+            let [west, south, east, north] = bbox.get_or_insert([x, y, x, y]);
+            *west = west.min(x);
+            *south = south.min(y);
+            *east = east.max(x);
+            *north = north.max(y);
+        }
+        _ => {
+            for item in items {
+                extend_positions(item, bbox);
+            }
+        }
+    }
+}
+
+/// Spans of `tms` covering `bounds` (in the set's SRID) at each zoom level,
+/// grown by `margin` tiles on every side.
+fn tile_spans(
+    tms: &TileMatrixSet,
+    bounds: [f64; 4],
+    zmin: u32,
+    zmax: u32,
+    margin: f64,
+) -> Vec<TileSpan> {
+    (zmin..=zmax.min(tms.max_zoom()))
+        .filter_map(|z| {
+            let pad = margin * (tms.bounds[2] - tms.bounds[0]) / tms.matrix_width_at(z) as f64;
+            let [west, south, east, north] = bounds;
+            let bounds = [west - pad, south - pad, east + pad, north + pad];
+            let [xmin, ymin, xmax, ymax] = tms.tile_range(bounds, z)?;
+            Some(TileSpan {
+                matrix_set: (!tms.is_default()).then(|| tms.id.clone()),
+                z,
+                xmin,
+                xmax,
+                ymin,
+                ymax,
+            })
+        })
+        .collect()
+}
+
+/// The WGS84 bbox in the set's SRID; other projections than Web Mercator
+/// and WGS84 are left to PostGIS.
+pub async fn bounds_in(tms: &TileMatrixSet, layer: &Layer, bbox: [f64; 4]) -> AppResult<[f64; 4]> {
+    let [west, south, east, north] = bbox;
+    match tms.srid {
+        4326 => Ok(bbox),
+        3857 => {
+            let (xmin, ymin) = lonlat_to_web_mercator(west, south);
+            let (xmax, ymax) = lonlat_to_web_mercator(east, north);
+            Ok([xmin, ymin, xmax, ymax])
+        }
+        srid => {
+            let extent = transform_bbox(&layer.database_id, bbox, srid).await?;
+            Ok([extent.xmin, extent.ymin, extent.xmax, extent.ymax])
+        }
+    }
+}
+
+/// Spans of every registered matrix set covering `bbox` (EPSG:4326).
+async fn layer_spans(
+    layer: &Layer,
+    bbox: [f64; 4],
+    zmin: u32,
+    zmax: u32,
+    margin: f64,
+) -> AppResult<Vec<TileSpan>> {
+    let mut spans = Vec::new();
+    for tms in get_tile_matrix_sets() {
+        let bounds = bounds_in(tms, layer, bbox).await?;
+        spans.extend(tile_spans(tms, bounds, zmin, zmax, margin));
+    }
+    Ok(spans)
+}
+
+/// Tiles around a change whose content it can reach: the layer's render
+/// buffer draws features into neighbouring tiles, binned layers read
+/// features `BIN_READ_MARGIN` cells beyond the tile, and clustered layers
+/// half a cell.
+fn change_margin(layer: &Layer) -> f64 {
+    if layer.is_binned() {
+        return BIN_READ_MARGIN * layer.get_bin_size() as f64 / TILE_SIZE as f64;
+    }
+    let buffer = layer.get_buffer() as f64 / layer.get_extent().max(1) as f64;
+    if layer.cluster_zoom.is_some() {
+        return buffer.max(layer.get_cluster_radius() as f64 / 2.0 / TILE_SIZE as f64);
+    }
+    buffer
+}
+
+/// Drops the cached tiles of `layer` around each bbox (EPSG:4326) over the
+/// layer's zooms, as a single region, and returns the tiles covered. The
+/// margin is `change_margin`.
+pub async fn invalidate_layer_bboxes(layer: &Layer, bboxes: &[[f64; 4]]) -> AppResult<u64> {
+    let key = format!("{}_{}", layer.category.name, layer.name);
+    let margin = change_margin(layer);
+    let mut spans = Vec::new();
+    for bbox in bboxes {
+        spans.extend(layer_spans(layer, *bbox, layer.get_zmin(), layer.get_zmax(), margin).await?);
+    }
+    get_cache_wrapper().invalidate_region(&key, spans).await
+}
+
+/// Drops the cached tiles of `layer` inside the bbox or geometry of
+/// `options`, in every registered matrix set.
+pub async fn invalidate_layer_region(
+    layer: &Layer,
+    options: &InvalidateOptions,
+) -> AppResult<InvalidateSummary> {
+    validate_seedable(layer)?;
+    let key = format!("{}_{}", layer.category.name, layer.name);
+
+    let zmin = options.zmin.unwrap_or(0).max(layer.get_zmin());
+    let zmax = options.zmax.unwrap_or(u32::MAX).min(layer.get_zmax());
+    if zmin > zmax {
+        return Err(AppError::InvalidInput(format!(
+            "zoom range {zmin}-{zmax} is outside the layer zooms {}-{}",
+            layer.get_zmin(),
+            layer.get_zmax()
+        )));
+    }
+    let bbox = match (options.bbox, &options.geometry) {
+        (Some(bbox), None) => bbox,
+        (None, Some(geometry)) => geometry_bbox(geometry)?,
+        _ => {
+            return Err(AppError::InvalidInput(
+                "give either a bbox or a GeoJSON geometry".to_string(),
+            ));
+        }
+    };
+    if !(bbox[0] <= bbox[2] && bbox[1] <= bbox[3]) {
+        return Err(AppError::InvalidInput(
+            "bbox must be west,south,east,north".to_string(),
+        ));
+    }
+
+    let spans = layer_spans(layer, bbox, zmin, zmax, 0.0).await?;
+    let tiles = get_cache_wrapper().invalidate_region(&key, spans).await?;
+    info!(layer = %key, zmin, zmax, ?bbox, tiles, "cache: region invalidated");
+
+    Ok(InvalidateSummary {
+        layer: key,
+        zmin,
+        zmax,
+        bbox,
+        tiles,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::seed::tile_ranges;
+    use serde_json::json;
+
+    #[test]
+    fn geometry_bbox_walks_every_geojson_kind() {
+        let polygon = json!({
+            "type": "Polygon",
+            "coordinates": [[[-58.5, -34.7], [-58.3, -34.7], [-58.4, -34.5], [-58.5, -34.7]]]
+        });
+        assert_eq!(
+            geometry_bbox(&polygon).unwrap(),
+            [-58.5, -34.7, -58.3, -34.5]
+        );
+
+        let collection = json!({
+            "type": "FeatureCollection",
+            "features": [
+                { "type": "Feature", "properties": {}, "geometry": polygon },
+                { "type": "Feature", "properties": {}, "geometry": {
+                    "type": "GeometryCollection",
+                    "geometries": [{ "type": "Point", "coordinates": [-60.0, -31.6, 25.0] }]
+                }}
+            ]
+        });
+        assert_eq!(
+            geometry_bbox(&collection).unwrap(),
+            [-60.0, -34.7, -58.3, -31.6]
+        );
+
+        assert!(geometry_bbox(&json!({ "type": "Point", "coordinates": [] })).is_err());
+        assert!(geometry_bbox(&json!({ "features": [] })).is_err());
+    }
+
+    #[test]
+    fn binned_and_clustered_layers_widen_the_change_margin_to_their_read_margin() {
+        let mut layer = crate::config::test_support::test_layer("layer-1");
+        assert_eq!(change_margin(&layer), 256.0 / 4096.0);
+
+        layer.cluster_zoom = Some(8);
+        layer.cluster_radius = Some(256);
+        assert_eq!(change_margin(&layer), 0.5);
+        layer.cluster_zoom = None;
+
+        layer.bin_shape = Some("hexagon".to_string());
+        layer.bin_size = Some(64);
+        assert_eq!(change_margin(&layer), 0.5);
+    }
+
+    #[test]
+    fn web_mercator_spans_match_the_seeding_grid() {
+        let bbox = [-58.53, -34.71, -58.33, -34.52];
+        let [west, south, east, north] = bbox;
+        let (xmin, ymin) = lonlat_to_web_mercator(west, south);
+        let (xmax, ymax) = lonlat_to_web_mercator(east, north);
+        let spans = tile_spans(
+            &TileMatrixSet::web_mercator_quad(),
+            [xmin, ymin, xmax, ymax],
+            0,
+            16,
+            0.0,
+        );
+
+        let ranges = tile_ranges(
+            &TileMatrixSet::web_mercator_quad(),
+            [xmin, ymin, xmax, ymax],
+            0,
+            16,
+        );
+        assert_eq!(spans.len(), ranges.len());
+        for (span, range) in spans.iter().zip(&ranges) {
+            assert_eq!(span.matrix_set, None);
+            assert_eq!(
+                (span.z, span.xmin, span.xmax, span.ymin, span.ymax),
+                (range.z, range.xmin, range.xmax, range.ymin, range.ymax)
+            );
+        }
+
+        let crs84 = tile_spans(&TileMatrixSet::world_crs84_quad(), bbox, 20, 30, 0.0);
+        assert_eq!(crs84.len(), 5);
+        assert_eq!(crs84[0].matrix_set.as_deref(), Some("WorldCRS84Quad"));
+
+        // A point on a tile corner, with a render buffer, reaches the
+        // three tiles around the corner too.
+        let corner = tile_spans(
+            &TileMatrixSet::world_crs84_quad(),
+            [0.0, 0.0, 0.0, 0.0],
+            1,
+            1,
+            0.0625,
+        );
+        let corner = &corner[0];
+        assert_eq!(
+            (corner.xmin, corner.xmax, corner.ymin, corner.ymax),
+            (1, 2, 0, 1)
+        );
+    }
+}
@@ -1,8 +1,14 @@
+pub mod cache_stats;
+pub mod changes;
 pub mod health;
+pub mod invalidate;
 pub mod legends;
+pub mod ogc;
+pub mod seed;
 pub mod styles;
 #[cfg(test)]
 mod tests;
 pub mod tilejson;
 pub mod tiles;
 pub mod utils;
+pub mod wmts;
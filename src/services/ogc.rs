@@ -0,0 +1,707 @@
+//! OGC API - Tiles (Part 1: Core) over the published catalog.
+//!
+//! Every published layer is a collection (`{category}:{name}`) and every
+//! category with visible layers is one too (`{category}`), served as a
+//! combined tile. Documents are built from the in-memory catalog and the
+//! tile matrix set registry; tiles go through the same code as the
+//! `/services/tiles` routes, so caching, ETags and group checks are shared.
+
+use std::collections::BTreeMap;
+
+use salvo::prelude::*;
+use serde_json::{Value, json};
+
+use crate::{
+    error::{AppError, AppResult},
+    get_catalog, get_categories, get_tile_matrix_sets,
+    models::{
+        catalog::{Layer, StateLayer},
+        category::Category,
+        tile_matrix_set::{TileMatrixSet, find_tile_matrix_set},
+    },
+    services::{
+        tilejson::{base_url_from_request, layer_metadata, set_json_cache_headers},
+        tiles::{
+            builder::{TileFilter, supported_tile_matrix_sets},
+            handlers::{LayerTileRequest, serve_combined_tile, serve_layer_tile},
+        },
+        utils::validate_user_groups,
+    },
+};
+
+const MVT_MEDIA_TYPE: &str = "application/vnd.mapbox-vector-tile";
+const CRS84: &str = "http://www.opengis.net/def/crs/OGC/1.3/CRS84";
+const REL_TILING_SCHEME: &str = "http://www.opengis.net/def/rel/ogc/1.0/tiling-scheme";
+const REL_TILING_SCHEMES: &str = "http://www.opengis.net/def/rel/ogc/1.0/tiling-schemes";
+const REL_TILESETS_VECTOR: &str = "http://www.opengis.net/def/rel/ogc/1.0/tilesets-vector";
+
+pub const CONFORMANCE_CLASSES: &[&str] = &[
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/landing-page",
+    "http://www.opengis.net/spec/ogcapi-common-1/1.0/conf/json",
+    "http://www.opengis.net/spec/ogcapi-common-2/1.0/conf/collections",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/core",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tileset",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/tilesets-list",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/geodata-tilesets",
+    "http://www.opengis.net/spec/ogcapi-tiles-1/1.0/conf/mvt",
+    "http://www.opengis.net/spec/tms/2.0/conf/tilematrixset",
+    "http://www.opengis.net/spec/tms/2.0/conf/json",
+];
+
+/// A collection of the API: one layer, or every visible layer of a category.
+#[derive(Debug, Clone)]
+pub enum Collection {
+    Layer(Box<Layer>),
+    Category {
+        category: Category,
+        layers: Vec<Layer>,
+    },
+}
+
+impl Collection {
+    pub fn id(&self) -> String {
+        match self {
+            Self::Layer(layer) => format!("{}:{}", layer.category.name, layer.name),
+            Self::Category { category, .. } => category.name.clone(),
+        }
+    }
+
+    pub fn title(&self) -> String {
+        match self {
+            Self::Layer(layer) if !layer.alias.is_empty() => layer.alias.clone(),
+            Self::Layer(layer) => layer.name.clone(),
+            Self::Category { category, .. } => category.name.clone(),
+        }
+    }
+
+    pub fn description(&self) -> String {
+        match self {
+            Self::Layer(layer) => layer.description.clone(),
+            Self::Category { category, .. } => category.description.clone(),
+        }
+    }
+
+    pub fn layers(&self) -> &[Layer] {
+        match self {
+            Self::Layer(layer) => std::slice::from_ref(layer.as_ref()),
+            Self::Category { layers, .. } => layers,
+        }
+    }
+
+    /// Sets any of the collection's layers can be served in.
+    pub fn tile_matrix_sets(&self) -> Vec<&'static TileMatrixSet> {
+        get_tile_matrix_sets()
+            .iter()
+            .filter(|tms| {
+                self.layers()
+                    .iter()
+                    .any(|l| supported_tile_matrix_sets(l).iter().any(|s| s.id == tms.id))
+            })
+            .collect()
+    }
+}
+
+fn link(href: String, rel: &str, media_type: &str, title: &str) -> Value {
+    json!({ "href": href, "rel": rel, "type": media_type, "title": title })
+}
+
+pub fn landing_page(base_url: &str) -> Value {
+    json!({
+        "title": "MVT Server",
+        "description": "Vector tiles of the published catalog, as OGC API - Tiles",
+        "links": [
+            link(format!("{base_url}/ogc"), "self", "application/json", "This document"),
+            link(
+                format!("{base_url}/ogc/conformance"),
+                "conformance",
+                "application/json",
+                "Conformance classes",
+            ),
+            link(
+                format!("{base_url}/ogc/collections"),
+                "data",
+                "application/json",
+                "Collections",
+            ),
+            link(
+                format!("{base_url}/ogc/tileMatrixSets"),
+                REL_TILING_SCHEMES,
+                "application/json",
+                "Tile matrix sets",
+            ),
+        ],
+    })
+}
+
+pub fn collection_doc(base_url: &str, collection: &Collection) -> Value {
+    let href = format!("{base_url}/ogc/collections/{}", collection.id());
+    json!({
+        "id": collection.id(),
+        "title": collection.title(),
+        "description": collection.description(),
+        "dataType": "vector",
+        "links": [
+            link(href.clone(), "self", "application/json", "This collection"),
+            link(
+                format!("{href}/tiles"),
+                REL_TILESETS_VECTOR,
+                "application/json",
+                "Vector tilesets",
+            ),
+        ],
+    })
+}
+
+/// Entry of a tileset in the `/tiles` list; the full tileset adds limits,
+/// layers and the tile link.
+fn tileset_summary(base_url: &str, collection_id: &str, title: &str, tms: &TileMatrixSet) -> Value {
+    let mut summary = json!({
+        "title": format!("{title} ({})", tms.id),
+        "dataType": "vector",
+        "crs": tms.crs_uri(),
+        "links": [
+            link(
+                format!("{base_url}/ogc/collections/{collection_id}/tiles/{}", tms.id),
+                "self",
+                "application/json",
+                "Tileset metadata",
+            ),
+            link(
+                format!("{base_url}/ogc/tileMatrixSets/{}", tms.id),
+                REL_TILING_SCHEME,
+                "application/json",
+                &tms.id,
+            ),
+        ],
+    });
+    if let Some(uri) = tms.uri() {
+        summary["tileMatrixSetURI"] = json!(uri);
+    }
+    summary
+}
+
+pub fn tilesets_doc(base_url: &str, collection: &Collection) -> Value {
+    let id = collection.id();
+    let title = collection.title();
+    json!({
+        "links": [link(
+            format!("{base_url}/ogc/collections/{id}/tiles"),
+            "self",
+            "application/json",
+            "Vector tilesets",
+        )],
+        "tilesets": collection
+            .tile_matrix_sets()
+            .into_iter()
+            .map(|tms| tileset_summary(base_url, &id, &title, tms))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn geometry_dimension(geometry: &str) -> Option<u8> {
+    match geometry {
+        "points" => Some(0),
+        "lines" => Some(1),
+        "polygons" => Some(2),
+        _ => None,
+    }
+}
+
+/// Tileset metadata of a collection in one matrix set. `layers` carries the
+/// zooms, bounds and fields of each layer as returned by `layer_metadata`.
+pub fn tileset_doc(
+    base_url: &str,
+    collection: &Collection,
+    tms: &TileMatrixSet,
+    layers: &[(Layer, [f64; 4], BTreeMap<String, String>)],
+) -> Value {
+    let id = collection.id();
+    let mut doc = tileset_summary(base_url, &id, &collection.title(), tms);
+    if let Some(links) = doc["links"].as_array_mut() {
+        let mut item = link(
+            format!(
+                "{base_url}/ogc/collections/{id}/tiles/{}/{{tileMatrix}}/{{tileRow}}/{{tileCol}}",
+                tms.id
+            ),
+            "item",
+            MVT_MEDIA_TYPE,
+            "Tiles",
+        );
+        item["templated"] = json!(true);
+        links.push(item);
+    }
+
+    let zmin = layers
+        .iter()
+        .map(|(l, _, _)| l.get_zmin())
+        .min()
+        .unwrap_or(0);
+    let zmax = layers
+        .iter()
+        .map(|(l, _, _)| l.get_zmax())
+        .max()
+        .unwrap_or(0)
+        .min(tms.max_zoom());
+    doc["tileMatrixSetLimits"] = (zmin..=zmax)
+        .map(|z| {
+            json!({
+                "tileMatrix": z.to_string(),
+                "minTileRow": 0,
+                "maxTileRow": tms.matrix_height_at(z) - 1,
+                "minTileCol": 0,
+                "maxTileCol": tms.matrix_width_at(z) - 1,
+            })
+        })
+        .collect();
+
+    doc["layers"] = layers
+        .iter()
+        .map(|(layer, _, fields)| {
+            let mut entry = json!({
+                "id": layer.name,
+                "title": if layer.alias.is_empty() { &layer.name } else { &layer.alias },
+                "dataType": "vector",
+                "minTileMatrix": layer.get_zmin().to_string(),
+                "maxTileMatrix": layer.get_zmax().min(tms.max_zoom()).to_string(),
+                "propertiesSchema": {
+                    "type": "object",
+                    "properties": fields
+                        .iter()
+                        .map(|(name, description)| (name.clone(), json!({ "description": description })))
+                        .collect::<serde_json::Map<_, _>>(),
+                },
+            });
+            if !layer.description.is_empty() {
+                entry["description"] = json!(layer.description);
+            }
+            if let Some(dimension) = geometry_dimension(layer.output_geometry()) {
+                entry["geometryDimension"] = json!(dimension);
+            }
+            entry
+        })
+        .collect();
+
+    if let Some([xmin, ymin, xmax, ymax]) =
+        layers.iter().map(|(_, bounds, _)| *bounds).reduce(|a, b| {
+            [
+                a[0].min(b[0]),
+                a[1].min(b[1]),
+                a[2].max(b[2]),
+                a[3].max(b[3]),
+            ]
+        })
+    {
+        doc["boundingBox"] = json!({
+            "lowerLeft": [xmin, ymin],
+            "upperRight": [xmax, ymax],
+            "crs": CRS84,
+        });
+    }
+    doc
+}
+
+pub fn tile_matrix_set_doc(tms: &TileMatrixSet) -> Value {
+    let mut doc = json!({
+        "id": tms.id,
+        "title": tms.title,
+        "crs": tms.crs_uri(),
+        "orderedAxes": tms.ordered_axes(),
+        "tileMatrices": (0..=tms.max_zoom()).map(|z| tms.tile_matrix(z)).collect::<Vec<_>>(),
+    });
+    if let Some(uri) = tms.uri() {
+        doc["uri"] = json!(uri);
+    }
+    doc
+}
+
+/// Published layers the caller may see.
+async fn visible_layers(
+    req: &mut Request,
+    depot: &mut Depot,
+    layers: Vec<Layer>,
+) -> AppResult<Vec<Layer>> {
+    let mut visible = Vec::new();
+    for layer in layers {
+        if validate_user_groups(req, &layer, depot).await? {
+            visible.push(layer);
+        }
+    }
+    Ok(visible)
+}
+
+/// Resolves a collection id: 404 for unknown ids, 403 when none of its
+/// layers is visible to the caller.
+async fn find_collection(req: &mut Request, depot: &mut Depot) -> AppResult<Collection> {
+    let id = req.param::<String>("collectionId").unwrap_or_default();
+
+    if let Some((category, name)) = id.split_once(':') {
+        let layer = {
+            let catalog = get_catalog().await.read().await;
+            catalog
+                .find_layer_by_category_and_name(category, name, StateLayer::Published)
+                .cloned()
+        };
+        let layer = layer.ok_or_else(|| AppError::NotFound(format!("collection '{id}'")))?;
+        if !validate_user_groups(req, &layer, depot).await? {
+            return Err(AppError::Forbidden(format!("collection '{id}'")));
+        }
+        return Ok(Collection::Layer(Box::new(layer)));
+    }
+
+    let category = {
+        let categories = get_categories().await.read().await;
+        categories.iter().find(|c| c.name == id).cloned()
+    };
+    let published: Vec<Layer> = {
+        let catalog = get_catalog().await.read().await;
+        catalog
+            .find_layers_by_category(&id, StateLayer::Published)
+            .into_iter()
+            .cloned()
+            .collect()
+    };
+    let Some(category) = category.filter(|_| !published.is_empty()) else {
+        return Err(AppError::NotFound(format!("collection '{id}'")));
+    };
+    let layers = visible_layers(req, depot, published).await?;
+    if layers.is_empty() {
+        return Err(AppError::Forbidden(format!("collection '{id}'")));
+    }
+    Ok(Collection::Category { category, layers })
+}
+
+fn find_matrix_set(req: &Request, collection: &Collection) -> AppResult<&'static TileMatrixSet> {
+    let id = req.param::<String>("tileMatrixSetId").unwrap_or_default();
+    find_tile_matrix_set(&id)
+        .filter(|tms| collection.tile_matrix_sets().iter().any(|s| s.id == tms.id))
+        .ok_or_else(|| {
+            AppError::NotFound(format!(
+                "tileset '{id}' of collection '{}'",
+                collection.id()
+            ))
+        })
+}
+
+#[handler]
+pub async fn landing(req: &mut Request, res: &mut Response) {
+    set_json_cache_headers(res);
+    res.render(Json(landing_page(&base_url_from_request(req))));
+}
+
+#[handler]
+pub async fn conformance(res: &mut Response) {
+    set_json_cache_headers(res);
+    res.render(Json(json!({ "conformsTo": CONFORMANCE_CLASSES })));
+}
+
+#[handler]
+pub async fn list_collections(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let published = {
+        let catalog = get_catalog().await.read().await;
+        catalog.get_published_layers()
+    };
+    let layers = visible_layers(req, depot, published).await?;
+    let categories = get_categories().await.read().await.clone();
+
+    let mut collections: Vec<Collection> = categories
+        .into_iter()
+        .filter_map(|category| {
+            let members: Vec<Layer> = layers
+                .iter()
+                .filter(|l| l.category.name == category.name)
+                .cloned()
+                .collect();
+            (!members.is_empty()).then_some(Collection::Category {
+                category,
+                layers: members,
+            })
+        })
+        .collect();
+    collections.extend(
+        layers
+            .into_iter()
+            .map(|layer| Collection::Layer(Box::new(layer))),
+    );
+
+    let base_url = base_url_from_request(req);
+    set_json_cache_headers(res);
+    res.render(Json(json!({
+        "links": [link(format!("{base_url}/ogc/collections"), "self", "application/json", "Collections")],
+        "collections": collections
+            .iter()
+            .map(|c| collection_doc(&base_url, c))
+            .collect::<Vec<_>>(),
+    })));
+    Ok(())
+}
+
+#[handler]
+pub async fn get_collection(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let collection = find_collection(req, depot).await?;
+    set_json_cache_headers(res);
+    res.render(Json(collection_doc(
+        &base_url_from_request(req),
+        &collection,
+    )));
+    Ok(())
+}
+
+#[handler]
+pub async fn list_tilesets(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let collection = find_collection(req, depot).await?;
+    set_json_cache_headers(res);
+    res.render(Json(tilesets_doc(&base_url_from_request(req), &collection)));
+    Ok(())
+}
+
+#[handler]
+pub async fn get_tileset(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let collection = find_collection(req, depot).await?;
+    let tms = find_matrix_set(req, &collection)?;
+
+    let mut layers = Vec::new();
+    for layer in collection.layers() {
+        layers.push(layer_metadata(layer.clone()).await);
+    }
+
+    set_json_cache_headers(res);
+    res.render(Json(tileset_doc(
+        &base_url_from_request(req),
+        &collection,
+        tms,
+        &layers,
+    )));
+    Ok(())
+}
+
+#[handler]
+pub async fn get_collection_tile(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let collection = find_collection(req, depot).await?;
+    let tms = find_matrix_set(req, &collection)?;
+    let param = |name: &str| {
+        req.param::<u32>(name)
+            .ok_or_else(|| AppError::InvalidInput(format!("{name} must be a non-negative integer")))
+    };
+    let (z, y, x) = (param("tileMatrix")?, param("tileRow")?, param("tileCol")?);
+
+    match collection {
+        Collection::Layer(layer) => {
+            let tile_req = LayerTileRequest {
+                layer: *layer,
+                tms,
+                z,
+                x,
+                y,
+                filter: TileFilter::default(),
+            };
+            serve_layer_tile(req, res, depot, tile_req).await
+        }
+        Collection::Category { layers, .. } => {
+            serve_combined_tile(req, res, depot, layers, tms, z, x, y).await
+        }
+    }
+}
+
+#[handler]
+pub async fn list_tile_matrix_sets(req: &mut Request, res: &mut Response) {
+    let base_url = base_url_from_request(req);
+    let sets: Vec<Value> = get_tile_matrix_sets()
+        .iter()
+        .map(|tms| {
+            let mut entry = json!({
+                "id": tms.id,
+                "title": tms.title,
+                "links": [link(
+                    format!("{base_url}/ogc/tileMatrixSets/{}", tms.id),
+                    REL_TILING_SCHEME,
+                    "application/json",
+                    &tms.id,
+                )],
+            });
+            if let Some(uri) = tms.uri() {
+                entry["uri"] = json!(uri);
+            }
+            entry
+        })
+        .collect();
+    set_json_cache_headers(res);
+    res.render(Json(json!({ "tileMatrixSets": sets })));
+}
+
+#[handler]
+pub async fn get_tile_matrix_set(req: &mut Request, res: &mut Response) -> AppResult<()> {
+    let id = req.param::<String>("tileMatrixSetId").unwrap_or_default();
+    let tms = find_tile_matrix_set(&id)
+        .ok_or_else(|| AppError::NotFound(format!("tile matrix set '{id}'")))?;
+    set_json_cache_headers(res);
+    res.render(Json(tile_matrix_set_doc(tms)));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        catalog::SourceKind,
+        tile_matrix_set::{WEB_MERCATOR_QUAD, WORLD_CRS84_QUAD},
+    };
+
+    fn layer(name: &str, geometry: &str) -> Layer {
+        Layer {
+            id: format!("id-{name}"),
+            category: Category {
+                id: "cat-1".to_string(),
+                name: "transit".to_string(),
+                description: "".to_string(),
+            },
+            geometry: geometry.to_string(),
+            name: name.to_string(),
+            alias: "".to_string(),
+            description: "".to_string(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
+            database_id: "default".to_string(),
+            schema: "public".to_string(),
+            table_name: name.to_string(),
+            fields: vec!["name".to_string()],
+            zoom_fields: None,
+            generalization: None,
+            filter: None,
+            srid: None,
+            tile_matrix_set: None,
+            geom: None,
+            id_column: None,
+            sql_mode: None,
+            buffer: None,
+            extent: None,
+            zmin: Some(2),
+            zmax: Some(4),
+            zmax_do_not_simplify: None,
+            buffer_do_not_simplify: None,
+            extent_do_not_simplify: None,
+            clip_geom: None,
+            delete_cache_on_start: None,
+            max_cache_age: None,
+            max_records: None,
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
+            published: true,
+            url: None,
+            groups: None,
+        }
+    }
+
+    #[test]
+    fn tileset_describes_layers_limits_and_tile_template() {
+        let tms = TileMatrixSet::world_crs84_quad();
+        let points = layer("stops", "points");
+        let collection = Collection::Category {
+            category: points.category.clone(),
+            layers: vec![points.clone()],
+        };
+        let fields = BTreeMap::from([("name".to_string(), "text".to_string())]);
+        let doc = tileset_doc(
+            "http://h",
+            &collection,
+            &tms,
+            &[(points, [-10.0, -5.0, 10.0, 5.0], fields)],
+        );
+
+        let item = doc["links"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|l| l["rel"] == "item")
+            .unwrap();
+        assert_eq!(
+            item["href"],
+            format!(
+                "http://h/ogc/collections/{}/tiles/WorldCRS84Quad/{{tileMatrix}}/{{tileRow}}/{{tileCol}}",
+                collection.id()
+            )
+        );
+        assert_eq!(item["type"], MVT_MEDIA_TYPE);
+
+        let limits = doc["tileMatrixSetLimits"].as_array().unwrap();
+        assert_eq!(limits.len(), 3);
+        assert_eq!(limits[0]["tileMatrix"], "2");
+        assert_eq!(limits[0]["maxTileCol"], 7);
+        assert_eq!(limits[0]["maxTileRow"], 3);
+
+        assert_eq!(doc["layers"][0]["geometryDimension"], 0);
+        assert_eq!(
+            doc["layers"][0]["propertiesSchema"]["properties"]["name"]["description"],
+            "text"
+        );
+        assert_eq!(doc["boundingBox"]["lowerLeft"], json!([-10.0, -5.0]));
+        assert_eq!(
+            doc["tileMatrixSetURI"],
+            "http://www.opengis.net/def/tilematrixset/OGC/1.0/WorldCRS84Quad"
+        );
+    }
+
+    #[test]
+    fn static_collections_are_web_mercator_only() {
+        let mut mbtiles = layer("basemap", "polygons");
+        mbtiles.source_kind = SourceKind::Mbtiles;
+        let ids = |c: &Collection| {
+            c.tile_matrix_sets()
+                .iter()
+                .map(|t| t.id.clone())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            ids(&Collection::Layer(Box::new(mbtiles.clone()))),
+            vec![WEB_MERCATOR_QUAD]
+        );
+
+        let category = Collection::Category {
+            category: mbtiles.category.clone(),
+            layers: vec![mbtiles, layer("roads", "lines")],
+        };
+        assert!(ids(&category).contains(&WORLD_CRS84_QUAD.to_string()));
+    }
+
+    #[test]
+    fn tile_matrix_set_lists_every_level() {
+        let doc = tile_matrix_set_doc(&TileMatrixSet::web_mercator_quad());
+        let matrices = doc["tileMatrices"].as_array().unwrap();
+        assert_eq!(matrices.len(), 25);
+        assert_eq!(matrices[3]["matrixWidth"], 8);
+        assert_eq!(matrices[0]["cornerOfOrigin"], "topLeft");
+        assert_eq!(doc["crs"], "http://www.opengis.net/def/crs/EPSG/0/3857");
+    }
+}
@@ -0,0 +1,640 @@
+//! Cache seeding: renders every tile of a layer over a zoom range and a
+//! bounding box ahead of the first request and stores it with
+//! `CacheWrapper::write_tile`.
+//!
+//! Tiles are walked in a fixed order (zoom, column, row) in batches; after
+//! each batch the number of finished tiles is written to a checkpoint file
+//! under `<paths.config>/seed/`, so an interrupted run can resume from it.
+//! The checkpoint stops moving at the first batch with a failed tile, and
+//! is kept when the run ends with failures, so resuming retries them.
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::{
+    cache::key::TileKey,
+    config::settings::SeedArgs,
+    db::metadata::query_extent,
+    error::{AppError, AppResult},
+    get_cache_wrapper, get_catalog, get_config_dir, get_db_registry, get_plugin_registry,
+    models::{
+        catalog::{Layer, SourceKind, StateLayer},
+        tile_matrix_set::TileMatrixSet,
+    },
+    services::{
+        invalidate::bounds_in,
+        tiles::builder::{TileFilter, layer_tile_matrix_set, query_database},
+        utils::validate_filter,
+    },
+};
+
+pub const DEFAULT_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 64;
+/// Tiles rendered between two checkpoints.
+const BATCH_SIZE: usize = 256;
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Seeding jobs started from the admin API, by layer key. Finished jobs stay
+/// so their final counts can still be read.
+static SEED_JOBS: LazyLock<Mutex<HashMap<String, Arc<SeedProgress>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct SeedOptions {
+    /// Defaults to the layer's zmin; never below it.
+    pub zmin: Option<u32>,
+    /// Defaults to the layer's zmax; never above it.
+    pub zmax: Option<u32>,
+    /// west, south, east, north in EPSG:4326. Defaults to the layer extent.
+    pub bbox: Option<[f64; 4]>,
+    pub concurrency: Option<usize>,
+    /// Do not store tiles without features.
+    #[serde(default)]
+    pub skip_empty: bool,
+    /// Continue from the checkpoint of an interrupted run with the same
+    /// zoom range and bbox instead of starting over.
+    #[serde(default)]
+    pub resume: bool,
+}
+
+impl From<&SeedArgs> for SeedOptions {
+    fn from(args: &SeedArgs) -> Self {
+        Self {
+            zmin: args.zmin,
+            zmax: args.zmax,
+            bbox: args.bbox,
+            concurrency: Some(args.concurrency),
+            skip_empty: args.skip_empty,
+            resume: args.resume,
+        }
+    }
+}
+
+/// Live counters of a seeding run.
+#[derive(Debug, Default)]
+pub struct SeedProgress {
+    total: AtomicU64,
+    done: AtomicU64,
+    written: AtomicU64,
+    empty: AtomicU64,
+    failed: AtomicU64,
+    finished: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SeedStatus {
+    pub layer: String,
+    pub total: u64,
+    pub done: u64,
+    pub written: u64,
+    pub empty: u64,
+    pub failed: u64,
+    pub finished: bool,
+    pub error: Option<String>,
+}
+
+impl SeedProgress {
+    pub fn status(&self, layer: &str) -> SeedStatus {
+        SeedStatus {
+            layer: layer.to_string(),
+            total: self.total.load(Ordering::Relaxed),
+            done: self.done.load(Ordering::Relaxed),
+            written: self.written.load(Ordering::Relaxed),
+            empty: self.empty.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            finished: self.finished.load(Ordering::Relaxed),
+            error: self.error.lock().ok().and_then(|e| e.clone()),
+        }
+    }
+
+    fn finish(&self, result: &AppResult<()>) {
+        if let Err(e) = result
+            && let Ok(mut error) = self.error.lock()
+        {
+            *error = Some(e.to_string());
+        }
+        self.finished.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Tiles of one zoom level covering a bbox, bounds inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileRange {
+    pub z: u32,
+    pub xmin: u32,
+    pub xmax: u32,
+    pub ymin: u32,
+    pub ymax: u32,
+}
+
+impl TileRange {
+    pub fn count(&self) -> u64 {
+        u64::from(self.xmax - self.xmin + 1) * u64::from(self.ymax - self.ymin + 1)
+    }
+}
+
+/// Tiles of `tms` covering `bounds` (in the set's SRID) from `zmin` to
+/// `zmax`, capped at the deepest zoom of the set.
+pub fn tile_ranges(tms: &TileMatrixSet, bounds: [f64; 4], zmin: u32, zmax: u32) -> Vec<TileRange> {
+    (zmin..=zmax.min(tms.max_zoom()))
+        .filter_map(|z| {
+            let [xmin, ymin, xmax, ymax] = tms.tile_range(bounds, z)?;
+            Some(TileRange {
+                z,
+                xmin,
+                xmax,
+                ymin,
+                ymax,
+            })
+        })
+        .collect()
+}
+
+/// Every tile of the ranges in seeding order: zoom, then column, then row.
+pub fn tiles(ranges: &[TileRange]) -> impl Iterator<Item = (u32, u32, u32)> + '_ {
+    ranges.iter().flat_map(|r| {
+        (r.xmin..=r.xmax).flat_map(move |x| (r.ymin..=r.ymax).map(move |y| (r.z, x, y)))
+    })
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+struct Checkpoint {
+    matrix_set: String,
+    zmin: u32,
+    zmax: u32,
+    bbox: [f64; 4],
+    done: u64,
+}
+
+fn checkpoint_path(layer_key: &str) -> PathBuf {
+    Path::new(get_config_dir())
+        .join("seed")
+        .join(format!("{layer_key}.json"))
+}
+
+/// Tiles already finished by a previous run with the same matrix set, zoom
+/// range and bbox; anything else (no file, other parameters) starts from zero.
+fn read_checkpoint(path: &Path, matrix_set: &str, zmin: u32, zmax: u32, bbox: [f64; 4]) -> u64 {
+    std::fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice::<Checkpoint>(&bytes).ok())
+        .filter(|c| {
+            c.matrix_set == matrix_set && c.zmin == zmin && c.zmax == zmax && c.bbox == bbox
+        })
+        .map_or(0, |c| c.done)
+}
+
+fn write_checkpoint(path: &Path, checkpoint: &Checkpoint) -> AppResult<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_vec(checkpoint)?)?;
+    Ok(())
+}
+
+/// Rejects layers whose tiles never go through the tile cache.
+pub fn validate_seedable(layer: &Layer) -> AppResult<()> {
+    if layer.source_kind.is_static() {
+        return Err(AppError::InvalidInput(format!(
+            "layer '{}' is served from its tile file and is not cached",
+            layer.name
+        )));
+    }
+    let key = format!("{}_{}", layer.category.name, layer.name);
+    if get_plugin_registry().has_plugin(&key, &layer.category.name) {
+        return Err(AppError::InvalidInput(format!(
+            "layer '{}' has a Lua plugin and bypasses the tile cache",
+            layer.name
+        )));
+    }
+    Ok(())
+}
+
+/// Function and SQL layers have no table to take an extent from: they
+/// report the whole world, so seeding them without a bbox is refused.
+pub fn check_seed_bbox(layer: &Layer, bbox: Option<[f64; 4]>) -> AppResult<()> {
+    if bbox.is_none() && matches!(layer.source_kind, SourceKind::Function | SourceKind::Sql) {
+        return Err(AppError::InvalidInput(format!(
+            "layer '{}' has no extent of its own: give a bbox to seed it",
+            layer.name
+        )));
+    }
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn seed_tile(
+    pg_pool: PgPool,
+    layer: Layer,
+    key: &str,
+    matrix_set: &str,
+    z: u32,
+    x: u32,
+    y: u32,
+    where_clause: String,
+    skip_empty: bool,
+    progress: &SeedProgress,
+) {
+    let max_cache_age = layer.get_max_cache_age();
+    let filter = TileFilter {
+        where_clause,
+        ..TileFilter::default()
+    };
+    match query_database(pg_pool, layer, x, y, z, filter).await {
+        Ok(tile) if tile.is_empty() && skip_empty => {
+            progress.empty.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(tile) => {
+            match get_cache_wrapper()
+                .write_tile(
+                    &TileKey::new(key, z, x, y).with_matrix_set(matrix_set),
+                    &tile,
+                    max_cache_age,
+                )
+                .await
+            {
+                Ok(()) => progress.written.fetch_add(1, Ordering::Relaxed),
+                Err(e) => {
+                    warn!(layer = key, z, x, y, error = ?e, "seed: cache write failed");
+                    progress.failed.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+        }
+        Err(e) => {
+            warn!(layer = key, z, x, y, error = ?e, "seed: tile query failed");
+            progress.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+    progress.done.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Renders and caches every tile of `layer` for the given options,
+/// reporting into `progress`. Failed tiles are counted and skipped.
+pub async fn seed_layer(
+    layer: Layer,
+    options: &SeedOptions,
+    progress: &SeedProgress,
+) -> AppResult<()> {
+    validate_seedable(&layer)?;
+    check_seed_bbox(&layer, options.bbox)?;
+    let key = format!("{}_{}", layer.category.name, layer.name);
+    // Tiles are rendered and cached in the layer's default matrix set, the
+    // one its XYZ route serves.
+    let tms = layer_tile_matrix_set(&layer)?;
+
+    let zmin = options.zmin.unwrap_or(0).max(layer.get_zmin());
+    let zmax = options.zmax.unwrap_or(u32::MAX).min(layer.get_zmax());
+    if zmin > zmax {
+        return Err(AppError::InvalidInput(format!(
+            "zoom range {zmin}-{zmax} is outside the layer zooms {}-{}",
+            layer.get_zmin(),
+            layer.get_zmax()
+        )));
+    }
+    let bbox = match options.bbox {
+        Some(bbox) => bbox,
+        None => {
+            let extent = query_extent(&layer).await?;
+            [extent.xmin, extent.ymin, extent.xmax, extent.ymax]
+        }
+    };
+    if !(bbox[0] <= bbox[2] && bbox[1] <= bbox[3]) {
+        return Err(AppError::InvalidInput(
+            "bbox must be west,south,east,north".to_string(),
+        ));
+    }
+
+    let pg_pool = get_db_registry()
+        .get_pool(&layer.database_id)
+        .cloned()
+        .ok_or_else(|| AppError::DatabaseError("Pool not found".to_string()))?;
+    let where_clause = layer.get_filter();
+    if !where_clause.is_empty() {
+        validate_filter(&where_clause)?;
+    }
+    let concurrency = options
+        .concurrency
+        .unwrap_or(DEFAULT_CONCURRENCY)
+        .clamp(1, MAX_CONCURRENCY);
+
+    let ranges = tile_ranges(tms, bounds_in(tms, &layer, bbox).await?, zmin, zmax);
+    let total: u64 = ranges.iter().map(TileRange::count).sum();
+    let checkpoint = checkpoint_path(&key);
+    let start = if options.resume {
+        read_checkpoint(&checkpoint, &tms.id, zmin, zmax, bbox).min(total)
+    } else {
+        0
+    };
+    progress.total.store(total, Ordering::Relaxed);
+    progress.done.store(start, Ordering::Relaxed);
+    info!(layer = %key, matrix_set = %tms.id, zmin, zmax, ?bbox, total, start, "seed: started");
+
+    let mut pending = tiles(&ranges).skip(start as usize);
+    let mut last_log = Instant::now();
+    loop {
+        let batch: Vec<_> = pending.by_ref().take(BATCH_SIZE).collect();
+        if batch.is_empty() {
+            break;
+        }
+        futures::stream::iter(batch)
+            .for_each_concurrent(concurrency, |(z, x, y)| {
+                seed_tile(
+                    pg_pool.clone(),
+                    layer.clone(),
+                    &key,
+                    &tms.id,
+                    z,
+                    x,
+                    y,
+                    where_clause.clone(),
+                    options.skip_empty,
+                    progress,
+                )
+            })
+            .await;
+
+        if progress.failed.load(Ordering::Relaxed) == 0 {
+            write_checkpoint(
+                &checkpoint,
+                &Checkpoint {
+                    matrix_set: tms.id.clone(),
+                    zmin,
+                    zmax,
+                    bbox,
+                    done: progress.done.load(Ordering::Relaxed),
+                },
+            )?;
+        }
+        if last_log.elapsed() >= PROGRESS_LOG_INTERVAL {
+            let s = progress.status(&key);
+            info!(
+                layer = %key,
+                "seed: {}/{} tiles ({} written, {} empty, {} failed)",
+                s.done, s.total, s.written, s.empty, s.failed
+            );
+            last_log = Instant::now();
+        }
+    }
+
+    let s = progress.status(&key);
+    info!(
+        layer = %key,
+        "seed: finished {} tiles ({} written, {} empty, {} failed)",
+        s.done, s.written, s.empty, s.failed
+    );
+    if s.failed > 0 {
+        return Err(AppError::InternalServerError(format!(
+            "{} tiles failed; resume the run to retry them",
+            s.failed
+        )));
+    }
+    let _ = std::fs::remove_file(&checkpoint);
+    Ok(())
+}
+
+/// Starts a background seeding job for the admin API. Only one job per
+/// layer can run at a time.
+pub fn start_seed_job(layer: Layer, options: SeedOptions) -> AppResult<SeedStatus> {
+    validate_seedable(&layer)?;
+    check_seed_bbox(&layer, options.bbox)?;
+    let key = format!("{}_{}", layer.category.name, layer.name);
+    let progress = Arc::new(SeedProgress::default());
+    {
+        let mut jobs = SEED_JOBS
+            .lock()
+            .map_err(|_| AppError::InternalServerError("seed jobs lock poisoned".to_string()))?;
+        if jobs
+            .get(&key)
+            .is_some_and(|job| !job.finished.load(Ordering::Relaxed))
+        {
+            return Err(AppError::Conflict(format!(
+                "a seeding job for layer '{}' is already running",
+                layer.name
+            )));
+        }
+        jobs.insert(key.clone(), progress.clone());
+    }
+
+    let status = progress.status(&key);
+    tokio::spawn(async move {
+        let result = seed_layer(layer, &options, &progress).await;
+        if let Err(e) = &result {
+            warn!(layer = %key, error = ?e, "seed: job failed");
+        }
+        progress.finish(&result);
+    });
+    Ok(status)
+}
+
+pub fn seed_job_status(layer_key: &str) -> Option<SeedStatus> {
+    SEED_JOBS
+        .lock()
+        .ok()?
+        .get(layer_key)
+        .map(|job| job.status(layer_key))
+}
+
+/// Entry point of the `seed` subcommand: seeds the layer (`category:name`)
+/// or every cached layer of the category, one layer at a time.
+pub async fn run_cli(args: &SeedArgs) -> AppResult<()> {
+    if get_cache_wrapper().is_disabled() {
+        return Err(AppError::InvalidInput(
+            "seed needs a tile cache; remove --no-cache".to_string(),
+        ));
+    }
+    let layers: Vec<Layer> = {
+        let catalog = get_catalog().await.read().await;
+        match (&args.layer, &args.category) {
+            (Some(layer), _) => {
+                let (category, name) = layer.split_once(':').ok_or_else(|| {
+                    AppError::InvalidInput(format!(
+                        "layer '{layer}' must be given as category:name"
+                    ))
+                })?;
+                let layer = catalog
+                    .find_layer_by_category_and_name(category, name, StateLayer::Any)
+                    .ok_or_else(|| AppError::NotFound(format!("Layer {layer} not found")))?;
+                vec![layer.clone()]
+            }
+            (None, Some(category)) => catalog
+                .find_layers_by_category(category, StateLayer::Any)
+                .into_iter()
+                .filter(|layer| validate_seedable(layer).is_ok())
+                .filter(|layer| match check_seed_bbox(layer, args.bbox) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("seed: skipping layer '{}': {e}", layer.name);
+                        false
+                    }
+                })
+                .cloned()
+                .collect(),
+            (None, None) => {
+                return Err(AppError::RequestParamError(
+                    "--layer or --category".to_string(),
+                ));
+            }
+        }
+    };
+    if layers.is_empty() {
+        return Err(AppError::NotFound(
+            "no cacheable layers to seed".to_string(),
+        ));
+    }
+
+    let options = SeedOptions::from(args);
+    for layer in layers {
+        seed_layer(layer, &options, &SeedProgress::default()).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::tile_matrix_set::lonlat_to_web_mercator;
+
+    fn web_mercator_bounds([west, south, east, north]: [f64; 4]) -> [f64; 4] {
+        let (xmin, ymin) = lonlat_to_web_mercator(west, south);
+        let (xmax, ymax) = lonlat_to_web_mercator(east, north);
+        [xmin, ymin, xmax, ymax]
+    }
+
+    #[test]
+    fn tile_ranges_cover_bbox_per_zoom() {
+        let wmq = TileMatrixSet::web_mercator_quad();
+        let world = web_mercator_bounds([-180.0, -85.0, 180.0, 85.0]);
+        let ranges = tile_ranges(&wmq, world, 0, 2);
+        assert_eq!(
+            ranges.iter().map(TileRange::count).collect::<Vec<_>>(),
+            vec![1, 4, 16]
+        );
+
+        // Buenos Aires at z10.
+        let ranges = tile_ranges(
+            &wmq,
+            web_mercator_bounds([-58.5, -34.7, -58.3, -34.5]),
+            10,
+            10,
+        );
+        assert_eq!(ranges.len(), 1);
+        assert!(ranges[0].xmin <= 345 && 345 <= ranges[0].xmax);
+        assert!(ranges[0].ymin <= 617 && 617 <= ranges[0].ymax);
+    }
+
+    #[test]
+    fn tile_ranges_follow_the_matrix_set_grid() {
+        // WorldCRS84Quad is two tiles wide at its first zoom.
+        let crs84 = TileMatrixSet::world_crs84_quad();
+        let ranges = tile_ranges(&crs84, [-180.0, -90.0, 180.0, 90.0], 0, 1);
+        assert_eq!(
+            ranges.iter().map(TileRange::count).collect::<Vec<_>>(),
+            vec![2, 8]
+        );
+        let ranges = tile_ranges(&crs84, [-58.5, -34.7, -58.3, -34.5], 3, 3);
+        assert_eq!(
+            (
+                ranges[0].xmin,
+                ranges[0].ymin,
+                ranges[0].xmax,
+                ranges[0].ymax
+            ),
+            (5, 5, 5, 5)
+        );
+        // Zooms past the deepest level of the set are left out.
+        assert_eq!(
+            tile_ranges(&crs84, [-58.5, -34.7, -58.3, -34.5], 20, 40).len(),
+            5
+        );
+    }
+
+    #[test]
+    fn tiles_are_walked_in_stable_order() {
+        let wmq = TileMatrixSet::web_mercator_quad();
+        let ranges = tile_ranges(
+            &wmq,
+            web_mercator_bounds([-180.0, -85.0, 180.0, 85.0]),
+            0,
+            1,
+        );
+        let all: Vec<_> = tiles(&ranges).collect();
+        assert_eq!(
+            all,
+            vec![(0, 0, 0), (1, 0, 0), (1, 0, 1), (1, 1, 0), (1, 1, 1)]
+        );
+        // Resuming skips exactly the finished prefix.
+        assert_eq!(tiles(&ranges).nth(3), Some((1, 1, 0)));
+    }
+
+    #[test]
+    fn checkpoint_only_applies_to_the_same_run() {
+        let path = std::env::temp_dir()
+            .join(format!("mvt-rs-seed-{}", uuid::Uuid::new_v4().simple()))
+            .join("layer.json");
+        let bbox = [-60.0, -40.0, -50.0, -30.0];
+
+        assert_eq!(read_checkpoint(&path, "WebMercatorQuad", 0, 5, bbox), 0);
+        write_checkpoint(
+            &path,
+            &Checkpoint {
+                matrix_set: "WebMercatorQuad".to_string(),
+                zmin: 0,
+                zmax: 5,
+                bbox,
+                done: 42,
+            },
+        )
+        .unwrap();
+
+        assert_eq!(read_checkpoint(&path, "WebMercatorQuad", 0, 5, bbox), 42);
+        assert_eq!(read_checkpoint(&path, "WebMercatorQuad", 0, 6, bbox), 0);
+        assert_eq!(read_checkpoint(&path, "WorldCRS84Quad", 0, 5, bbox), 0);
+        assert_eq!(
+            read_checkpoint(&path, "WebMercatorQuad", 0, 5, [-61.0, -40.0, -50.0, -30.0]),
+            0
+        );
+
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn layers_without_an_extent_need_a_bbox() {
+        let mut layer = crate::config::test_support::test_layer("layer-1");
+        assert!(check_seed_bbox(&layer, None).is_ok());
+
+        for kind in [SourceKind::Function, SourceKind::Sql] {
+            layer.source_kind = kind;
+            assert!(matches!(
+                check_seed_bbox(&layer, None),
+                Err(AppError::InvalidInput(_))
+            ));
+            assert!(check_seed_bbox(&layer, Some([-60.0, -40.0, -50.0, -30.0])).is_ok());
+        }
+    }
+
+    #[test]
+    fn progress_status_reports_counters_and_error() {
+        let progress = SeedProgress::default();
+        progress.total.store(10, Ordering::Relaxed);
+        progress.done.store(4, Ordering::Relaxed);
+        progress.written.store(3, Ordering::Relaxed);
+        progress.empty.store(1, Ordering::Relaxed);
+        progress.finish(&Err(AppError::InvalidInput("boom".to_string())));
+
+        let status = progress.status("cat_layer");
+        assert_eq!(
+            (status.total, status.done, status.written, status.empty),
+            (10, 4, 3, 1)
+        );
+        assert!(status.finished);
+        assert!(status.error.unwrap().contains("boom"));
+    }
+}
@@ -6,11 +6,23 @@ use serde::Serialize;
 use tracing::warn;
 
 use crate::{
-    db::metadata::{query_extent, query_fields_with_comments},
-    error::AppResult,
-    get_catalog, get_public_url,
-    models::catalog::{Layer, StateLayer},
-    services::utils::validate_user_groups,
+    db::{
+        metadata::{FieldWithComment, query_extent, query_fields_with_comments},
+        static_tiles::TilesetMetadata,
+    },
+    error::{AppError, AppResult},
+    get_catalog, get_public_url, get_static_tile_registry,
+    models::{
+        catalog::{Layer, SourceKind, StateLayer},
+        tile_matrix_set::{TileMatrixSet, find_tile_matrix_set},
+    },
+    services::{
+        tiles::{
+            builder::{layer_tile_matrix_set, sql_layer_fields},
+            scheme::TileScheme,
+        },
+        utils::validate_user_groups,
+    },
 };
 
 /// A single entry of the TileJSON 3.0.0 `vector_layers` array.
@@ -36,6 +48,8 @@ pub struct TileJson {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub description: Option<String>,
     pub scheme: String,
+    /// OGC TileMatrixSet id of the tile grid (an extension of TileJSON).
+    pub tile_matrix_set: String,
     pub minzoom: u32,
     pub maxzoom: u32,
     pub bounds: [f64; 4],
@@ -68,24 +82,13 @@ pub fn resolve_base_url(
     format!("{proto}://{host}")
 }
 
-/// The layer's configured field list. Mirrors `utils::convert_fields`:
-/// a single element may hold a comma-separated list.
-pub fn configured_fields(layer: &Layer) -> Vec<String> {
-    if layer.fields.len() == 1 {
-        layer.fields[0]
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .collect()
-    } else {
-        layer.fields.iter().map(|s| s.trim().to_string()).collect()
-    }
-}
-
 pub fn build_tilejson(
     layer: &Layer,
     bounds: [f64; 4],
     fields: BTreeMap<String, String>,
     base_url: &str,
+    scheme: TileScheme,
+    tms: &TileMatrixSet,
 ) -> TileJson {
     let minzoom = layer.get_zmin();
     let maxzoom = layer.get_zmax();
@@ -109,8 +112,16 @@ pub fn build_tilejson(
     TileJson {
         tilejson: "3.0.0".to_string(),
         tiles: vec![format!(
-            "{base_url}/services/tiles/{}:{}/{{z}}/{{x}}/{{y}}.pbf",
-            layer.category.name, layer.name
+            "{base_url}/services/tiles{}/{}:{}/{{z}}/{{x}}/{{y}}.pbf{}",
+            scheme.route_prefix(),
+            layer.category.name,
+            layer.name,
+            // Only needed when the grid is not the layer's own default.
+            if tms.id == layer.get_tile_matrix_set() {
+                String::new()
+            } else {
+                format!("?tileMatrixSet={}", tms.id)
+            }
         )],
         vector_layers: vec![VectorLayer {
             id: layer.name.clone(),
@@ -121,7 +132,8 @@ pub fn build_tilejson(
         }],
         name: Some(name),
         description,
-        scheme: "xyz".to_string(),
+        scheme: scheme.as_str().to_string(),
+        tile_matrix_set: tms.id.clone(),
         minzoom,
         maxzoom,
         bounds,
@@ -129,11 +141,36 @@ pub fn build_tilejson(
     }
 }
 
+/// MBTiles `metadata` rows describing the same tileset as a TileJSON
+/// document; `json` carries its `vector_layers`.
+pub fn mbtiles_metadata(tilejson: &TileJson) -> Vec<(&'static str, String)> {
+    let join = |values: &[f64]| {
+        values.iter().map(f64::to_string).collect::<Vec<_>>().join(",")
+    };
+    let mut metadata = vec![
+        ("name", tilejson.name.clone().unwrap_or_default()),
+        ("format", "pbf".to_string()),
+        ("type", "overlay".to_string()),
+        ("minzoom", tilejson.minzoom.to_string()),
+        ("maxzoom", tilejson.maxzoom.to_string()),
+        ("bounds", join(&tilejson.bounds)),
+        ("center", join(&tilejson.center)),
+        (
+            "json",
+            serde_json::json!({ "vector_layers": tilejson.vector_layers }).to_string(),
+        ),
+    ];
+    if let Some(description) = &tilejson.description {
+        metadata.push(("description", description.clone()));
+    }
+    metadata
+}
+
 /// World bounds in EPSG:4326 (Web Mercator latitude limits), used when the
 /// extent query fails so the document is still valid.
 const WORLD_BOUNDS: [f64; 4] = [-180.0, -85.05112877980659, 180.0, 85.05112877980659];
 
-fn base_url_from_request(req: &Request) -> String {
+pub fn base_url_from_request(req: &Request) -> String {
     let header = |name: &str| {
         req.headers()
             .get(name)
@@ -160,17 +197,29 @@ async fn layer_bounds(layer: &Layer) -> [f64; 4] {
     }
 }
 
-/// `{field: description}` map for the layer's configured fields.
-/// Description is the PostgreSQL column comment, falling back to the type
-/// name. On query failure returns an empty map (never a 500).
+/// Columns of the layer's table, or the result columns of a SQL layer's
+/// query (which have no comments).
+async fn layer_columns(layer: &Layer) -> AppResult<Vec<FieldWithComment>> {
+    if layer.source_kind == SourceKind::Sql {
+        let fields = sql_layer_fields(layer).await?;
+        return Ok(fields
+            .into_iter()
+            .map(|field| FieldWithComment {
+                name: field.name,
+                udt: field.udt,
+                description: None,
+            })
+            .collect());
+    }
+    query_fields_with_comments(&layer.database_id, layer.schema.clone(), layer.table_name.clone())
+        .await
+}
+
+/// `{field: description}` map for the fields the layer's tiles carry at any
+/// zoom. Description is the PostgreSQL column comment, falling back to the
+/// type name. On query failure returns an empty map (never a 500).
 async fn layer_fields(layer: &Layer) -> BTreeMap<String, String> {
-    let columns = match query_fields_with_comments(
-        &layer.database_id,
-        layer.schema.clone(),
-        layer.table_name.clone(),
-    )
-    .await
-    {
+    let columns = match layer_columns(layer).await {
         Ok(columns) => columns,
         Err(e) => {
             warn!(layer = %layer.name, error = ?e, "TileJSON: field query failed, omitting fields");
@@ -178,22 +227,105 @@ async fn layer_fields(layer: &Layer) -> BTreeMap<String, String> {
         }
     };
 
+    field_descriptions(layer, columns)
+}
+
+/// Descriptions of the union of the layer's per-zoom field lists. Fields
+/// missing from the layer's lowest zooms say from which zoom they are
+/// available.
+pub fn field_descriptions(
+    layer: &Layer,
+    columns: Vec<FieldWithComment>,
+) -> BTreeMap<String, String> {
     let by_name: HashMap<String, _> = columns
         .into_iter()
         .map(|c| (c.name.clone(), c))
         .collect();
 
-    configured_fields(layer)
+    if layer.is_binned() {
+        let mut descriptions = BTreeMap::from([("count".to_string(), "Features in the cell".to_string())]);
+        for aggregate in layer.get_bin_aggregates() {
+            descriptions.insert(
+                aggregate.name(),
+                format!("{} of {} in the cell", aggregate.function, aggregate.field),
+            );
+        }
+        return descriptions;
+    }
+
+    // Clustered zooms carry the cluster attributes instead of the fields.
+    let cluster_zoom = layer.cluster_zoom.filter(|&zoom| zoom > layer.get_zmin());
+    let mut descriptions: BTreeMap<String, String> = layer
+        .field_zooms()
         .into_iter()
-        .filter_map(|name| {
-            by_name
-                .get(&name)
-                .map(|c| (name, c.description.clone().unwrap_or_else(|| c.udt.clone())))
+        .filter_map(|(name, zoom)| {
+            let column = by_name.get(&name)?;
+            let description = column.description.clone().unwrap_or_else(|| column.udt.clone());
+            let zoom = zoom.max(cluster_zoom.unwrap_or(0));
+            if zoom > layer.get_zmin() {
+                Some((name, format!("{description} (from zoom {zoom})")))
+            } else {
+                Some((name, description))
+            }
         })
-        .collect()
+        .collect();
+    if let Some(zoom) = cluster_zoom {
+        let below = format!("(below zoom {zoom})");
+        descriptions.insert("point_count".to_string(), format!("Clustered features {below}"));
+        for aggregate in layer.get_cluster_aggregates() {
+            descriptions.insert(
+                aggregate.name(),
+                format!("{} of {} {below}", aggregate.function, aggregate.field),
+            );
+        }
+    }
+    descriptions
 }
 
-fn set_json_cache_headers(res: &mut Response) {
+/// Applies a static tileset's own metadata: zooms the file declares override
+/// the catalog config, missing bounds fall back to world bounds.
+pub fn apply_tileset_metadata(
+    mut layer: Layer,
+    metadata: TilesetMetadata,
+) -> (Layer, [f64; 4], BTreeMap<String, String>) {
+    if let Some(minzoom) = metadata.minzoom {
+        layer.zmin = Some(minzoom);
+    }
+    if let Some(maxzoom) = metadata.maxzoom {
+        layer.zmax = Some(maxzoom);
+    }
+    (layer, metadata.bounds.unwrap_or(WORLD_BOUNDS), metadata.fields)
+}
+
+/// Metadata of a static layer's file; on read failure returns the empty
+/// metadata so the document still falls back to the catalog (never a 500).
+async fn static_layer_metadata(layer: &Layer) -> TilesetMetadata {
+    match get_static_tile_registry().read_metadata(layer).await {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!(layer = %layer.name, error = ?e, "TileJSON: static metadata read failed, using catalog config");
+            TilesetMetadata::default()
+        }
+    }
+}
+
+/// Zoom range, bounds and fields of a layer as advertised to clients:
+/// read from the file of static layers, queried from PostGIS for tables.
+/// Function layers are opaque: world bounds and no fields.
+pub async fn layer_metadata(layer: Layer) -> (Layer, [f64; 4], BTreeMap<String, String>) {
+    if layer.source_kind.is_static() {
+        let metadata = static_layer_metadata(&layer).await;
+        apply_tileset_metadata(layer, metadata)
+    } else if layer.source_kind == SourceKind::Function {
+        (layer, WORLD_BOUNDS, BTreeMap::new())
+    } else {
+        let bounds = layer_bounds(&layer).await;
+        let fields = layer_fields(&layer).await;
+        (layer, bounds, fields)
+    }
+}
+
+pub fn set_json_cache_headers(res: &mut Response) {
     res.headers_mut().insert(
         "Cache-Control",
         HeaderValue::from_static("public, max-age=3600"),
@@ -228,12 +360,24 @@ pub async fn tilejson_layer(
         return Ok(());
     }
 
-    let bounds = layer_bounds(&layer).await;
-    let fields = layer_fields(&layer).await;
+    let tms = match req.query::<String>("tileMatrixSet") {
+        Some(id) => find_tile_matrix_set(&id)
+            .ok_or_else(|| AppError::InvalidInput(format!("unknown tile matrix set '{id}'")))?,
+        None => layer_tile_matrix_set(&layer)?,
+    };
+    if layer.source_kind.is_web_mercator_only() && !tms.is_default() {
+        return Err(AppError::InvalidInput(format!(
+            "layer '{name}' is only available in WebMercatorQuad"
+        )));
+    }
+
+    let (layer, bounds, fields) = layer_metadata(layer).await;
     let base_url = base_url_from_request(req);
+    // `?scheme=tms` advertises TMS rows and points `tiles` at the TMS routes.
+    let scheme = TileScheme::from_request(req, depot);
 
     set_json_cache_headers(res);
-    res.render(Json(build_tilejson(&layer, bounds, fields, &base_url)));
+    res.render(Json(build_tilejson(&layer, bounds, fields, &base_url, scheme, tms)));
     Ok(())
 }
 
@@ -275,7 +419,11 @@ pub async fn tilejson_index(
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::category::Category;
+    use crate::db::static_tiles::parse_vector_layer_fields;
+    use crate::models::{
+        catalog::{FieldAggregate, ZoomFields},
+        category::Category,
+    };
 
     fn test_layer() -> Layer {
         Layer {
@@ -289,13 +437,22 @@ mod tests {
             name: "parcels".to_string(),
             alias: "Parcels".to_string(),
             description: "Cadastral parcels".to_string(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
             database_id: "default".to_string(),
             schema: "public".to_string(),
             table_name: "parcels".to_string(),
             fields: vec!["gid".to_string(), "owner".to_string()],
+            zoom_fields: None,
+            generalization: None,
             filter: None,
             srid: None,
+            tile_matrix_set: None,
             geom: None,
+            id_column: None,
             sql_mode: None,
             buffer: None,
             extent: None,
@@ -308,6 +465,16 @@ mod tests {
             delete_cache_on_start: None,
             max_cache_age: None,
             max_records: None,
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
             published: true,
             url: None,
             groups: None,
@@ -348,12 +515,78 @@ mod tests {
     fn configured_fields_splits_single_comma_separated_element() {
         let mut layer = test_layer();
         layer.fields = vec!["gid, owner ,area".to_string()];
-        assert_eq!(configured_fields(&layer), vec!["gid", "owner", "area"]);
+        assert_eq!(layer.fields_at(0), vec!["gid", "owner", "area"]);
+    }
+
+    #[test]
+    fn field_descriptions_report_the_zoom_fields_start_at() {
+        let mut layer = test_layer();
+        layer.zmin = Some(0);
+        layer.zmax = Some(14);
+        layer.zoom_fields = Some(vec![ZoomFields {
+            zmin: 0,
+            zmax: 8,
+            fields: vec!["gid".to_string()],
+        }]);
+        let column = |name: &str, description: Option<&str>| FieldWithComment {
+            name: name.to_string(),
+            udt: "text".to_string(),
+            description: description.map(str::to_string),
+        };
+        let fields = field_descriptions(
+            &layer,
+            vec![column("gid", None), column("owner", Some("Owner full name"))],
+        );
+        assert_eq!(fields["gid"], "text");
+        assert_eq!(fields["owner"], "Owner full name (from zoom 9)");
+
+        layer.zoom_fields = None;
+        let fields = field_descriptions(&layer, vec![column("gid", None), column("owner", None)]);
+        assert_eq!(fields["owner"], "text");
+    }
+
+    #[test]
+    fn field_descriptions_report_the_cluster_attributes() {
+        let mut layer = test_layer();
+        layer.zmin = Some(0);
+        layer.cluster_zoom = Some(8);
+        layer.cluster_aggregates = Some(vec![FieldAggregate {
+            function: "sum".to_string(),
+            field: "gid".to_string(),
+        }]);
+        let column = |name: &str| FieldWithComment {
+            name: name.to_string(),
+            udt: "int4".to_string(),
+            description: None,
+        };
+        let fields = field_descriptions(&layer, vec![column("gid"), column("owner")]);
+        assert_eq!(fields["gid"], "int4 (from zoom 8)");
+        assert_eq!(fields["point_count"], "Clustered features (below zoom 8)");
+        assert_eq!(fields["sum_gid"], "sum of gid (below zoom 8)");
+    }
+
+    #[test]
+    fn field_descriptions_of_binned_layers_are_the_cell_attributes() {
+        let mut layer = test_layer();
+        layer.bin_shape = Some("square".to_string());
+        layer.bin_aggregates = Some(vec![FieldAggregate {
+            function: "avg".to_string(),
+            field: "gid".to_string(),
+        }]);
+        let column = FieldWithComment {
+            name: "gid".to_string(),
+            udt: "int4".to_string(),
+            description: None,
+        };
+        let fields = field_descriptions(&layer, vec![column]);
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields["count"], "Features in the cell");
+        assert_eq!(fields["avg_gid"], "avg of gid in the cell");
     }
 
     #[test]
     fn configured_fields_keeps_multiple_elements() {
-        assert_eq!(configured_fields(&test_layer()), vec!["gid", "owner"]);
+        assert_eq!(test_layer().fields_at(0), vec!["gid", "owner"]);
     }
 
     #[test]
@@ -367,6 +600,8 @@ mod tests {
             [-60.0, -40.0, -50.0, -30.0],
             fields,
             "http://localhost:5887",
+            TileScheme::Xyz,
+            &TileMatrixSet::web_mercator_quad(),
         );
 
         assert_eq!(doc.tilejson, "3.0.0");
@@ -377,6 +612,7 @@ mod tests {
         assert_eq!(doc.name.as_deref(), Some("Parcels"));
         assert_eq!(doc.description.as_deref(), Some("Cadastral parcels"));
         assert_eq!(doc.scheme, "xyz");
+        assert_eq!(doc.tile_matrix_set, "WebMercatorQuad");
         assert_eq!(doc.minzoom, 4);
         assert_eq!(doc.maxzoom, 14);
         assert_eq!(doc.bounds, [-60.0, -40.0, -50.0, -30.0]);
@@ -391,13 +627,89 @@ mod tests {
         assert_eq!(vl.fields.get("owner").map(String::as_str), Some("Owner full name"));
     }
 
+    #[test]
+    fn mbtiles_metadata_describes_the_tileset() {
+        let doc = build_tilejson(
+            &test_layer(),
+            [-60.0, -40.0, -50.0, -30.0],
+            BTreeMap::from([("gid".to_string(), "int4".to_string())]),
+            "",
+            TileScheme::Xyz,
+            &TileMatrixSet::web_mercator_quad(),
+        );
+        let metadata: BTreeMap<&str, String> = mbtiles_metadata(&doc).into_iter().collect();
+
+        assert_eq!(metadata["name"], "Parcels");
+        assert_eq!(metadata["format"], "pbf");
+        assert_eq!(metadata["bounds"], "-60,-40,-50,-30");
+        assert_eq!(metadata["center"], "-55,-35,9");
+        assert_eq!((metadata["minzoom"].as_str(), metadata["maxzoom"].as_str()), ("4", "14"));
+        assert_eq!(parse_vector_layer_fields(&metadata["json"])["gid"], "int4");
+    }
+
+    #[test]
+    fn build_tilejson_advertises_tms_scheme() {
+        let doc = build_tilejson(
+            &test_layer(),
+            [0.0, 0.0, 1.0, 1.0],
+            BTreeMap::new(),
+            "http://h",
+            TileScheme::Tms,
+            &TileMatrixSet::web_mercator_quad(),
+        );
+
+        assert_eq!(doc.scheme, "tms");
+        assert_eq!(
+            doc.tiles,
+            vec!["http://h/services/tiles/tms/public:parcels/{z}/{x}/{y}.pbf"]
+        );
+    }
+
+    #[test]
+    fn build_tilejson_names_other_matrix_sets_in_tile_urls() {
+        let crs84 = TileMatrixSet::world_crs84_quad();
+        let doc = build_tilejson(
+            &test_layer(),
+            [0.0, 0.0, 1.0, 1.0],
+            BTreeMap::new(),
+            "http://h",
+            TileScheme::Xyz,
+            &crs84,
+        );
+        assert_eq!(doc.tile_matrix_set, "WorldCRS84Quad");
+        assert_eq!(
+            doc.tiles,
+            vec!["http://h/services/tiles/public:parcels/{z}/{x}/{y}.pbf?tileMatrixSet=WorldCRS84Quad"]
+        );
+
+        // The layer's own default needs no parameter.
+        let mut layer = test_layer();
+        layer.tile_matrix_set = Some("WorldCRS84Quad".to_string());
+        let doc = build_tilejson(
+            &layer,
+            [0.0, 0.0, 1.0, 1.0],
+            BTreeMap::new(),
+            "http://h",
+            TileScheme::Xyz,
+            &crs84,
+        );
+        assert_eq!(doc.tiles, vec!["http://h/services/tiles/public:parcels/{z}/{x}/{y}.pbf"]);
+    }
+
     #[test]
     fn build_tilejson_alias_fallback_and_empty_description_omitted() {
         let mut layer = test_layer();
         layer.alias = "".to_string();
         layer.description = "".to_string();
 
-        let doc = build_tilejson(&layer, [0.0, 0.0, 1.0, 1.0], BTreeMap::new(), "http://h");
+        let doc = build_tilejson(
+            &layer,
+            [0.0, 0.0, 1.0, 1.0],
+            BTreeMap::new(),
+            "http://h",
+            TileScheme::Xyz,
+            &TileMatrixSet::web_mercator_quad(),
+        );
 
         assert_eq!(doc.name.as_deref(), Some("parcels"));
         assert!(doc.description.is_none());
@@ -408,4 +720,42 @@ mod tests {
         assert!(json.get("tiles").is_some());
         assert!(json.get("vector_layers").is_some());
     }
+
+    #[test]
+    fn apply_tileset_metadata_overrides_zooms_and_bounds() {
+        let mut fields = BTreeMap::new();
+        fields.insert("name".to_string(), "String".to_string());
+        let metadata = TilesetMetadata {
+            bounds: Some([-60.0, -40.0, -50.0, -30.0]),
+            minzoom: Some(2),
+            maxzoom: Some(9),
+            fields,
+        };
+
+        let (layer, bounds, fields) = apply_tileset_metadata(test_layer(), metadata);
+        let doc = build_tilejson(
+            &layer,
+            bounds,
+            fields,
+            "http://h",
+            TileScheme::Xyz,
+            &TileMatrixSet::web_mercator_quad(),
+        );
+
+        assert_eq!(doc.minzoom, 2);
+        assert_eq!(doc.maxzoom, 9);
+        assert_eq!(doc.bounds, [-60.0, -40.0, -50.0, -30.0]);
+        assert_eq!(doc.vector_layers[0].id, "parcels");
+        assert!(doc.vector_layers[0].fields.contains_key("name"));
+    }
+
+    #[test]
+    fn apply_tileset_metadata_falls_back_to_catalog_and_world_bounds() {
+        let (layer, bounds, fields) =
+            apply_tileset_metadata(test_layer(), TilesetMetadata::default());
+
+        assert_eq!((layer.get_zmin(), layer.get_zmax()), (4, 14));
+        assert_eq!(bounds, WORLD_BOUNDS);
+        assert!(fields.is_empty());
+    }
 }
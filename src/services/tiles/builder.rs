@@ -1,21 +1,73 @@
 use bytes::Bytes;
-use sqlx::PgPool;
+use sqlx::{
+    Connection, PgConnection, PgPool, Postgres, Type,
+    pool::PoolConnection,
+    postgres::{PgArguments, PgConnectOptions, PgTypeInfo},
+    query::QueryAs,
+};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
 // use tracing::{error, warn};
 
 use crate::services::utils::{convert_fields, validate_filter};
 use crate::{
+    cache::{cachewrapper::CacheTier, encoding::EncodedTile, key::TileKey},
     config::consts::*,
-    error::AppResult,
+    db::{
+        metadata::{Field, query_fields, query_is_table, query_result_fields},
+        quote_ident,
+        static_tiles::decompress_tile,
+    },
+    error::{AppError, AppResult},
     get_cache_wrapper,
+    get_db_registry,
     get_plugin_registry,
-    models::catalog::Layer,
-    monitor::{record_cache_hit, record_cache_miss, record_request},
+    get_static_tile_registry,
+    get_tile_matrix_sets,
+    models::{
+        catalog::{BIN_READ_MARGIN, FieldAggregate, Layer, SourceKind},
+        tile_matrix_set::{TileMatrixSet, find_tile_matrix_set},
+    },
+    monitor::{record_cache_hit, record_cache_miss, record_layer_cache_lookup, record_request},
     plugins::PluginContext,
+    services::tiles::coalesce::TILE_FLIGHTS,
 };
 
 pub enum Via {
     Database,
-    Cache,
+    Cache(CacheTier),
+    Static,
+}
+
+/// Request-level filtering of a tile. Table and SQL layers get the filter
+/// query parameters as a SQL condition built by `filters::SqlQueryBuilder`;
+/// function layers get the raw query parameters instead, and SQL layers the
+/// values of their declared parameters.
+#[derive(Debug, Clone, Default)]
+pub struct TileFilter {
+    pub where_clause: String,
+    pub bindings: Vec<String>,
+    /// Passed to function layers as their `query_params json` argument, and
+    /// bound to the declared parameters of SQL layers.
+    pub params: BTreeMap<String, String>,
+}
+
+impl TileFilter {
+    /// A filtered tile depends on the request: it is neither read from nor
+    /// written to the tile cache.
+    pub fn is_empty(&self) -> bool {
+        self.where_clause.is_empty() && self.params.is_empty()
+    }
+}
+
+/// Call of a function layer: `fn(z, x, y, query_params json)`.
+pub fn function_sql(schema: &str, function: &str) -> String {
+    format!(
+        r#"SELECT "{}"."{}"($1, $2, $3, $4::json)"#,
+        schema.replace('"', "\"\""),
+        function.replace('"', "\"\"")
+    )
 }
 
 pub fn build_sql_template(sql_mode: &str) -> &'static str {
@@ -26,32 +78,34 @@ pub fn build_sql_template(sql_mode: &str) -> &'static str {
                 SELECT
                     {fields},
                     ST_AsMVTGeom(
-                        ST_Transform({geom}, 3857),
-                        ST_TileEnvelope($1, $2, $3),
+                        {tile_geom},
+                        {tile_envelope},
                         $4, $5, $6
                     ) AS geom
-                FROM "{schema}"."{table}"
-                WHERE {geom} && ST_Transform(ST_TileEnvelope($1, $2, $3), $7)
+                FROM {source}
+                WHERE {geom} && ST_Transform({tile_envelope}, $7)
                     AND {geom} IS NOT NULL
+                    {generalization_placeholder}
                     {query_placeholder}
                 {limit_placeholder}
             )
-            SELECT ST_AsMVT(mvtgeom.*, $8, $4, 'geom') AS tile FROM mvtgeom;
+            SELECT ST_AsMVT(mvtgeom.*, $8, $4, 'geom'{feature_id}) AS tile FROM mvtgeom;
         "#
         }
         _ => {
             r#"
-            SELECT ST_AsMVT(tile, $8, $4, 'geom') FROM (
+            SELECT ST_AsMVT(tile, $8, $4, 'geom'{feature_id}) FROM (
                 SELECT
                     {fields},
                     ST_AsMVTGeom(
-                        ST_Transform({geom}, 3857),
-                        ST_TileEnvelope($1, $2, $3),
+                        {tile_geom},
+                        {tile_envelope},
                         $4, $5, $6
                     ) AS geom
-                FROM "{schema}"."{table}"
-                WHERE {geom} && ST_Transform(ST_TileEnvelope($1, $2, $3), $7)
+                FROM {source}
+                WHERE {geom} && ST_Transform({tile_envelope}, $7)
                     AND {geom} IS NOT NULL
+                    {generalization_placeholder}
                     {query_placeholder}
                 {limit_placeholder}
             ) as tile;
@@ -60,74 +114,694 @@ pub fn build_sql_template(sql_mode: &str) -> &'static str {
     }
 }
 
-pub async fn query_database(
-    pg_pool: PgPool,
-    layer_conf: Layer,
-    x: u32,
-    y: u32,
+/// Replaces the placeholders of a SQL layer's query: `!bbox!` is the tile
+/// envelope in the layer's SRID, `!zoom!`, `!x!` and `!y!` the requested
+/// tile, and each declared parameter a text parameter numbered from
+/// `first_param`. `!zoom!` is $9 rather than the envelope zoom bound to $1,
+/// which differs on matrix sets with a non-square top level.
+pub fn expand_sql_query(
+    query: &str,
+    params: &[String],
+    tile_envelope: &str,
+    first_param: usize,
+) -> String {
+    let mut sql = query
+        .replace("!bbox!", &format!("ST_Transform({tile_envelope}, $7)"))
+        .replace("!zoom!", "$9")
+        .replace("!x!", "$2")
+        .replace("!y!", "$3");
+    for (i, name) in params.iter().enumerate() {
+        sql = sql.replace(&format!("!{name}!"), &format!("${}::text", first_param + i));
+    }
+    sql
+}
+
+/// `FROM` item of the tile query: the layer's table, or its SQL query as a
+/// subquery.
+fn layer_source_sql(layer_conf: &Layer, tms: &TileMatrixSet) -> String {
+    if layer_conf.source_kind == SourceKind::Sql {
+        let query = expand_sql_query(
+            &layer_conf.get_sql_query(),
+            &layer_conf.get_sql_params(),
+            &tile_envelope_sql(tms),
+            SQL_PARAMS_START,
+        );
+        return format!("({query}) AS source");
+    }
+    format!(r#""{}"."{}""#, layer_conf.schema, layer_conf.table_name)
+}
+
+/// First `$n` of a SQL layer's declared parameters; filter bindings follow
+/// them.
+pub const SQL_PARAMS_START: usize = 10;
+
+/// Values of the tile query's fixed parameters $1-$9, see `build_tile_query`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileParams {
+    /// Zoom of the `ST_TileEnvelope` grid, `TileMatrixSet::envelope_zoom`.
+    pub envelope_zoom: i32,
+    pub x: i32,
+    pub y: i32,
+    pub extent: i32,
+    pub buffer: i32,
+    pub clip_geom: bool,
+    pub srid: i32,
+    pub name: String,
+    /// Requested zoom, the `!zoom!` of SQL layers.
+    pub zoom: i32,
+}
+
+impl TileParams {
+    pub fn new(layer_conf: &Layer, tms: &TileMatrixSet, x: u32, y: u32, z: u32) -> Self {
+        let (buffer, extent) = if z
+            >= layer_conf
+                .zmax_do_not_simplify
+                .unwrap_or(DEFAULT_ZMAX_DO_NOT_SIMPLIFY)
+        {
+            (
+                layer_conf.buffer_do_not_simplify.unwrap_or(DEFAULT_BUFFER),
+                layer_conf.extent_do_not_simplify.unwrap_or(DEFAULT_EXTENT),
+            )
+        } else {
+            (
+                layer_conf.buffer.unwrap_or(DEFAULT_BUFFER),
+                layer_conf.extent.unwrap_or(DEFAULT_EXTENT),
+            )
+        };
+        Self {
+            envelope_zoom: tms.envelope_zoom(z) as i32,
+            x: x as i32,
+            y: y as i32,
+            extent: extent as i32,
+            buffer: buffer as i32,
+            clip_geom: layer_conf.clip_geom.unwrap_or(true),
+            srid: layer_conf.srid.unwrap_or(DEFAULT_SRID) as i32,
+            name: layer_conf.name.clone(),
+            zoom: z as i32,
+        }
+    }
+
+    fn bind(self, query: TileQuery<'_>) -> TileQuery<'_> {
+        query
+            .bind(self.envelope_zoom)
+            .bind(self.x)
+            .bind(self.y)
+            .bind(self.extent)
+            .bind(self.buffer)
+            .bind(self.clip_geom)
+            .bind(self.srid)
+            .bind(self.name)
+            .bind(self.zoom)
+    }
+}
+
+/// Tile query of a table or SQL layer at zoom `z`. Binds the tile ($1-$3), extent,
+/// buffer, clip flag, data SRID, layer name ($4-$8), the requested zoom ($9),
+/// then the SQL layer's parameters and the filter bindings.
+pub fn build_tile_query(
+    layer_conf: &Layer,
+    tms: &TileMatrixSet,
     z: u32,
-    where_clause: String,
-    bindings: Vec<String>,
-) -> AppResult<Bytes> {
-    let name = layer_conf.name;
-    let schema = layer_conf.schema;
-    let table = layer_conf.table_name;
-    let fields = convert_fields(layer_conf.fields);
-    let geom = layer_conf.geom.unwrap_or_else(|| "geom".to_string());
-    let sql_mode = layer_conf.sql_mode.unwrap_or_else(|| "CTE".to_string());
-    let srid = layer_conf.srid.unwrap_or(DEFAULT_SRID);
-
-    let query_placeholder = if !where_clause.is_empty() {
-        validate_filter(&where_clause)?;
-        Some(format!(" AND {where_clause}"))
+    where_clause: &str,
+) -> String {
+    let query_placeholder = if where_clause.is_empty() {
+        String::new()
     } else {
-        None
+        format!(" AND {where_clause}")
     };
 
-    let (buffer, extent) = if z
-        >= layer_conf
-            .zmax_do_not_simplify
-            .unwrap_or(DEFAULT_ZMAX_DO_NOT_SIMPLIFY)
-    {
-        (
-            layer_conf.buffer_do_not_simplify.unwrap_or(DEFAULT_BUFFER),
-            layer_conf.extent_do_not_simplify.unwrap_or(DEFAULT_EXTENT),
-        )
+    let limit_clause = limit_sql(layer_conf);
+
+    if layer_conf.is_binned() {
+        return build_bin_query(layer_conf, tms, z, &query_placeholder);
+    }
+    if layer_conf.is_clustered_at(z) {
+        return build_cluster_query(layer_conf, tms, z, &query_placeholder);
+    }
+    let (tile_geom, generalization) = generalization_sql(layer_conf, tms, z);
+
+    // The id column is selected even when it is not one of the fields.
+    let mut fields = convert_fields(layer_conf, z);
+    let mut feature_id = String::new();
+    if let Some(id_column) = layer_conf.get_id_column() {
+        if !layer_conf.fields_at(z).iter().any(|field| field == id_column) {
+            fields = format!("{}, {fields}", quote_ident(id_column));
+        }
+        feature_id = format!(", '{}'", id_column.replace('\'', "''"));
+    }
+
+    // The source goes in last so the query's own text is never rewritten.
+    build_sql_template(&layer_conf.get_sql_mode())
+        .replace("{fields}", &fields)
+        .replace("{feature_id}", &feature_id)
+        .replace("{tile_geom}", &tile_geom)
+        .replace("{generalization_placeholder}", &generalization)
+        .replace("{geom}", &layer_conf.get_geom())
+        .replace("{tile_srid}", &tms.srid.to_string())
+        .replace("{tile_envelope}", &tile_envelope_sql(tms))
+        .replace("{query_placeholder}", &query_placeholder)
+        .replace("{limit_placeholder}", &limit_clause)
+        .replace("{source}", &layer_source_sql(layer_conf, tms))
+}
+
+/// `ORDER BY ... LIMIT` keeping `max_records` features per tile. Features
+/// are ranked by the layer's priority, then by the id column, the row's
+/// `ctid` or, for SQL layers without an id column, the geometry bytes, so
+/// every tile keeps the same features; `random` opts in to
+/// `ORDER BY RANDOM()`. `check_limit_tiebreak` keeps `ctid` to tables.
+pub fn limit_sql(layer_conf: &Layer) -> String {
+    let Some(max) = layer_conf.max_records.filter(|&max| max > 0) else {
+        return String::new();
+    };
+    let order = layer_conf.get_priority_order();
+    if order == "random" {
+        return format!("ORDER BY RANDOM() LIMIT {max}");
+    }
+
+    let tiebreak = match layer_conf.get_id_column() {
+        Some(id_column) => quote_ident(id_column),
+        None if layer_conf.source_kind != SourceKind::Sql => "ctid".to_string(),
+        // Rows of a SQL layer's query have no ctid. A bare name would
+        // resolve to the clipped output geometry.
+        None => format!("ST_AsEWKB({})", layer_conf.get_geom()),
+    };
+    match layer_conf.get_priority() {
+        Some(priority) => format!(
+            "ORDER BY ({priority}) {} NULLS LAST, {tiebreak} LIMIT {max}",
+            order.to_uppercase()
+        ),
+        None => format!("ORDER BY {tiebreak} LIMIT {max}"),
+    }
+}
+
+/// Geometry handed to `ST_AsMVTGeom` and size filters of the layer's
+/// generalization rule at zoom `z`. Pixel sizes become tile grid units
+/// through the zoom's cell size.
+pub fn generalization_sql(layer_conf: &Layer, tms: &TileMatrixSet, z: u32) -> (String, String) {
+    let geom = layer_conf.get_geom();
+    let transformed = format!("ST_Transform({geom}, {})", tms.srid);
+    let Some(rule) = layer_conf.generalization_at(z) else {
+        return (transformed, String::new());
+    };
+    let cell_size = tms.tile_matrix(z).cell_size;
+
+    let tile_geom = match rule.simplify {
+        Some(pixels) => format!(
+            "ST_SimplifyPreserveTopology({transformed}, {})",
+            pixels * cell_size
+        ),
+        None => transformed.clone(),
+    };
+    let mut filters = String::new();
+    if let Some(pixels) = rule.min_area {
+        filters += &format!(
+            " AND (ST_Dimension({geom}) <> 2 OR ST_Area({transformed}) >= {})",
+            pixels * cell_size * cell_size
+        );
+    }
+    if let Some(pixels) = rule.min_length {
+        filters += &format!(
+            " AND (ST_Dimension({geom}) <> 1 OR ST_Length({transformed}) >= {})",
+            pixels * cell_size
+        );
+    }
+    (tile_geom, filters)
+}
+
+/// Aggregate select items (`sum("f")::float8 AS "sum_f",` lines) and the
+/// matching output column list; `qualifier` prefixes the field names.
+fn aggregate_columns(aggregates: &[FieldAggregate], qualifier: &str) -> (String, String) {
+    let mut columns = String::new();
+    let mut names = String::new();
+    for aggregate in aggregates {
+        let value = format!(
+            "{}({qualifier}{})",
+            aggregate.function,
+            quote_ident(&aggregate.field)
+        );
+        let value = match aggregate.function.as_str() {
+            // Sums and averages come out as numeric, which MVT cannot hold.
+            "sum" | "avg" => format!("{value}::float8"),
+            _ => value,
+        };
+        let name = quote_ident(&aggregate.name());
+        columns += &format!("\n                    {value} AS {name},");
+        names += &format!(" {name},");
+    }
+    (columns, names)
+}
+
+/// Tile query of a binned layer: a hexagon or square grid of `bin_size`
+/// pixels covers the tile, each cell holding the `count` and aggregates of
+/// the features whose point on surface falls in it. Grids are aligned on
+/// the CRS origin, and features are read a margin beyond the tile so cells
+/// shared with a neighbouring tile count the same features in both.
+fn build_bin_query(
+    layer_conf: &Layer,
+    tms: &TileMatrixSet,
+    z: u32,
+    query_placeholder: &str,
+) -> String {
+    let size = layer_conf.get_bin_size() as f64 * tms.tile_matrix(z).cell_size;
+    let grid = match layer_conf.bin_shape.as_deref() {
+        Some("square") => "ST_SquareGrid",
+        _ => "ST_HexagonGrid",
+    };
+    let aggregates = layer_conf.get_bin_aggregates();
+    let mut fields = String::new();
+    for aggregate in aggregates {
+        let field = format!(", {}", quote_ident(&aggregate.field));
+        if !fields.contains(&field) {
+            fields += &field;
+        }
+    }
+    let (aggregates, names) = aggregate_columns(aggregates, "features.");
+
+    format!(
+        r#"
+            WITH features AS (
+                SELECT ST_Transform(ST_PointOnSurface({{geom}}), {{tile_srid}}) AS point{fields}
+                FROM {{source}}
+                WHERE {{geom}} && ST_Transform(ST_Expand({{tile_envelope}}, {margin}), $7)
+                    AND {{geom}} IS NOT NULL
+                    {{query_placeholder}}
+            ),
+            bins AS (
+                SELECT
+                    count(*) AS count,{aggregates}
+                    bin.geom
+                FROM {grid}({size}, {{tile_envelope}}) AS bin
+                JOIN features ON ST_Intersects(bin.geom, features.point)
+                GROUP BY bin.i, bin.j, bin.geom
+            )
+            SELECT ST_AsMVT(mvtgeom.*, $8, $4, 'geom') AS tile FROM (
+                SELECT count,{names} ST_AsMVTGeom(geom, {{tile_envelope}}, $4, $5, $6) AS geom
+                FROM bins
+            ) AS mvtgeom;
+        "#,
+        margin = BIN_READ_MARGIN * size,
+    )
+    .replace("{geom}", &layer_conf.get_geom())
+    .replace("{tile_srid}", &tms.srid.to_string())
+    .replace("{tile_envelope}", &tile_envelope_sql(tms))
+    .replace("{query_placeholder}", query_placeholder)
+    .replace("{source}", &layer_source_sql(layer_conf, tms))
+}
+
+/// Tile query of a clustered layer's low zooms: features are grouped in
+/// square cells of `cluster_radius` pixels, aligned on the CRS origin, and
+/// each cell becomes a point at the centroid of its features with
+/// `point_count` and the layer's aggregates. Features are read half a cell
+/// beyond the tile and a cell belongs to the tile holding its centre, so a
+/// cell straddling a tile edge is emitted whole, by one tile only.
+fn build_cluster_query(
+    layer_conf: &Layer,
+    tms: &TileMatrixSet,
+    z: u32,
+    query_placeholder: &str,
+) -> String {
+    let cell = layer_conf.get_cluster_radius() as f64 * tms.tile_matrix(z).cell_size;
+    let (aggregates, names) = aggregate_columns(layer_conf.get_cluster_aggregates(), "");
+
+    format!(
+        r#"
+            WITH clusters AS (
+                SELECT
+                    count(*) AS point_count,{aggregates}
+                    ST_Centroid(ST_Collect(ST_Transform({{geom}}, {{tile_srid}}))) AS geom,
+                    {cell_centre} AS centre
+                FROM {{source}}
+                WHERE {{geom}} && ST_Transform(ST_Expand({{tile_envelope}}, {half}), $7)
+                    AND {{geom}} IS NOT NULL
+                    {{query_placeholder}}
+                GROUP BY {cell_centre}
+            )
+            SELECT ST_AsMVT(mvtgeom.*, $8, $4, 'geom') AS tile FROM (
+                SELECT point_count,{names} ST_AsMVTGeom(geom, {{tile_envelope}}, $4, $5, $6) AS geom
+                FROM clusters
+                WHERE ST_X(centre) >= ST_XMin({{tile_envelope}}) AND ST_X(centre) < ST_XMax({{tile_envelope}})
+                    AND ST_Y(centre) >= ST_YMin({{tile_envelope}}) AND ST_Y(centre) < ST_YMax({{tile_envelope}})
+            ) AS mvtgeom;
+        "#,
+        half = cell / 2.0,
+        cell_centre = format!(
+            "ST_SnapToGrid(ST_Transform({{geom}}, {{tile_srid}}), {half}, {half}, {cell}, {cell})",
+            half = cell / 2.0
+        ),
+    )
+    .replace("{geom}", &layer_conf.get_geom())
+    .replace("{tile_srid}", &tms.srid.to_string())
+    .replace("{tile_envelope}", &tile_envelope_sql(tms))
+    .replace("{query_placeholder}", query_placeholder)
+    .replace("{source}", &layer_source_sql(layer_conf, tms))
+}
+
+/// Types of the tile query's parameters up to the SQL layer's own.
+fn tile_query_param_types(layer_conf: &Layer) -> Vec<PgTypeInfo> {
+    let int = <i32 as Type<Postgres>>::type_info();
+    let text = <String as Type<Postgres>>::type_info();
+    let mut types = vec![int.clone(); 5];
+    types.push(<bool as Type<Postgres>>::type_info());
+    types.push(int.clone());
+    types.push(text.clone());
+    types.push(int);
+    types.extend(std::iter::repeat_n(text, layer_conf.get_sql_params().len()));
+    types
+}
+
+/// Result columns of a SQL layer's query.
+pub async fn sql_layer_fields(layer_conf: &Layer) -> AppResult<Vec<Field>> {
+    let tms = layer_tile_matrix_set(layer_conf)?;
+    let sql = format!("SELECT * FROM {} LIMIT 0", layer_source_sql(layer_conf, tms));
+    query_result_fields(&layer_conf.database_id, sql, &tile_query_param_types(layer_conf)).await
+}
+
+/// Checks a SQL layer against its database before it is saved: the query
+/// must return the geometry column and some other column, and `EXPLAIN` of
+/// the whole tile query must succeed. The layer's fields become the other
+/// result columns.
+pub async fn prepare_sql_layer(layer_conf: &mut Layer) -> AppResult<()> {
+    let pg_pool = get_db_registry()
+        .get_pool(&layer_conf.database_id)
+        .cloned()
+        .ok_or_else(|| {
+            AppError::InvalidInput(format!("unknown database '{}'", layer_conf.database_id))
+        })?;
+    let invalid = |e: AppError| AppError::InvalidInput(format!("invalid SQL query: {e}"));
+
+    let geom = layer_conf.get_geom();
+    let columns = sql_layer_fields(layer_conf).await.map_err(invalid)?;
+    if !columns.iter().any(|c| c.name == geom) {
+        return Err(AppError::InvalidInput(format!(
+            "the SQL query returns no '{geom}' column"
+        )));
+    }
+    layer_conf.fields = columns
+        .into_iter()
+        .map(|c| c.name)
+        .filter(|name| *name != geom)
+        .collect();
+    if layer_conf.fields.is_empty() {
+        return Err(AppError::InvalidInput(
+            "the SQL query must return a column besides the geometry".to_string(),
+        ));
+    }
+
+    let tms = layer_tile_matrix_set(layer_conf)?;
+    let explain = format!("EXPLAIN {}", build_tile_query(layer_conf, tms, layer_conf.get_zmin(), ""));
+    let mut query = sqlx::query(sqlx::AssertSqlSafe(explain))
+        .bind(0i32)
+        .bind(0i32)
+        .bind(0i32)
+        .bind(layer_conf.get_extent() as i32)
+        .bind(layer_conf.get_buffer() as i32)
+        .bind(layer_conf.get_clip_geom())
+        .bind(layer_conf.get_srid() as i32)
+        .bind(&layer_conf.name)
+        .bind(layer_conf.get_zmin() as i32);
+    for _ in layer_conf.get_sql_params() {
+        query = query.bind(None::<String>);
+    }
+    query
+        .fetch_all(&pg_pool)
+        .await
+        .map_err(|e| invalid(e.into()))?;
+    Ok(())
+}
+
+/// Checks that a layer's id column, if any, is an integer column of its
+/// table or SQL query.
+pub async fn check_id_column(layer_conf: &Layer) -> AppResult<()> {
+    let Some(id_column) = layer_conf.get_id_column() else {
+        return Ok(());
+    };
+    let fields = if layer_conf.source_kind == SourceKind::Sql {
+        sql_layer_fields(layer_conf).await?
     } else {
-        (
-            layer_conf.buffer.unwrap_or(DEFAULT_BUFFER),
-            layer_conf.extent.unwrap_or(DEFAULT_EXTENT),
+        query_fields(
+            &layer_conf.database_id,
+            layer_conf.schema.clone(),
+            layer_conf.table_name.clone(),
         )
+        .await?
     };
+    match fields.iter().find(|field| field.name == id_column) {
+        Some(field) if field.is_integer() => Ok(()),
+        Some(field) => Err(AppError::InvalidInput(format!(
+            "id column '{id_column}' must be an integer column, not {}",
+            field.udt
+        ))),
+        None => Err(AppError::InvalidInput(format!(
+            "id column '{id_column}' not found"
+        ))),
+    }
+}
 
-    let clip_geom = layer_conf.clip_geom.unwrap_or(true);
-
-    let limit_clause = layer_conf
-        .max_records
-        .filter(|&max| max > 0)
-        .map_or_else(String::new, |max| format!("ORDER BY RANDOM() LIMIT {max}"));
+/// Whether `limit_sql` breaks ties on the row's `ctid`.
+fn limit_uses_ctid(layer_conf: &Layer) -> bool {
+    layer_conf.max_records.is_some_and(|max| max > 0)
+        && layer_conf.get_priority_order() != "random"
+        && layer_conf.get_id_column().is_none()
+        && layer_conf.source_kind == SourceKind::Postgis
+}
 
-    let sql_template = build_sql_template(&sql_mode);
-    let sql_query = sql_template
-        .replace("{fields}", &fields)
-        .replace("{schema}", &schema)
-        .replace("{table}", &table)
-        .replace("{geom}", &geom)
-        .replace(
-            "{query_placeholder}",
-            query_placeholder.as_deref().unwrap_or(""),
+/// Views have no `ctid`: a layer keeping `max_records` features from one
+/// needs an id column to order them by.
+pub async fn check_limit_tiebreak(layer_conf: &Layer) -> AppResult<()> {
+    if !limit_uses_ctid(layer_conf)
+        || query_is_table(
+            &layer_conf.database_id,
+            &layer_conf.schema,
+            &layer_conf.table_name,
         )
-        .replace("{limit_placeholder}", &limit_clause);
+        .await?
+    {
+        return Ok(());
+    }
+    Err(AppError::InvalidInput(format!(
+        "'{}.{}' is not a table: set an id column to limit the features per tile",
+        layer_conf.schema, layer_conf.table_name
+    )))
+}
+
+/// `ST_TileEnvelope` call for the tile bound to $1-$3. Other matrix sets
+/// than WebMercatorQuad pass their grid bounds, and the zoom bound to $1 is
+/// `TileMatrixSet::envelope_zoom`.
+pub fn tile_envelope_sql(tms: &TileMatrixSet) -> String {
+    if tms.is_default() {
+        return "ST_TileEnvelope($1, $2, $3)".to_string();
+    }
+    let [xmin, ymin, xmax, ymax] = tms.envelope_bounds();
+    format!(
+        "ST_TileEnvelope($1, $2, $3, ST_MakeEnvelope({xmin}, {ymin}, {xmax}, {ymax}, {}))",
+        tms.srid
+    )
+}
+
+/// Matrix set a layer's tiles are rendered in.
+pub fn layer_tile_matrix_set(layer_conf: &Layer) -> AppResult<&'static TileMatrixSet> {
+    let id = layer_conf.get_tile_matrix_set();
+    find_tile_matrix_set(&id)
+        .ok_or_else(|| AppError::InvalidInput(format!("unknown tile matrix set '{id}'")))
+}
+
+/// Matrix sets a layer can be served in: static files only hold
+/// WebMercatorQuad tiles, PostGIS layers are rendered in any set.
+pub fn supported_tile_matrix_sets(layer_conf: &Layer) -> Vec<&'static TileMatrixSet> {
+    get_tile_matrix_sets()
+        .iter()
+        .filter(|tms| !layer_conf.source_kind.is_web_mercator_only() || tms.is_default())
+        .collect()
+}
+
+type TileQuery<'q> = QueryAs<'q, Postgres, (Option<Vec<u8>>,), PgArguments>;
+
+/// Cancel connections open at once. Past this, an abandoned query runs
+/// until it finishes or hits its `statement_timeout`, on a connection that
+/// no longer belongs to the pool.
+const MAX_CANCEL_CONNECTIONS: usize = 4;
+
+static CANCEL_CONNECTIONS: Semaphore = Semaphore::const_new(MAX_CANCEL_CONNECTIONS);
+
+/// Holds the pooled connection a tile query runs on. Dropped
+/// armed, i.e. when the request future goes away mid-query because the
+/// client disconnected, it takes the connection out of the pool and cancels
+/// its query with `pg_cancel_backend` over a fresh connection: the pool may
+/// be exhausted, and the backend must not have been handed to another
+/// request.
+struct CancelOnDrop {
+    conn: Option<PoolConnection<Postgres>>,
+    /// Backend running the query, known once its transaction has begun.
+    pid: Option<i32>,
+    connect_options: Arc<PgConnectOptions>,
+}
+
+impl CancelOnDrop {
+    async fn acquire(pg_pool: &PgPool) -> AppResult<Self> {
+        Ok(Self {
+            conn: Some(pg_pool.acquire().await?),
+            pid: None,
+            connect_options: pg_pool.connect_options(),
+        })
+    }
+
+    /// Returns the connection to the pool once its query has finished.
+    fn disarm(mut self) {
+        self.conn = None;
+    }
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        let Some(conn) = self.conn.take() else {
+            return;
+        };
+        // Dropped before the query was sent: the connection can go back.
+        let Some(pid) = self.pid else {
+            return;
+        };
+        // Still busy with the abandoned query: keep it out of the pool.
+        let conn = conn.detach();
+        let Ok(permit) = CANCEL_CONNECTIONS.try_acquire() else {
+            tracing::debug!(pid, "too many cancel connections, leaving the query to its timeout");
+            return;
+        };
+        let connect_options = self.connect_options.clone();
+        tokio::spawn(async move {
+            let cancelled = async {
+                let mut canceller = PgConnection::connect_with(&connect_options).await?;
+                sqlx::query("SELECT pg_cancel_backend($1)")
+                    .bind(pid)
+                    .execute(&mut canceller)
+                    .await?;
+                canceller.close().await
+            };
+            if let Err(e) = cancelled.await {
+                tracing::warn!(pid, error = %e, "failed to cancel abandoned tile query");
+            }
+            drop(permit);
+            drop(conn);
+        });
+    }
+}
+
+/// SQLSTATE of a statement cancelled by `statement_timeout`.
+const QUERY_CANCELED: &str = "57014";
+
+fn tile_query_error(layer_conf: &Layer, error: sqlx::Error) -> AppError {
+    match &error {
+        sqlx::Error::Database(db) if db.code().as_deref() == Some(QUERY_CANCELED) => {
+            AppError::QueryTimeout(format!(
+                "layer '{}' took longer than {} ms",
+                layer_conf.name,
+                layer_conf.get_query_timeout_ms()
+            ))
+        }
+        _ => error.into(),
+    }
+}
+
+/// Runs a tile query, cancelling it on the server if the request is dropped
+/// before it returns. Layers with a `query_timeout_ms` run it in its own
+/// transaction under a local `statement_timeout`.
+async fn fetch_tile(pg_pool: &PgPool, layer_conf: &Layer, query: TileQuery<'_>) -> AppResult<Bytes> {
+    let mut guard = CancelOnDrop::acquire(pg_pool).await?;
+    let conn = guard.conn.as_mut().expect("connection is held until disarmed");
+    let timeout_ms = layer_conf.query_timeout_ms.filter(|&ms| ms > 0);
+    let rec = fetch_cancellable(conn, &mut guard.pid, timeout_ms, query).await;
+    guard.disarm();
+    let rec = rec.map_err(|e| tile_query_error(layer_conf, e))?;
+    Ok(rec.0.unwrap_or_default().into())
+}
+
+/// Runs `query` after recording the backend pid, in a transaction under a
+/// `statement_timeout` of `timeout_ms` if given, set in the same round trip
+/// as the pid lookup.
+async fn fetch_cancellable(
+    conn: &mut PgConnection,
+    pid: &mut Option<i32>,
+    timeout_ms: Option<u64>,
+    query: TileQuery<'_>,
+) -> Result<(Option<Vec<u8>>,), sqlx::Error> {
+    let Some(ms) = timeout_ms else {
+        let (backend_pid,): (i32,) = sqlx::query_as("SELECT pg_backend_pid()")
+            .fetch_one(&mut *conn)
+            .await?;
+        *pid = Some(backend_pid);
+        return query.fetch_one(conn).await;
+    };
+    let mut tx = conn.begin().await?;
+    let (backend_pid, _): (i32, String) =
+        sqlx::query_as("SELECT pg_backend_pid(), set_config('statement_timeout', $1, true)")
+            .bind(ms.to_string())
+            .fetch_one(&mut *tx)
+            .await?;
+    *pid = Some(backend_pid);
+    // A failed query drops, and so rolls back, the transaction.
+    let rec = query.fetch_one(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(rec)
+}
 
-    let mut query_builder = sqlx::query_as::<_, (Option<Vec<u8>>,)>(sqlx::AssertSqlSafe(sql_query))
+async fn query_function(
+    pg_pool: &PgPool,
+    layer_conf: &Layer,
+    x: u32,
+    y: u32,
+    z: u32,
+    params: &BTreeMap<String, String>,
+) -> AppResult<Bytes> {
+    let sql = function_sql(&layer_conf.schema, &layer_conf.get_function_name());
+    let query = sqlx::query_as::<_, (Option<Vec<u8>>,)>(sqlx::AssertSqlSafe(sql))
         .bind(z as i32)
         .bind(x as i32)
         .bind(y as i32)
-        .bind(extent as i32)
-        .bind(buffer as i32)
-        .bind(clip_geom)
-        .bind(srid as i32)
-        .bind(name);
+        .bind(serde_json::to_string(params)?);
+    fetch_tile(pg_pool, layer_conf, query).await
+}
+
+pub async fn query_database(
+    pg_pool: PgPool,
+    layer_conf: Layer,
+    x: u32,
+    y: u32,
+    z: u32,
+    filter: TileFilter,
+) -> AppResult<Bytes> {
+    let tms = layer_tile_matrix_set(&layer_conf)?;
+    if !tms.contains(z, x, y) {
+        return Err(AppError::InvalidInput(format!(
+            "tile {z}/{x}/{y} is outside the {} tile matrix set",
+            tms.id
+        )));
+    }
+    if layer_conf.source_kind == SourceKind::Function {
+        // The function builds its own query: a plugin filter has nowhere to go.
+        if !filter.where_clause.is_empty() {
+            return Err(AppError::InvalidInput(format!(
+                "function layer '{}' does not support filters",
+                layer_conf.name
+            )));
+        }
+        return query_function(&pg_pool, &layer_conf, x, y, z, &filter.params).await;
+    }
+    let TileFilter {
+        where_clause,
+        bindings,
+        params,
+    } = filter;
+    if !where_clause.is_empty() {
+        validate_filter(&where_clause)?;
+    }
+
+    let sql_query = build_tile_query(&layer_conf, tms, z, &where_clause);
+
+    let query = sqlx::query_as(sqlx::AssertSqlSafe(sql_query));
+    let mut query_builder = TileParams::new(&layer_conf, tms, x, y, z).bind(query);
+
+    for name in layer_conf.get_sql_params() {
+        query_builder = query_builder.bind(params.get(&name).cloned());
+    }
 
     if !where_clause.is_empty() {
         for binding in bindings {
@@ -141,10 +815,7 @@ pub async fn query_database(
         }
     }
 
-    let rec = query_builder.fetch_one(&pg_pool).await?;
-    let tile = rec.0.unwrap_or_default();
-
-    Ok(tile.into())
+    fetch_tile(&pg_pool, &layer_conf, query_builder).await
 }
 
 pub async fn get_tile(
@@ -153,19 +824,24 @@ pub async fn get_tile(
     x: u32,
     y: u32,
     z: u32,
-    where_clause: String,
-    bindings: Vec<String>,
+    filter: TileFilter,
     user: Option<String>,
     groups: Option<Vec<String>>,
-) -> AppResult<(Bytes, Via)> {
+) -> AppResult<(EncodedTile, Via)> {
     let name_owned = format!("{}_{}", layer_conf.category.name, layer_conf.name);
     let name = &name_owned;
     let max_cache_age = layer_conf.max_cache_age.unwrap_or(0);
-    let mut local_where_clause = where_clause;
-    let original_local_where_clause_is_empty = local_where_clause.is_empty();
+    let unfiltered = filter.is_empty();
+    let TileFilter {
+        where_clause: mut local_where_clause,
+        bindings,
+        params,
+    } = filter;
 
     let query = layer_conf.clone().filter.unwrap_or_default();
     let cache_wrapper = get_cache_wrapper();
+    let cache_key =
+        TileKey::new(name, z, x, y).with_matrix_set(&layer_conf.get_tile_matrix_set());
 
     record_request();
 
@@ -174,11 +850,16 @@ pub async fn get_tile(
     let category = &layer_conf.category.name;
     let has_plugin = get_plugin_registry().has_plugin(name, category);
 
-    if local_where_clause.is_empty() && !has_plugin
-        && let Some(tile) = cache_wrapper.get_tile(name, z, x, y, max_cache_age).await
+    let cacheable = unfiltered && !has_plugin;
+    if cacheable
+        && let Some((tile, tier)) = cache_wrapper.get_tile(&cache_key, max_cache_age).await
     {
-        record_cache_hit();
-        return Ok((tile, Via::Cache));
+        record_cache_hit(tier.as_str());
+        record_layer_cache_lookup(name, true);
+        return Ok((tile, Via::Cache(tier)));
+    }
+    if cacheable {
+        record_layer_cache_lookup(name, false);
     }
     record_cache_miss();
 
@@ -211,22 +892,517 @@ pub async fn get_tile(
     }
     // --- end Lua plugin ---
 
-    let tile: Bytes = query_database(
-        pg_pool.clone(),
-        layer_conf.clone(),
-        x,
-        y,
-        z,
-        local_where_clause.clone(),
-        bindings,
-    )
-    .await?;
-
-    if original_local_where_clause_is_empty && !has_plugin {
-        cache_wrapper
-            .write_tile(name, z, x, y, &tile, max_cache_age)
+    // Concurrent misses for the same tile and effective filter share a query.
+    let flight_key = format!("{cache_key}|{local_where_clause}|{bindings:?}|{params:?}");
+    let tile = TILE_FLIGHTS
+        .run(&flight_key, || async {
+            let tile: Bytes = query_database(
+                pg_pool.clone(),
+                layer_conf.clone(),
+                x,
+                y,
+                z,
+                TileFilter {
+                    where_clause: local_where_clause,
+                    bindings,
+                    params,
+                },
+            )
             .await?;
-    }
+
+            if cacheable {
+                // Compressed once here; cache hits and this response share the bytes.
+                let tile = cache_wrapper.encode_tile(tile)?;
+                cache_wrapper
+                    .write_encoded_tile(&cache_key, &tile, max_cache_age)
+                    .await?;
+                return Ok(tile);
+            }
+            Ok(EncodedTile::identity(tile))
+        })
+        .await?;
 
     Ok((tile, Via::Database))
 }
+
+/// Reads a tile of a static (pre-rendered file) layer. The bytes are
+/// returned as stored, so they may be gzip-compressed; a missing tile is an
+/// empty body. These layers skip the Redis/disk tile cache: the file already
+/// is a tile store.
+pub async fn get_static_tile(layer_conf: &Layer, x: u32, y: u32, z: u32) -> AppResult<(Bytes, Via)> {
+    record_request();
+    let tile = get_static_tile_registry()
+        .read_tile(layer_conf, z, x, y)
+        .await?
+        .unwrap_or_default();
+    Ok((tile, Via::Static))
+}
+
+/// Tile of one layer inside a multi-layer (composite or category) response.
+/// Compressed tiles are inflated so every part is plain protobuf and the
+/// parts can be concatenated.
+pub async fn get_layer_tile_part(
+    layer_conf: Layer,
+    x: u32,
+    y: u32,
+    z: u32,
+    user: Option<String>,
+    groups: Option<Vec<String>>,
+) -> AppResult<(Bytes, Via)> {
+    if layer_conf.source_kind.is_web_mercator_only()
+        && !layer_tile_matrix_set(&layer_conf)?.is_default()
+    {
+        return Err(AppError::InvalidInput(format!(
+            "layer '{}' is only available in WebMercatorQuad",
+            layer_conf.name
+        )));
+    }
+    if layer_conf.source_kind.is_static() {
+        let (tile, via) = get_static_tile(&layer_conf, x, y, z).await?;
+        return Ok((decompress_tile(tile)?, via));
+    }
+
+    let pg_pool = get_db_registry()
+        .get_pool(&layer_conf.database_id)
+        .cloned()
+        .ok_or_else(|| AppError::DatabaseError("Pool not found".to_string()))?;
+    let (tile, via) =
+        get_tile(pg_pool, layer_conf, x, y, z, TileFilter::default(), user, groups).await?;
+    Ok((tile.into_identity()?, via))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{
+        catalog::GeneralizationRule,
+        category::Category,
+    };
+
+    fn test_layer() -> Layer {
+        Layer {
+            id: "layer-1".to_string(),
+            category: Category {
+                id: "cat-1".to_string(),
+                name: "public".to_string(),
+                description: "".to_string(),
+            },
+            geometry: "lines".to_string(),
+            name: "roads".to_string(),
+            alias: "".to_string(),
+            description: "".to_string(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
+            database_id: "default".to_string(),
+            schema: "public".to_string(),
+            table_name: "".to_string(),
+            fields: vec![],
+            zoom_fields: None,
+            generalization: None,
+            filter: None,
+            srid: None,
+            tile_matrix_set: None,
+            geom: None,
+            id_column: None,
+            sql_mode: None,
+            buffer: None,
+            extent: None,
+            zmin: None,
+            zmax: None,
+            zmax_do_not_simplify: None,
+            buffer_do_not_simplify: None,
+            extent_do_not_simplify: None,
+            clip_geom: None,
+            delete_cache_on_start: None,
+            max_cache_age: None,
+            max_records: None,
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
+            published: true,
+            url: None,
+            groups: None,
+        }
+    }
+
+    #[test]
+    fn tile_envelope_uses_default_grid_for_web_mercator() {
+        assert_eq!(
+            tile_envelope_sql(&TileMatrixSet::web_mercator_quad()),
+            "ST_TileEnvelope($1, $2, $3)"
+        );
+    }
+
+    #[test]
+    fn tile_envelope_passes_bounds_of_other_matrix_sets() {
+        assert_eq!(
+            tile_envelope_sql(&TileMatrixSet::world_crs84_quad()),
+            "ST_TileEnvelope($1, $2, $3, ST_MakeEnvelope(-180, -270, 180, 90, 4326))"
+        );
+    }
+
+    #[test]
+    fn function_sql_quotes_schema_and_function() {
+        assert_eq!(
+            function_sql("tiles", "parcels_tile"),
+            r#"SELECT "tiles"."parcels_tile"($1, $2, $3, $4::json)"#
+        );
+        assert_eq!(
+            function_sql("public", r#"odd"name"#),
+            r#"SELECT "public"."odd""name"($1, $2, $3, $4::json)"#
+        );
+    }
+
+    #[tokio::test]
+    async fn function_layers_reject_plugin_filters() {
+        let pg_pool = PgPool::connect_lazy("postgres://localhost/unused").unwrap();
+        let layer = Layer {
+            source_kind: SourceKind::Function,
+            function_name: Some("roads_tile".to_string()),
+            ..test_layer()
+        };
+        let filter = TileFilter {
+            where_clause: "class = 'primary'".to_string(),
+            ..TileFilter::default()
+        };
+        let result = query_database(pg_pool, layer, 0, 0, 0, filter).await;
+        assert!(matches!(result, Err(AppError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn sql_layer_placeholders_become_parameters() {
+        let sql = expand_sql_query(
+            "SELECT * FROM roads WHERE geom && !bbox! AND !zoom! > 4 AND class = !class! AND area > !min_area!::numeric AND gid != 0",
+            &["class".to_string(), "min_area".to_string()],
+            "ST_TileEnvelope($1, $2, $3)",
+            SQL_PARAMS_START,
+        );
+        assert_eq!(
+            sql,
+            "SELECT * FROM roads WHERE geom && ST_Transform(ST_TileEnvelope($1, $2, $3), $7) AND $9 > 4 AND class = $10::text AND area > $11::text::numeric AND gid != 0"
+        );
+    }
+
+    #[test]
+    fn sql_layer_zoom_is_the_requested_zoom_on_world_crs84_quad() {
+        let layer = Layer {
+            source_kind: SourceKind::Sql,
+            sql_query: Some("SELECT gid, geom FROM roads WHERE !zoom! > 4".to_string()),
+            tile_matrix_set: Some("WorldCRS84Quad".to_string()),
+            fields: vec!["gid".to_string()],
+            ..test_layer()
+        };
+        let tms = layer_tile_matrix_set(&layer).unwrap();
+        let sql = build_tile_query(&layer, tms, 5, "");
+        assert!(sql.contains("FROM (SELECT gid, geom FROM roads WHERE $9 > 4) AS source"));
+        assert!(sql.contains("ST_TileEnvelope($1, $2, $3, "));
+
+        let params = TileParams::new(&layer, tms, 3, 2, 5);
+        assert_eq!(params.zoom, 5);
+        assert_eq!(params.envelope_zoom, 6);
+    }
+
+    #[test]
+    fn tile_query_wraps_the_sql_layer_query() {
+        let mut layer = Layer {
+            source_kind: SourceKind::Sql,
+            sql_query: Some("SELECT gid, geom FROM roads WHERE class = !class!".to_string()),
+            sql_params: Some(vec!["class".to_string()]),
+            fields: vec!["gid".to_string()],
+            ..test_layer()
+        };
+        let tms = layer_tile_matrix_set(&layer).unwrap();
+        let sql = build_tile_query(&layer, tms, 0, "\"gid\" = $11");
+        assert!(sql.contains("FROM (SELECT gid, geom FROM roads WHERE class = $10::text) AS source"));
+        assert!(sql.contains("AND \"gid\" = $11"));
+        assert!(sql.contains("ST_AsMVT(mvtgeom.*, $8, $4, 'geom')"));
+
+        layer.source_kind = SourceKind::Postgis;
+        layer.table_name = "roads".to_string();
+        let sql = build_tile_query(&layer, tms, 0, "");
+        assert!(sql.contains(r#"FROM "public"."roads""#));
+        assert_eq!(tile_query_param_types(&layer).len(), 10);
+    }
+
+    fn generalized_layer(rule: GeneralizationRule) -> Layer {
+        Layer {
+            table_name: "parcels".to_string(),
+            generalization: Some(vec![rule]),
+            ..test_layer()
+        }
+    }
+
+    fn rule(
+        simplify: Option<f64>,
+        min_area: Option<f64>,
+        min_length: Option<f64>,
+    ) -> GeneralizationRule {
+        GeneralizationRule {
+            zmin: 0,
+            zmax: 10,
+            simplify,
+            min_area,
+            min_length,
+        }
+    }
+
+    #[test]
+    fn generalization_simplifies_by_pixels() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        let layer = generalized_layer(rule(Some(2.0), None, None));
+        let cell_size = tms.tile_matrix(1).cell_size;
+        let (tile_geom, filters) = generalization_sql(&layer, &tms, 1);
+        assert_eq!(
+            tile_geom,
+            format!("ST_SimplifyPreserveTopology(ST_Transform(geom, 3857), {})", 2.0 * cell_size)
+        );
+        assert!(filters.is_empty());
+        let sql = build_tile_query(&layer, &tms, 1, "");
+        assert!(sql.contains(&format!("{tile_geom},")));
+    }
+
+    #[test]
+    fn generalization_drops_small_polygons() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        let layer = generalized_layer(rule(None, Some(4.0), None));
+        let cell_size = tms.tile_matrix(3).cell_size;
+        let (tile_geom, filters) = generalization_sql(&layer, &tms, 3);
+        assert_eq!(tile_geom, "ST_Transform(geom, 3857)");
+        let filter = format!(
+            " AND (ST_Dimension(geom) <> 2 OR ST_Area(ST_Transform(geom, 3857)) >= {})",
+            4.0 * cell_size * cell_size
+        );
+        assert_eq!(filters, filter);
+        assert!(build_tile_query(&layer, &tms, 3, "").contains(&filter));
+    }
+
+    #[test]
+    fn generalization_drops_short_lines() {
+        let tms = TileMatrixSet::world_crs84_quad();
+        let layer = generalized_layer(rule(None, None, Some(1.5)));
+        let cell_size = tms.tile_matrix(2).cell_size;
+        let (_, filters) = generalization_sql(&layer, &tms, 2);
+        let filter = format!(
+            " AND (ST_Dimension(geom) <> 1 OR ST_Length(ST_Transform(geom, 4326)) >= {})",
+            1.5 * cell_size
+        );
+        assert_eq!(filters, filter);
+        assert!(build_tile_query(&layer, &tms, 2, "").contains(&filter));
+    }
+
+    #[test]
+    fn generalization_applies_only_to_its_zooms() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        let layer = generalized_layer(rule(Some(1.0), Some(1.0), Some(1.0)));
+        let (tile_geom, filters) = generalization_sql(&layer, &tms, 11);
+        assert_eq!(tile_geom, "ST_Transform(geom, 3857)");
+        assert!(filters.is_empty());
+        assert!(!build_tile_query(&layer, &tms, 11, "").contains("ST_Simplify"));
+    }
+
+    #[test]
+    fn id_column_becomes_the_feature_id() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        let mut layer = Layer {
+            table_name: "roads".to_string(),
+            fields: vec!["name".to_string()],
+            id_column: Some("gid".to_string()),
+            ..test_layer()
+        };
+        for mode in ["CTE", "SQ"] {
+            layer.sql_mode = Some(mode.to_string());
+            let sql = build_tile_query(&layer, &tms, 0, "");
+            assert!(sql.contains("\"gid\", \"name\","), "{mode}");
+            assert!(sql.contains("$8, $4, 'geom', 'gid')"), "{mode}");
+        }
+
+        layer.fields = vec!["gid".to_string(), "name".to_string()];
+        let sql = build_tile_query(&layer, &tms, 0, "");
+        assert!(sql.contains("SELECT\n                    \"gid\", \"name\","));
+
+        layer.id_column = Some(r#"odd"id"#.to_string());
+        layer.max_records = Some(10);
+        let sql = build_tile_query(&layer, &tms, 0, "");
+        assert!(sql.contains(r#""odd""id", "gid", "name","#));
+        assert!(sql.contains(r#"ORDER BY "odd""id" LIMIT 10"#));
+
+        layer.id_column = None;
+        let sql = build_tile_query(&layer, &tms, 0, "");
+        assert!(sql.contains("$8, $4, 'geom')"));
+    }
+
+    #[test]
+    fn max_records_keep_features_by_priority() {
+        let mut layer = Layer {
+            max_records: Some(50),
+            ..test_layer()
+        };
+        assert_eq!(limit_sql(&layer), "ORDER BY ctid LIMIT 50");
+        assert!(limit_uses_ctid(&layer));
+        layer.source_kind = SourceKind::Sql;
+        assert_eq!(limit_sql(&layer), "ORDER BY ST_AsEWKB(geom) LIMIT 50");
+        assert!(!limit_uses_ctid(&layer));
+        layer.source_kind = SourceKind::Postgis;
+
+        layer.id_column = Some("gid".to_string());
+        layer.priority = Some("population".to_string());
+        assert_eq!(
+            limit_sql(&layer),
+            "ORDER BY (population) DESC NULLS LAST, \"gid\" LIMIT 50"
+        );
+
+        layer.priority = Some("ST_Area(geom)".to_string());
+        layer.priority_order = Some("asc".to_string());
+        assert_eq!(
+            limit_sql(&layer),
+            "ORDER BY (ST_Area(geom)) ASC NULLS LAST, \"gid\" LIMIT 50"
+        );
+        let tms = TileMatrixSet::web_mercator_quad();
+        assert!(build_tile_query(&layer, &tms, 0, "").contains(&limit_sql(&layer)));
+
+        layer.priority_order = Some("random".to_string());
+        assert_eq!(limit_sql(&layer), "ORDER BY RANDOM() LIMIT 50");
+        layer.id_column = None;
+        assert!(!limit_uses_ctid(&layer));
+
+        layer.max_records = Some(0);
+        assert_eq!(limit_sql(&layer), "");
+    }
+
+    #[test]
+    fn low_zooms_of_clustered_layers_serve_cluster_points() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        let layer = Layer {
+            geometry: "points".to_string(),
+            table_name: "incidents".to_string(),
+            fields: vec!["victims".to_string(), "severity".to_string()],
+            cluster_zoom: Some(10),
+            cluster_radius: Some(32),
+            cluster_aggregates: Some(vec![
+                FieldAggregate {
+                    function: "sum".to_string(),
+                    field: "victims".to_string(),
+                },
+                FieldAggregate {
+                    function: "max".to_string(),
+                    field: "severity".to_string(),
+                },
+            ]),
+            max_records: Some(10),
+            ..test_layer()
+        };
+        let cell = 32.0 * tms.tile_matrix(4).cell_size;
+        let sql = build_tile_query(&layer, &tms, 4, "\"severity\" > $10");
+        assert!(sql.contains("count(*) AS point_count,"));
+        assert!(sql.contains(r#"sum("victims")::float8 AS "sum_victims","#));
+        assert!(sql.contains(r#"max("severity") AS "max_severity","#));
+        assert!(sql.contains("ST_Centroid(ST_Collect(ST_Transform(geom, 3857))) AS geom"));
+        assert!(sql.contains(&format!(
+            "GROUP BY ST_SnapToGrid(ST_Transform(geom, 3857), {}, {}, {cell}, {cell})",
+            cell / 2.0,
+            cell / 2.0
+        )));
+        // Cells on a tile edge are read whole and kept by the tile holding
+        // their centre.
+        assert!(sql.contains(&format!(
+            "geom && ST_Transform(ST_Expand(ST_TileEnvelope($1, $2, $3), {}), $7)",
+            cell / 2.0
+        )));
+        assert!(sql.contains("WHERE ST_X(centre) >= ST_XMin(ST_TileEnvelope($1, $2, $3)) AND ST_X(centre) < ST_XMax("));
+        assert!(sql.contains(r#"SELECT point_count, "sum_victims", "max_severity", ST_AsMVTGeom("#));
+        assert!(sql.contains(r#"FROM "public"."incidents""#));
+        assert!(sql.contains("AND \"severity\" > $10"));
+        assert!(!sql.contains("LIMIT"));
+
+        let sql = build_tile_query(&layer, &tms, 10, "");
+        assert!(!sql.contains("point_count"));
+        assert!(sql.contains("LIMIT 10"));
+
+        let layer = Layer {
+            fields: vec![r#"odd"field"#.to_string()],
+            cluster_aggregates: Some(vec![FieldAggregate {
+                function: "min".to_string(),
+                field: r#"odd"field"#.to_string(),
+            }]),
+            ..layer
+        };
+        let sql = build_tile_query(&layer, &tms, 4, "");
+        assert!(sql.contains(r#"min("odd""field") AS "min_odd""field","#));
+        assert!(sql.contains(r#"SELECT point_count, "min_odd""field", ST_AsMVTGeom("#));
+    }
+
+    #[test]
+    fn binned_layers_count_features_in_grid_cells() {
+        let tms = TileMatrixSet::web_mercator_quad();
+        let mut layer = Layer {
+            table_name: "incidents".to_string(),
+            fields: vec!["victims".to_string()],
+            bin_shape: Some("hexagon".to_string()),
+            bin_size: Some(16),
+            bin_aggregates: Some(vec![
+                FieldAggregate {
+                    function: "avg".to_string(),
+                    field: "victims".to_string(),
+                },
+                FieldAggregate {
+                    function: "max".to_string(),
+                    field: "victims".to_string(),
+                },
+            ]),
+            ..test_layer()
+        };
+        let size = 16.0 * tms.tile_matrix(6).cell_size;
+        let sql = build_tile_query(&layer, &tms, 6, "\"victims\" > 0");
+        assert!(sql.contains(
+            r#"SELECT ST_Transform(ST_PointOnSurface(geom), 3857) AS point, "victims""#
+        ));
+        assert!(sql.contains(&format!(
+            "WHERE geom && ST_Transform(ST_Expand(ST_TileEnvelope($1, $2, $3), {}), $7)",
+            2.0 * size
+        )));
+        assert!(sql.contains("AND \"victims\" > 0"));
+        assert!(sql.contains(r#"avg(features."victims")::float8 AS "avg_victims","#));
+        assert!(sql.contains(r#"max(features."victims") AS "max_victims","#));
+        assert!(sql.contains(&format!(
+            "FROM ST_HexagonGrid({size}, ST_TileEnvelope($1, $2, $3)) AS bin"
+        )));
+        assert!(sql.contains(r#"SELECT count, "avg_victims", "max_victims", ST_AsMVTGeom("#));
+
+        layer.bin_shape = Some("square".to_string());
+        layer.bin_aggregates = None;
+        let sql = build_tile_query(&layer, &tms, 6, "");
+        assert!(sql.contains(&format!(
+            "FROM ST_SquareGrid({size}, ST_TileEnvelope($1, $2, $3)) AS bin"
+        )));
+        assert!(sql.contains("SELECT count, ST_AsMVTGeom("));
+
+        layer.fields = vec![r#"odd"field"#.to_string()];
+        layer.bin_aggregates = Some(vec![FieldAggregate {
+            function: "sum".to_string(),
+            field: r#"odd"field"#.to_string(),
+        }]);
+        let sql = build_tile_query(&layer, &tms, 6, "");
+        assert!(sql.contains(r#"AS point, "odd""field""#));
+        assert!(sql.contains(r#"sum(features."odd""field")::float8 AS "sum_odd""field","#));
+    }
+
+    #[test]
+    fn sql_templates_take_tile_grid_from_placeholders() {
+        for mode in ["CTE", "SQ"] {
+            let sql = build_sql_template(mode);
+            assert!(!sql.contains("3857"));
+            assert!(sql.contains("{tile_geom}"));
+            assert!(sql.contains("ST_Transform({tile_envelope}, $7)"));
+        }
+    }
+}
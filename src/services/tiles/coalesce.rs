@@ -0,0 +1,218 @@
+//! Single-flight deduplication of tile queries. Concurrent requests for the
+//! same tile and effective filter share one database query: the first runs
+//! it and the others await its result.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+};
+use tokio::sync::OnceCell;
+
+use crate::{
+    cache::encoding::EncodedTile,
+    error::{AppError, AppResult},
+    monitor::metrics::{record_coalesced, set_tile_queries_in_flight},
+};
+
+/// In-flight tile queries of the server.
+pub static TILE_FLIGHTS: LazyLock<SingleFlight<EncodedTile>> = LazyLock::new(SingleFlight::new);
+
+type Flight<T> = Arc<OnceCell<Result<T, Arc<AppError>>>>;
+
+pub struct SingleFlight<T> {
+    flights: Mutex<HashMap<String, Flight<T>>>,
+}
+
+impl<T: Clone> SingleFlight<T> {
+    pub fn new() -> Self {
+        Self {
+            flights: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `work` unless a call with the same key is in flight, in which
+    /// case that call's result is awaited instead. If the running call is
+    /// dropped (its client went away), one of the waiters takes over.
+    pub async fn run<F, Fut>(&self, key: &str, work: F) -> AppResult<T>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = AppResult<T>>,
+    {
+        let guard = {
+            let mut flights = self.flights.lock().unwrap_or_else(PoisonError::into_inner);
+            let flight = flights.entry(key.to_string()).or_default().clone();
+            set_tile_queries_in_flight(flights.len());
+            FlightGuard {
+                flights: &self.flights,
+                key,
+                flight: Some(flight),
+            }
+        };
+
+        let mut led = false;
+        let result = guard
+            .flight()
+            .get_or_init(|| {
+                led = true;
+                async { work().await.map_err(Arc::new) }
+            })
+            .await
+            .clone();
+        drop(guard);
+
+        if !led {
+            record_coalesced();
+        }
+        result.map_err(|e| shared_error(&e))
+    }
+
+    /// Number of distinct calls in flight.
+    #[cfg(test)]
+    fn in_flight(&self) -> usize {
+        self.flights.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+/// A caller's hold on a flight. The first caller to leave a finished
+/// flight, or the last to leave an unfinished one, removes it from the map,
+/// so flights whose callers all went away do not linger until the next
+/// request for the same tile.
+struct FlightGuard<'a, T> {
+    flights: &'a Mutex<HashMap<String, Flight<T>>>,
+    key: &'a str,
+    flight: Option<Flight<T>>,
+}
+
+impl<T> FlightGuard<'_, T> {
+    fn flight(&self) -> &Flight<T> {
+        self.flight.as_ref().expect("flight is held until drop")
+    }
+}
+
+impl<T> Drop for FlightGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut flights = self.flights.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(flight) = self.flight.take() else {
+            return;
+        };
+        // References are only taken and released under the lock, so a count
+        // of two means the map and this guard are the last holders.
+        let current = flights.get(self.key).is_some_and(|f| Arc::ptr_eq(f, &flight));
+        if current && (flight.initialized() || Arc::strong_count(&flight) == 2) {
+            flights.remove(self.key);
+        }
+        drop(flight);
+        set_tile_queries_in_flight(flights.len());
+    }
+}
+
+impl<T: Clone> Default for SingleFlight<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Error handed to every caller of a failed flight, keeping the kinds that
+/// decide the response status.
+fn shared_error(error: &AppError) -> AppError {
+    match error {
+        AppError::QueryTimeout(message) => AppError::QueryTimeout(message.clone()),
+        AppError::InvalidInput(message) => AppError::InvalidInput(message.clone()),
+        AppError::SqlInjectionError(message) => AppError::SqlInjectionError(message.clone()),
+        other => AppError::DatabaseError(other.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn concurrent_calls_share_one_run() {
+        let flights = SingleFlight::<u32>::new();
+        let runs = AtomicUsize::new(0);
+        let work = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(7)
+        };
+
+        let (a, b, c) = tokio::join!(
+            flights.run("roads:3:1:2", work),
+            flights.run("roads:3:1:2", work),
+            flights.run("roads:3:1:2", work),
+        );
+        assert_eq!((a.unwrap(), b.unwrap(), c.unwrap()), (7, 7, 7));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(flights.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn different_keys_and_later_calls_run_again() {
+        let flights = SingleFlight::<u32>::new();
+        let runs = AtomicUsize::new(0);
+        let work = || async {
+            runs.fetch_add(1, Ordering::SeqCst);
+            Ok(1)
+        };
+
+        let (a, b) = tokio::join!(
+            flights.run("roads:3:1:2", work),
+            flights.run("roads:3:1:3", work)
+        );
+        assert!(a.is_ok() && b.is_ok());
+        flights.run("roads:3:1:2", work).await.unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn waiters_share_the_error_kind() {
+        let flights = SingleFlight::<u32>::new();
+        let work = || async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Err(AppError::QueryTimeout("slow".to_string()))
+        };
+
+        let (a, b) = tokio::join!(flights.run("k", work), flights.run("k", work));
+        assert!(matches!(a, Err(AppError::QueryTimeout(_))));
+        assert!(matches!(b, Err(AppError::QueryTimeout(_))));
+    }
+
+    #[tokio::test]
+    async fn abandoned_calls_leave_no_flight_behind() {
+        let flights = SingleFlight::<u32>::new();
+        let slow = || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(1)
+        };
+        let _ = tokio::time::timeout(Duration::from_millis(10), flights.run("k", slow)).await;
+        assert_eq!(flights.in_flight(), 0);
+
+        // Abandoned together, the leader and its waiter both let go.
+        let both = async { tokio::join!(flights.run("k", slow), flights.run("k", slow)) };
+        let _ = tokio::time::timeout(Duration::from_millis(10), both).await;
+        assert_eq!(flights.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_waiter_takes_over_when_the_running_call_is_dropped() {
+        let flights = SingleFlight::<u32>::new();
+        let mut first = Box::pin(flights.run("k", || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(1)
+        }));
+        let mut second = Box::pin(flights.run("k", || async { Ok(2) }));
+        // Both polled once, so the first holds the flight and the second
+        // waits on it; then the first is abandoned.
+        let _ = tokio::time::timeout(Duration::from_millis(10), &mut first).await;
+        let _ = tokio::time::timeout(Duration::from_millis(10), &mut second).await;
+        drop(first);
+        assert_eq!(flights.in_flight(), 1);
+
+        assert_eq!(second.await.unwrap(), 2);
+        assert_eq!(flights.in_flight(), 0);
+    }
+}
@@ -1,32 +1,55 @@
 use bytes::Bytes;
-use salvo::http::{StatusCode, header::HeaderValue};
+use salvo::http::{
+    StatusCode,
+    header::{ACCEPT_ENCODING, CONTENT_ENCODING, HeaderValue},
+};
 use salvo::prelude::*;
 use std::collections::HashMap;
 use std::time::Instant;
 use tracing::warn;
 
-use super::builder::{Via, get_tile};
+use super::builder::{
+    SQL_PARAMS_START, TileFilter, Via, get_layer_tile_part, get_static_tile, get_tile, layer_tile_matrix_set,
+};
+use super::scheme::TileScheme;
 use crate::services::utils::{get_request_user, validate_user_groups};
 use crate::{
+    cache::{cachewrapper::CacheTier, encoding::EncodedTile, key::TileKey},
     error::{AppError, AppResult},
     filters,
     get_cache_wrapper,
     get_catalog,
     get_db_registry,
     get_plugin_registry,
-    models::catalog::StateLayer,
+    models::{
+        catalog::{Layer, SourceKind, StateLayer},
+        tile_matrix_set::{TileMatrixSet, default_tile_matrix_set, find_tile_matrix_set},
+    },
     monitor::record_latency,
 };
 
 /// FNV-1a 64-bit hash of an arbitrary string. Used to produce ETags from
 /// structured inputs (layer_name:z:x:y:version) without touching tile bytes.
+/// The ETag is weak: the same tile is sent gzip-encoded or as identity
+/// depending on Accept-Encoding, and those bodies are not byte-identical.
 fn compute_etag(input: &str) -> String {
     let mut hash: u64 = 14695981039346656037;
     for byte in input.as_bytes() {
         hash ^= *byte as u64;
         hash = hash.wrapping_mul(1099511628211);
     }
-    format!("\"{hash:x}\"")
+    format!("W/\"{hash:x}\"")
+}
+
+/// `?tileMatrixSet=` of a request. Unknown ids are rejected rather than
+/// silently served on another grid.
+fn requested_matrix_set(req: &Request) -> AppResult<Option<&'static TileMatrixSet>> {
+    match req.query::<String>("tileMatrixSet") {
+        None => Ok(None),
+        Some(id) => find_tile_matrix_set(&id)
+            .map(Some)
+            .ok_or_else(|| AppError::InvalidInput(format!("unknown tile matrix set '{id}'"))),
+    }
 }
 
 /// Cache-Control value based on the layer's max_cache_age.
@@ -58,68 +81,144 @@ fn set_cache_headers(res: &mut Response, etag: &str, max_cache_age: u64) {
     }
 }
 
-#[handler]
-pub async fn get_single_layer_tile(
-    req: &mut Request,
+/// Sends the tile in its stored encoding when the client's Accept-Encoding
+/// allows it, and as plain protobuf otherwise. `Vary: Accept-Encoding` is
+/// added at the Service level, outside the response cache.
+fn render_tile(req: &Request, res: &mut Response, tile: EncodedTile) -> AppResult<()> {
+    let accept_encoding = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok());
+    let tile = tile.negotiate(accept_encoding)?;
+
+    if let Some(coding) = tile.encoding.content_encoding() {
+        res.headers_mut()
+            .insert(CONTENT_ENCODING, HeaderValue::from_static(coding));
+    }
+    res.body(salvo::http::ResBody::Once(tile.data));
+    Ok(())
+}
+
+/// Serves a tile of a static (pre-rendered file) layer. Uses the same
+/// version-based ETag as PostGIS layers; filters and plugins do not apply.
+/// gzip-compressed tiles are passed through to clients accepting gzip
+/// instead of being inflated, and a missing tile is a 204.
+async fn serve_static_tile(
+    req: &Request,
     res: &mut Response,
-    depot: &mut Depot,
+    layer: &Layer,
+    x: u32,
+    y: u32,
+    z: u32,
 ) -> AppResult<()> {
-    res.headers_mut().insert(
-        "content-type",
-        "application/x-protobuf;type=mapbox-vector".parse()?,
-    );
-
-    let layer_name = req.param::<String>("layer_name").unwrap_or_default();
-    let (category, name) = layer_name.split_once(':').unwrap_or(("", ""));
-    let x = req.param::<u32>("x").unwrap_or(0);
-    let y = req.param::<u32>("y").unwrap_or(0);
-    let z = req.param::<u32>("z").unwrap_or(0);
+    let max_cache_age = layer.max_cache_age.unwrap_or(0);
+    let layer_key = format!("{}_{}", layer.category.name, layer.name);
+    let version = get_cache_wrapper().get_layer_version(&layer_key).await;
+    let etag = compute_etag(&format!("{layer_key}:{z}:{x}:{y}:{version}"));
 
-    let known_params = ["layer_name", "x", "y", "z"];
-    let mut filter_params: HashMap<String, String> = HashMap::new();
-    for (key, values) in req.queries() {
-        if !known_params.contains(&key.as_str())
-            && let Some(value) = values.first()
-        {
-            filter_params.insert(key.to_string(), value.to_string());
-        }
+    if is_not_modified(req, &etag) {
+        set_cache_headers(res, &etag, max_cache_age);
+        res.status_code(StatusCode::NOT_MODIFIED);
+        return Ok(());
     }
-    let has_filters = !filter_params.is_empty();
 
-    let filters = filters::parse_query_params(&filter_params);
-    let mut builder = filters::SqlQueryBuilder::new(9);
-    let (where_clause, bindings) = builder.build(&filters);
-
-    let layer = {
-        let catalog = get_catalog().await.read().await;
-        catalog
-            .find_layer_by_category_and_name(category, name, StateLayer::Published)
-            .cloned()
+    let start_time = Instant::now();
+    let (tile, _) = match get_static_tile(layer, x, y, z).await {
+        Ok(result) => result,
+        Err(e) => {
+            warn!(layer = %layer_key, error = %e, "Static tile read failed");
+            res.status_code(StatusCode::NOT_FOUND);
+            res.render(Json(serde_json::json!({
+                "error": "Static tile source unavailable",
+                "message": e.to_string()
+            })));
+            return Ok(());
+        }
     };
+    let elapsed_time = start_time.elapsed();
+    record_latency(elapsed_time.as_secs_f64());
 
-    let Some(layer) = layer else {
-        warn!(category = %category, name = %name, "Layer not found");
-        res.status_code(StatusCode::NOT_FOUND);
-        res.body(salvo::http::ResBody::Once(Bytes::new()));
+    res.headers_mut().insert(
+        "X-Data-Source-Time",
+        HeaderValue::from_str(&elapsed_time.as_millis().to_string())
+            .unwrap_or_else(|_| HeaderValue::from_static("0")),
+    );
+    res.headers_mut().insert("X-Cache", HeaderValue::from_static("STATIC"));
+    set_cache_headers(res, &etag, max_cache_age);
+
+    if tile.is_empty() {
+        res.status_code(StatusCode::NO_CONTENT);
         return Ok(());
-    };
+    }
+    render_tile(req, res, EncodedTile::sniff(tile))
+}
 
-    let pg_pool = get_db_registry()
-        .get_pool(&layer.database_id)
-        .cloned()
-        .ok_or_else(|| {
-            warn!(db = %layer.database_id, "Database pool not found");
-            AppError::DatabaseError("Pool not found".to_string())
-        })?;
+/// One layer tile, with `y` already converted to the canonical XYZ row of
+/// `tms`.
+pub struct LayerTileRequest {
+    pub layer: Layer,
+    pub tms: &'static TileMatrixSet,
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+    /// Query filters; a non-empty filter makes the tile dynamic (no ETag,
+    /// no server cache).
+    pub filter: TileFilter,
+}
 
-    let (req_user, req_groups) = get_request_user(req, depot).await;
+/// XYZ row of a requested tile row. Zooms past the grid are rejected before
+/// its height at `z` is computed.
+fn requested_xyz_y(
+    req: &Request,
+    depot: &Depot,
+    tms: &TileMatrixSet,
+    z: u32,
+    requested_y: u32,
+) -> AppResult<u32> {
+    if !tms.contains(z, 0, 0) {
+        return Err(AppError::InvalidInput(format!(
+            "zoom {z} is outside the {} tile matrix set",
+            tms.id
+        )));
+    }
+    Ok(TileScheme::from_request(req, depot).xyz_y(tms.matrix_height_at(z), requested_y))
+}
 
-    if !validate_user_groups(req, &layer, depot).await? {
-        warn!(category = %category, name = %name, "User not authorized for layer");
-        res.status_code(StatusCode::FORBIDDEN);
-        res.body(salvo::http::ResBody::Once(Bytes::new()));
+/// Rejects tiles outside the grid before anything is rendered or cached.
+fn check_tile_in_grid(tms: &TileMatrixSet, z: u32, x: u32, y: u32) -> AppResult<()> {
+    if tms.contains(z, x, y) {
         return Ok(());
-    };
+    }
+    Err(AppError::InvalidInput(format!(
+        "tile {z}/{x}/{y} is outside the {} tile matrix set",
+        tms.id
+    )))
+}
+
+/// Serves a tile of one found and authorized layer. Shared by the XYZ
+/// routes and the OGC API - Tiles endpoints.
+pub async fn serve_layer_tile(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+    tile_req: LayerTileRequest,
+) -> AppResult<()> {
+    res.headers_mut().insert(
+        "content-type",
+        "application/x-protobuf;type=mapbox-vector".parse()?,
+    );
+
+    let LayerTileRequest {
+        mut layer,
+        tms,
+        z,
+        x,
+        y,
+        filter,
+    } = tile_req;
+    let has_filters = !filter.is_empty();
+    let category = layer.category.name.clone();
+    let name = layer.name.clone();
 
     let zmin = layer.zmin.unwrap_or(0);
     let zmax = layer.zmax.unwrap_or(22);
@@ -136,16 +235,38 @@ pub async fn get_single_layer_tile(
         res.body(salvo::http::ResBody::Once(Bytes::new()));
         return Ok(());
     }
+    check_tile_in_grid(tms, z, x, y)?;
+
+    if layer.source_kind.is_web_mercator_only() && !tms.is_default() {
+        return Err(AppError::InvalidInput(format!(
+            "layer '{name}' is only available in WebMercatorQuad"
+        )));
+    }
+    if layer.source_kind.is_static() {
+        return serve_static_tile(req, res, &layer, x, y, z).await;
+    }
+    layer.tile_matrix_set = Some(tms.id.clone());
+
+    let (req_user, req_groups) = get_request_user(req, depot).await;
+
+    let pg_pool = get_db_registry()
+        .get_pool(&layer.database_id)
+        .cloned()
+        .ok_or_else(|| {
+            warn!(db = %layer.database_id, "Database pool not found");
+            AppError::DatabaseError("Pool not found".to_string())
+        })?;
 
     let max_cache_age = layer.max_cache_age.unwrap_or(0);
     let layer_key = format!("{}_{}", layer.category.name, layer.name);
-    let has_plugin = get_plugin_registry().has_plugin(&layer_key, category);
+    let has_plugin = get_plugin_registry().has_plugin(&layer_key, &category);
 
     // Filtered or plugin-driven requests are dynamic: skip ETags and client cache.
     // Plugin layers can change per request (time, user) independently of tile version.
     if !has_filters && !has_plugin {
-        let version = get_cache_wrapper().get_layer_version(&layer_key).await;
-        let etag = compute_etag(&format!("{layer_key}:{z}:{x}:{y}:{version}"));
+        let tile_key = TileKey::new(&layer_key, z, x, y).with_matrix_set(&tms.id);
+        let version = get_cache_wrapper().get_tile_version(&tile_key).await;
+        let etag = compute_etag(&format!("{tile_key}:{version}"));
 
         // Early exit: browser already has the current version.
         // No DB query, no cache read.
@@ -158,8 +279,9 @@ pub async fn get_single_layer_tile(
         let start_time = Instant::now();
 
         let (tile, via) =
-            match get_tile(pg_pool, layer.clone(), x, y, z, where_clause, bindings, req_user.clone(), req_groups.clone()).await {
+            match get_tile(pg_pool, layer.clone(), x, y, z, filter, req_user.clone(), req_groups.clone()).await {
                 Ok(result) => result,
+                Err(e @ AppError::QueryTimeout(_)) => return Err(e),
                 Err(e) => {
                     res.status_code(StatusCode::BAD_REQUEST);
                     res.render(Json(serde_json::json!({
@@ -182,19 +304,21 @@ pub async fn get_single_layer_tile(
             "X-Cache",
             match via {
                 Via::Database => HeaderValue::from_static("MISS"),
-                Via::Cache => HeaderValue::from_static("HIT"),
+                Via::Cache(tier) => HeaderValue::from_static(tier.header()),
+                Via::Static => HeaderValue::from_static("STATIC"),
             },
         );
 
         set_cache_headers(res, &etag, max_cache_age);
-        res.body(salvo::http::ResBody::Once(tile));
+        render_tile(req, res, tile)?;
     } else {
         // Filtered or plugin-driven request: always hits the DB, no server cache, no ETag.
         let start_time = Instant::now();
 
         let (tile, _) =
-            match get_tile(pg_pool, layer.clone(), x, y, z, where_clause, bindings, req_user, req_groups).await {
+            match get_tile(pg_pool, layer.clone(), x, y, z, filter, req_user, req_groups).await {
                 Ok(result) => result,
+                Err(e @ AppError::QueryTimeout(_)) => return Err(e),
                 Err(e) => {
                     res.status_code(StatusCode::BAD_REQUEST);
                     res.render(Json(serde_json::json!({
@@ -221,55 +345,124 @@ pub async fn get_single_layer_tile(
             }
         }
 
-        res.body(salvo::http::ResBody::Once(tile));
+        render_tile(req, res, tile)?;
     }
 
     Ok(())
 }
 
 #[handler]
-pub async fn get_composite_layers_tile(
+pub async fn get_single_layer_tile(
     req: &mut Request,
     res: &mut Response,
     depot: &mut Depot,
 ) -> AppResult<()> {
-    res.headers_mut().insert(
-        "content-type",
-        "application/x-protobuf;type=mapbox-vector".parse()?,
-    );
-
-    let layers = req.param::<String>("layers").unwrap_or_default();
+    let layer_name = req.param::<String>("layer_name").unwrap_or_default();
+    let (category, name) = layer_name.split_once(':').unwrap_or(("", ""));
     let x = req.param::<u32>("x").unwrap_or(0);
-    let y = req.param::<u32>("y").unwrap_or(0);
+    let requested_y = req.param::<u32>("y").unwrap_or(0);
     let z = req.param::<u32>("z").unwrap_or(0);
+    let requested_tms = requested_matrix_set(req)?;
 
-    let layers_vec: Vec<String> = layers
-        .split(',')
-        .map(|s| s.trim().to_string())
-        .filter(|s| !s.is_empty())
-        .collect();
-
-    let (req_user, req_groups) = get_request_user(req, depot).await;
+    let known_params = ["layer_name", "x", "y", "z", "scheme", "tileMatrixSet"];
+    let mut filter_params: HashMap<String, String> = HashMap::new();
+    for (key, values) in req.queries() {
+        if !known_params.contains(&key.as_str())
+            && let Some(value) = values.first()
+        {
+            filter_params.insert(key.to_string(), value.to_string());
+        }
+    }
 
-    let candidates: Vec<_> = {
+    let layer = {
         let catalog = get_catalog().await.read().await;
-        layers_vec
-            .iter()
-            .filter_map(|layer_name| {
-                let (category, name) = layer_name.split_once(':').unwrap_or(("", ""));
-                catalog
-                    .find_layer_by_category_and_name(category, name, StateLayer::Published)
-                    .cloned()
-            })
-            .collect()
+        catalog
+            .find_layer_by_category_and_name(category, name, StateLayer::Published)
+            .cloned()
+    };
+
+    let Some(layer) = layer else {
+        warn!(category = %category, name = %name, "Layer not found");
+        res.status_code(StatusCode::NOT_FOUND);
+        res.body(salvo::http::ResBody::Once(Bytes::new()));
+        return Ok(());
     };
 
+    if !validate_user_groups(req, &layer, depot).await? {
+        warn!(category = %category, name = %name, "User not authorized for layer");
+        res.status_code(StatusCode::FORBIDDEN);
+        res.body(salvo::http::ResBody::Once(Bytes::new()));
+        return Ok(());
+    };
+
+    let filter = if layer.source_kind == SourceKind::Function {
+        TileFilter {
+            params: filter_params.into_iter().collect(),
+            ..TileFilter::default()
+        }
+    } else {
+        // Declared parameters of SQL layers are bound before the filters.
+        let declared = layer.get_sql_params();
+        let (params, filter_params): (HashMap<_, _>, HashMap<_, _>) = filter_params
+            .into_iter()
+            .partition(|(key, _)| declared.contains(key));
+        let filters = filters::parse_query_params(&filter_params);
+        let mut builder = filters::SqlQueryBuilder::new(SQL_PARAMS_START + declared.len());
+        let (where_clause, bindings) = builder.build(&filters);
+        TileFilter {
+            where_clause,
+            bindings,
+            params: params.into_iter().collect(),
+        }
+    };
+
+    let tms = match requested_tms {
+        Some(tms) => tms,
+        None => layer_tile_matrix_set(&layer)?,
+    };
+    // Cache keys, ETags and sources always use XYZ rows.
+    let y = requested_xyz_y(req, depot, tms, z, requested_y)?;
+
+    let tile_req = LayerTileRequest {
+        layer,
+        tms,
+        z,
+        x,
+        y,
+        filter,
+    };
+    serve_layer_tile(req, res, depot, tile_req).await
+}
+
+/// Serves the concatenated tiles of several layers on one grid. Layers the
+/// user may not see, or without data at `z`, are left out. Shared by the
+/// multi-layer and category routes and the OGC API - Tiles endpoints.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_combined_tile(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+    candidates: Vec<Layer>,
+    tms: &'static TileMatrixSet,
+    z: u32,
+    x: u32,
+    y: u32,
+) -> AppResult<()> {
+    res.headers_mut().insert(
+        "content-type",
+        "application/x-protobuf;type=mapbox-vector".parse()?,
+    );
+    check_tile_in_grid(tms, z, x, y)?;
+
+    let (req_user, req_groups) = get_request_user(req, depot).await;
+
     let mut layer_configs = Vec::new();
-    for layer in candidates {
+    for mut layer in candidates {
         if validate_user_groups(req, &layer, depot).await? {
             let zmin = layer.zmin.unwrap_or(0);
             let zmax = layer.zmax.unwrap_or(22);
             if z >= zmin && z <= zmax {
+                layer.tile_matrix_set = Some(tms.id.clone());
                 layer_configs.push(layer);
             }
         }
@@ -288,13 +481,17 @@ pub async fn get_composite_layers_tile(
         get_plugin_registry().has_plugin(&key, &l.category.name)
     });
 
-    // Build version-based ETag from all layer versions combined.
+    // Build version-based ETag from all the layers' tile versions combined.
     // Skipped when any layer has a plugin (dynamic content).
     let cache_wrapper = get_cache_wrapper();
     let mut etag_input = format!("{z}:{x}:{y}");
+    if !tms.is_default() {
+        etag_input = format!("{}:{etag_input}", tms.id);
+    }
     for layer in &layer_configs {
         let key = format!("{}_{}", layer.category.name, layer.name);
-        let version = cache_wrapper.get_layer_version(&key).await;
+        let tile_key = TileKey::new(&key, z, x, y).with_matrix_set(&tms.id);
+        let version = cache_wrapper.get_tile_version(&tile_key).await;
         etag_input.push(':');
         etag_input.push_str(&key);
         etag_input.push(':');
@@ -310,11 +507,7 @@ pub async fn get_composite_layers_tile(
 
     let mut futures = Vec::new();
     for layer in layer_configs {
-        let pg_pool = match get_db_registry().get_pool(&layer.database_id) {
-            Some(pool) => pool.clone(),
-            None => continue,
-        };
-        futures.push(get_tile(pg_pool, layer, x, y, z, String::new(), Vec::new(), req_user.clone(), req_groups.clone()));
+        futures.push(get_layer_tile_part(layer, x, y, z, req_user.clone(), req_groups.clone()));
     }
 
     let results = futures::future::join_all(futures).await;
@@ -322,53 +515,118 @@ pub async fn get_composite_layers_tile(
     let mut output_data = Vec::new();
     let mut cache_hits = 0;
     let mut cache_misses = 0;
+    let mut memory_hits = 0;
+    let mut any_failed = false;
 
     for result in results {
-        if let Ok((tile, via)) = result {
-            match via {
-                Via::Database => cache_misses += 1,
-                Via::Cache => cache_hits += 1,
+        let (tile, via) = match result {
+            Ok(part) => part,
+            Err(e @ AppError::QueryTimeout(_)) => return Err(e),
+            Err(e) => {
+                warn!(error = %e, "Layer left out of combined tile");
+                any_failed = true;
+                continue;
+            }
+        };
+        match via {
+            Via::Database => cache_misses += 1,
+            Via::Cache(tier) => {
+                cache_hits += 1;
+                if tier == CacheTier::Memory {
+                    memory_hits += 1;
+                }
             }
-            output_data.push(tile);
+            Via::Static => {}
         }
+        output_data.push(tile);
     }
 
     let final_output = Bytes::from(output_data.concat());
 
     res.headers_mut().insert(
         "X-Cache",
-        HeaderValue::from_str(&format!("HIT: {cache_hits}, MISS: {cache_misses}"))
+        HeaderValue::from_str(&format!(
+            "HIT: {cache_hits} (MEMORY: {memory_hits}), MISS: {cache_misses}"
+        ))
             .unwrap_or_else(|_| HeaderValue::from_static("UNKNOWN")),
     );
 
-    if any_has_plugin {
+    // A tile missing some of its parts must not be cached under the full ETag.
+    if any_has_plugin || any_failed {
         if let Ok(v) = HeaderValue::from_str("no-store, no-cache") {
             res.headers_mut().insert("Cache-Control", v);
         }
     } else {
         set_cache_headers(res, &etag, min_cache_age);
     }
-    res.body(salvo::http::ResBody::Once(final_output));
-    Ok(())
+    // The parts are concatenated raw and the whole tile compressed once.
+    let accept_encoding = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok());
+    let tile = EncodedTile::encode_for(
+        final_output,
+        get_cache_wrapper().compression(),
+        accept_encoding,
+    )?;
+    render_tile(req, res, tile)
+}
+
+/// Grid and canonical tile address of a multi-layer request. All parts of
+/// a combined tile share one grid: the requested one or WebMercatorQuad,
+/// whatever the layers' own defaults are.
+fn combined_tile_address(
+    req: &Request,
+    depot: &Depot,
+) -> AppResult<(&'static TileMatrixSet, u32, u32, u32)> {
+    let x = req.param::<u32>("x").unwrap_or(0);
+    let z = req.param::<u32>("z").unwrap_or(0);
+    let tms = requested_matrix_set(req)?.unwrap_or_else(default_tile_matrix_set);
+    // Cache keys, ETags and sources always use XYZ rows.
+    let requested_y = req.param::<u32>("y").unwrap_or(0);
+    let y = requested_xyz_y(req, depot, tms, z, requested_y)?;
+    Ok((tms, z, x, y))
 }
 
 #[handler]
-pub async fn get_category_layers_tile(
+pub async fn get_composite_layers_tile(
     req: &mut Request,
     res: &mut Response,
     depot: &mut Depot,
 ) -> AppResult<()> {
-    res.headers_mut().insert(
-        "content-type",
-        "application/x-protobuf;type=mapbox-vector".parse()?,
-    );
+    let layers = req.param::<String>("layers").unwrap_or_default();
+    let (tms, z, x, y) = combined_tile_address(req, depot)?;
 
-    let category = req.param::<String>("category").unwrap_or_default();
-    let x = req.param::<u32>("x").unwrap_or(0);
-    let y = req.param::<u32>("y").unwrap_or(0);
-    let z = req.param::<u32>("z").unwrap_or(0);
+    let layers_vec: Vec<String> = layers
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect();
 
-    let (req_user, req_groups) = get_request_user(req, depot).await;
+    let candidates: Vec<_> = {
+        let catalog = get_catalog().await.read().await;
+        layers_vec
+            .iter()
+            .filter_map(|layer_name| {
+                let (category, name) = layer_name.split_once(':').unwrap_or(("", ""));
+                catalog
+                    .find_layer_by_category_and_name(category, name, StateLayer::Published)
+                    .cloned()
+            })
+            .collect()
+    };
+
+    serve_combined_tile(req, res, depot, candidates, tms, z, x, y).await
+}
+
+#[handler]
+pub async fn get_category_layers_tile(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let category = req.param::<String>("category").unwrap_or_default();
+    let (tms, z, x, y) = combined_tile_address(req, depot)?;
 
     let candidates: Vec<_> = {
         let catalog = get_catalog().await.read().await;
@@ -379,89 +637,5 @@ pub async fn get_category_layers_tile(
             .collect()
     };
 
-    let mut layer_configs = Vec::new();
-    for layer in candidates {
-        if validate_user_groups(req, &layer, depot).await? {
-            let zmin = layer.zmin.unwrap_or(0);
-            let zmax = layer.zmax.unwrap_or(22);
-            if z >= zmin && z <= zmax {
-                layer_configs.push(layer);
-            }
-        }
-    }
-
-    let min_cache_age = layer_configs
-        .iter()
-        .map(|l| l.max_cache_age.unwrap_or(0))
-        .filter(|&v| v > 0)
-        .min()
-        .unwrap_or(0);
-
-    let any_has_plugin = layer_configs.iter().any(|l| {
-        let key = format!("{}_{}", l.category.name, l.name);
-        get_plugin_registry().has_plugin(&key, &l.category.name)
-    });
-
-    // Build version-based ETag from all layer versions combined.
-    // Skipped when any layer has a plugin (dynamic content).
-    let cache_wrapper = get_cache_wrapper();
-    let mut etag_input = format!("{z}:{x}:{y}");
-    for layer in &layer_configs {
-        let key = format!("{}_{}", layer.category.name, layer.name);
-        let version = cache_wrapper.get_layer_version(&key).await;
-        etag_input.push(':');
-        etag_input.push_str(&key);
-        etag_input.push(':');
-        etag_input.push_str(&version.to_string());
-    }
-    let etag = compute_etag(&etag_input);
-
-    if !any_has_plugin && is_not_modified(req, &etag) {
-        set_cache_headers(res, &etag, min_cache_age);
-        res.status_code(StatusCode::NOT_MODIFIED);
-        return Ok(());
-    }
-
-    let mut futures = Vec::new();
-    for layer in layer_configs {
-        let pg_pool = match get_db_registry().get_pool(&layer.database_id) {
-            Some(pool) => pool.clone(),
-            None => continue,
-        };
-        futures.push(get_tile(pg_pool, layer, x, y, z, String::new(), Vec::new(), req_user.clone(), req_groups.clone()));
-    }
-
-    let results = futures::future::join_all(futures).await;
-
-    let mut output_data = Vec::new();
-    let mut cache_hits = 0;
-    let mut cache_misses = 0;
-
-    for result in results {
-        if let Ok((tile, via)) = result {
-            match via {
-                Via::Database => cache_misses += 1,
-                Via::Cache => cache_hits += 1,
-            }
-            output_data.push(tile);
-        }
-    }
-
-    let final_output = Bytes::from(output_data.concat());
-
-    res.headers_mut().insert(
-        "X-Cache",
-        HeaderValue::from_str(&format!("HIT: {cache_hits}, MISS: {cache_misses}"))
-            .unwrap_or_else(|_| HeaderValue::from_static("UNKNOWN")),
-    );
-
-    if any_has_plugin {
-        if let Ok(v) = HeaderValue::from_str("no-store, no-cache") {
-            res.headers_mut().insert("Cache-Control", v);
-        }
-    } else {
-        set_cache_headers(res, &etag, min_cache_age);
-    }
-    res.body(salvo::http::ResBody::Once(final_output));
-    Ok(())
+    serve_combined_tile(req, res, depot, candidates, tms, z, x, y).await
 }
@@ -1,5 +1,7 @@
 pub mod builder;
+pub mod coalesce;
 pub mod handlers;
+pub mod scheme;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,87 @@
+use salvo::prelude::*;
+
+const DEPOT_KEY: &str = "tile_scheme";
+
+/// Row numbering of tile requests. Caches, ETags and sources always work
+/// with XYZ rows; TMS requests are flipped on the way in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TileScheme {
+    #[default]
+    Xyz,
+    /// Origin at the bottom-left, as used by TMS clients (QGIS, OpenLayers).
+    Tms,
+}
+
+impl TileScheme {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Xyz => "xyz",
+            Self::Tms => "tms",
+        }
+    }
+
+    /// Scheme of a request: the `/tiles/tms/` route prefix, or a
+    /// `?scheme=tms` query parameter on the regular routes.
+    pub fn from_request(req: &Request, depot: &Depot) -> Self {
+        if depot.get::<TileScheme>(DEPOT_KEY).is_ok_and(|s| *s == Self::Tms) {
+            return Self::Tms;
+        }
+        match req.query::<String>("scheme").as_deref() {
+            Some(s) if s.eq_ignore_ascii_case("tms") => Self::Tms,
+            _ => Self::Xyz,
+        }
+    }
+
+    /// Canonical (XYZ) row for a requested row in this scheme, given the
+    /// number of rows of the zoom level. The flip is its own inverse; rows
+    /// past the grid stay out of range.
+    pub fn xyz_y(&self, rows: u32, y: u32) -> u32 {
+        match self {
+            Self::Xyz => y,
+            Self::Tms => (rows - 1).checked_sub(y).unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Path segment placed after `/services/tiles` in tile URLs.
+    pub fn route_prefix(&self) -> &'static str {
+        match self {
+            Self::Xyz => "",
+            Self::Tms => "/tms",
+        }
+    }
+}
+
+/// Hoop of the `/tiles/tms/` routes.
+#[handler]
+pub async fn tms_scheme(depot: &mut Depot) {
+    depot.insert(DEPOT_KEY, TileScheme::Tms);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tms_rows_flip_to_xyz_and_back() {
+        assert_eq!(TileScheme::Xyz.xyz_y(8, 2), 2);
+        assert_eq!(TileScheme::Tms.xyz_y(8, 2), 5);
+        assert_eq!(TileScheme::Tms.xyz_y(8, TileScheme::Tms.xyz_y(8, 2)), 2);
+        assert_eq!(TileScheme::Tms.xyz_y(1, 0), 0);
+        assert_eq!(TileScheme::Tms.xyz_y(8, 8), u32::MAX);
+    }
+
+    #[test]
+    fn scheme_is_read_from_query_or_route_prefix() {
+        let depot = Depot::new();
+        let mut req = Request::default();
+        *req.uri_mut() = "http://h/tiles/a:b/1/0/0.pbf?scheme=TMS".parse().unwrap();
+        assert_eq!(TileScheme::from_request(&req, &depot), TileScheme::Tms);
+
+        let req = Request::default();
+        assert_eq!(TileScheme::from_request(&req, &depot), TileScheme::Xyz);
+
+        let mut depot = Depot::new();
+        depot.insert(DEPOT_KEY, TileScheme::Tms);
+        assert_eq!(TileScheme::from_request(&req, &depot), TileScheme::Tms);
+    }
+}
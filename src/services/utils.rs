@@ -59,19 +59,14 @@ const DANGEROUS_KEYWORDS: &[&str] = &[
     "xp_cmdshell",
 ];
 
-pub fn convert_fields(fields: Vec<String>) -> String {
-    let vec_fields: Vec<String> = if fields.len() == 1 {
-        fields[0]
-            .split(',')
-            .map(|s| format!("\"{}\"", s.trim()))
-            .collect()
-    } else {
-        fields
-            .iter()
-            .map(|field| format!("\"{field}\""))
-            .collect::<Vec<_>>()
-    };
-    vec_fields.join(", ")
+/// Quoted select list of the layer's fields at zoom `z`.
+pub fn convert_fields(layer: &Layer, z: u32) -> String {
+    layer
+        .fields_at(z)
+        .iter()
+        .map(|field| format!("\"{field}\""))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 // fn is_inside_quotes(filter: &str, pos: usize) -> bool {
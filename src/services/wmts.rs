@@ -0,0 +1,333 @@
+//! WMTS 1.0.0 `GetCapabilities` for clients that discover layers through
+//! WMTS (ArcGIS Pro, older QGIS). Only the capabilities document is served
+//! here; its resource URLs point at the regular `/services/tiles` routes.
+
+use askama::Template;
+use salvo::http::StatusCode;
+use salvo::prelude::*;
+
+use crate::{
+    error::AppResult,
+    get_catalog, get_tile_matrix_sets,
+    models::{
+        catalog::Layer,
+        tile_matrix_set::{TileMatrix, TileMatrixSet, WEB_MERCATOR_QUAD},
+    },
+    services::{
+        tilejson::base_url_from_request, tiles::builder::supported_tile_matrix_sets,
+        utils::validate_user_groups,
+    },
+};
+
+/// `TileMatrixSetLimits` entry: the whole grid of one zoom level.
+pub struct WmtsLimits {
+    pub tile_matrix: u32,
+    pub max_tile_row: u32,
+    pub max_tile_col: u32,
+}
+
+pub struct WmtsMatrixSetLink {
+    pub id: String,
+    pub limits: Vec<WmtsLimits>,
+}
+
+pub struct WmtsLayer {
+    pub identifier: String,
+    pub title: String,
+    pub abstract_text: String,
+    pub links: Vec<WmtsMatrixSetLink>,
+    pub resource_url: String,
+}
+
+pub struct WmtsMatrixSet {
+    pub id: String,
+    pub title: String,
+    pub supported_crs: String,
+    pub well_known_scale_set: Option<&'static str>,
+    pub matrices: Vec<TileMatrix>,
+}
+
+#[derive(Template)]
+#[template(path = "wmts/capabilities.xml")]
+pub struct CapabilitiesTemplate {
+    pub base_url: String,
+    pub layers: Vec<WmtsLayer>,
+    pub matrix_sets: Vec<WmtsMatrixSet>,
+}
+
+#[derive(Template)]
+#[template(path = "wmts/exception.xml")]
+pub struct ExceptionTemplate<'a> {
+    pub code: &'a str,
+    pub locator: &'a str,
+    pub text: String,
+}
+
+/// CRS as an OGC URN; EPSG:4326 tiles are lon/lat, i.e. CRS84.
+fn supported_crs(tms: &TileMatrixSet) -> String {
+    match tms.srid {
+        4326 => "urn:ogc:def:crs:OGC:1.3:CRS84".to_string(),
+        srid => format!("urn:ogc:def:crs:EPSG::{srid}"),
+    }
+}
+
+fn matrix_set_link(layer: &Layer, tms: &TileMatrixSet) -> WmtsMatrixSetLink {
+    let zmax = layer.get_zmax().min(tms.max_zoom());
+    WmtsMatrixSetLink {
+        id: tms.id.clone(),
+        limits: (layer.get_zmin()..=zmax)
+            .map(|z| WmtsLimits {
+                tile_matrix: z,
+                max_tile_row: tms.matrix_height_at(z) - 1,
+                max_tile_col: tms.matrix_width_at(z) - 1,
+            })
+            .collect(),
+    }
+}
+
+pub fn wmts_layer(base_url: &str, layer: &Layer) -> WmtsLayer {
+    let identifier = format!("{}:{}", layer.category.name, layer.name);
+    WmtsLayer {
+        resource_url: format!(
+            "{base_url}/services/tiles/{identifier}/{{TileMatrix}}/{{TileCol}}/{{TileRow}}.pbf?tileMatrixSet={{TileMatrixSet}}"
+        ),
+        title: if layer.alias.is_empty() {
+            layer.name.clone()
+        } else {
+            layer.alias.clone()
+        },
+        abstract_text: layer.description.clone(),
+        links: supported_tile_matrix_sets(layer)
+            .into_iter()
+            .map(|tms| matrix_set_link(layer, tms))
+            .collect(),
+        identifier,
+    }
+}
+
+pub fn wmts_matrix_set(tms: &TileMatrixSet) -> WmtsMatrixSet {
+    WmtsMatrixSet {
+        id: tms.id.clone(),
+        title: tms.title.clone(),
+        supported_crs: supported_crs(tms),
+        well_known_scale_set: (tms.id == WEB_MERCATOR_QUAD)
+            .then_some("urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible"),
+        matrices: (0..=tms.max_zoom()).map(|z| tms.tile_matrix(z)).collect(),
+    }
+}
+
+/// Capabilities listing `layers`, which the caller has already filtered
+/// down to what the requester may see.
+pub fn build_capabilities(base_url: &str, layers: &[Layer]) -> CapabilitiesTemplate {
+    CapabilitiesTemplate {
+        base_url: base_url.to_string(),
+        layers: layers.iter().map(|l| wmts_layer(base_url, l)).collect(),
+        matrix_sets: get_tile_matrix_sets().iter().map(wmts_matrix_set).collect(),
+    }
+}
+
+fn render_exception(
+    res: &mut Response,
+    status: StatusCode,
+    code: &str,
+    locator: &str,
+    text: String,
+) -> AppResult<()> {
+    let exception = ExceptionTemplate {
+        code,
+        locator,
+        text,
+    };
+    res.status_code(status);
+    res.render(Text::Xml(exception.render()?));
+    Ok(())
+}
+
+async fn render_capabilities(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    let published = {
+        let catalog = get_catalog().await.read().await;
+        catalog.get_published_layers()
+    };
+
+    let mut layers = Vec::new();
+    for layer in published {
+        if validate_user_groups(req, &layer, depot).await? {
+            layers.push(layer);
+        }
+    }
+
+    let document = build_capabilities(&base_url_from_request(req), &layers);
+    res.render(Text::Xml(document.render()?));
+    Ok(())
+}
+
+/// RESTful `GET /services/wmts/1.0.0/WMTSCapabilities.xml`.
+#[handler]
+pub async fn capabilities(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> AppResult<()> {
+    render_capabilities(req, res, depot).await
+}
+
+/// KVP `GET /services/wmts?SERVICE=WMTS&REQUEST=GetCapabilities`. Parameter
+/// names are case-insensitive, as OWS requires. Tiles are only served
+/// RESTfully, so any other request is reported as not supported.
+#[handler]
+pub async fn kvp(req: &mut Request, res: &mut Response, depot: &mut Depot) -> AppResult<()> {
+    let param = |name: &str| {
+        req.queries()
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.to_string())
+    };
+    let service = param("service");
+    let request = param("request");
+
+    match service.as_deref() {
+        Some(s) if s.eq_ignore_ascii_case("WMTS") => {}
+        Some(s) => {
+            let text = format!("Unknown service '{s}'");
+            return render_exception(
+                res,
+                StatusCode::BAD_REQUEST,
+                "InvalidParameterValue",
+                "service",
+                text,
+            );
+        }
+        None => {
+            let text = "Missing SERVICE parameter".to_string();
+            return render_exception(
+                res,
+                StatusCode::BAD_REQUEST,
+                "MissingParameterValue",
+                "service",
+                text,
+            );
+        }
+    }
+
+    match request.as_deref() {
+        Some(r) if r.eq_ignore_ascii_case("GetCapabilities") => {
+            render_capabilities(req, res, depot).await
+        }
+        Some(r) => {
+            let text = format!("Request '{r}' is not supported; use the RESTful tile URLs");
+            render_exception(
+                res,
+                StatusCode::NOT_IMPLEMENTED,
+                "OperationNotSupported",
+                "request",
+                text,
+            )
+        }
+        None => {
+            let text = "Missing REQUEST parameter".to_string();
+            render_exception(
+                res,
+                StatusCode::BAD_REQUEST,
+                "MissingParameterValue",
+                "request",
+                text,
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{catalog::SourceKind, category::Category};
+
+    fn test_layer() -> Layer {
+        Layer {
+            id: "layer-1".to_string(),
+            category: Category {
+                id: "cat-1".to_string(),
+                name: "public".to_string(),
+                description: "".to_string(),
+            },
+            geometry: "polygons".to_string(),
+            name: "parcels".to_string(),
+            alias: "Parcels & lots".to_string(),
+            description: "".to_string(),
+            source_kind: SourceKind::Postgis,
+            static_file: None,
+            function_name: None,
+            sql_query: None,
+            sql_params: None,
+            database_id: "default".to_string(),
+            schema: "public".to_string(),
+            table_name: "parcels".to_string(),
+            fields: vec!["gid".to_string()],
+            zoom_fields: None,
+            generalization: None,
+            filter: None,
+            srid: None,
+            tile_matrix_set: None,
+            geom: None,
+            id_column: None,
+            sql_mode: None,
+            buffer: None,
+            extent: None,
+            zmin: Some(1),
+            zmax: Some(2),
+            zmax_do_not_simplify: None,
+            buffer_do_not_simplify: None,
+            extent_do_not_simplify: None,
+            clip_geom: None,
+            delete_cache_on_start: None,
+            max_cache_age: None,
+            max_records: None,
+            priority: None,
+            priority_order: None,
+            cluster_zoom: None,
+            cluster_radius: None,
+            cluster_aggregates: None,
+            bin_shape: None,
+            bin_size: None,
+            bin_aggregates: None,
+            query_timeout_ms: None,
+            notify_changes: None,
+            published: true,
+            url: None,
+            groups: None,
+        }
+    }
+
+    #[test]
+    fn capabilities_point_at_the_tile_routes() {
+        let xml = build_capabilities("https://tiles.example.com", &[test_layer()])
+            .render()
+            .unwrap();
+
+        assert!(xml.contains("<ows:Identifier>public:parcels</ows:Identifier>"));
+        assert!(xml.contains("<ows:Title>Parcels &#38; lots</ows:Title>"));
+        assert!(xml.contains(
+            r#"<ResourceURL format="application/vnd.mapbox-vector-tile" resourceType="tile" template="https://tiles.example.com/services/tiles/public:parcels/{TileMatrix}/{TileCol}/{TileRow}.pbf?tileMatrixSet={TileMatrixSet}"/>"#
+        ));
+        assert!(xml.contains("<TileMatrixSet>WorldCRS84Quad</TileMatrixSet>"));
+        assert!(xml.contains(
+            "<WellKnownScaleSet>urn:ogc:def:wkss:OGC:1.0:GoogleMapsCompatible</WellKnownScaleSet>"
+        ));
+        assert!(xml.contains("<ows:SupportedCRS>urn:ogc:def:crs:OGC:1.3:CRS84</ows:SupportedCRS>"));
+        // Limits follow the layer's zoom range.
+        assert_eq!(xml.matches("<TileMatrixLimits>").count(), 4);
+    }
+
+    #[test]
+    fn static_layers_only_link_web_mercator() {
+        let mut layer = test_layer();
+        layer.source_kind = SourceKind::Pmtiles;
+        let wmts = wmts_layer("http://h", &layer);
+        let ids: Vec<_> = wmts.links.iter().map(|l| l.id.as_str()).collect();
+        assert_eq!(ids, vec![WEB_MERCATOR_QUAD]);
+        assert_eq!(wmts.links[0].limits[1].max_tile_col, 3);
+    }
+}